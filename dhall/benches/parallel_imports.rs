@@ -0,0 +1,91 @@
+//! Compares resolving a file with 10 independent imports with and without `parallel_imports`,
+//! both for files in the page cache and for files behind a slow resolver, like a network
+//! filesystem.
+//!
+//! Run with `cargo bench -p dhall --bench parallel_imports --features parallel-imports`.
+//! The files are read by rayon's thread pool, which has one thread per CPU unless
+//! `RAYON_NUM_THREADS` says otherwise.
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dhall::error::Error;
+use dhall::semantics::{ImportEnv, ImportResolver};
+use dhall::{Ctxt, Parsed};
+
+const IMPORTS: usize = 10;
+const RUNS: usize = 100;
+const LATENCY: Duration = Duration::from_millis(1);
+
+/// Reads files from the filesystem after waiting for `LATENCY`.
+struct SlowResolver;
+
+impl ImportResolver for SlowResolver {
+    fn read_file(&self, path: &Path) -> Result<String, Error> {
+        thread::sleep(LATENCY);
+        Ok(std::fs::read_to_string(path)?)
+    }
+}
+
+/// Writes `main.dhall`, which imports `0.dhall` to `9.dhall`, and returns its path.
+fn write_files(dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).unwrap();
+    let mut imports = Vec::new();
+    for i in 0..IMPORTS {
+        let file = format!("{}.dhall", i);
+        let contents = format!("{{ id = {}, name = \"file {}\" }}", i, i);
+        std::fs::write(dir.join(&file), contents).unwrap();
+        imports.push(format!("./{}", file));
+    }
+    let main = dir.join("main.dhall");
+    std::fs::write(&main, format!("[{}]", imports.join(", "))).unwrap();
+    main
+}
+
+fn time(main: &Path, parallel_imports: bool, slow: bool) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        Ctxt::with_new(|cx| {
+            let mut env =
+                ImportEnv::new(cx).with_parallel_imports(parallel_imports);
+            if slow {
+                env = env.with_resolver(SlowResolver);
+            }
+            Parsed::parse_file(main)
+                .unwrap()
+                .resolve_with_env(&mut env)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap();
+        })
+    }
+    start.elapsed() / RUNS as u32
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!(
+        "dhall_bench_parallel_imports_{}",
+        std::process::id()
+    ));
+    let main = write_files(&dir);
+    println!("resolving {} independent local imports:", IMPORTS);
+    println!(
+        "  sequential:                {:?}",
+        time(&main, false, false)
+    );
+    println!(
+        "  parallel:                  {:?}",
+        time(&main, true, false)
+    );
+    println!(
+        "  sequential, {:?} latency:  {:?}",
+        LATENCY,
+        time(&main, false, true)
+    );
+    println!(
+        "  parallel, {:?} latency:    {:?}",
+        LATENCY,
+        time(&main, true, true)
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
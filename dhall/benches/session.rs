@@ -0,0 +1,75 @@
+//! Compares typechecking many expressions that share a preamble with a `Session` built once,
+//! against wrapping each expression in the preamble's `let`s.
+//!
+//! Run with `cargo bench -p dhall --bench session`.
+use std::time::{Duration, Instant};
+
+use dhall::syntax::{Expr, Label};
+use dhall::{Ctxt, Parsed, Session};
+
+const BINDINGS: usize = 100;
+const EXPRS: usize = 200;
+
+fn parse(s: &str) -> Expr {
+    Parsed::parse_str(s).unwrap().to_expr()
+}
+
+/// Type aliases and functions that each refer to the previous one.
+fn preamble() -> Vec<(Label, Option<Expr>, Expr)> {
+    let mut bindings = vec![
+        ("T0".into(), None, parse("{ a : Natural, b : List Text }")),
+        ("f0".into(), None, parse("λ(x : T0) → x.a")),
+    ];
+    for i in 1..BINDINGS {
+        let ty = format!("{{ prev : T{}, c : Optional Bool }}", i - 1);
+        let f = format!("λ(x : T{}) → f{} x.prev + 1", i, i - 1);
+        bindings.push((format!("T{}", i).as_str().into(), None, parse(&ty)));
+        bindings.push((format!("f{}", i).as_str().into(), None, parse(&f)));
+    }
+    bindings
+}
+
+fn exprs() -> Vec<Parsed> {
+    (0..EXPRS)
+        .map(|i| {
+            let i = i % BINDINGS;
+            let s = format!("λ(x : T{}) → [f{} x, {}]", i, i, i);
+            Parsed::parse_str(&s).unwrap()
+        })
+        .collect()
+}
+
+fn time(f: impl FnOnce()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn main() {
+    let rebuilt = time(|| {
+        Ctxt::with_new(|cx| {
+            for expr in exprs() {
+                let bindings = preamble().into_iter().map(|(l, _, e)| (l, e));
+                let parsed = Parsed::from_expr_without_imports(
+                    expr.add_let_bindings(bindings).to_expr(),
+                );
+                parsed.skip_resolve(cx).unwrap().typecheck(cx).unwrap();
+            }
+        })
+    });
+    let shared = time(|| {
+        Ctxt::with_new(|cx| {
+            let session = Session::from_let_bindings(cx, preamble()).unwrap();
+            for ty in session.type_of_all(exprs()) {
+                ty.unwrap();
+            }
+        })
+    });
+    println!(
+        "{} expressions with a preamble of {} bindings:",
+        EXPRS,
+        2 * BINDINGS
+    );
+    println!("  preamble typechecked for each: {:?}", rebuilt);
+    println!("  preamble typechecked once:     {:?}", shared);
+}
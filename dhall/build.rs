@@ -64,6 +64,16 @@ fn convert_abnf_to_pest() -> std::io::Result<()> {
     }}"
     )?;
 
+    // Builtins we support that aren't in the standard grammar. The abnf file is
+    // kept identical to the one from dhall-lang, so we extend the rule here.
+    let mut standard_builtin = rules.remove("builtin").unwrap();
+    standard_builtin.silent = true;
+    rules.insert("standard_builtin".to_owned(), standard_builtin);
+    writeln!(
+        &mut file,
+        r#"builtin = {{ "Text/split" | standard_builtin }}"#
+    )?;
+
     // Setup grammar for precedence climbing
     rules.remove("operator_expression");
     writeln!(
@@ -8,80 +8,70 @@ use crate::syntax::{
     Const, Expr, ExprKind, InterpolatedText, InterpolatedTextContents, Label,
     NaiveDouble, NumKind, Span, UnspannedExpr, V,
 };
-use crate::{Ctxt, Parsed};
-
-/// Built-ins
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-pub enum Builtin {
-    Bool,
-    Natural,
-    Integer,
-    Double,
-    Text,
-    List,
-    Optional,
-    OptionalNone,
-    NaturalBuild,
-    NaturalFold,
-    NaturalIsZero,
-    NaturalEven,
-    NaturalOdd,
-    NaturalToInteger,
-    NaturalShow,
-    NaturalSubtract,
-    IntegerToDouble,
-    IntegerShow,
-    IntegerNegate,
-    IntegerClamp,
-    DoubleShow,
-    ListBuild,
-    ListFold,
-    ListLength,
-    ListHead,
-    ListLast,
-    ListIndexed,
-    ListReverse,
-    TextShow,
-    TextReplace,
-}
+use crate::{Ctxt, Parsed, TraceEvent};
 
-impl Builtin {
-    pub fn parse(s: &str) -> Option<Self> {
-        use Builtin::*;
-        match s {
-            "Bool" => Some(Bool),
-            "Natural" => Some(Natural),
-            "Integer" => Some(Integer),
-            "Double" => Some(Double),
-            "Text" => Some(Text),
-            "List" => Some(List),
-            "Optional" => Some(Optional),
-            "None" => Some(OptionalNone),
-            "Natural/build" => Some(NaturalBuild),
-            "Natural/fold" => Some(NaturalFold),
-            "Natural/isZero" => Some(NaturalIsZero),
-            "Natural/even" => Some(NaturalEven),
-            "Natural/odd" => Some(NaturalOdd),
-            "Natural/toInteger" => Some(NaturalToInteger),
-            "Natural/show" => Some(NaturalShow),
-            "Natural/subtract" => Some(NaturalSubtract),
-            "Integer/toDouble" => Some(IntegerToDouble),
-            "Integer/show" => Some(IntegerShow),
-            "Integer/negate" => Some(IntegerNegate),
-            "Integer/clamp" => Some(IntegerClamp),
-            "Double/show" => Some(DoubleShow),
-            "List/build" => Some(ListBuild),
-            "List/fold" => Some(ListFold),
-            "List/length" => Some(ListLength),
-            "List/head" => Some(ListHead),
-            "List/last" => Some(ListLast),
-            "List/indexed" => Some(ListIndexed),
-            "List/reverse" => Some(ListReverse),
-            "Text/show" => Some(TextShow),
-            "Text/replace" => Some(TextReplace),
-            _ => None,
+// Declares the builtins along with their names in Dhall source, so that parsing, printing and
+// listing them can't disagree.
+macro_rules! builtins {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Built-ins
+        #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+        pub enum Builtin {
+            $($variant,)*
         }
-    }
+
+        impl Builtin {
+            pub fn parse(s: &str) -> Option<Self> {
+                match s {
+                    $($name => Some(Builtin::$variant),)*
+                    _ => None,
+                }
+            }
+            /// The name of the builtin, as written in Dhall source.
+            fn name(self) -> &'static str {
+                match self {
+                    $(Builtin::$variant => $name,)*
+                }
+            }
+        }
+
+        /// The names of all the builtins, as written in Dhall source.
+        static BUILTIN_NAMES: &[&str] = &[$($name,)*];
+    };
+}
+
+builtins! {
+    Bool => "Bool",
+    Natural => "Natural",
+    Integer => "Integer",
+    Double => "Double",
+    Text => "Text",
+    List => "List",
+    Optional => "Optional",
+    OptionalNone => "None",
+    NaturalBuild => "Natural/build",
+    NaturalFold => "Natural/fold",
+    NaturalIsZero => "Natural/isZero",
+    NaturalEven => "Natural/even",
+    NaturalOdd => "Natural/odd",
+    NaturalToInteger => "Natural/toInteger",
+    NaturalShow => "Natural/show",
+    NaturalSubtract => "Natural/subtract",
+    IntegerToDouble => "Integer/toDouble",
+    IntegerShow => "Integer/show",
+    IntegerNegate => "Integer/negate",
+    IntegerClamp => "Integer/clamp",
+    DoubleShow => "Double/show",
+    ListBuild => "List/build",
+    ListFold => "List/fold",
+    ListLength => "List/length",
+    ListHead => "List/head",
+    ListLast => "List/last",
+    ListIndexed => "List/indexed",
+    ListReverse => "List/reverse",
+    TextShow => "Text/show",
+    TextReplace => "Text/replace",
+    TextSplit => "Text/split",
 }
 
 /// A partially applied builtin.
@@ -101,7 +91,13 @@ impl<'cx> BuiltinClosure<'cx> {
     pub fn apply(&self, a: Nir<'cx>) -> NirKind<'cx> {
         use std::iter::once;
         let args = self.args.iter().cloned().chain(once(a)).collect();
-        apply_builtin(self.b, args, self.env.clone())
+        // Builtins like `Natural/fold` compute their result right away, which nests.
+        self.env
+            .cx()
+            .nested_eval(|| apply_builtin(self.b, args, self.env.clone()))
+    }
+    pub(crate) fn args(&self) -> &[Nir<'cx>] {
+        &self.args
     }
     pub fn to_hirkind(&self, venv: VarEnv) -> HirKind<'cx> {
         HirKind::Expr(self.args.iter().fold(
@@ -176,9 +172,27 @@ macro_rules! make_type {
     };
 }
 
+/// The names of all the builtins recognized by the typechecker.
+pub fn builtin_names() -> &'static [&'static str] {
+    BUILTIN_NAMES
+}
+
+/// The type the typechecker assigns to the builtin with the given name, or `None` if there is no
+/// such builtin.
+pub fn builtin_type(name: &str) -> Option<Expr> {
+    Builtin::parse(name).map(type_expr_of_builtin)
+}
+
 pub fn type_of_builtin<'cx>(cx: Ctxt<'cx>, b: Builtin) -> Hir<'cx> {
+    Parsed::from_expr_without_imports(type_expr_of_builtin(b))
+        .resolve(cx)
+        .unwrap()
+        .0
+}
+
+fn type_expr_of_builtin(b: Builtin) -> Expr {
     use Builtin::*;
-    let expr = match b {
+    match b {
         Bool | Natural | Integer | Double | Text => make_type!(Type),
         List | Optional => make_type!(
             Type -> Type
@@ -218,6 +232,11 @@ pub fn type_of_builtin<'cx>(cx: Ctxt<'cx>, b: Builtin) -> Hir<'cx> {
             forall (haystack: Text) ->
             Text
         ),
+        TextSplit => make_type!(
+            forall (separator: Text) ->
+            forall (input: Text) ->
+            List Text
+        ),
         ListBuild => make_type!(
             forall (a: Type) ->
             (forall (list: Type) ->
@@ -250,11 +269,7 @@ pub fn type_of_builtin<'cx>(cx: Ctxt<'cx>, b: Builtin) -> Hir<'cx> {
         OptionalNone => make_type!(
             forall (A: Type) -> Optional A
         ),
-    };
-    Parsed::from_expr_without_imports(expr)
-        .resolve(cx)
-        .unwrap()
-        .0
+    }
 }
 
 // Ad-hoc macro to help construct closures
@@ -333,6 +348,10 @@ fn apply_builtin<'cx>(
             .eval(env.clone())
     };
 
+    if cx.limit_reached() && !args.is_empty() {
+        return AppliedBuiltin(BuiltinClosure { b, args, env });
+    }
+
     let ret = match (b, args.as_slice()) {
         (Builtin::Bool, [])
         | (Builtin::Natural, [])
@@ -392,7 +411,11 @@ fn apply_builtin<'cx>(
             _ => Ret::DoneAsIs,
         },
         (Builtin::IntegerNegate, [n]) => match &*n.kind() {
-            Num(Integer(n)) => Ret::NirKind(Num(Integer(-n))),
+            // Integers are stored as `i64`, which can't hold `-i64::MIN`.
+            Num(Integer(n)) => match n.checked_neg() {
+                Some(n) => Ret::NirKind(Num(Integer(n))),
+                None => Ret::DoneAsIs,
+            },
             _ => Ret::DoneAsIs,
         },
         (Builtin::IntegerClamp, [n]) => match &*n.kind() {
@@ -439,7 +462,9 @@ fn apply_builtin<'cx>(
                     if let Some(h) = nir_to_string(haystack) {
                         // Fast case when replacement is fully evaluated
                         if let Some(r) = nir_to_string(replacement) {
-                            Ret::Nir(Nir::from_text(h.replace(&n, &r)))
+                            Ret::NirKind(TextLit(nze::nir::TextLit::from_text(
+                                h.replace(&n, &r),
+                            )))
                         } else {
                             use itertools::Itertools;
 
@@ -450,12 +475,12 @@ fn apply_builtin<'cx>(
                                 replacement.clone(),
                             );
 
-                            Ret::Nir(Nir::from_kind(NirKind::TextLit(
+                            Ret::NirKind(NirKind::TextLit(
                                 nze::nir::TextLit::new(Itertools::intersperse(
                                     parts,
                                     replacement,
                                 )),
-                            )))
+                            ))
                         }
                     } else {
                         Ret::DoneAsIs
@@ -464,6 +489,31 @@ fn apply_builtin<'cx>(
                 _ => Ret::DoneAsIs,
             }
         }
+        (Builtin::TextSplit, [separator, input]) => {
+            let as_text = |n: &Nir<'cx>| match n.kind() {
+                TextLit(lit) => lit.as_text(),
+                _ => None,
+            };
+            match (as_text(separator), as_text(input)) {
+                (Some(sep), Some(input)) => {
+                    // An empty separator splits the input into characters.
+                    let parts: Vec<_> = if sep.is_empty() {
+                        input.chars().map(Nir::from_text).collect()
+                    } else {
+                        input.split(&sep).map(Nir::from_text).collect()
+                    };
+                    if parts.is_empty() {
+                        Ret::NirKind(EmptyListLit(Nir::from_builtin(
+                            cx,
+                            Builtin::Text,
+                        )))
+                    } else {
+                        Ret::NirKind(NEListLit(parts))
+                    }
+                }
+                _ => Ret::DoneAsIs,
+            }
+        }
         (Builtin::ListLength, [_, l]) => match &*l.kind() {
             EmptyListLit(_) => Ret::NirKind(Num(Natural(0))),
             NEListLit(xs) => Ret::NirKind(Num(Natural(xs.len() as u64))),
@@ -562,22 +612,40 @@ fn apply_builtin<'cx>(
                 .app(Num(Natural(0)).into_nir()),
         ),
 
-        (Builtin::NaturalFold, [n, t, succ, zero]) => match &*n.kind() {
-            Num(Natural(0)) => Ret::Nir(zero.clone()),
+        (Builtin::NaturalFold, [n, _, succ, zero]) => match &*n.kind() {
             Num(Natural(n)) => {
-                let fold = Nir::from_builtin(cx, Builtin::NaturalFold)
-                    .app(Num(Natural(n - 1)).into_nir())
-                    .app(t.clone())
-                    .app(succ.clone())
-                    .app(zero.clone());
-                Ret::Nir(succ.app(fold))
+                // Like `List/fold`, this applies `succ` in a loop rather than recursing, so that
+                // long folds don't overflow the stack. Each step is computed right away, so it
+                // is counted and traced. Once `succ` stops changing the value, the remaining
+                // steps wouldn't change it either, so huge folds of e.g. a constant function
+                // stop early.
+                let mut v = zero.clone();
+                for _ in 0..*n {
+                    if cx.limit_reached() {
+                        break;
+                    }
+                    cx.count_nodes(1);
+                    cx.trace(|depth| TraceEvent::Builtin { builtin: b, depth });
+                    let next = succ.app(v.clone());
+                    if next == v {
+                        break;
+                    }
+                    v = next;
+                }
+                Ret::Nir(v)
             }
             _ => Ret::DoneAsIs,
         },
         _ => Ret::DoneAsIs,
     };
+    if !args.is_empty() && !matches!(ret, Ret::DoneAsIs) {
+        cx.trace(|depth| TraceEvent::Builtin { builtin: b, depth });
+    }
     match ret {
-        Ret::NirKind(v) => v,
+        Ret::NirKind(v) => {
+            cx.count_nodes(v.size());
+            v
+        }
         Ret::Nir(v) => v.kind().clone(),
         Ret::DoneAsIs => AppliedBuiltin(BuiltinClosure { b, args, env }),
     }
@@ -592,38 +660,223 @@ impl<'cx> std::cmp::Eq for BuiltinClosure<'cx> {}
 
 impl std::fmt::Display for Builtin {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use Builtin::*;
-        f.write_str(match *self {
-            Bool => "Bool",
-            Natural => "Natural",
-            Integer => "Integer",
-            Double => "Double",
-            Text => "Text",
-            List => "List",
-            Optional => "Optional",
-            OptionalNone => "None",
-            NaturalBuild => "Natural/build",
-            NaturalFold => "Natural/fold",
-            NaturalIsZero => "Natural/isZero",
-            NaturalEven => "Natural/even",
-            NaturalOdd => "Natural/odd",
-            NaturalToInteger => "Natural/toInteger",
-            NaturalShow => "Natural/show",
-            NaturalSubtract => "Natural/subtract",
-            IntegerToDouble => "Integer/toDouble",
-            IntegerNegate => "Integer/negate",
-            IntegerClamp => "Integer/clamp",
-            IntegerShow => "Integer/show",
-            DoubleShow => "Double/show",
-            ListBuild => "List/build",
-            ListFold => "List/fold",
-            ListLength => "List/length",
-            ListHead => "List/head",
-            ListLast => "List/last",
-            ListIndexed => "List/indexed",
-            ListReverse => "List/reverse",
-            TextShow => "Text/show",
-            TextReplace => "Text/replace",
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builtin_names, builtin_type, Builtin};
+    use crate::{Ctxt, Parsed};
+
+    fn normalize(s: &str) -> String {
+        Ctxt::with_new(|cx| {
+            Parsed::parse_str(s)
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap()
+                .normalize(cx)
+                .to_expr(cx)
+                .to_string()
         })
     }
+
+    #[test]
+    fn natural_predicates_on_literals() {
+        assert_eq!(normalize("Natural/even 0"), "True");
+        assert_eq!(normalize("Natural/odd 0"), "False");
+        assert_eq!(normalize("Natural/isZero 0"), "True");
+
+        assert_eq!(normalize("Natural/even 3"), "False");
+        assert_eq!(normalize("Natural/odd 3"), "True");
+        assert_eq!(normalize("Natural/isZero 3"), "False");
+
+        assert_eq!(normalize("Natural/even 42"), "True");
+        assert_eq!(normalize("Natural/odd 42"), "False");
+    }
+
+    #[test]
+    fn natural_predicates_on_variables() {
+        assert_eq!(
+            normalize("λ(n : Natural) → Natural/even n"),
+            "λ(n : Natural) → Natural/even n"
+        );
+        assert_eq!(
+            normalize("λ(n : Natural) → Natural/isZero n"),
+            "λ(n : Natural) → Natural/isZero n"
+        );
+    }
+
+    #[test]
+    fn natural_subtract() {
+        // `Natural/subtract m n` is `n - m`, clamped to zero.
+        assert_eq!(normalize("Natural/subtract 3 5"), "2");
+        assert_eq!(normalize("Natural/subtract 5 3"), "0");
+        assert_eq!(normalize("Natural/subtract 5 5"), "0");
+        assert_eq!(normalize("Natural/subtract 0 7"), "7");
+        assert_eq!(
+            normalize("λ(n : Natural) → Natural/subtract 0 n"),
+            "λ(n : Natural) → n"
+        );
+        assert_eq!(
+            normalize("λ(n : Natural) → Natural/subtract n 0"),
+            "λ(n : Natural) → 0"
+        );
+        assert_eq!(
+            normalize("λ(n : Natural) → Natural/subtract n n"),
+            "λ(n : Natural) → 0"
+        );
+        assert_eq!(
+            normalize("λ(n : Natural) → Natural/subtract 1 n"),
+            "λ(n : Natural) → Natural/subtract 1 n"
+        );
+    }
+
+    #[test]
+    fn text_split() {
+        assert_eq!(
+            normalize(r#"Text/split "," "a,b,,c""#),
+            r#"["a", "b", "", "c"]"#
+        );
+        assert_eq!(normalize(r#"Text/split "," "abc""#), r#"["abc"]"#);
+        assert_eq!(normalize(r#"Text/split "," """#), r#"[""]"#);
+        assert_eq!(normalize(r#"Text/split "" "aλc""#), r#"["a", "λ", "c"]"#);
+        assert_eq!(normalize(r#"Text/split "" """#), "[] : List Text");
+        assert_eq!(
+            normalize(r#"λ(t : Text) → Text/split "," t"#),
+            r#"λ(t : Text) → Text/split "," t"#
+        );
+    }
+
+    #[test]
+    fn text_replace() {
+        assert_eq!(
+            normalize(r#"Text/replace "a" "X" "banana""#),
+            r#""bXnXnX""#
+        );
+        assert_eq!(normalize(r#"Text/replace "" "X" "ab""#), r#""ab""#);
+        assert_eq!(normalize(r#"Text/replace "aa" "a" "aaaaa""#), r#""aaa""#);
+        // The replacement doesn't need to be a literal.
+        assert_eq!(
+            normalize(r#"λ(t : Text) → Text/replace "a" t "banana""#),
+            r#"λ(t : Text) → "b${ t }n${ t }n${ t }""#
+        );
+        assert_eq!(
+            normalize(r#"λ(t : Text) → Text/replace "a" "X" t"#),
+            r#"λ(t : Text) → Text/replace "a" "X" t"#
+        );
+    }
+
+    #[test]
+    fn integer_show() {
+        assert_eq!(normalize("Integer/show +0"), r#""+0""#);
+        assert_eq!(normalize("Integer/show -0"), r#""+0""#);
+        assert_eq!(normalize("Integer/show +1"), r#""+1""#);
+        assert_eq!(normalize("Integer/show -1"), r#""-1""#);
+        assert_eq!(
+            normalize("Integer/show +9223372036854775807"),
+            r#""+9223372036854775807""#
+        );
+        assert_eq!(
+            normalize("Integer/show -9223372036854775808"),
+            r#""-9223372036854775808""#
+        );
+        assert_eq!(normalize("Integer/show (Integer/negate +0)"), r#""+0""#);
+        assert_eq!(normalize("Integer/show (Integer/negate +5)"), r#""-5""#);
+        assert_eq!(
+            normalize("Integer/negate -9223372036854775807"),
+            "+9223372036854775807"
+        );
+        // The result doesn't fit.
+        assert_eq!(
+            normalize("Integer/negate -9223372036854775808"),
+            "Integer/negate -9223372036854775808"
+        );
+    }
+
+    #[test]
+    fn double_show() {
+        assert_eq!(normalize("Double/show NaN"), r#""NaN""#);
+        assert_eq!(normalize("Double/show Infinity"), r#""Infinity""#);
+        assert_eq!(normalize("Double/show -Infinity"), r#""-Infinity""#);
+        assert_eq!(normalize("Double/show -0.0"), r#""-0.0""#);
+        assert_eq!(normalize("Double/show 1.0"), r#""1.0""#);
+        assert_eq!(normalize("Double/show 0.1"), r#""0.1""#);
+        assert_eq!(normalize("Double/show 1e3"), r#""1000.0""#);
+        assert_eq!(normalize("Double/show 1e21"), r#""1.0e21""#);
+        assert_eq!(
+            normalize("Double/show -2.2250738585072014e-308"),
+            r#""-2.2250738585072014e-308""#
+        );
+    }
+
+    #[test]
+    fn natural_fold_fixed_point() {
+        assert_eq!(
+            normalize("Natural/fold 5 Natural (λ(n : Natural) → n + 2) 1"),
+            "11"
+        );
+        // The value stops changing after one step, so the rest of the steps are skipped.
+        assert_eq!(
+            normalize(
+                "Natural/fold 1000000000000 Natural (λ(_ : Natural) → 0) 3"
+            ),
+            "0"
+        );
+        assert_eq!(
+            normalize("λ(x : Natural) → Natural/fold 1000000000000 Natural (λ(n : Natural) → n) x"),
+            "λ(x : Natural) → x"
+        );
+    }
+
+    #[test]
+    fn fold_of_build() {
+        assert_eq!(
+            normalize(
+                "List/fold Natural \
+                   ( List/build Natural \
+                       ( λ(list : Type) → \
+                         λ(cons : Natural → list → list) → \
+                         λ(nil : list) → \
+                           Natural/fold 3 list (cons 1) nil \
+                       ) \
+                   ) Natural (λ(x : Natural) → λ(acc : Natural) → x + acc) 0"
+            ),
+            "3"
+        );
+        // Folds are not fused with builds: when `g` is abstract, the normal form keeps the
+        // intermediate list, as the standard requires.
+        let g = "∀(list : Type) → ∀(cons : Natural → list → list) → ∀(nil : list) → list";
+        assert_eq!(
+            normalize(&format!(
+                "λ(g : {}) → List/fold Natural (List/build Natural g) Natural (λ(x : Natural) → λ(acc : Natural) → x + acc) 0",
+                g
+            )),
+            format!(
+                "λ(g : {}) → List/fold Natural (g (List Natural) (λ(a : Natural) → λ(`as` : List Natural) → [a] # `as`) ([] : List Natural)) Natural (λ(x : Natural) → λ(acc : Natural) → x + acc) 0",
+                g
+            )
+        );
+    }
+
+    #[test]
+    fn builtin_types() {
+        assert_eq!(
+            builtin_type("List/head").unwrap().to_string(),
+            "∀(a : Type) → List a → Optional a"
+        );
+        assert_eq!(
+            builtin_type("Natural/even").unwrap().to_string(),
+            "Natural → Bool"
+        );
+        assert_eq!(builtin_type("List/foo"), None);
+
+        for name in builtin_names() {
+            let b = Builtin::parse(name).unwrap();
+            assert_eq!(&b.to_string(), name);
+            assert!(builtin_type(name).is_some());
+        }
+    }
 }
@@ -1,10 +1,17 @@
 use elsa::vec::FrozenVec;
 use once_cell::sync::OnceCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::ops::{Deref, Index};
 
-use crate::semantics::{Import, ImportLocation, ImportNode};
+use crate::builtins::Builtin;
+use crate::error::NormalizationError;
+use crate::semantics::{
+    Import, ImportLocation, ImportNode, LiteralTypes, Type,
+};
 use crate::syntax::Span;
+use crate::utils::with_stack;
 use crate::Typed;
 
 /////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -16,6 +23,22 @@ pub struct CtxtS<'cx> {
     imports: FrozenVec<Box<StoredImport<'cx>>>,
     import_alternatives: FrozenVec<Box<StoredImportAlternative<'cx>>>,
     import_results: FrozenVec<Box<StoredImportResult<'cx>>>,
+    /// How many more nodes normalization may build, when it is limited.
+    node_budget: Cell<Option<usize>>,
+    /// Whether normalization built more nodes than `node_budget` allowed.
+    node_limit_reached: Cell<bool>,
+    /// How deeply evaluations may nest, when normalization is limited.
+    max_eval_depth: Cell<Option<usize>>,
+    /// Whether evaluations nested deeper than `max_eval_depth` allowed.
+    depth_limit_reached: Cell<bool>,
+    /// The hook set with `Ctxt::with_new_traced`, if any.
+    tracer: Option<&'cx TraceHook<'cx>>,
+    /// How many evaluations are nested, counted only when normalization is limited or traced.
+    eval_depth: Cell<usize>,
+    /// The types of literals, built the first time they are needed.
+    literal_types: once_cell::unsync::OnceCell<LiteralTypes<'cx>>,
+    /// The types of the builtins that were typechecked so far.
+    builtin_types: RefCell<HashMap<Builtin, Type<'cx>>>,
 }
 
 /// Context for the dhall compiler. Stores various global maps.
@@ -209,3 +232,146 @@ impl<'cx> Index<ImportResultId<'cx>> for CtxtS<'cx> {
         &self.import_results[id.0]
     }
 }
+
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+// Types
+
+impl<'cx> Ctxt<'cx> {
+    /// The types of literals, shared by all the typechecking done in this context.
+    pub(crate) fn literal_types(self) -> &'cx LiteralTypes<'cx> {
+        self.0.literal_types.get_or_init(|| LiteralTypes::new(self))
+    }
+    /// The type of the builtin `b`, computed by `f` the first time it is needed.
+    pub(crate) fn builtin_type<E>(
+        self,
+        b: Builtin,
+        f: impl FnOnce() -> Result<Type<'cx>, E>,
+    ) -> Result<Type<'cx>, E> {
+        if let Some(ty) = self.0.builtin_types.borrow().get(&b) {
+            return Ok(ty.clone());
+        }
+        let ty = f()?;
+        self.0.builtin_types.borrow_mut().insert(b, ty.clone());
+        Ok(ty)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+// Normalization limit
+
+impl<'cx> Ctxt<'cx> {
+    /// Runs `f`, failing if normalization builds more than `max_nodes` nodes in the meantime, or
+    /// if evaluations nest more than `max_depth` levels deep. Once a limit is reached,
+    /// normalization stops making progress: operations and function applications are left
+    /// unevaluated. The values forced by `f` are then wrong, so the error is returned instead of
+    /// the result of `f`.
+    pub(crate) fn with_limits<T>(
+        self,
+        max_nodes: usize,
+        max_depth: usize,
+        f: impl FnOnce() -> Result<T, NormalizationError>,
+    ) -> Result<T, NormalizationError> {
+        let outer_budget = self.0.node_budget.replace(Some(max_nodes));
+        let outer_reached = self.0.node_limit_reached.replace(false);
+        let outer_depth = self.0.max_eval_depth.replace(Some(max_depth));
+        let outer_too_deep = self.0.depth_limit_reached.replace(false);
+        let result = f();
+        let reached = self.0.node_limit_reached.replace(outer_reached);
+        let too_deep = self.0.depth_limit_reached.replace(outer_too_deep);
+        self.0.node_budget.set(outer_budget);
+        self.0.max_eval_depth.set(outer_depth);
+        if too_deep {
+            Err(NormalizationError::TooDeep(max_depth))
+        } else if reached {
+            Err(NormalizationError::TooManyNodes(max_nodes))
+        } else {
+            result
+        }
+    }
+    /// Counts `n` nodes built by normalization.
+    pub(crate) fn count_nodes(self, n: usize) {
+        if let Some(budget) = self.0.node_budget.get() {
+            match budget.checked_sub(n) {
+                Some(budget) => self.0.node_budget.set(Some(budget)),
+                None => self.0.node_limit_reached.set(true),
+            }
+        }
+    }
+    /// Whether normalization went over a limit set with `with_limits`. Evaluation checks this
+    /// before doing any work that could build new nodes or nest deeper.
+    pub(crate) fn limit_reached(self) -> bool {
+        self.0.node_limit_reached.get() || self.0.depth_limit_reached.get()
+    }
+    /// Runs `f`, an evaluation nested in the current one. Evaluations are only counted when
+    /// tracing or when their depth is limited, since counting them costs a little.
+    pub(crate) fn nested_eval<T>(self, f: impl FnOnce() -> T) -> T {
+        let max_depth = self.0.max_eval_depth.get();
+        if max_depth.is_none() && !self.is_tracing() {
+            return with_stack(f);
+        }
+        /// Restores the depth when dropped, so that it stays right if `f` panics.
+        struct DepthGuard<'a>(&'a Cell<usize>, usize);
+        impl Drop for DepthGuard<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.1);
+            }
+        }
+
+        let depth = self.0.eval_depth.get();
+        let _guard = DepthGuard(&self.0.eval_depth, depth);
+        if let Some(max_depth) = max_depth {
+            if depth >= max_depth {
+                self.0.depth_limit_reached.set(true);
+            }
+        }
+        self.0.eval_depth.set(depth + 1);
+        with_stack(f)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////////////////
+// Tracing
+
+/// A step of normalization, reported to the hook set with [`Ctxt::with_new_traced`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A function was applied to its argument.
+    Beta { depth: usize },
+    /// A builtin got enough arguments to compute its result, like each step of a `Natural/fold`.
+    Builtin { builtin: Builtin, depth: usize },
+}
+
+/// The hook that gets the events of a `Ctxt`.
+type TraceHook<'cx> = RefCell<dyn FnMut(&TraceEvent) + 'cx>;
+
+impl Ctxt<'_> {
+    /// Like `with_new`, calling `hook` for each step of normalization done in the new context.
+    /// `depth` in the events is how many evaluations are nested. Since normalization is lazy, `f`
+    /// must force the values it wants traced, e.g. with `to_expr`.
+    pub fn with_new_traced<T>(
+        hook: impl FnMut(&TraceEvent),
+        f: impl for<'cx> FnOnce(Ctxt<'cx>) -> T,
+    ) -> T {
+        let hook = RefCell::new(hook);
+        let cx = CtxtS {
+            tracer: Some(&hook),
+            ..CtxtS::default()
+        };
+        let cx = Ctxt(&cx);
+        f(cx)
+    }
+}
+
+impl<'cx> Ctxt<'cx> {
+    /// Whether a hook is set. Checked before computing events, so that tracing costs nothing
+    /// when it is not used.
+    pub(crate) fn is_tracing(self) -> bool {
+        self.0.tracer.is_some()
+    }
+    /// Reports an event to the hook, if there is one. `event` gets the current depth.
+    pub(crate) fn trace(self, event: impl FnOnce(usize) -> TraceEvent) {
+        if let Some(hook) = self.0.tracer {
+            (hook.borrow_mut())(&event(self.0.eval_depth.get()));
+        }
+    }
+}
@@ -70,9 +70,9 @@ impl ErrorBuilder {
         annotation_type: AnnotationType,
     ) -> &mut Self {
         // Ignore spans not coming from a source file
-        let span = match span {
-            Span::Parsed(span) => span,
-            _ => return self,
+        let span = match span.parsed() {
+            Some(span) => span.clone(),
+            None => return self,
         };
         self.annotations.push(SpannedAnnotation {
             span,
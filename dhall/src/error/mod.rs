@@ -1,4 +1,6 @@
 use std::io::Error as IOError;
+use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::semantics::resolve::{CyclesStack, ImportLocation};
 use crate::syntax::{Import, ParseError};
@@ -23,6 +25,7 @@ pub enum ErrorKind {
     Resolve(ImportError),
     Typecheck(TypeError),
     Cache(CacheError),
+    Normalization(NormalizationError),
 }
 
 #[derive(Debug)]
@@ -33,6 +36,8 @@ pub enum ImportError {
     UnexpectedImport(Import<()>),
     ImportCycle(CyclesStack, ImportLocation),
     Url(url::ParseError),
+    /// Reading the file at this path took longer than this.
+    Timeout(PathBuf, Duration),
 }
 
 #[derive(Debug)]
@@ -49,13 +54,30 @@ pub enum EncodeError {
 /// A structured type error
 #[derive(Debug)]
 pub struct TypeError {
-    message: TypeMessage,
+    message: Box<TypeMessage>,
 }
 
 /// The specific type error
 #[derive(Debug)]
 pub enum TypeMessage {
     Custom(String),
+    /// The expression is nested more than `depth` levels deep, which is the most the typechecker
+    /// allows.
+    ExpressionTooDeep {
+        depth: usize,
+    },
+    /// A union constructor was applied to a payload of the wrong type. Holds the name of the
+    /// alternative, the expected payload type and the type of the argument. These are kept as
+    /// strings so that errors stay `Send`.
+    ConstructorArgMismatch(String, String, String),
+}
+
+#[derive(Debug)]
+pub enum NormalizationError {
+    /// Normalization evaluated more than this many nodes.
+    TooManyNodes(usize),
+    /// Evaluations nested more than this many levels deep.
+    TooDeep(usize),
 }
 
 #[derive(Debug)]
@@ -76,21 +98,51 @@ impl Error {
 
 impl TypeError {
     pub fn new(message: TypeMessage) -> Self {
-        TypeError { message }
+        TypeError {
+            message: Box::new(message),
+        }
+    }
+
+    /// What went wrong.
+    pub fn message(&self) -> &TypeMessage {
+        &self.message
     }
 }
 
+/// Only says that this is a type error: what went wrong is the `source()`, so that error reporters
+/// that print the chain of sources don't print it twice.
 impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("Type error")
+    }
+}
+
+impl std::error::Error for TypeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.message)
+    }
+}
+
+impl std::fmt::Display for TypeMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         use TypeMessage::*;
-        let msg = match &self.message {
-            Custom(s) => format!("Type error: {}", s),
-        };
-        write!(f, "{}", msg)
+        match self {
+            Custom(s) => write!(f, "{}", s),
+            ExpressionTooDeep { depth } => write!(
+                f,
+                "expression is nested too deeply (more than {} levels)",
+                depth
+            ),
+            ConstructorArgMismatch(l, expected, found) => write!(
+                f,
+                "wrong type of payload for alternative `{}`: expected `{}`, found `{}`",
+                l, expected, found
+            ),
+        }
     }
 }
 
-impl std::error::Error for TypeError {}
+impl std::error::Error for TypeMessage {}
 
 impl std::fmt::Display for EncodeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -103,6 +155,25 @@ impl std::fmt::Display for EncodeError {
 
 impl std::error::Error for EncodeError {}
 
+impl std::fmt::Display for NormalizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NormalizationError::TooManyNodes(max_nodes) => write!(
+                f,
+                "normalization produced more than {} nodes",
+                max_nodes
+            ),
+            NormalizationError::TooDeep(max_depth) => write!(
+                f,
+                "normalization nested more than {} levels deep",
+                max_depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NormalizationError {}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match &self.kind {
@@ -111,8 +182,11 @@ impl std::fmt::Display for Error {
             ErrorKind::Decode(err) => write!(f, "{:?}", err),
             ErrorKind::Encode(err) => write!(f, "{:?}", err),
             ErrorKind::Resolve(err) => write!(f, "{:?}", err),
-            ErrorKind::Typecheck(err) => write!(f, "{}", err),
+            ErrorKind::Typecheck(err) => {
+                write!(f, "{}: {}", err, err.message())
+            }
             ErrorKind::Cache(err) => write!(f, "{:?}", err),
+            ErrorKind::Normalization(err) => write!(f, "{}", err),
         }
     }
 }
@@ -163,3 +237,8 @@ impl From<CacheError> for Error {
         ErrorKind::Cache(err).into()
     }
 }
+impl From<NormalizationError> for Error {
+    fn from(err: NormalizationError) -> Error {
+        ErrorKind::Normalization(err).into()
+    }
+}
@@ -18,31 +18,46 @@ pub mod ctxt;
 pub mod error;
 pub mod operations;
 pub mod semantics;
+pub mod session;
 pub mod syntax;
 pub mod utils;
 
 use std::path::Path;
 use url::Url;
 
-use crate::error::{Error, TypeError};
+use crate::error::{Error, NormalizationError, TypeError};
 use crate::semantics::parse;
 use crate::semantics::resolve;
 use crate::semantics::resolve::ImportLocation;
-use crate::semantics::{typecheck, typecheck_with, Hir, Nir, Tir, Type};
-use crate::syntax::Expr;
+use crate::semantics::{
+    desugar_let, quick_check, typecheck, typecheck_with, Hir, Nir, Tir, TyEnv,
+    Type, VarEnv, MAX_EVAL_DEPTH,
+};
+use crate::syntax::{Expr, Hash, ParseError};
 
 pub use ctxt::*;
+pub use session::Session;
 
+/// A parsed expression, the first step of evaluating dhall code.
+///
+/// The steps are `Parsed` → `Resolved` → `Typed` → `Normalized`, and each can be inspected
+/// before going to the next: e.g. `Resolved::to_expr` gives the expression with imports replaced
+/// by their contents, and `Typed::get_type` its type.
 #[derive(Debug, Clone)]
 pub struct Parsed(Expr, ImportLocation);
 
+/// An expression parsed with `Parsed::parse_str_recovering`, where the parts that failed to parse
+/// are `ExprKind::Error` nodes.
+#[derive(Debug, Clone)]
+pub struct PartialParsed(Expr);
+
 /// An expression where all imports have been resolved
 ///
 /// Invariant: there must be no `Import` nodes or `ImportAlt` operations left.
 #[derive(Debug, Clone)]
 pub struct Resolved<'cx>(Hir<'cx>);
 
-/// A typed expression
+/// An expression that typechecked, with its type.
 #[derive(Debug, Clone)]
 pub struct Typed<'cx> {
     pub hir: Hir<'cx>,
@@ -67,6 +82,11 @@ impl Parsed {
     pub fn from_expr_without_imports(e: Expr) -> Self {
         Parsed(e, ImportLocation::dhall_code_without_imports())
     }
+    /// Construct from an `Expr` built in Rust. Its imports are resolved like those of
+    /// `parse_str`, relative to the current directory.
+    pub fn from_expr(e: Expr) -> Self {
+        Parsed(e, ImportLocation::dhall_code_of_unknown_origin())
+    }
 
     pub fn parse_file(f: &Path) -> Result<Parsed, Error> {
         parse::parse_file(f)
@@ -77,17 +97,51 @@ impl Parsed {
     pub fn parse_str(s: &str) -> Result<Parsed, Error> {
         parse::parse_str(s)
     }
+    /// Parses `s` without stopping at the first syntax error, for editors that need to
+    /// understand broken code. Returns the partial expression along with all the syntax errors.
+    /// The parts that failed to parse are replaced by `ExprKind::Error` nodes, which fail to
+    /// typecheck.
+    pub fn parse_str_recovering(s: &str) -> (PartialParsed, Vec<ParseError>) {
+        let (expr, errors) = syntax::parse_expr_recovering(s);
+        (PartialParsed(expr), errors)
+    }
+    /// Parses `text` as the contents of the file at `f`, so that its imports are resolved relative
+    /// to `f`. The file itself is not read.
+    pub fn parse_file_contents(
+        f: &Path,
+        text: String,
+    ) -> Result<Parsed, Error> {
+        Ok(parse::parse_file_without_cache(f, text)?.0)
+    }
     pub fn parse_binary_file(f: &Path) -> Result<Parsed, Error> {
         parse::parse_binary_file(f)
     }
-    #[allow(dead_code)]
+    /// Decodes an expression in the CBOR-based binary format of dhall. Malformed CBOR gives a
+    /// `DecodeError::CBORError`, and valid CBOR that doesn't encode a dhall expression gives a
+    /// `DecodeError::WrongFormatError`.
     pub fn parse_binary(data: &[u8]) -> Result<Parsed, Error> {
         parse::parse_binary(data)
     }
 
+    /// Replaces the imports of the expression by their contents, reading them as needed.
     pub fn resolve<'cx>(self, cx: Ctxt<'cx>) -> Result<Resolved<'cx>, Error> {
         resolve::resolve(cx, self)
     }
+    /// Like `resolve`, but calls `on_import_resolved` each time an import gets resolved.
+    pub fn resolve_with_callback<'cx>(
+        self,
+        cx: Ctxt<'cx>,
+        on_import_resolved: impl Fn(&ImportLocation, &resolve::ResolvedMeta)
+            + 'static,
+    ) -> Result<Resolved<'cx>, Error> {
+        resolve::resolve_with_callback(cx, self, on_import_resolved)
+    }
+    /// Adds the semantic hash of each remote import, or of each import if `all` is set, like
+    /// `dhall freeze`. This resolves the imports to compute the hashes.
+    pub fn freeze(self, cx: Ctxt<'_>, all: bool) -> Result<Expr, Error> {
+        resolve::freeze(cx, self, all)
+    }
+    /// Like `resolve`, but fails if the expression has imports.
     pub fn skip_resolve<'cx>(
         self,
         cx: Ctxt<'cx>,
@@ -100,16 +154,77 @@ impl Parsed {
         self.0.clone()
     }
 
+    /// The constructs of the expression that were removed from the standard, like
+    /// `[] : Optional T`. They fail to typecheck, and the warnings say what to write instead.
+    pub fn warnings(&self) -> Vec<syntax::Warning> {
+        syntax::deprecations(&self.0)
+    }
+
+    /// Wraps the expression in `let label = value in ...`, making `label` available to it.
     pub fn add_let_binding(self, label: syntax::Label, value: Expr) -> Parsed {
         let Parsed(expr, import_location) = self;
         Parsed(expr.add_let_binding(label, value), import_location)
     }
+
+    /// Wraps the expression in several let-bindings. They are added in the order provided, so
+    /// later bindings can refer to earlier ones.
+    pub fn add_let_bindings(
+        self,
+        bindings: impl IntoIterator<Item = (syntax::Label, Expr)>,
+    ) -> Parsed {
+        let bindings: Vec<_> = bindings.into_iter().collect();
+        bindings
+            .into_iter()
+            .rev()
+            .fold(self, |acc, (label, value)| {
+                acc.add_let_binding(label, value)
+            })
+    }
+}
+
+impl PartialParsed {
+    pub fn to_expr(&self) -> Expr {
+        self.0.clone()
+    }
+    /// Whether the expression has no `ExprKind::Error` nodes, i.e. the source had no syntax
+    /// errors.
+    pub fn is_complete(&self) -> bool {
+        struct FindError(bool);
+        impl syntax::visitor::ExprVisitor for FindError {
+            fn visit_error(&mut self, _e: &syntax::ErrorNode) {
+                self.0 = true;
+            }
+        }
+        let mut finder = FindError(false);
+        syntax::visitor::ExprVisitor::visit_expr(&mut finder, &self.0);
+        !finder.0
+    }
 }
 
 impl<'cx> Resolved<'cx> {
+    /// Looks for obvious type errors without normalizing anything. This is much faster than
+    /// `typecheck` to reject some bad expressions, but passing it doesn't mean the expression
+    /// typechecks.
+    pub fn quick_check(&self, cx: Ctxt<'cx>) -> Result<(), TypeError> {
+        quick_check(cx, &self.0)
+    }
+    /// Infers the type of the expression.
     pub fn typecheck(&self, cx: Ctxt<'cx>) -> Result<Typed<'cx>, TypeError> {
         Ok(Typed::from_tir(typecheck(cx, &self.0)?))
     }
+    /// Typechecks the expression and normalizes it. This is the same as calling `typecheck`, then
+    /// `get_type` and `normalize` on the result: the expression is only typechecked once, and
+    /// normalization reuses its result.
+    pub fn type_and_normalize(
+        &self,
+        cx: Ctxt<'cx>,
+    ) -> Result<(Normalized<'cx>, Normalized<'cx>), TypeError> {
+        let typed = self.typecheck(cx)?;
+        let nf = typed.normalize(cx);
+        Ok((Normalized(typed.ty.into_nir()), nf))
+    }
+    /// Checks that the expression has type `ty`. The type can come from another expression,
+    /// with `Normalized::to_hir`.
     pub fn typecheck_with(
         self,
         cx: Ctxt<'cx>,
@@ -121,6 +236,17 @@ impl<'cx> Resolved<'cx> {
     pub fn to_expr(&self, cx: Ctxt<'cx>) -> Expr {
         self.0.to_expr_noopts(cx)
     }
+    pub fn as_hir(&self) -> &Hir<'cx> {
+        &self.0
+    }
+    /// Replaces each `let x : T = e in b` by `(λ(x : T) → b) e`, inferring the type of the `let`s
+    /// that don't have an annotation. The result has no `let`s and normalizes to the same value,
+    /// but doesn't always typecheck, because the body of a lambda doesn't know the value of its
+    /// variable.
+    pub fn desugar_let(&self, cx: Ctxt<'cx>) -> Result<Expr, TypeError> {
+        Ok(desugar_let(&TyEnv::new(cx), &self.0)?
+            .to_expr(cx, ToExprOptions { alpha: false }))
+    }
 }
 
 impl<'cx> Typed<'cx> {
@@ -134,6 +260,41 @@ impl<'cx> Typed<'cx> {
     pub fn normalize(&self, cx: Ctxt<'cx>) -> Normalized<'cx> {
         Normalized(self.hir.eval_closed_expr(cx))
     }
+    /// Like `normalize`, but computes the whole normal form right away, and fails if the normal
+    /// form has more than `max_nodes` nodes, or if computing it builds more than `max_nodes`
+    /// nodes along the way. Each list element, record field and byte of text counts as a node.
+    /// This protects against expressions like folds that build huge values. Functions in the
+    /// normal form have their body normalized again when it is converted.
+    ///
+    /// It also fails if evaluations nest more than `MAX_EVAL_DEPTH` levels deep, e.g. for a long
+    /// chain of `let`s each using the previous one, so that normalization can't overflow the
+    /// stack.
+    pub fn normalize_bounded(
+        &self,
+        cx: Ctxt<'cx>,
+        max_nodes: usize,
+    ) -> Result<Normalized<'cx>, Error> {
+        let nf = cx.with_limits(max_nodes, MAX_EVAL_DEPTH, || {
+            let nf = self.normalize(cx);
+            let mut budget = max_nodes;
+            if nf.0.fits_in(&mut budget) {
+                Ok(nf)
+            } else {
+                Err(NormalizationError::TooManyNodes(max_nodes))
+            }
+        })?;
+        Ok(nf)
+    }
+    /// Reduce an expression to its weak-head normal form: only the outermost constructor is
+    /// computed, and its sub-expressions are returned unevaluated, with their `let`s and
+    /// unapplied lambdas. Variables bound outside of a sub-expression are replaced by their
+    /// value.
+    pub fn normalize_whnf(&self, cx: Ctxt<'cx>) -> Expr {
+        self.hir
+            .eval_closed_expr(cx)
+            .to_hir_whnf(VarEnv::new())
+            .to_expr(cx, ToExprOptions { alpha: false })
+    }
 
     /// Converts a value back to the corresponding AST expression.
     fn to_expr(&self, cx: Ctxt<'cx>) -> Expr {
@@ -146,6 +307,8 @@ impl<'cx> Typed<'cx> {
     pub fn ty(&self) -> &Type<'cx> {
         &self.ty
     }
+    /// The type of the expression, which can be converted to an `Expr` with
+    /// `Normalized::to_expr`.
     pub fn get_type(&self) -> Result<Normalized<'cx>, TypeError> {
         Ok(Normalized(self.ty.clone().into_nir()))
     }
@@ -164,9 +327,59 @@ impl<'cx> Normalized<'cx> {
         &self.0
     }
     /// Converts a value back to the corresponding AST expression, alpha-normalizing in the process.
+    /// This gives the same as [`syntax::alpha_normalize`] on [`to_expr`](Normalized::to_expr),
+    /// without building the names of the variables first.
     pub fn to_expr_alpha(&self, cx: Ctxt<'cx>) -> Expr {
         self.0.to_expr(cx, ToExprOptions { alpha: true })
     }
+    /// The semantic hash of the value, like `sha256:1f2a...`. This is the hash that imports are
+    /// checked against: it only depends on the normal form, up to the names of bound variables.
+    pub fn fingerprint(&self, cx: Ctxt<'cx>) -> Result<String, Error> {
+        let hash = self.to_expr_alpha(cx).sha256_hash()?;
+        Ok(Hash::SHA256(hash).to_string())
+    }
+}
+
+/// The semantic hash of an expression, as given by [`Normalized::fingerprint`]. The expression
+/// can't contain imports; resolve them first, e.g. with [`Parsed::resolve`].
+pub fn fingerprint(e: &Expr) -> Result<String, Error> {
+    Ctxt::with_new(|cx| {
+        let typed = Parsed::from_expr_without_imports(e.clone())
+            .skip_resolve(cx)?
+            .typecheck(cx)?;
+        typed.normalize(cx).fingerprint(cx)
+    })
+}
+
+/// Normalizes an expression like [`Typed::normalize`], calling `hook` for each step of the
+/// evaluation, e.g. to find out why a config is slow to evaluate. See [`Ctxt::with_new_traced`]
+/// to trace other computations. The expression can't contain imports; resolve them first, e.g. with
+/// [`Parsed::resolve`].
+pub fn normalize_with_trace(
+    e: &Expr,
+    hook: &mut impl FnMut(&TraceEvent),
+) -> Result<Expr, Error> {
+    Ctxt::with_new_traced(hook, |cx| {
+        let typed = Parsed::from_expr_without_imports(e.clone())
+            .skip_resolve(cx)?
+            .typecheck(cx)?;
+        Ok(typed.normalize(cx).to_expr(cx))
+    })
+}
+
+/// The canonical text of an expression, for files kept in version control: the expression is
+/// normalized, then printed over lines of at most 80 columns where it can be broken, with record
+/// fields in alphabetical order and a trailing newline. The text only changes when the meaning of
+/// the expression does, and it is its own canonical text. The expression can't contain imports;
+/// resolve them first, e.g. with [`Parsed::resolve`].
+pub fn to_canonical_string(e: &Expr) -> Result<String, Error> {
+    Ctxt::with_new(|cx| {
+        let typed = Parsed::from_expr_without_imports(e.clone())
+            .skip_resolve(cx)?
+            .typecheck(cx)?;
+        let nf = typed.normalize(cx).to_expr(cx);
+        Ok(format!("{}\n", syntax::pretty(&nf)))
+    })
 }
 
 macro_rules! derive_traits_for_wrapper_struct {
@@ -3,7 +3,7 @@ use std::cmp::max;
 use std::collections::HashMap;
 
 use crate::builtins::Builtin;
-use crate::error::{ErrorBuilder, TypeError};
+use crate::error::{ErrorBuilder, TypeError, TypeMessage};
 use crate::operations::{BinOp, OpKind};
 use crate::semantics::{
     merge_maps, mk_span_err, mkerr, Binder, Closure, Hir, HirKind, Nir,
@@ -51,7 +51,6 @@ fn typecheck_binop<'cx>(
     l: Tir<'cx, '_>,
     r: Tir<'cx, '_>,
 ) -> Result<Type<'cx>, TypeError> {
-    let cx = env.cx();
     let span_err = |msg: &str| mk_span_err(span.clone(), msg);
     use BinOp::*;
     use NirKind::{ListType, RecordType};
@@ -125,27 +124,40 @@ fn typecheck_binop<'cx>(
             Type::from_const(Const::Type)
         }
         op => {
-            let t = Type::from_builtin(
-                cx,
-                match op {
-                    BoolAnd | BoolOr | BoolEQ | BoolNE => Builtin::Bool,
-                    NaturalPlus | NaturalTimes => Builtin::Natural,
-                    TextAppend => Builtin::Text,
-                    ListAppend
-                    | RightBiasedRecordMerge
-                    | RecursiveRecordMerge
-                    | RecursiveRecordTypeMerge
-                    | Equivalence => unreachable!(),
-                    ImportAlt => unreachable!("ImportAlt leftover in tck"),
-                },
-            );
-
-            if *l.ty() != t {
-                return span_err("BinOpTypeMismatch");
-            }
-
-            if *r.ty() != t {
-                return span_err("BinOpTypeMismatch");
+            let t = env.literal_type(match op {
+                BoolAnd | BoolOr | BoolEQ | BoolNE => Builtin::Bool,
+                NaturalPlus | NaturalTimes => Builtin::Natural,
+                TextAppend => Builtin::Text,
+                ListAppend
+                | RightBiasedRecordMerge
+                | RecursiveRecordMerge
+                | RecursiveRecordTypeMerge
+                | Equivalence => unreachable!(),
+                ImportAlt => unreachable!("ImportAlt leftover in tck"),
+            });
+
+            for &(side, operand) in &[("left", &l), ("right", &r)] {
+                if *operand.ty() != t {
+                    return mkerr(
+                        ErrorBuilder::new(format!(
+                            "wrong type of {} operand of `{}`",
+                            side, op
+                        ))
+                        .span_err(
+                            operand.span(),
+                            format!(
+                                "this has type: {}",
+                                operand.ty().to_expr_tyenv(env)
+                            ),
+                        )
+                        .note(format!(
+                            "expected type `{}`\n   found type `{}`",
+                            t.to_expr_tyenv(env),
+                            operand.ty().to_expr_tyenv(env),
+                        ))
+                        .format(),
+                    );
+                }
             }
 
             t
@@ -289,6 +301,42 @@ fn typecheck_merge<'cx>(
     })
 }
 
+/// The error for a function applied to an argument of the wrong type. Union constructors get a
+/// dedicated message that names the alternative.
+fn app_arg_mismatch<'cx>(
+    env: &TyEnv<'cx>,
+    f: &Tir<'cx, '_>,
+    arg: &Tir<'cx, '_>,
+    annot: &Nir<'cx>,
+) -> TypeError {
+    if let NirKind::UnionConstructor(l, _) = f.eval(env).kind() {
+        return TypeError::new(TypeMessage::ConstructorArgMismatch(
+            l.to_string(),
+            annot.to_expr_tyenv(env).to_string(),
+            arg.ty().to_expr_tyenv(env).to_string(),
+        ));
+    }
+    let msg = ErrorBuilder::new("wrong type of function argument")
+        .span_err(
+            f.span(),
+            format!(
+                "this expects an argument of type: {}",
+                annot.to_expr_tyenv(env),
+            ),
+        )
+        .span_err(
+            arg.span(),
+            format!("but this has type: {}", arg.ty().to_expr_tyenv(env)),
+        )
+        .note(format!(
+            "expected type `{}`\n   found type `{}`",
+            annot.to_expr_tyenv(env),
+            arg.ty().to_expr_tyenv(env),
+        ))
+        .format();
+    TypeError::new(TypeMessage::Custom(msg))
+}
+
 pub fn typecheck_operation<'cx>(
     env: &TyEnv<'cx>,
     span: Span,
@@ -305,31 +353,7 @@ pub fn typecheck_operation<'cx>(
                 // TODO: store Type in closure
                 PiClosure { annot, closure, .. } => {
                     if arg.ty().as_nir() != annot {
-                        return mkerr(
-                            ErrorBuilder::new(format!(
-                                "wrong type of function argument"
-                            ))
-                            .span_err(
-                                f.span(),
-                                format!(
-                                    "this expects an argument of type: {}",
-                                    annot.to_expr_tyenv(env),
-                                ),
-                            )
-                            .span_err(
-                                arg.span(),
-                                format!(
-                                    "but this has type: {}",
-                                    arg.ty().to_expr_tyenv(env),
-                                ),
-                            )
-                            .note(format!(
-                                "expected type `{}`\n   found type `{}`",
-                                annot.to_expr_tyenv(env),
-                                arg.ty().to_expr_tyenv(env),
-                            ))
-                            .format(),
-                        );
+                        return Err(app_arg_mismatch(env, &f, &arg, annot));
                     }
 
                     let arg_nf = arg.eval(env);
@@ -87,6 +87,14 @@ impl<'cx, T: Clone> ValEnv<'cx, T> {
             EnvItem::Replaced(x, _) => x.kind().clone(),
         }
     }
+    /// Like `lookup_val`, but doesn't evaluate the value.
+    pub fn lookup_nir(&self, var: AlphaVar) -> Nir<'cx> {
+        let idx = self.items.len() - 1 - var.idx();
+        match &self.items[idx] {
+            EnvItem::Kept(_) => Nir::from_kind(NirKind::Var(NzVar::new(idx))),
+            EnvItem::Replaced(x, _) => x.clone(),
+        }
+    }
     pub fn lookup_ty(&self, var: AlphaVar) -> T {
         let idx = self.items.len() - 1 - var.idx();
         match &self.items[idx] {
@@ -43,6 +43,17 @@ where
         })
     }
 
+    /// Calls `f` on the initializing value if the value hasn't been initialized yet.
+    pub fn peek_src<R>(&self, f: impl FnOnce(&Src) -> R) -> Option<R> {
+        if self.tgt.get().is_some() {
+            return None;
+        }
+        let src = self.src.take()?;
+        let ret = f(&src);
+        self.src.set(Some(src));
+        Some(ret)
+    }
+
     pub fn get_mut(&mut self) -> &mut Tgt {
         self.force();
         self.tgt.get_mut().unwrap()
@@ -5,12 +5,13 @@ use crate::builtins::{Builtin, BuiltinClosure};
 use crate::operations::{BinOp, OpKind};
 use crate::semantics::nze::lazy;
 use crate::semantics::{
-    apply_any, normalize_hir, normalize_one_layer, squash_textlit, Binder, Hir,
-    HirKind, NzEnv, NzVar, TyEnv, Type, Universe, VarEnv,
+    apply_any, normalize_hir, normalize_one_layer, squash_textlit, AlphaVar,
+    Binder, Hir, HirKind, NzEnv, NzVar, TyEnv, Type, Universe, VarEnv,
 };
 use crate::syntax::{
     Const, Expr, ExprKind, InterpolatedTextContents, Label, NumKind, Span,
 };
+use crate::utils::with_stack;
 use crate::{Ctxt, ToExprOptions};
 
 /// Stores a possibly unevaluated value. Gets (partially) normalized on-demand, sharing computation
@@ -163,12 +164,24 @@ impl<'cx> Nir<'cx> {
     }
 
     pub fn to_hir(&self, venv: VarEnv) -> Hir<'cx> {
+        self.to_hir_opts(venv, false)
+    }
+    /// Converts to Hir like `to_hir`, but without evaluating the sub-expressions that haven't
+    /// been evaluated yet: they are given as they were written, with their free variables
+    /// replaced by their values.
+    pub fn to_hir_whnf(&self, venv: VarEnv) -> Hir<'cx> {
+        self.to_hir_opts(venv, true)
+    }
+    fn to_hir_opts(&self, venv: VarEnv, whnf: bool) -> Hir<'cx> {
         let map_uniontype =
             |kts: &HashMap<Label, Option<Nir<'cx>>>| -> ExprKind<Hir<'cx>> {
                 ExprKind::UnionType(
                     kts.iter()
                         .map(|(k, v)| {
-                            (k.clone(), v.as_ref().map(|v| v.to_hir(venv)))
+                            (
+                                k.clone(),
+                                v.as_ref().map(|v| v.to_hir_child(venv, whnf)),
+                            )
                         })
                         .collect(),
                 )
@@ -189,8 +202,8 @@ impl<'cx> Nir<'cx> {
                     closure,
                 } => ExprKind::Lam(
                     binder.to_label(),
-                    annot.to_hir(venv),
-                    closure.to_hir(venv),
+                    annot.to_hir_child(venv, whnf),
+                    closure.to_hir_opts(venv, whnf),
                 ),
                 NirKind::PiClosure {
                     binder,
@@ -198,48 +211,50 @@ impl<'cx> Nir<'cx> {
                     closure,
                 } => ExprKind::Pi(
                     binder.to_label(),
-                    annot.to_hir(venv),
-                    closure.to_hir(venv),
+                    annot.to_hir_child(venv, whnf),
+                    closure.to_hir_opts(venv, whnf),
                 ),
                 NirKind::Const(c) => ExprKind::Const(*c),
                 NirKind::BuiltinType(b) => ExprKind::Builtin(*b),
                 NirKind::Num(l) => ExprKind::Num(l.clone()),
                 NirKind::OptionalType(t) => ExprKind::Op(OpKind::App(
                     builtin(Builtin::Optional),
-                    t.to_hir(venv),
+                    t.to_hir_child(venv, whnf),
                 )),
                 NirKind::EmptyOptionalLit(n) => ExprKind::Op(OpKind::App(
                     builtin(Builtin::OptionalNone),
-                    n.to_hir(venv),
+                    n.to_hir_child(venv, whnf),
                 )),
-                NirKind::NEOptionalLit(n) => ExprKind::SomeLit(n.to_hir(venv)),
+                NirKind::NEOptionalLit(n) => {
+                    ExprKind::SomeLit(n.to_hir_child(venv, whnf))
+                }
                 NirKind::ListType(t) => ExprKind::Op(OpKind::App(
                     builtin(Builtin::List),
-                    t.to_hir(venv),
+                    t.to_hir_child(venv, whnf),
                 )),
                 NirKind::EmptyListLit(n) => ExprKind::EmptyListLit(Hir::new(
                     HirKind::Expr(ExprKind::Op(OpKind::App(
                         builtin(Builtin::List),
-                        n.to_hir(venv),
+                        n.to_hir_child(venv, whnf),
                     ))),
                     Span::Artificial,
                 )),
                 NirKind::NEListLit(elts) => ExprKind::NEListLit(
-                    elts.iter().map(|v| v.to_hir(venv)).collect(),
+                    elts.iter().map(|v| v.to_hir_child(venv, whnf)).collect(),
                 ),
                 NirKind::TextLit(elts) => ExprKind::TextLit(
                     elts.iter()
-                        .map(|t| t.map_ref(|v| v.to_hir(venv)))
+                        .map(|t| t.map_ref(|v| v.to_hir_child(venv, whnf)))
                         .collect(),
                 ),
                 NirKind::RecordLit(kvs) => ExprKind::RecordLit(
                     kvs.iter()
-                        .map(|(k, v)| (k.clone(), v.to_hir(venv)))
+                        .map(|(k, v)| (k.clone(), v.to_hir_child(venv, whnf)))
                         .collect(),
                 ),
                 NirKind::RecordType(kts) => ExprKind::RecordType(
                     kts.iter()
-                        .map(|(k, v)| (k.clone(), v.to_hir(venv)))
+                        .map(|(k, v)| (k.clone(), v.to_hir_child(venv, whnf)))
                         .collect(),
                 ),
                 NirKind::UnionType(kts) => map_uniontype(kts),
@@ -263,23 +278,107 @@ impl<'cx> Nir<'cx> {
                         ))),
                         Span::Artificial,
                     ),
-                    v.to_hir(venv),
+                    v.to_hir_child(venv, whnf),
                 )),
                 NirKind::Equivalence(x, y) => ExprKind::Op(OpKind::BinOp(
                     BinOp::Equivalence,
-                    x.to_hir(venv),
-                    y.to_hir(venv),
+                    x.to_hir_child(venv, whnf),
+                    y.to_hir_child(venv, whnf),
                 )),
-                NirKind::Assert(x) => ExprKind::Assert(x.to_hir(venv)),
-                NirKind::Op(e) => ExprKind::Op(e.map_ref(|v| v.to_hir(venv))),
+                NirKind::Assert(x) => {
+                    ExprKind::Assert(x.to_hir_child(venv, whnf))
+                }
+                NirKind::Op(e) => {
+                    ExprKind::Op(e.map_ref(|v| v.to_hir_child(venv, whnf)))
+                }
             }),
         };
 
         Hir::new(hir, Span::Artificial)
     }
+    /// Converts a sub-expression to Hir. With `whnf`, a sub-expression that hasn't been evaluated
+    /// yet is kept as is.
+    fn to_hir_child(&self, venv: VarEnv, whnf: bool) -> Hir<'cx> {
+        with_stack(|| {
+            if whnf {
+                if let Some(hir) =
+                    self.0.peek_src(|thunk| thunk.to_hir_whnf(venv))
+                {
+                    return hir;
+                }
+            }
+            self.to_hir_opts(venv, whnf)
+        })
+    }
+
     pub fn to_hir_noenv(&self) -> Hir<'cx> {
         self.to_hir(VarEnv::new())
     }
+
+    /// Computes the normal form of this value, stopping as soon as it has more nodes than
+    /// `budget`. Returns whether it fit, and subtracts its number of nodes from `budget`. Unlike
+    /// `to_hir`, values that are shared are counted each time they appear.
+    pub(crate) fn fits_in(&self, budget: &mut usize) -> bool {
+        with_stack(|| {
+            match budget.checked_sub(self.kind().size()) {
+                Some(rest) => *budget = rest,
+                None => return false,
+            }
+            let fits_opt = |v: &Option<Nir<'cx>>, budget: &mut usize| match v {
+                Some(v) => v.fits_in(budget),
+                None => true,
+            };
+            match self.kind() {
+                NirKind::Var(..)
+                | NirKind::Const(..)
+                | NirKind::Num(..)
+                | NirKind::BuiltinType(..) => true,
+                NirKind::LamClosure { annot, closure, .. }
+                | NirKind::PiClosure { annot, closure, .. } => {
+                    annot.fits_in(budget)
+                        && closure.apply_var(NzVar::fresh()).fits_in(budget)
+                }
+                NirKind::AppliedBuiltin(closure) => {
+                    closure.args().iter().all(|v| v.fits_in(budget))
+                }
+                NirKind::TextLit(elts) => elts.iter().all(|t| match t {
+                    InterpolatedTextContents::Text(_) => true,
+                    InterpolatedTextContents::Expr(v) => v.fits_in(budget),
+                }),
+                NirKind::EmptyOptionalLit(v)
+                | NirKind::NEOptionalLit(v)
+                | NirKind::OptionalType(v)
+                | NirKind::EmptyListLit(v)
+                | NirKind::ListType(v)
+                | NirKind::Assert(v) => v.fits_in(budget),
+                NirKind::NEListLit(elts) => {
+                    elts.iter().all(|v| v.fits_in(budget))
+                }
+                NirKind::RecordLit(kvs) | NirKind::RecordType(kvs) => {
+                    kvs.values().all(|v| v.fits_in(budget))
+                }
+                NirKind::UnionType(kts) | NirKind::UnionConstructor(_, kts) => {
+                    kts.values().all(|v| fits_opt(v, budget))
+                }
+                NirKind::UnionLit(_, v, kts) => {
+                    v.fits_in(budget)
+                        && kts.values().all(|v| fits_opt(v, budget))
+                }
+                NirKind::Equivalence(x, y) => {
+                    x.fits_in(budget) && y.fits_in(budget)
+                }
+                NirKind::Op(e) => e
+                    .traverse_ref(|v| {
+                        if v.fits_in(budget) {
+                            Ok(())
+                        } else {
+                            Err(())
+                        }
+                    })
+                    .is_ok(),
+            }
+        })
+    }
 }
 
 impl<'cx> NirKind<'cx> {
@@ -293,6 +392,29 @@ impl<'cx> NirKind<'cx> {
     pub fn from_builtin_env(b: Builtin, env: NzEnv<'cx>) -> Self {
         BuiltinClosure::new(b, env)
     }
+
+    /// How many nodes this counts for in the limit set with `Typed::normalize_bounded`, not
+    /// counting its sub-values. Text counts one node per byte, so that building long strings
+    /// is limited too.
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            NirKind::NEListLit(elts) => 1 + elts.len(),
+            NirKind::RecordLit(kvs) | NirKind::RecordType(kvs) => 1 + kvs.len(),
+            NirKind::UnionType(kts)
+            | NirKind::UnionConstructor(_, kts)
+            | NirKind::UnionLit(_, _, kts) => 1 + kts.len(),
+            NirKind::TextLit(elts) => {
+                1 + elts
+                    .iter()
+                    .map(|t| match t {
+                        InterpolatedTextContents::Text(s) => s.len(),
+                        InterpolatedTextContents::Expr(_) => 1,
+                    })
+                    .sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
 }
 
 impl<'cx> Thunk<'cx> {
@@ -304,12 +426,53 @@ impl<'cx> Thunk<'cx> {
     }
     fn eval(self) -> NirKind<'cx> {
         match self {
-            Thunk::Thunk { env, body, .. } => normalize_hir(&env, &body),
-            Thunk::PartialExpr { expr } => normalize_one_layer(expr),
+            Thunk::Thunk { env, body, .. } => {
+                env.cx().nested_eval(|| normalize_hir(&env, &body))
+            }
+            Thunk::PartialExpr { expr } => {
+                with_stack(|| normalize_one_layer(expr))
+            }
+        }
+    }
+    /// The expression of this thunk, with the variables of its environment replaced by their
+    /// values, themselves converted with `Nir::to_hir_whnf`.
+    fn to_hir_whnf(&self, venv: VarEnv) -> Hir<'cx> {
+        match self {
+            Thunk::Thunk { env, body } => subst_env(body, env, venv, 0),
+            Thunk::PartialExpr { expr } => Hir::new(
+                HirKind::Expr(expr.map_ref(|v| v.to_hir_child(venv, true))),
+                Span::Artificial,
+            ),
         }
     }
 }
 
+/// Replaces the variables of `hir` that are bound in `env` by their values. `depth` counts the
+/// binders of `hir` we are under.
+fn subst_env<'cx>(
+    hir: &Hir<'cx>,
+    env: &NzEnv<'cx>,
+    venv: VarEnv,
+    depth: usize,
+) -> Hir<'cx> {
+    let kind = match hir.kind() {
+        HirKind::Var(v) if v.idx() < depth => HirKind::Var(*v),
+        HirKind::Var(v) => {
+            return env
+                .lookup_nir(AlphaVar::new(v.idx() - depth))
+                .to_hir_child(venv, true)
+        }
+        HirKind::Expr(e) => HirKind::Expr(e.map_ref_maybe_binder(|l, h| {
+            with_stack(|| match l {
+                Some(_) => subst_env(h, env, venv.insert(), depth + 1),
+                None => subst_env(h, env, venv, depth),
+            })
+        })),
+        kind => kind.clone(),
+    };
+    Hir::new(kind, hir.span())
+}
+
 impl<'cx> Closure<'cx> {
     pub fn new(env: &NzEnv<'cx>, body: Hir<'cx>) -> Self {
         Closure::Closure {
@@ -341,8 +504,11 @@ impl<'cx> Closure<'cx> {
 
     /// Convert this closure to a Hir expression
     pub fn to_hir(&self, venv: VarEnv) -> Hir<'cx> {
+        self.to_hir_opts(venv, false)
+    }
+    fn to_hir_opts(&self, venv: VarEnv, whnf: bool) -> Hir<'cx> {
         self.apply_var(NzVar::new(venv.size()))
-            .to_hir(venv.insert())
+            .to_hir_child(venv.insert(), whnf)
     }
     /// If the closure variable is free in the closure, return `None`. Otherwise, return the value
     /// with that free variable remove.
@@ -447,3 +613,135 @@ impl<'cx> std::fmt::Debug for Nir<'cx> {
         x.finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Ctxt, Parsed};
+
+    fn normalize_whnf(s: &str) -> String {
+        Ctxt::with_new(|cx| {
+            Parsed::parse_str(s)
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap()
+                .normalize_whnf(cx)
+                .to_string()
+        })
+    }
+
+    #[test]
+    fn whnf() {
+        assert_eq!(
+            normalize_whnf(
+                "let x = 1 in { a = x + 1, b = let y = 2 in y, c = λ(n : Natural) → n + x }"
+            ),
+            "{ a = 1 + 1, b = let y = 2 in y, c = λ(n : Natural) → n + 1 }"
+        );
+        assert_eq!(
+            normalize_whnf("(λ(x : Natural) → { a = x + x }) (1 + 1)"),
+            "{ a = 1 + 1 + (1 + 1) }"
+        );
+        assert_eq!(normalize_whnf("if True then 1 + 1 else 0"), "2");
+        assert_eq!(
+            normalize_whnf("let xs = [1 + 1] in xs # xs"),
+            "[1 + 1, 1 + 1]"
+        );
+    }
+
+    #[test]
+    fn normalize_bounded() {
+        let normalize_bounded = |s: &str, max_nodes| {
+            Ctxt::with_new(|cx| {
+                let typed = Parsed::parse_str(s)
+                    .unwrap()
+                    .skip_resolve(cx)
+                    .unwrap()
+                    .typecheck(cx)
+                    .unwrap();
+                typed
+                    .normalize_bounded(cx, max_nodes)
+                    .map(|nf| nf.to_expr(cx).to_string())
+                    .map_err(|e| e.to_string())
+            })
+        };
+        let replicate = |n| {
+            format!(
+                "Natural/fold {} (List Natural) (λ(xs : List Natural) → xs # [1]) ([] : List Natural)",
+                n
+            )
+        };
+        assert_eq!(
+            normalize_bounded(&replicate(3), 100),
+            Ok("[1, 1, 1]".to_owned())
+        );
+        assert_eq!(
+            normalize_bounded(&replicate(1_000_000), 100),
+            Err("normalization produced more than 100 nodes".to_owned())
+        );
+        // A single step can build a large list.
+        let double = |n| {
+            format!(
+                "Natural/fold {} (List Natural) (λ(xs : List Natural) → xs # xs) [1]",
+                n
+            )
+        };
+        assert!(normalize_bounded(&double(5), 1000).is_ok());
+        assert_eq!(
+            normalize_bounded(&double(40), 1000),
+            Err("normalization produced more than 1000 nodes".to_owned())
+        );
+        // Values that are shared count each time they appear in the normal form.
+        assert_eq!(
+            normalize_bounded(
+                "let a = [1, 2] let b = [a, a] let c = [b, b] in [c, c]",
+                20
+            ),
+            Err("normalization produced more than 20 nodes".to_owned())
+        );
+        // Building long text counts too.
+        assert!(normalize_bounded(
+            r#"Natural/fold 20 Text (λ(t : Text) → t ++ t) "ab""#,
+            1000
+        )
+        .is_err());
+        // Long folds don't nest, so they only count towards the node limit.
+        assert_eq!(
+            normalize_bounded(
+                "Natural/fold 100000 Natural (λ(n : Natural) → n + 1) 0",
+                usize::MAX
+            ),
+            Ok("100000".to_owned())
+        );
+    }
+
+    #[test]
+    fn normalize_too_deep() {
+        // Each `let` uses the previous one, so evaluating the last one nests through all of them.
+        let mut chain = String::from("let x0 = 0 ");
+        for i in 1..30 {
+            chain += &format!("let x{} = x{} + 1 ", i, i - 1);
+        }
+        chain += "in x29";
+        let normalize_with_depth = |max_depth| {
+            Ctxt::with_new(|cx| {
+                let typed = Parsed::parse_str(&chain)
+                    .unwrap()
+                    .skip_resolve(cx)
+                    .unwrap()
+                    .typecheck(cx)
+                    .unwrap();
+                cx.with_limits(usize::MAX, max_depth, || {
+                    Ok(typed.normalize(cx).to_expr(cx).to_string())
+                })
+                .map_err(|e| e.to_string())
+            })
+        };
+        assert_eq!(normalize_with_depth(1000), Ok("29".to_owned()));
+        assert_eq!(
+            normalize_with_depth(20),
+            Err("normalization nested more than 20 levels deep".to_owned())
+        );
+    }
+}
@@ -4,10 +4,23 @@ use crate::operations::{normalize_operation, OpKind};
 use crate::semantics::NzEnv;
 use crate::semantics::{Binder, Closure, Hir, HirKind, Nir, NirKind, TextLit};
 use crate::syntax::{ExprKind, InterpolatedTextContents};
+use crate::TraceEvent;
 
 pub fn apply_any<'cx>(f: &Nir<'cx>, a: Nir<'cx>) -> NirKind<'cx> {
     match f.kind() {
-        NirKind::LamClosure { closure, .. } => closure.apply(a).kind().clone(),
+        NirKind::LamClosure { closure, .. } => {
+            if let Closure::Closure { env, .. } = closure {
+                let cx = env.cx();
+                if cx.limit_reached() {
+                    return NirKind::Op(OpKind::App(f.clone(), a));
+                }
+                // Count the binding of the argument, so that deep recursions are limited too.
+                cx.count_nodes(1);
+                cx.trace(|depth| TraceEvent::Beta { depth });
+                return cx.nested_eval(|| closure.apply(a).kind().clone());
+            }
+            closure.apply(a).kind().clone()
+        }
         NirKind::AppliedBuiltin(closure) => closure.apply(a),
         NirKind::UnionConstructor(l, kts) => {
             NirKind::UnionLit(l.clone(), a, kts.clone())
@@ -148,6 +161,9 @@ pub fn normalize_one_layer<'cx>(expr: ExprKind<Nir<'cx>>) -> NirKind<'cx> {
         ExprKind::Import(..) => {
             unreachable!("This case should have been handled in resolution")
         }
+        ExprKind::Error(..) => {
+            unreachable!("Syntax errors should have failed typechecking")
+        }
     }
 }
 
@@ -170,6 +186,7 @@ pub fn normalize_hir<'cx>(env: &NzEnv<'cx>, hir: &Hir<'cx>) -> NirKind<'cx> {
         }
         HirKind::Expr(ExprKind::Lam(binder, annot, body)) => {
             let annot = annot.eval(env);
+            env.cx().count_nodes(1);
             NirKind::LamClosure {
                 binder: Binder::new(binder.clone()),
                 annot,
@@ -178,6 +195,7 @@ pub fn normalize_hir<'cx>(env: &NzEnv<'cx>, hir: &Hir<'cx>) -> NirKind<'cx> {
         }
         HirKind::Expr(ExprKind::Pi(binder, annot, body)) => {
             let annot = annot.eval(env);
+            env.cx().count_nodes(1);
             NirKind::PiClosure {
                 binder: Binder::new(binder.clone()),
                 annot,
@@ -192,8 +210,224 @@ pub fn normalize_hir<'cx>(env: &NzEnv<'cx>, hir: &Hir<'cx>) -> NirKind<'cx> {
             NirKind::from_builtin_env(*b, env.clone())
         }
         HirKind::Expr(e) => {
+            let cx = env.cx();
             let e = e.map_ref(|hir| hir.eval(env));
-            normalize_one_layer(e)
+            match e {
+                // Over the node limit, operations are left as they are.
+                ExprKind::Op(op) if cx.limit_reached() => NirKind::Op(op),
+                e => {
+                    let kind = normalize_one_layer(e);
+                    cx.count_nodes(kind.size());
+                    kind
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::builtins::Builtin;
+    use crate::operations::{BinOp, OpKind};
+    use crate::syntax::{shift, subst, Expr, ExprKind, NumKind, Span, V};
+    use crate::{Ctxt, Parsed};
+
+    fn expr(kind: ExprKind<Expr>) -> Expr {
+        Expr::new(kind, Span::Artificial)
+    }
+
+    fn natural() -> Expr {
+        expr(ExprKind::Builtin(Builtin::Natural))
+    }
+
+    /// Expressions of type `Natural` with variables called `x`, `y` and `z`, that are bound
+    /// outside of the expression. They have redexes of each kind: applied functions, `let`s and
+    /// builtins applied to numbers.
+    fn arb_expr() -> impl Strategy<Value = Expr> {
+        let label = || prop_oneof![Just("x"), Just("y"), Just("z")];
+        let leaf = prop_oneof![
+            label().prop_map(|x| expr(ExprKind::Var(V(x.into(), 0)))),
+            (0..3u64).prop_map(|n| expr(ExprKind::Num(NumKind::Natural(n)))),
+        ];
+        leaf.prop_recursive(5, 48, 3, move |inner| {
+            let app = |f, a| expr(ExprKind::Op(OpKind::App(f, a)));
+            prop_oneof![
+                (label(), inner.clone(), inner.clone()).prop_map(
+                    |(x, v, e)| expr(ExprKind::Let(x.into(), None, v, e))
+                ),
+                (label(), inner.clone(), inner.clone()).prop_map(
+                    move |(x, e, a)| {
+                        app(expr(ExprKind::Lam(x.into(), natural(), e)), a)
+                    }
+                ),
+                // `(λ(f : Natural → Natural) → f (f a)) (λ(x : Natural) → e)`
+                (label(), inner.clone(), inner.clone()).prop_map(
+                    move |(x, e, a)| {
+                        let f = || expr(ExprKind::Var(V("f".into(), 0)));
+                        let ty = expr(ExprKind::Pi(
+                            "_".into(),
+                            natural(),
+                            natural(),
+                        ));
+                        let twice = expr(ExprKind::Lam(
+                            "f".into(),
+                            ty,
+                            app(f(), app(f(), a)),
+                        ));
+                        app(twice, expr(ExprKind::Lam(x.into(), natural(), e)))
+                    }
+                ),
+                (
+                    prop_oneof![
+                        Just(BinOp::NaturalPlus),
+                        Just(BinOp::NaturalTimes)
+                    ],
+                    inner.clone(),
+                    inner.clone()
+                )
+                    .prop_map(|(op, a, b)| expr(ExprKind::Op(
+                        OpKind::BinOp(op, a, b)
+                    ))),
+                (inner.clone(), inner).prop_map(move |(a, b)| {
+                    let sub = expr(ExprKind::Builtin(Builtin::NaturalSubtract));
+                    app(app(sub, a), b)
+                }),
+            ]
+        })
+    }
+
+    /// Reduces `e` if it is a redex, without reducing its sub-expressions.
+    fn reduce(e: &Expr) -> Option<Expr> {
+        let natural = |e: &Expr| match e.kind() {
+            ExprKind::Num(NumKind::Natural(n)) => Some(*n),
+            _ => None,
+        };
+        let num = |n| expr(ExprKind::Num(NumKind::Natural(n)));
+        let beta = |x, v: &Expr, body| {
+            shift(-1, x, 0, &subst(x, 0, &shift(1, x, 0, v), body))
+        };
+        match e.kind() {
+            ExprKind::Let(x, _, v, body) => Some(beta(x, v, body)),
+            ExprKind::Op(OpKind::App(f, a)) => match f.kind() {
+                ExprKind::Lam(x, _, body) => Some(beta(x, a, body)),
+                ExprKind::Op(OpKind::App(sub, m)) => match sub.kind() {
+                    ExprKind::Builtin(Builtin::NaturalSubtract) => {
+                        Some(num(natural(a)?.saturating_sub(natural(m)?)))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            ExprKind::Op(OpKind::BinOp(BinOp::NaturalPlus, a, b)) => {
+                Some(num(natural(a)? + natural(b)?))
+            }
+            ExprKind::Op(OpKind::BinOp(BinOp::NaturalTimes, a, b)) => {
+                Some(num(natural(a)? * natural(b)?))
+            }
+            _ => None,
+        }
+    }
+
+    fn count_redexes(e: &Expr) -> usize {
+        let mut count = reduce(e).is_some() as usize;
+        e.kind().map_ref(|e| count += count_redexes(e));
+        count
+    }
+
+    /// Reduces the `n`-th redex of `e` in pre-order, and counts down `n` for the redexes before
+    /// it. `n` becomes `None` once the redex is reduced.
+    fn reduce_nth(e: &Expr, n: &mut Option<usize>) -> Expr {
+        if let Some(reduced) = reduce(e) {
+            match n {
+                Some(0) => {
+                    *n = None;
+                    return reduced;
+                }
+                Some(k) => *k -= 1,
+                None => {}
+            }
+        }
+        Expr::new(e.kind().map_ref(|e| reduce_nth(e, n)), e.span())
+    }
+
+    fn normalize(s: &str) -> String {
+        Ctxt::with_new(|cx| {
+            Parsed::parse_str(s)
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap()
+                .normalize(cx)
+                .to_expr(cx)
+                .to_string()
+        })
+    }
+
+    #[test]
+    fn text_append_folds_literals() {
+        assert_eq!(
+            normalize(r#"λ(x : Text) → "a" ++ "b" ++ x ++ "c" ++ "d""#),
+            r#"λ(x : Text) → "ab${ x }cd""#
+        );
+        assert_eq!(
+            normalize(
+                r#"λ(x : Text) → λ(y : Text) → ((x ++ "a") ++ "b") ++ y"#
+            ),
+            r#"λ(x : Text) → λ(y : Text) → "${ x }ab${ y }""#
+        );
+        // Interpolations are folded like appends.
+        assert_eq!(
+            normalize(r#"λ(x : Text) → "a${"b" ++ x}" ++ "${"c"}d""#),
+            r#"λ(x : Text) → "ab${ x }cd""#
+        );
+        assert_eq!(
+            normalize(r#"λ(x : Text) → λ(y : Text) → x ++ y"#),
+            r#"λ(x : Text) → λ(y : Text) → x ++ y"#
+        );
+    }
+
+    proptest! {
+        /// Reducing any redex first doesn't change the normal form. CI runs this with more cases,
+        /// set with `PROPTEST_CASES`.
+        #[test]
+        fn normalization_is_confluent(
+            e in arb_expr(),
+            redex in any::<prop::sample::Index>(),
+        ) {
+            let e = ["z", "y", "x"].iter().fold(e, |e, x| {
+                expr(ExprKind::Let(
+                    (*x).into(),
+                    None,
+                    expr(ExprKind::Num(NumKind::Natural(1))),
+                    e,
+                ))
+            });
+            let mut n = Some(redex.index(count_redexes(&e)));
+            let reduced = reduce_nth(&e, &mut n);
+            prop_assert!(n.is_none());
+            Ctxt::with_new(|cx| {
+                let normalize = |e: &Expr| {
+                    Parsed::from_expr_without_imports(e.clone())
+                        .skip_resolve(cx)
+                        .unwrap()
+                        .typecheck(cx)
+                        .unwrap()
+                        .normalize(cx)
+                };
+                let (nf, reduced_nf) = (normalize(&e), normalize(&reduced));
+                prop_assert!(
+                    nf == reduced_nf,
+                    "\noriginal: {}\nreduced: {}\nnormal form: {}\nnormal form after the step: {}",
+                    e,
+                    reduced,
+                    nf.to_expr(cx),
+                    reduced_nf.to_expr(cx)
+                );
+                Ok(())
+            })?;
         }
     }
 }
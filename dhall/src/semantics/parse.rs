@@ -1,4 +1,8 @@
-use std::path::Path;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use url::Url;
 
 use crate::error::Error;
@@ -6,11 +10,120 @@ use crate::semantics::resolve::{download_http_text, ImportLocation};
 use crate::syntax::{binary, parse_expr};
 use crate::Parsed;
 
-pub fn parse_file(f: &Path) -> Result<Parsed, Error> {
-    let text = std::fs::read_to_string(f)?;
+/// Parsed local files, reused as long as the modification time and size of the file don't change.
+///
+/// Imports only use one when it is set with `ImportEnv::with_parse_cache`. The same cache can be
+/// shared by the `ImportEnv`s of several resolutions, so that files they all import are read and
+/// parsed once. It keeps at most `capacity` files, and forgets the least recently used ones first.
+#[derive(Debug)]
+pub struct ParseCache {
+    capacity: usize,
+    entries: RefCell<HashMap<PathBuf, CacheEntry>>,
+    /// Counts the uses of the cache, to know which entry was used least recently.
+    clock: Cell<u64>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    mtime: SystemTime,
+    size: u64,
+    parsed: Parsed,
+    last_used: u64,
+}
+
+impl ParseCache {
+    /// A cache that keeps at most `capacity` files.
+    pub fn new(capacity: usize) -> Self {
+        ParseCache {
+            capacity,
+            entries: RefCell::new(HashMap::new()),
+            clock: Cell::new(0),
+        }
+    }
+
+    /// The number of files in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Parses the file at `f`, reading it with `read` unless it hasn't changed since it was last
+    /// parsed. Also returns the size of the file.
+    fn parse_file_with(
+        &self,
+        f: &Path,
+        read: impl FnOnce(&Path) -> io::Result<String>,
+    ) -> Result<(Parsed, u64), Error> {
+        let metadata = std::fs::metadata(f)?;
+        let mtime = metadata.modified()?;
+        let size = metadata.len();
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        if let Some(entry) = self.entries.borrow_mut().get_mut(f) {
+            if entry.mtime == mtime && entry.size == size {
+                entry.last_used = now;
+                return Ok((entry.parsed.clone(), size));
+            }
+        }
+
+        let (parsed, _) = parse_file_without_cache(f, read(f)?)?;
+        if self.capacity == 0 {
+            return Ok((parsed, size));
+        }
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() >= self.capacity && !entries.contains_key(f) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let entry = CacheEntry {
+            mtime,
+            size,
+            parsed: parsed.clone(),
+            last_used: now,
+        };
+        entries.insert(f.to_owned(), entry);
+        Ok((parsed, size))
+    }
+}
+
+/// Parses a local file, with `cache` if there is one. If the contents of the file were already
+/// read, they are passed as `text`. Also returns the size of the file.
+pub(crate) fn parse_local_file(
+    f: &Path,
+    text: Option<String>,
+    cache: Option<&ParseCache>,
+) -> Result<(Parsed, u64), Error> {
+    let read = |f: &Path| match text {
+        Some(text) => Ok(text),
+        None => std::fs::read_to_string(f),
+    };
+    match cache {
+        Some(cache) => cache.parse_file_with(f, read),
+        None => parse_file_without_cache(f, read(f)?),
+    }
+}
+
+/// Parses the contents of the file at `f`, when they were not read from the filesystem. The result
+/// isn't cached since the file may not exist on disk, or may not have these contents.
+pub(crate) fn parse_file_without_cache(
+    f: &Path,
+    text: String,
+) -> Result<(Parsed, u64), Error> {
     let expr = parse_expr(&text)?;
     let root = ImportLocation::local_dhall_code(f.to_owned());
-    Ok(Parsed(expr, root))
+    Ok((Parsed(expr, root), text.len() as u64))
+}
+
+pub fn parse_file(f: &Path) -> Result<Parsed, Error> {
+    Ok(parse_local_file(f, None, None)?.0)
 }
 
 pub fn parse_remote(url: Url) -> Result<Parsed, Error> {
@@ -38,3 +151,52 @@ pub fn parse_binary_file(f: &Path) -> Result<Parsed, Error> {
     let root = ImportLocation::local_dhall_code(f.to_owned());
     Ok(Parsed(expr, root))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn parse_cache() {
+        let dir = std::env::temp_dir()
+            .join(format!("dhall_parse_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("x.dhall");
+        std::fs::write(&file, "1 + 1").unwrap();
+
+        let cache = ParseCache::new(1);
+        let reads = Cell::new(0);
+        let parse = || {
+            cache
+                .parse_file_with(&file, |f| {
+                    reads.set(reads.get() + 1);
+                    std::fs::read_to_string(f)
+                })
+                .unwrap()
+        };
+        let (first, size) = parse();
+        assert_eq!(size, 5);
+        // The file hasn't changed: it isn't opened again.
+        let (second, _) = parse();
+        assert_eq!(reads.get(), 1);
+        assert_eq!(first.0, second.0);
+        // The size changed: the file is read again.
+        std::fs::write(&file, "1 + 10").unwrap();
+        let (third, _) = parse();
+        assert_eq!(reads.get(), 2);
+        assert_ne!(first.0, third.0);
+
+        // Over capacity, the least recently used file is forgotten.
+        let other = dir.join("y.dhall");
+        std::fs::write(&other, "2").unwrap();
+        cache
+            .parse_file_with(&other, |f| std::fs::read_to_string(f))
+            .unwrap();
+        assert_eq!(cache.len(), 1);
+        parse();
+        assert_eq!(reads.get(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
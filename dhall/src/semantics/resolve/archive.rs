@@ -0,0 +1,148 @@
+use std::io::{self, Read, Seek};
+use std::path::{Component, Path};
+use std::sync::Mutex;
+
+use zip::ZipArchive;
+
+use crate::error::Error;
+use crate::semantics::ImportResolver;
+
+/// Reads local imports from a ZIP archive instead of the filesystem, e.g. to bundle a
+/// configuration with all its imports in a single file.
+///
+/// The archive is seen as if it was the root of the filesystem: the file `config/main.dhall` in the
+/// archive is imported as `/config/main.dhall`, and relative imports between files of the archive
+/// work as usual. Any other local import fails, including the ones that leave the archive with
+/// `..`. To start from a file of the archive, resolve an expression that imports it by its
+/// absolute path.
+pub struct ZipImportResolver<R> {
+    archive: Mutex<ZipArchive<R>>,
+    /// Prefix of the names of the files in the archive, either empty or ending with `/`.
+    root: String,
+}
+
+impl<R: Read + Seek> ZipImportResolver<R> {
+    pub fn new(archive: ZipArchive<R>) -> Self {
+        ZipImportResolver {
+            archive: Mutex::new(archive),
+            root: String::new(),
+        }
+    }
+
+    /// Only reads the files in the directory `root` of the archive, and sees it as the root of the
+    /// filesystem: `/main.dhall` is then read from `root/main.dhall`.
+    pub fn with_root(self, root: &str) -> Self {
+        let root = root.trim_matches('/');
+        ZipImportResolver {
+            root: if root.is_empty() {
+                String::new()
+            } else {
+                format!("{}/", root)
+            },
+            ..self
+        }
+    }
+
+    /// The name in the archive of the file at `path`, if `path` points inside the archive.
+    fn name_in_archive(&self, path: &Path) -> Option<String> {
+        let mut components = path.components();
+        if components.next() != Some(Component::RootDir) {
+            return None;
+        }
+        let mut names = Vec::new();
+        for component in components {
+            match component {
+                Component::Normal(name) => names.push(name.to_str()?),
+                _ => return None,
+            }
+        }
+        Some(format!("{}{}", self.root, names.join("/")))
+    }
+}
+
+impl<R: Read + Seek + Send> ImportResolver for ZipImportResolver<R> {
+    fn read_file(&self, path: &Path) -> Result<String, Error> {
+        let not_found = || {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` is not in the archive", path.display()),
+            )
+        };
+        let name = self.name_in_archive(path).ok_or_else(not_found)?;
+        // The archive reads one file at a time, even when imports are fetched in parallel.
+        let mut archive = self.archive.lock().unwrap();
+        let mut file = archive.by_name(&name).map_err(|_| not_found())?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use zip::write::{FileOptions, ZipWriter};
+
+    use super::*;
+    use crate::semantics::ImportEnv;
+    use crate::{Ctxt, Parsed};
+
+    fn archive(files: &[(&str, &str)]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    fn eval(resolver: ZipImportResolver<Cursor<Vec<u8>>>, s: &str) -> String {
+        Ctxt::with_new(|cx| {
+            let mut env = ImportEnv::new(cx).with_resolver(resolver);
+            let mut result = || -> Result<String, Error> {
+                let resolved =
+                    Parsed::parse_str(s)?.resolve_with_env(&mut env)?;
+                let typed = resolved.typecheck(cx)?;
+                Ok(typed.normalize(cx).to_expr(cx).to_string())
+            };
+            result().unwrap_or_else(|e| e.to_string())
+        })
+    }
+
+    #[test]
+    fn imports_from_archive() {
+        let files = [
+            (
+                "pkg/config/main.dhall",
+                "{ servers = ./servers.dhall, port = (../common/defaults.dhall).port }",
+            ),
+            ("pkg/config/servers.dhall", "[\"a\", ./name.txt as Text]"),
+            ("pkg/config/name.txt", "b"),
+            ("pkg/common/defaults.dhall", "{ port = 80 }"),
+            ("secret.dhall", "42"),
+        ];
+        let resolver = || ZipImportResolver::new(archive(&files));
+        let pkg = || resolver().with_root("pkg/");
+
+        assert_eq!(
+            eval(pkg(), "/config/main.dhall"),
+            "{ port = 80, servers = [\"a\", \"b\"] }"
+        );
+        assert_eq!(
+            eval(resolver(), "/pkg/config/servers.dhall"),
+            "[\"a\", \"b\"]"
+        );
+        assert_eq!(eval(resolver(), "/secret.dhall"), "42");
+
+        // Files outside of the root can't be imported.
+        assert!(eval(pkg(), "/secret.dhall").contains("not in the archive"));
+        assert!(eval(pkg(), "/../secret.dhall").contains("not in the archive"));
+        assert!(eval(pkg(), "./secret.dhall").contains("not in the archive"));
+        // Nor can files of the filesystem.
+        assert!(eval(resolver(), "/etc/hostname as Text")
+            .contains("not in the archive"));
+        // Other imports are resolved as usual.
+        assert_eq!(eval(pkg(), "missing ? /config/name.txt as Text"), "\"b\"");
+    }
+}
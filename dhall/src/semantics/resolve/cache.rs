@@ -1,13 +1,16 @@
 use std::env;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_cbor::Value as CborValue;
 
 use crate::error::{CacheError, Error};
 use crate::parse::parse_binary;
-use crate::syntax::{binary, Hash};
+use crate::syntax::{binary, Expr, Hash};
 use crate::{Ctxt, Typed};
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 
 #[cfg(any(unix, windows))]
 const CACHE_ENV_VAR: &str = "XDG_CACHE_HOME";
@@ -108,6 +111,233 @@ fn write_cache_file<'cx>(
     Ok(())
 }
 
+/// Stamped on the entries of a `PersistentCache`, so that the entries written by another version
+/// of the crate are ignored.
+const ENTRY_VERSION: &str = concat!("dhall-rust ", env!("CARGO_PKG_VERSION"));
+
+/// How long a writer can hold the lock on an entry. A lock older than that was left by a writer
+/// that crashed.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Normal forms stored on disk, so that they are kept across runs of the program.
+///
+/// Entries are keyed by a hash of the source they were computed from, and hold the normal form
+/// in the binary format. An entry is ignored and removed when it is corrupt, when it was written
+/// by another version of this crate, or when one of the files it was read from has changed since.
+/// An entry older than the time-to-live, if one is set, is ignored but kept, since caches with a
+/// longer time-to-live may still use it.
+///
+/// Entries are written to a temporary file that is then renamed, so readers never see a partial
+/// entry. Writers take a lock on the entry; when it is already taken, the other writer is left to
+/// write it. Invalid entries are only removed under the same lock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistentCache {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+}
+
+impl PersistentCache {
+    /// A cache that keeps its entries in `dir`. The directory is created when the first entry is
+    /// written.
+    pub fn new(dir: &Path) -> PersistentCache {
+        PersistentCache {
+            dir: dir.to_owned(),
+            ttl: None,
+        }
+    }
+
+    /// A cache in the `normal-forms` directory of the dhall cache, next to the cached imports.
+    pub fn in_default_dir() -> Result<PersistentCache, Error> {
+        Ok(PersistentCache::new(
+            &default_cache_dir()?.join("normal-forms"),
+        ))
+    }
+
+    /// Ignores the entries written at least `ttl` ago.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        PersistentCache {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, source_hash: &[u8; 32], extension: &str) -> PathBuf {
+        let mut name = hex::encode(source_hash);
+        name.push_str(extension);
+        self.dir.join(name)
+    }
+
+    /// The normal form stored for `source_hash`, if there is a valid entry for it.
+    pub fn get(&self, source_hash: &[u8; 32]) -> Option<Expr> {
+        let path = self.entry_path(source_hash, "");
+        let data = std::fs::read(&path).ok()?;
+        match self.read_entry(&data) {
+            Entry::Valid(expr) => Some(expr),
+            Entry::Expired => None,
+            Entry::Invalid => {
+                // Delete the entry since it's invalid, unless a writer holds the lock or has
+                // replaced the entry since we read it. We ignore the errors.
+                let lock_path = self.entry_path(source_hash, ".lock");
+                if let Ok(Some(_lock)) = EntryLock::acquire(lock_path) {
+                    if std::fs::read(&path).ok().as_ref() == Some(&data) {
+                        let _ = std::fs::remove_file(&path);
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn read_entry(&self, data: &[u8]) -> Entry {
+        let fields = match serde_cbor::from_slice(data) {
+            Ok(CborValue::Array(fields)) => fields,
+            _ => return Entry::Invalid,
+        };
+        let (version, written_at, dependencies, expr) = match fields.as_slice()
+        {
+            [CborValue::Text(version), CborValue::Integer(written_at), CborValue::Array(dependencies), CborValue::Bytes(expr)] => {
+                (version, *written_at, dependencies, expr)
+            }
+            _ => return Entry::Invalid,
+        };
+        if version != ENTRY_VERSION {
+            return Entry::Invalid;
+        }
+        for dependency in dependencies {
+            match dependency {
+                CborValue::Array(dep) => match dep.as_slice() {
+                    [CborValue::Text(path), CborValue::Bytes(hash)] => {
+                        let unchanged = std::fs::read(path)
+                            .map(|data| {
+                                crate::utils::sha256_hash(&data)[..] == hash[..]
+                            })
+                            .unwrap_or(false);
+                        if !unchanged {
+                            return Entry::Invalid;
+                        }
+                    }
+                    _ => return Entry::Invalid,
+                },
+                _ => return Entry::Invalid,
+            }
+        }
+        if let Some(ttl) = self.ttl {
+            let written_at =
+                UNIX_EPOCH + Duration::from_secs(written_at as u64);
+            let age = SystemTime::now()
+                .duration_since(written_at)
+                .unwrap_or_default();
+            if age >= ttl {
+                return Entry::Expired;
+            }
+        }
+        match binary::decode(expr) {
+            Ok(expr) => Entry::Valid(expr),
+            Err(_) => Entry::Invalid,
+        }
+    }
+
+    /// Stores `expr` as the normal form for `source_hash`.
+    pub fn insert(
+        &self,
+        source_hash: &[u8; 32],
+        expr: &Expr,
+    ) -> Result<(), Error> {
+        self.insert_with_dependencies::<&Path>(source_hash, expr, &[])
+    }
+
+    /// Like `insert`, for a normal form that depends on the contents of the files
+    /// `dependencies`, like imported files: the entry is dropped once one of them changes.
+    pub fn insert_with_dependencies<P: AsRef<Path>>(
+        &self,
+        source_hash: &[u8; 32],
+        expr: &Expr,
+        dependencies: &[P],
+    ) -> Result<(), Error> {
+        let dependencies = dependencies
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                let data = crate::utils::read_binary_file(path)?;
+                Ok(CborValue::Array(vec![
+                    CborValue::Text(path.to_string_lossy().into_owned()),
+                    CborValue::Bytes(crate::utils::sha256_hash(&data).into()),
+                ]))
+            })
+            .collect::<Result<_, Error>>()?;
+        let written_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let entry = CborValue::Array(vec![
+            CborValue::Text(ENTRY_VERSION.to_owned()),
+            CborValue::Integer(written_at.into()),
+            CborValue::Array(dependencies),
+            CborValue::Bytes(binary::encode(expr)?),
+        ]);
+        let data = serde_cbor::to_vec(&entry)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        std::fs::create_dir_all(&self.dir)?;
+        let _lock =
+            match EntryLock::acquire(self.entry_path(source_hash, ".lock"))? {
+                Some(lock) => lock,
+                None => return Ok(()),
+            };
+        let tmp_path = self.entry_path(source_hash, ".tmp");
+        File::create(&tmp_path)?.write_all(&data)?;
+        std::fs::rename(&tmp_path, self.entry_path(source_hash, ""))?;
+        Ok(())
+    }
+}
+
+/// What `PersistentCache` found in an entry.
+enum Entry {
+    Valid(Expr),
+    /// Older than the time-to-live of the cache that read it. Other caches may still use it.
+    Expired,
+    /// Unusable by any cache: corrupt, from another version, or with changed dependencies.
+    Invalid,
+}
+
+/// The lock on an entry of a `PersistentCache`, released when dropped.
+struct EntryLock(PathBuf);
+
+impl EntryLock {
+    /// Takes the lock, or returns `None` if another writer holds it.
+    fn acquire(path: PathBuf) -> Result<Option<EntryLock>, Error> {
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Some(EntryLock(path))),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|time| time.elapsed().ok())
+                        .map(|age| age > LOCK_TIMEOUT)
+                        .unwrap_or(false);
+                    if !stale {
+                        return Ok(None);
+                    }
+                    // Take over the lock of the writer that crashed.
+                    let _ = std::fs::remove_file(&path);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for EntryLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
 fn filename_for_hash(hash: &Hash) -> String {
     match hash {
         Hash::SHA256(sha) => format!("1220{}", hex::encode(&sha)),
@@ -125,4 +355,73 @@ mod test {
             Hash::SHA256(parse_expr("1").unwrap().sha256_hash().unwrap());
         assert_eq!("1220d60d8415e36e86dae7f42933d3b0c4fe3ca238f057fba206c7e9fbf5d784fe15".to_string(), filename_for_hash(&hash));
     }
+
+    #[test]
+    fn persistent_cache() {
+        let dir = env::temp_dir()
+            .join(format!("dhall_persistent_cache_{}", std::process::id()));
+        let cache = PersistentCache::new(&dir);
+        let expr = parse_expr("{ x = 1 }").unwrap();
+        let key = [1; 32];
+        let run = || -> Result<(), Error> {
+            assert_eq!(cache.get(&key), None);
+            cache.insert(&key, &expr)?;
+            assert_eq!(
+                cache.get(&key).map(|e| e.to_string()),
+                Some(expr.to_string())
+            );
+            assert_eq!(cache.get(&[2; 32]), None);
+
+            // Entries are dropped once they are too old.
+            let stale = cache.clone().with_ttl(Duration::from_secs(0));
+            assert!(cache
+                .clone()
+                .with_ttl(Duration::from_secs(60))
+                .get(&key)
+                .is_some());
+            assert_eq!(stale.get(&key), None);
+            // The entry is kept for the caches that still find it fresh.
+            assert!(cache.get(&key).is_some());
+
+            // Or when a file they depend on changes.
+            let dep = dir.join("dep.dhall");
+            std::fs::write(&dep, "1")?;
+            cache.insert_with_dependencies(&key, &expr, &[&dep])?;
+            assert!(cache.get(&key).is_some());
+            std::fs::write(&dep, "2")?;
+            assert_eq!(cache.get(&key), None);
+
+            // Or when they were written by another version.
+            let entry = CborValue::Array(vec![
+                CborValue::Text("dhall-rust 0.0.0".to_owned()),
+                CborValue::Integer(0),
+                CborValue::Array(Vec::new()),
+                CborValue::Bytes(binary::encode(&expr)?),
+            ]);
+            std::fs::write(
+                dir.join(hex::encode(key)),
+                serde_cbor::to_vec(&entry).unwrap(),
+            )?;
+            // Invalid entries are only removed under the lock.
+            let lock = EntryLock::acquire(cache.entry_path(&key, ".lock"))?;
+            assert_eq!(cache.get(&key), None);
+            assert!(dir.join(hex::encode(key)).exists());
+            drop(lock);
+            assert_eq!(cache.get(&key), None);
+            assert!(!dir.join(hex::encode(key)).exists());
+
+            // Writers leave the entry to the one that holds the lock.
+            let lock = EntryLock::acquire(cache.entry_path(&key, ".lock"))?;
+            assert!(lock.is_some());
+            cache.insert(&key, &expr)?;
+            assert_eq!(cache.get(&key), None);
+            drop(lock);
+            cache.insert(&key, &expr)?;
+            assert!(cache.get(&key).is_some());
+            Ok(())
+        };
+        let result = run();
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+    }
 }
@@ -1,7 +1,12 @@
 use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
 
 use crate::error::{Error, ImportError};
-use crate::semantics::{check_hash, AlphaVar, Cache, ImportLocation, VarEnv};
+use crate::semantics::parse::ParseCache;
+use crate::semantics::{
+    check_hash, AlphaVar, Cache, ImportLocation, ResolvedMeta, VarEnv,
+};
 use crate::syntax::{Hash, Label, V};
 use crate::{Ctxt, ImportId, ImportResultId, Typed};
 
@@ -13,12 +18,32 @@ pub struct NameEnv {
 
 pub type CyclesStack = Vec<ImportLocation>;
 
+/// Called each time an import gets resolved.
+type ImportCallback = Box<dyn Fn(&ImportLocation, &ResolvedMeta)>;
+
+/// Reads the files of local imports, in place of the filesystem. With the `parallel-imports`
+/// feature, files may be read from several threads at once.
+pub trait ImportResolver: Send + Sync {
+    /// Returns the contents of the file at `path`. The path of an import is resolved against the
+    /// path of the file that contains it, so it is absolute unless that file's path was relative.
+    fn read_file(&self, path: &Path) -> Result<String, Error>;
+}
+
 /// Environment for resolving imports
 pub struct ImportEnv<'cx> {
     cx: Ctxt<'cx>,
     disk_cache: Option<Cache>, // `None` if it failed to initialize
     mem_cache: HashMap<ImportLocation, ImportResultId<'cx>>,
     stack: CyclesStack,
+    on_import_resolved: Option<ImportCallback>,
+    /// Sources fetched ahead of time, that haven't been used yet.
+    prefetched: HashMap<ImportLocation, String>,
+    parallel_imports: bool,
+    resolver: Option<Box<dyn ImportResolver>>,
+    parse_cache: Option<Rc<ParseCache>>,
+    keep_field_order: bool,
+    canonicalize_imports: bool,
+    used_fallback: bool,
 }
 
 impl NameEnv {
@@ -40,6 +65,19 @@ impl NameEnv {
     pub fn remove_mut(&mut self) {
         self.names.pop();
     }
+    /// Removes the most recent binding of `x`, so that `x` refers to the binding it shadowed.
+    /// Bindings of other names are kept.
+    pub fn remove_last(&self, x: &Label) -> Self {
+        let mut env = self.clone();
+        if let Some(i) = env.names.iter().rposition(|n| n == x) {
+            env.names.remove(i);
+        }
+        env
+    }
+    /// The number of bindings of `x` in scope, i.e. the smallest `n` such that `x@n` is free.
+    pub fn depth(&self, x: &Label) -> usize {
+        self.names.iter().filter(|n| *n == x).count()
+    }
 
     pub fn unlabel_var(&self, var: &V) -> Option<AlphaVar> {
         let V(name, idx) = var;
@@ -72,6 +110,76 @@ impl<'cx> ImportEnv<'cx> {
             disk_cache: Cache::new().ok(),
             mem_cache: Default::default(),
             stack: Default::default(),
+            on_import_resolved: None,
+            prefetched: Default::default(),
+            parallel_imports: true,
+            resolver: None,
+            parse_cache: None,
+            keep_field_order: false,
+            canonicalize_imports: false,
+            used_fallback: false,
+        }
+    }
+
+    /// Calls `f` each time an import gets resolved.
+    pub fn with_callback(
+        self,
+        f: impl Fn(&ImportLocation, &ResolvedMeta) + 'static,
+    ) -> Self {
+        ImportEnv {
+            on_import_resolved: Some(Box::new(f)),
+            ..self
+        }
+    }
+
+    /// Whether to fetch the independent imports of a file in parallel, local and remote. This is
+    /// on by default, and only has an effect with the `parallel-imports` feature.
+    pub fn with_parallel_imports(self, parallel_imports: bool) -> Self {
+        ImportEnv {
+            parallel_imports,
+            ..self
+        }
+    }
+
+    /// Whether imported expressions keep the order in which the fields of their records were
+    /// written, which normalization would otherwise lose. This is off by default. The order is
+    /// available from the spans of the records, see `Span::field_order`. Imports read from the
+    /// on-disk cache are sorted.
+    pub fn with_field_order(self, keep_field_order: bool) -> Self {
+        ImportEnv {
+            keep_field_order,
+            ..self
+        }
+    }
+
+    /// Whether to resolve symlinks in the paths of local imports before reading them, so that
+    /// imports of the same file through different paths share a cache entry. This is off by
+    /// default, and has no effect with a resolver.
+    pub fn with_canonical_imports(self, canonicalize_imports: bool) -> Self {
+        ImportEnv {
+            canonicalize_imports,
+            ..self
+        }
+    }
+
+    /// Reads local imports with `resolver` instead of from the filesystem.
+    pub fn with_resolver(
+        self,
+        resolver: impl ImportResolver + 'static,
+    ) -> Self {
+        ImportEnv {
+            resolver: Some(Box::new(resolver)),
+            ..self
+        }
+    }
+
+    /// Reuses the local files parsed in `cache` when they haven't changed, and adds the ones
+    /// parsed now. The cache can be shared with other `ImportEnv`s. It has no effect with a
+    /// resolver.
+    pub fn with_parse_cache(self, cache: Rc<ParseCache>) -> Self {
+        ImportEnv {
+            parse_cache: Some(cache),
+            ..self
         }
     }
 
@@ -79,6 +187,37 @@ impl<'cx> ImportEnv<'cx> {
         self.cx
     }
 
+    pub fn parallel_imports(&self) -> bool {
+        self.parallel_imports
+    }
+
+    pub fn keep_field_order(&self) -> bool {
+        self.keep_field_order
+    }
+
+    pub fn canonicalize_imports(&self) -> bool {
+        self.canonicalize_imports && self.resolver.is_none()
+    }
+
+    pub fn resolver(&self) -> Option<&dyn ImportResolver> {
+        self.resolver.as_deref()
+    }
+
+    pub fn parse_cache(&self) -> Option<&ParseCache> {
+        self.parse_cache.as_deref()
+    }
+
+    /// Whether an import alternative fell back on its right side, because its left side failed
+    /// to resolve. The result then depends on imports that failed, which the callback isn't told
+    /// about.
+    pub fn used_fallback(&self) -> bool {
+        self.used_fallback
+    }
+
+    pub fn set_used_fallback(&mut self) {
+        self.used_fallback = true;
+    }
+
     pub fn get_from_mem_cache(
         &self,
         location: &ImportLocation,
@@ -86,6 +225,28 @@ impl<'cx> ImportEnv<'cx> {
         Some(*self.mem_cache.get(location)?)
     }
 
+    pub fn add_prefetched(
+        &mut self,
+        sources: impl IntoIterator<Item = (ImportLocation, String)>,
+    ) {
+        self.prefetched.extend(sources);
+    }
+
+    /// Returns the source of this location if it was fetched ahead of time.
+    pub fn take_prefetched(
+        &mut self,
+        location: &ImportLocation,
+    ) -> Option<String> {
+        self.prefetched.remove(location)
+    }
+
+    /// Drops the sources of these locations that were fetched ahead of time but not used.
+    pub fn forget_prefetched(&mut self, locations: &[ImportLocation]) {
+        for location in locations {
+            self.prefetched.remove(location);
+        }
+    }
+
     pub fn get_from_disk_cache(
         &self,
         hash: &Option<Hash>,
@@ -95,6 +256,20 @@ impl<'cx> ImportEnv<'cx> {
         Some(expr)
     }
 
+    pub fn has_callback(&self) -> bool {
+        self.on_import_resolved.is_some()
+    }
+
+    pub fn call_callback(
+        &self,
+        location: &ImportLocation,
+        meta: &ResolvedMeta,
+    ) {
+        if let Some(f) = &self.on_import_resolved {
+            f(location, meta)
+        }
+    }
+
     pub fn check_hash(
         &self,
         import: ImportId<'cx>,
@@ -124,11 +299,11 @@ impl<'cx> ImportEnv<'cx> {
         }
     }
 
-    pub fn with_cycle_detection(
+    pub fn with_cycle_detection<T>(
         &mut self,
         location: ImportLocation,
-        do_resolve: impl FnOnce(&mut Self) -> Result<Typed<'cx>, Error>,
-    ) -> Result<Typed<'cx>, Error> {
+        do_resolve: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
         if self.stack.contains(&location) {
             return Err(
                 ImportError::ImportCycle(self.stack.clone(), location).into()
@@ -144,3 +319,28 @@ impl<'cx> ImportEnv<'cx> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_last() {
+        let x = Label::from("x");
+        let y = Label::from("y");
+        let env = NameEnv::new().insert(&x).insert(&y).insert(&x);
+        assert_eq!(env.depth(&x), 2);
+        assert_eq!(env.unlabel_var(&V(x.clone(), 0)), Some(AlphaVar::new(0)));
+
+        // `x` now refers to the binding below `y`.
+        let env = env.remove_last(&x);
+        assert_eq!(env.depth(&x), 1);
+        assert_eq!(env.depth(&y), 1);
+        assert_eq!(env.unlabel_var(&V(x.clone(), 0)), Some(AlphaVar::new(1)));
+        assert_eq!(env.unlabel_var(&V(x.clone(), 1)), None);
+
+        let env = env.remove_last(&x).remove_last(&x);
+        assert_eq!(env.depth(&x), 0);
+        assert_eq!(env.unlabel_var(&V(y, 0)), Some(AlphaVar::new(0)));
+    }
+}
@@ -1,8 +1,186 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
 use crate::error::TypeError;
 use crate::semantics::{type_with, typecheck, NameEnv, Nir, NzEnv, Tir, TyEnv};
-use crate::syntax::{Expr, ExprKind, Span, V};
+use crate::syntax::{Expr, ExprKind, Label, Span, V};
+use crate::utils::with_stack;
 use crate::{Ctxt, ImportAlternativeId, ImportId, ToExprOptions};
 
+/// The order in which the fields of the records of an expression were written, which
+/// normalization loses. A record is given the order of the record literal at the same path of
+/// fields from the root of the expression, if there is one with the same fields. Otherwise it is
+/// given the order of the record literals, or else record types, that have the same fields, as
+/// long as they were all written in the same order.
+#[derive(Debug, Clone, Default)]
+pub struct FieldOrders {
+    by_path: HashMap<Vec<Label>, Rc<[Label]>>,
+    /// By the sorted labels; `None` when the records were written in different orders.
+    literals: HashMap<Vec<Label>, Option<Rc<[Label]>>>,
+    types: HashMap<Vec<Label>, Option<Rc<[Label]>>>,
+}
+
+impl FieldOrders {
+    /// Collects the orders of the records of `hir`, including in imports.
+    pub fn collect<'cx>(cx: Ctxt<'cx>, hir: &Hir<'cx>) -> Self {
+        let mut orders = FieldOrders::default();
+        orders.visit(cx, hir, Some(&mut Vec::new()));
+        orders
+    }
+
+    /// `path` is the path of fields from the root to `hir`, if it is reached only through
+    /// record literals.
+    fn visit<'cx>(
+        &mut self,
+        cx: Ctxt<'cx>,
+        hir: &Hir<'cx>,
+        mut path: Option<&mut Vec<Label>>,
+    ) {
+        fn insert(
+            map: &mut HashMap<Vec<Label>, Option<Rc<[Label]>>>,
+            order: Rc<[Label]>,
+        ) {
+            let mut labels = order.to_vec();
+            labels.sort();
+            map.entry(labels)
+                .and_modify(|o| {
+                    if o.as_ref() != Some(&order) {
+                        *o = None
+                    }
+                })
+                .or_insert_with(|| Some(order));
+        }
+        let written_order = |kvs: &BTreeMap<Label, Hir<'cx>>| {
+            hir.span
+                .field_order()
+                .cloned()
+                .unwrap_or_else(|| kvs.keys().cloned().collect())
+        };
+
+        let kind = match hir.kind() {
+            HirKind::Import(import) => {
+                let hir = &cx[import].unwrap_result().hir;
+                return self.visit(cx, hir, path);
+            }
+            HirKind::ImportAlternative(alt, left, right) => {
+                let hir = if cx[alt].unwrap_selected() {
+                    left
+                } else {
+                    right
+                };
+                return self.visit(cx, hir, path);
+            }
+            HirKind::Var(_) | HirKind::MissingVar(_) => return,
+            HirKind::Expr(kind) => kind,
+        };
+        match kind {
+            ExprKind::RecordLit(kvs) => {
+                let order = written_order(kvs);
+                if let Some(path) = &path {
+                    self.by_path.insert(path.to_vec(), order.clone());
+                }
+                insert(&mut self.literals, order);
+                for (l, v) in kvs {
+                    match path.as_deref_mut() {
+                        Some(path) => {
+                            path.push(l.clone());
+                            self.visit(cx, v, Some(path));
+                            path.pop();
+                        }
+                        None => self.visit(cx, v, None),
+                    }
+                }
+            }
+            ExprKind::RecordType(kts) => {
+                insert(&mut self.types, written_order(kts));
+                for t in kts.values() {
+                    self.visit(cx, t, None);
+                }
+            }
+            // The value of these is the value of their body.
+            ExprKind::Let(_, t, v, body) => {
+                if let Some(t) = t {
+                    self.visit(cx, t, None);
+                }
+                self.visit(cx, v, None);
+                self.visit(cx, body, path);
+            }
+            ExprKind::Annot(v, t) => {
+                self.visit(cx, v, path);
+                self.visit(cx, t, None);
+            }
+            kind => {
+                kind.map_ref(|hir| self.visit(cx, hir, None));
+            }
+        }
+    }
+
+    /// The order of the fields of the record with the sorted fields `labels`, at `path` if it is
+    /// known.
+    pub fn get(
+        &self,
+        path: Option<&[Label]>,
+        labels: &[Label],
+    ) -> Option<&Rc<[Label]>> {
+        let at_path = path.and_then(|path| self.by_path.get(path));
+        if let Some(order) = at_path {
+            let mut fields = order.to_vec();
+            fields.sort();
+            if fields == labels {
+                return Some(order);
+            }
+        }
+        match self.literals.get(labels) {
+            Some(order) => order.as_ref(),
+            None => self.types.get(labels)?.as_ref(),
+        }
+    }
+
+    /// Gives the records of `hir` their order of fields. `hir` must not contain imports.
+    fn apply<'cx>(
+        &self,
+        hir: &Hir<'cx>,
+        mut path: Option<&mut Vec<Label>>,
+    ) -> Hir<'cx> {
+        let e = match hir.kind() {
+            HirKind::Expr(e) => e,
+            _ => return hir.clone(),
+        };
+        let (kind, labels): (_, Option<Vec<Label>>) = match e {
+            ExprKind::RecordLit(kvs) => {
+                let mut new_kvs = BTreeMap::new();
+                for (l, v) in kvs {
+                    let v = match path.as_deref_mut() {
+                        Some(path) => {
+                            path.push(l.clone());
+                            let v = self.apply(v, Some(path));
+                            path.pop();
+                            v
+                        }
+                        None => self.apply(v, None),
+                    };
+                    new_kvs.insert(l.clone(), v);
+                }
+                let labels = kvs.keys().cloned().collect();
+                (ExprKind::RecordLit(new_kvs), Some(labels))
+            }
+            ExprKind::RecordType(kts) => (
+                e.map_ref(|t| self.apply(t, None)),
+                Some(kts.keys().cloned().collect()),
+            ),
+            e => (e.map_ref(|e| self.apply(e, None)), None),
+        };
+        let order = labels.and_then(|labels| {
+            self.get(path.as_deref().map(Vec::as_slice), &labels)
+        });
+        let span = match order {
+            Some(order) => hir.span().with_field_order(order.to_vec()),
+            None => hir.span(),
+        };
+        Hir::new(HirKind::Expr(kind), span)
+    }
+}
+
 /// Stores an alpha-normalized variable.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct AlphaVar {
@@ -24,12 +202,22 @@ pub enum HirKind<'cx> {
 }
 
 // An expression with resolved variables and imports.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Hir<'cx> {
     kind: Box<HirKind<'cx>>,
     span: Span,
 }
 
+// Cloning recurses over the whole expression, so it makes room on the stack at each level.
+impl<'cx> Clone for Hir<'cx> {
+    fn clone(&self) -> Self {
+        with_stack(|| Hir {
+            kind: self.kind.clone(),
+            span: self.span.clone(),
+        })
+    }
+}
+
 impl AlphaVar {
     pub fn new(idx: usize) -> Self {
         AlphaVar { idx }
@@ -54,6 +242,12 @@ impl<'cx> Hir<'cx> {
         self.span.clone()
     }
 
+    /// Gives the records of `self` the order of fields that `orders` has for them. This must not
+    /// contain imports.
+    pub(crate) fn with_field_orders(&self, orders: &FieldOrders) -> Self {
+        orders.apply(self, Some(&mut Vec::new()))
+    }
+
     /// Converts a closed Hir expr back to the corresponding AST expression.
     pub fn to_expr(&self, cx: Ctxt<'cx>, opts: ToExprOptions) -> Expr {
         hir_to_expr(cx, self, opts, &mut NameEnv::new())
@@ -111,7 +305,9 @@ fn hir_to_expr<'cx>(
         HirKind::MissingVar(v) => ExprKind::Var(v.clone()),
         HirKind::Import(import) => {
             let typed = cx[import].unwrap_result();
-            return hir_to_expr(cx, &typed.hir, opts, &mut NameEnv::new());
+            // The imported expression is located where it was imported.
+            return hir_to_expr(cx, &typed.hir, opts, &mut NameEnv::new())
+                .with_span(hir.span());
         }
         HirKind::ImportAlternative(alt, left, right) => {
             let hir = if cx[alt].unwrap_selected() {
@@ -126,7 +322,7 @@ fn hir_to_expr<'cx>(
                 if let Some(l) = l {
                     env.insert_mut(l);
                 }
-                let e = hir_to_expr(cx, hir, opts, env);
+                let e = with_stack(|| hir_to_expr(cx, hir, opts, env));
                 if l.is_some() {
                     env.remove_mut();
                 }
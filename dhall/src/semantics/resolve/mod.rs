@@ -1,8 +1,14 @@
+#[cfg(feature = "zip")]
+pub mod archive;
 pub mod cache;
 pub mod env;
 pub mod hir;
 pub mod resolve;
+pub mod timed;
+#[cfg(feature = "zip")]
+pub use archive::*;
 pub use cache::*;
 pub use env::*;
 pub use hir::*;
 pub use resolve::*;
+pub use timed::*;
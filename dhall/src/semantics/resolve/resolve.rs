@@ -1,20 +1,27 @@
 use itertools::Itertools;
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use url::Url;
 
 use crate::builtins::Builtin;
 use crate::error::ErrorBuilder;
-use crate::error::{Error, ImportError};
+use crate::error::{Error, ImportError, TypeError, TypeMessage};
 use crate::operations::{BinOp, OpKind};
-use crate::semantics::{mkerr, Hir, HirKind, ImportEnv, NameEnv, Type};
+use crate::semantics::parse::ParseCache;
+use crate::semantics::{
+    mkerr, parse, FieldOrders, Hir, HirKind, ImportEnv, ImportResolver,
+    NameEnv, Type, DEFAULT_MAX_DEPTH,
+};
 use crate::syntax;
 use crate::syntax::{
     Expr, ExprKind, FilePath, FilePrefix, Hash, ImportMode, ImportTarget, Span,
     UnspannedExpr, URL,
 };
+use crate::utils::with_stack;
 use crate::{
     Ctxt, ImportAlternativeId, ImportId, ImportResultId, Parsed, Resolved,
     Typed,
@@ -23,6 +30,17 @@ use crate::{
 // TODO: evaluate import headers
 pub type Import = syntax::Import<()>;
 
+/// Information about an import that was just resolved.
+#[derive(Debug, Clone)]
+pub struct ResolvedMeta {
+    /// The size in bytes of the fetched source, if it was fetched.
+    pub size: Option<usize>,
+    /// Whether the result was taken from the in-memory or on-disk cache instead of being fetched.
+    pub from_cache: bool,
+    /// The semantic hash of the resolved expression.
+    pub hash: Hash,
+}
+
 /// The location of some data, usually some dhall code.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum ImportLocationKind {
@@ -110,29 +128,51 @@ impl ImportLocationKind {
         })
     }
 
-    fn fetch_dhall(&self) -> Result<Parsed, Error> {
-        Ok(match self {
-            ImportLocationKind::Local(path) => Parsed::parse_file(path)?,
+    /// Fetches and parses the dhall code at this location, unless its source was already
+    /// fetched. Also returns the size of the source. Local files are read with `resolver` if there
+    /// is one, and otherwise parsed with `parse_cache` if there is one.
+    fn fetch_dhall(
+        &self,
+        prefetched: Option<String>,
+        resolver: Option<&dyn ImportResolver>,
+        parse_cache: Option<&ParseCache>,
+    ) -> Result<(Parsed, usize), Error> {
+        if let ImportLocationKind::Local(path) = self {
+            let (parsed, size) = match resolver {
+                Some(resolver) => {
+                    let text = match prefetched {
+                        Some(text) => text,
+                        None => resolver.read_file(path)?,
+                    };
+                    parse::parse_file_without_cache(path, text)?
+                }
+                None => parse::parse_local_file(path, prefetched, parse_cache)?,
+            };
+            return Ok((parsed, size as usize));
+        }
+        let text = match prefetched {
+            Some(text) => text,
+            None => self.fetch_text(resolver)?,
+        };
+        let expr = syntax::parse_expr(&text)?;
+        let root = match self {
             ImportLocationKind::Remote(url) => {
-                Parsed::parse_remote(url.clone())?
+                ImportLocation::remote_dhall_code(url.clone())
             }
-            ImportLocationKind::Env(var_name) => {
-                let val = match env::var(var_name) {
-                    Ok(val) => val,
-                    Err(_) => return Err(ImportError::MissingEnvVar.into()),
-                };
-                Parsed::parse_str(&val)?
-            }
-            ImportLocationKind::Missing => {
-                return Err(ImportError::Missing.into())
-            }
-            ImportLocationKind::NoImport => unreachable!(),
-        })
+            _ => ImportLocation::dhall_code_of_unknown_origin(),
+        };
+        Ok((Parsed(expr, root), text.len()))
     }
 
-    fn fetch_text(&self) -> Result<String, Error> {
+    fn fetch_text(
+        &self,
+        resolver: Option<&dyn ImportResolver>,
+    ) -> Result<String, Error> {
         Ok(match self {
-            ImportLocationKind::Local(path) => std::fs::read_to_string(path)?,
+            ImportLocationKind::Local(path) => match resolver {
+                Some(resolver) => resolver.read_file(path)?,
+                None => std::fs::read_to_string(path)?,
+            },
             ImportLocationKind::Remote(url) => download_http_text(url.clone())?,
             ImportLocationKind::Env(var_name) => match env::var(var_name) {
                 Ok(val) => val,
@@ -199,6 +239,14 @@ impl ImportLocation {
         }
     }
 
+    /// The path of the file, if this is a local import.
+    pub fn local_path(&self) -> Option<&Path> {
+        match &self.kind {
+            ImportLocationKind::Local(path) => Some(path),
+            _ => None,
+        }
+    }
+
     /// Given an import pointing to `target` found in the current location, compute the next
     /// location, or error if not allowed.
     /// `sanity_check` indicates whether to check if that location is allowed to be referenced,
@@ -245,43 +293,76 @@ impl ImportLocation {
         })
     }
 
-    /// Fetches the expression corresponding to this location.
+    /// The location with symlinks resolved and `..` collapsed by the filesystem, if it is a local
+    /// file that exists. Imports `as Location` keep the path as written.
+    fn canonicalized(self) -> Self {
+        match &self.kind {
+            ImportLocationKind::Local(path)
+                if !matches!(self.mode, ImportMode::Location) =>
+            {
+                match std::fs::canonicalize(path) {
+                    Ok(path) => ImportLocation {
+                        kind: ImportLocationKind::Local(path),
+                        mode: self.mode,
+                    },
+                    Err(_) => self,
+                }
+            }
+            _ => self,
+        }
+    }
+
+    /// Fetches the expression corresponding to this location. Also returns the size of the
+    /// fetched source, if anything was fetched.
     fn fetch<'cx>(
         &self,
         env: &mut ImportEnv<'cx>,
         span: Span,
-    ) -> Result<Typed<'cx>, Error> {
+    ) -> Result<(Typed<'cx>, Option<usize>), Error> {
         let cx = env.cx();
-        let typed = match self.mode {
+        Ok(match self.mode {
             ImportMode::Code => {
-                let parsed = self.kind.fetch_dhall()?;
+                let prefetched = env.take_prefetched(self);
+                let (parsed, size) = self.kind.fetch_dhall(
+                    prefetched,
+                    env.resolver(),
+                    env.parse_cache(),
+                )?;
                 let typed = parsed.resolve_with_env(env)?.typecheck(cx)?;
-                Typed {
-                    // TODO: manage to keep the Nir around. Will need fixing variables.
-                    hir: typed.normalize(cx).to_hir(),
-                    ty: typed.ty,
+                // TODO: manage to keep the Nir around. Will need fixing variables.
+                let mut hir = typed.normalize(cx).to_hir();
+                if env.keep_field_order() {
+                    let orders = FieldOrders::collect(cx, typed.as_hir());
+                    hir = hir.with_field_orders(&orders);
                 }
+                let typed = Typed { hir, ty: typed.ty };
+                (typed, Some(size))
             }
             ImportMode::RawText => {
-                let text = self.kind.fetch_text()?;
-                Typed {
+                let text = match env.take_prefetched(self) {
+                    Some(text) => text,
+                    None => self.kind.fetch_text(env.resolver())?,
+                };
+                let size = text.len();
+                let typed = Typed {
                     hir: Hir::new(
                         HirKind::Expr(ExprKind::TextLit(text.into())),
                         span,
                     ),
                     ty: Type::from_builtin(cx, Builtin::Text),
-                }
+                };
+                (typed, Some(size))
             }
             ImportMode::Location => {
                 let expr = self.kind.to_location();
-                Parsed::from_expr_without_imports(expr)
+                let typed = Parsed::from_expr_without_imports(expr)
                     .resolve(cx)
                     .unwrap()
                     .typecheck(cx)
-                    .unwrap()
+                    .unwrap();
+                (typed, None)
             }
-        };
-        Ok(typed)
+        })
     }
 }
 
@@ -369,6 +450,24 @@ fn desugar(expr: &Expr) -> Cow<'_, Expr> {
     }
 }
 
+impl std::fmt::Display for ImportLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.kind {
+            ImportLocationKind::Local(path) => write!(f, "{}", path.display())?,
+            ImportLocationKind::Remote(url) => write!(f, "{}", url)?,
+            ImportLocationKind::Env(name) => write!(f, "env:{}", name)?,
+            ImportLocationKind::Missing | ImportLocationKind::NoImport => {
+                write!(f, "missing")?
+            }
+        }
+        match self.mode {
+            ImportMode::Code => Ok(()),
+            ImportMode::RawText => write!(f, " as Text"),
+            ImportMode::Location => write!(f, " as Location"),
+        }
+    }
+}
+
 /// Fetch the import and store the result in the global context.
 fn fetch_import<'cx>(
     env: &mut ImportEnv<'cx>,
@@ -377,7 +476,10 @@ fn fetch_import<'cx>(
     let cx = env.cx();
     let import = &cx[import_id].import;
     let span = cx[import_id].span.clone();
-    let location = cx[import_id].base_location.chain(import)?;
+    let mut location = cx[import_id].base_location.chain(import)?;
+    if env.canonicalize_imports() {
+        location = location.canonicalized();
+    }
 
     // If the hash is in the on-disk cache, return
     // the cached contents.
@@ -388,12 +490,16 @@ fn fetch_import<'cx>(
         // This actually means that importing many times a same hashed import will take
         // longer than importing many times a same non-hashed import.
         let res_id = cx.push_import_result(typed);
+        notify_resolved(env, &location, res_id, None, true)?;
         return Ok(res_id);
     }
 
     // If the import is in the in-memory cache return the cached contents. Otherwise fetch the
     // import.
+    let mut size = None;
+    let mut from_cache = false;
     let res_id = if let Some(res_id) = env.get_from_mem_cache(&location) {
+        from_cache = true;
         res_id
     } else {
         // Resolve this import, making sure that recursive imports don't cycle back to the
@@ -402,7 +508,10 @@ fn fetch_import<'cx>(
             location.fetch(env, span.clone())
         });
         let typed = match res {
-            Ok(typed) => typed,
+            Ok((typed, fetched_size)) => {
+                size = fetched_size;
+                typed
+            }
             Err(e) => mkerr(
                 ErrorBuilder::new("error")
                     .span_err(span.clone(), e.to_string())
@@ -412,17 +521,39 @@ fn fetch_import<'cx>(
 
         let res_id = cx.push_import_result(typed);
         // Cache the mapping from this location to the result.
-        env.write_to_mem_cache(location, res_id);
+        env.write_to_mem_cache(location.clone(), res_id);
         res_id
     };
 
     // Add the resolved import to the on-disk cache if the hash matches.
     env.check_hash(import_id, res_id)?;
     env.write_to_disk_cache(&import.hash, res_id);
+    notify_resolved(env, &location, res_id, size, from_cache)?;
 
     Ok(res_id)
 }
 
+/// Call the import callback, if any.
+fn notify_resolved<'cx>(
+    env: &ImportEnv<'cx>,
+    location: &ImportLocation,
+    res_id: ImportResultId<'cx>,
+    size: Option<usize>,
+    from_cache: bool,
+) -> Result<(), Error> {
+    if env.has_callback() {
+        let cx = env.cx();
+        let hash = cx[res_id].hir.to_expr_alpha(cx).sha256_hash()?;
+        let meta = ResolvedMeta {
+            size,
+            from_cache,
+            hash: Hash::SHA256(hash),
+        };
+        env.call_callback(location, &meta);
+    }
+    Ok(())
+}
+
 /// Part of a tree of imports.
 #[derive(Debug, Clone, Copy)]
 pub enum ImportNode<'cx> {
@@ -432,13 +563,23 @@ pub enum ImportNode<'cx> {
 
 /// Traverse the expression and replace each import and import alternative by an id into the global
 /// context. The ids are also accumulated into `nodes` so that we can resolve them afterwards.
+/// `depth` is how deeply nested `expr` is: past `DEFAULT_MAX_DEPTH`, this fails with the same
+/// error as the typechecker would give.
 fn traverse_accumulate<'cx>(
     env: &mut ImportEnv<'cx>,
     name_env: &mut NameEnv,
     nodes: &mut Vec<ImportNode<'cx>>,
     base_location: &ImportLocation,
     expr: &Expr,
-) -> Hir<'cx> {
+    depth: usize,
+) -> Result<Hir<'cx>, Error> {
+    if depth >= DEFAULT_MAX_DEPTH {
+        return Err(TypeError::new(TypeMessage::ExpressionTooDeep {
+            depth: DEFAULT_MAX_DEPTH,
+        })
+        .into());
+    }
+    let depth = depth + 1;
     let cx = env.cx();
     let expr = desugar(expr);
     let kind = match expr.kind() {
@@ -448,38 +589,52 @@ fn traverse_accumulate<'cx>(
         },
         ExprKind::Op(OpKind::BinOp(BinOp::ImportAlt, l, r)) => {
             let mut imports_l = Vec::new();
-            let l = traverse_accumulate(
-                env,
-                name_env,
-                &mut imports_l,
-                base_location,
-                l,
-            );
+            let l = with_stack(|| {
+                traverse_accumulate(
+                    env,
+                    name_env,
+                    &mut imports_l,
+                    base_location,
+                    l,
+                    depth,
+                )
+            })?;
             let mut imports_r = Vec::new();
-            let r = traverse_accumulate(
-                env,
-                name_env,
-                &mut imports_r,
-                base_location,
-                r,
-            );
+            let r = with_stack(|| {
+                traverse_accumulate(
+                    env,
+                    name_env,
+                    &mut imports_r,
+                    base_location,
+                    r,
+                    depth,
+                )
+            })?;
             let alt =
                 cx.push_import_alternative(imports_l.into(), imports_r.into());
             nodes.push(ImportNode::Alternative(alt));
             HirKind::ImportAlternative(alt, l, r)
         }
         kind => {
-            let kind = kind.map_ref_maybe_binder(|l, e| {
+            let kind = kind.traverse_ref_maybe_binder(|l, e| {
                 if let Some(l) = l {
                     name_env.insert_mut(l);
                 }
-                let hir =
-                    traverse_accumulate(env, name_env, nodes, base_location, e);
+                let hir = with_stack(|| {
+                    traverse_accumulate(
+                        env,
+                        name_env,
+                        nodes,
+                        base_location,
+                        e,
+                        depth,
+                    )
+                });
                 if l.is_some() {
                     name_env.remove_mut();
                 }
                 hir
-            });
+            })?;
             match kind {
                 ExprKind::Import(import) => {
                     // TODO: evaluate import headers
@@ -496,7 +651,7 @@ fn traverse_accumulate<'cx>(
             }
         }
     };
-    Hir::new(kind, expr.span())
+    Ok(Hir::new(kind, expr.span()))
 }
 
 /// Take a list of nodes and recursively resolve them.
@@ -515,6 +670,7 @@ fn resolve_nodes<'cx>(
                 if resolve_nodes(env, &alt.left_imports).is_ok() {
                     alt.set_selected(true);
                 } else {
+                    env.set_used_fallback();
                     resolve_nodes(env, &alt.right_imports)?;
                     alt.set_selected(false);
                 }
@@ -524,22 +680,103 @@ fn resolve_nodes<'cx>(
     Ok(())
 }
 
+/// Fetches the sources of the given imports in parallel, so that `resolve_nodes` doesn't have to
+/// wait for each of them in turn. Local files are read with the resolver if there is one. Only the
+/// imports that are sure to be needed are fetched, i.e. not the ones in import alternatives. Local
+/// code imports are left to the parse cache if there is one, since it reads the files itself.
+/// Failures are ignored here: they will happen again when the import gets resolved, which reports
+/// them properly. Returns the locations that were fetched.
+#[cfg(feature = "parallel-imports")]
+fn prefetch<'cx>(
+    env: &mut ImportEnv<'cx>,
+    nodes: &[ImportNode<'cx>],
+) -> Vec<ImportLocation> {
+    use rayon::prelude::*;
+
+    let cx = env.cx();
+    let uses_parse_cache =
+        env.resolver().is_none() && env.parse_cache().is_some();
+    let locations: Vec<ImportLocation> = nodes
+        .iter()
+        .filter_map(|node| match node {
+            ImportNode::Import(import_id) => Some(*import_id),
+            ImportNode::Alternative(_) => None,
+        })
+        // Hashed imports may be in the disk cache.
+        .filter(|&import_id| cx[import_id].import.hash.is_none())
+        .filter_map(|import_id| {
+            cx[import_id]
+                .base_location
+                .chain(&cx[import_id].import)
+                .ok()
+        })
+        .map(|location| {
+            if env.canonicalize_imports() {
+                location.canonicalized()
+            } else {
+                location
+            }
+        })
+        .filter(|location| {
+            matches!(location.mode, ImportMode::Code | ImportMode::RawText)
+                && match location.kind {
+                    ImportLocationKind::Local(_) => {
+                        !(uses_parse_cache && location.mode == ImportMode::Code)
+                    }
+                    ImportLocationKind::Remote(_) => true,
+                    _ => false,
+                }
+                && env.get_from_mem_cache(location).is_none()
+        })
+        .unique()
+        .collect();
+    if locations.len() < 2 {
+        return Vec::new();
+    }
+    let resolver = env.resolver();
+    let sources: Vec<_> = locations
+        .into_par_iter()
+        .filter_map(|location| {
+            let text = location.kind.fetch_text(resolver).ok()?;
+            Some((location, text))
+        })
+        .collect();
+    let fetched = sources
+        .iter()
+        .map(|(location, _)| location.clone())
+        .collect();
+    env.add_prefetched(sources);
+    fetched
+}
+
 fn resolve_with_env<'cx>(
     env: &mut ImportEnv<'cx>,
     parsed: Parsed,
+    names: &NameEnv,
 ) -> Result<Resolved<'cx>, Error> {
     let Parsed(expr, base_location) = parsed;
     let mut nodes = Vec::new();
     // First we collect all imports.
     let resolved = traverse_accumulate(
         env,
-        &mut NameEnv::new(),
+        &mut names.clone(),
         &mut nodes,
         &base_location,
         &expr,
-    );
+        0,
+    )?;
     // Then we resolve them and choose sides for the alternatives.
-    resolve_nodes(env, &nodes)?;
+    #[cfg(feature = "parallel-imports")]
+    let prefetched = if env.parallel_imports() {
+        prefetch(env, &nodes)
+    } else {
+        Vec::new()
+    };
+    let result = resolve_nodes(env, &nodes);
+    // Sources that weren't needed after all, e.g. because an error stopped the resolution.
+    #[cfg(feature = "parallel-imports")]
+    env.forget_prefetched(&prefetched);
+    result?;
     Ok(Resolved(resolved))
 }
 
@@ -552,6 +789,84 @@ pub fn resolve<'cx>(
     parsed.resolve_with_env(&mut ImportEnv::new(cx))
 }
 
+/// Like `resolve`, but the variables of `names` are in scope, e.g. to typecheck the expression in
+/// a `TyEnv` that binds them.
+pub fn resolve_with_names<'cx>(
+    cx: Ctxt<'cx>,
+    parsed: Parsed,
+    names: &NameEnv,
+) -> Result<Resolved<'cx>, Error> {
+    resolve_with_env(&mut ImportEnv::new(cx), parsed, names)
+}
+
+/// Like `resolve`, but calls `on_import_resolved` each time an import gets resolved.
+pub fn resolve_with_callback<'cx>(
+    cx: Ctxt<'cx>,
+    parsed: Parsed,
+    on_import_resolved: impl Fn(&ImportLocation, &ResolvedMeta) + 'static,
+) -> Result<Resolved<'cx>, Error> {
+    let mut env = ImportEnv::new(cx).with_callback(on_import_resolved);
+    parsed.resolve_with_env(&mut env)
+}
+
+/// Resolves the imports of `parsed`, and returns its expression with the semantic hash of the
+/// result added to each import of Dhall code that doesn't have one yet. This is what `dhall
+/// freeze` does. Only remote imports are frozen, unless `all` is set. Imports of `missing` and
+/// imports that were not needed, like the unused side of a `?`, are left alone. Only the imports
+/// written in `parsed` are changed, not the ones in the files it imports.
+pub fn freeze<'cx>(
+    cx: Ctxt<'cx>,
+    parsed: Parsed,
+    all: bool,
+) -> Result<Expr, Error> {
+    let hashes: Rc<RefCell<HashMap<ImportLocation, Hash>>> = Default::default();
+    let Parsed(expr, base_location) = parsed;
+    {
+        let hashes = hashes.clone();
+        resolve_with_callback(
+            cx,
+            Parsed(expr.clone(), base_location.clone()),
+            move |location, meta| {
+                hashes
+                    .borrow_mut()
+                    .insert(location.clone(), meta.hash.clone());
+            },
+        )?;
+    }
+    let hashes = hashes.borrow();
+    freeze_imports(&expr, &|import| {
+        let should_freeze = match import.location {
+            ImportTarget::Remote(_) => true,
+            ImportTarget::Local(..) | ImportTarget::Env(_) => all,
+            ImportTarget::Missing => false,
+        };
+        if !should_freeze
+            || import.hash.is_some()
+            || !matches!(import.mode, ImportMode::Code)
+        {
+            return Ok(None);
+        }
+        let location = base_location.chain(import)?;
+        Ok(hashes.get(&location).cloned())
+    })
+}
+
+fn freeze_imports(
+    expr: &Expr,
+    hash_for: &impl Fn(&Import) -> Result<Option<Hash>, Error>,
+) -> Result<Expr, Error> {
+    let kind = match expr.kind() {
+        ExprKind::Import(import) => {
+            let mut import = import.clone();
+            import.hash =
+                hash_for(&import.traverse_ref(|_| Ok::<_, Error>(()))?)?;
+            ExprKind::Import(import)
+        }
+        kind => kind.traverse_ref(|e| freeze_imports(e, hash_for))?,
+    };
+    Ok(Expr::new(kind, expr.span()))
+}
+
 /// Resolves names, and errors if we find any imports.
 pub fn skip_resolve<'cx>(
     cx: Ctxt<'cx>,
@@ -562,11 +877,12 @@ pub fn skip_resolve<'cx>(
 }
 
 impl Parsed {
-    fn resolve_with_env<'cx>(
+    /// Like `resolve`, with an `ImportEnv` that can be configured, e.g. with a callback.
+    pub fn resolve_with_env<'cx>(
         self,
         env: &mut ImportEnv<'cx>,
     ) -> Result<Resolved<'cx>, Error> {
-        resolve_with_env(env, self)
+        resolve_with_env(env, self, &NameEnv::new())
     }
 }
 
@@ -632,3 +948,121 @@ impl<SE: Copy> Canonicalize for ImportTarget<SE> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Ctxt, Parsed};
+
+    /// The normal form and the type of `s`, or the error message.
+    fn eval(s: &str) -> Result<(String, String), String> {
+        Ctxt::with_new(|cx| {
+            let parsed = Parsed::parse_str(s).map_err(|e| e.to_string())?;
+            let resolved = parsed.resolve(cx).map_err(|e| e.to_string())?;
+            let typed = resolved.typecheck(cx).map_err(|e| e.to_string())?;
+            Ok((
+                typed.normalize(cx).to_expr(cx).to_string(),
+                typed.get_type().unwrap().to_expr(cx).to_string(),
+            ))
+        })
+    }
+
+    #[test]
+    fn missing() {
+        let ok = |v: &str, t: &str| Ok((v.to_owned(), t.to_owned()));
+        assert_eq!(eval("1 ? missing"), ok("1", "Natural"));
+        assert_eq!(eval("missing ? 1"), ok("1", "Natural"));
+        assert_eq!(
+            eval("missing ? missing ? { x = True }"),
+            ok("{ x = True }", "{ x : Bool }")
+        );
+        // Import errors are reported at the location of the import.
+        assert!(eval("missing").unwrap_err().contains("^^^^^^^ Missing"));
+    }
+
+    #[test]
+    fn shared_parse_cache() {
+        use crate::semantics::parse::ParseCache;
+        use crate::semantics::ImportEnv;
+        use std::rc::Rc;
+
+        let dir = std::env::temp_dir()
+            .join(format!("dhall_shared_parse_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.dhall"), "1").unwrap();
+        std::fs::write(dir.join("b.dhall"), "2").unwrap();
+        std::fs::write(dir.join("main.dhall"), "./a.dhall + ./b.dhall")
+            .unwrap();
+
+        let cache = Rc::new(ParseCache::new(2));
+        let eval = || {
+            Ctxt::with_new(|cx| {
+                let mut env =
+                    ImportEnv::new(cx).with_parse_cache(cache.clone());
+                Parsed::parse_file(&dir.join("main.dhall"))
+                    .unwrap()
+                    .resolve_with_env(&mut env)
+                    .unwrap()
+                    .typecheck(cx)
+                    .unwrap()
+                    .normalize(cx)
+                    .to_expr(cx)
+                    .to_string()
+            })
+        };
+        assert_eq!(eval(), "3");
+        // Only the imports are cached, up to the capacity.
+        assert_eq!(cache.len(), 2);
+        // Another resolution sees the changes to the files.
+        std::fs::write(dir.join("b.dhall"), "20").unwrap();
+        assert_eq!(eval(), "21");
+        assert_eq!(cache.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Local files are left to the parse cache, which reads them only when they changed.
+    #[cfg(feature = "parallel-imports")]
+    #[test]
+    fn prefetch_skips_files_of_the_parse_cache() {
+        use super::{prefetch, traverse_accumulate};
+        use crate::semantics::parse::ParseCache;
+        use crate::semantics::{ImportEnv, NameEnv};
+        use std::rc::Rc;
+
+        let dir = std::env::temp_dir()
+            .join(format!("dhall_prefetch_parse_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.dhall"), "1").unwrap();
+        std::fs::write(dir.join("b.dhall"), "2").unwrap();
+        std::fs::write(
+            dir.join("main.dhall"),
+            "[./a.dhall, ./b.dhall, ./a.dhall as Text, ./b.dhall as Text]",
+        )
+        .unwrap();
+
+        let prefetched = |env: &mut ImportEnv<'_>| {
+            let Parsed(expr, base_location) =
+                Parsed::parse_file(&dir.join("main.dhall")).unwrap();
+            let mut nodes = Vec::new();
+            traverse_accumulate(
+                env,
+                &mut NameEnv::new(),
+                &mut nodes,
+                &base_location,
+                &expr,
+                0,
+            )
+            .unwrap();
+            prefetch(env, &nodes).len()
+        };
+        Ctxt::with_new(|cx| {
+            assert_eq!(prefetched(&mut ImportEnv::new(cx)), 4);
+            let cache = Rc::new(ParseCache::new(2));
+            let mut env = ImportEnv::new(cx).with_parse_cache(cache);
+            // Only the raw text imports are read ahead.
+            assert_eq!(prefetched(&mut env), 2);
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{Error, ImportError};
+use crate::semantics::ImportResolver;
+
+/// Reads local imports from the filesystem, failing with `ImportError::Timeout` when a file takes
+/// longer than a given time to read, e.g. on a network filesystem that stopped responding.
+///
+/// Each file is read in a new thread. A blocking read can't be interrupted, so when the timeout
+/// fires the thread is left running in the background: it will eventually finish the read, and its
+/// result is dropped.
+#[derive(Debug, Clone, Copy)]
+pub struct TimedImportResolver {
+    timeout: Duration,
+}
+
+impl TimedImportResolver {
+    pub fn new(timeout: Duration) -> Self {
+        TimedImportResolver { timeout }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl ImportResolver for TimedImportResolver {
+    fn read_file(&self, path: &Path) -> Result<String, Error> {
+        let (sender, receiver) = mpsc::channel();
+        let owned_path = path.to_owned();
+        thread::spawn(move || {
+            // The receiver is gone if the read timed out.
+            let _ = sender.send(std::fs::read_to_string(owned_path));
+        });
+        match receiver.recv_timeout(self.timeout) {
+            Ok(text) => Ok(text?),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(ImportError::Timeout(path.to_owned(), self.timeout).into())
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to read `{}`", path.display()),
+            )
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantics::ImportEnv;
+    use crate::{Ctxt, Parsed};
+
+    fn eval(resolver: TimedImportResolver, s: &str) -> Result<String, Error> {
+        Ctxt::with_new(|cx| {
+            let mut env = ImportEnv::new(cx).with_resolver(resolver);
+            let resolved = Parsed::parse_str(s)?.resolve_with_env(&mut env)?;
+            let typed = resolved.typecheck(cx)?;
+            Ok(typed.normalize(cx).to_expr(cx).to_string())
+        })
+    }
+
+    #[test]
+    fn timed_imports() {
+        let dir = std::env::temp_dir()
+            .join(format!("dhall-timed-imports-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.dhall"), "./b.dhall + 1").unwrap();
+        std::fs::write(dir.join("b.dhall"), "41").unwrap();
+        let a = dir.join("a.dhall");
+        let resolver = TimedImportResolver::new(Duration::from_secs(10));
+
+        assert_eq!(eval(resolver, &a.to_string_lossy()).unwrap(), "42");
+        assert!(eval(resolver, &dir.join("c.dhall").to_string_lossy())
+            .unwrap_err()
+            .to_string()
+            .contains("No such file"));
+
+        // Reading a named pipe blocks until something writes to it.
+        #[cfg(unix)]
+        {
+            let fifo = dir.join("fifo.dhall");
+            let status = std::process::Command::new("mkfifo")
+                .arg(&fifo)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            let resolver = TimedImportResolver::new(Duration::from_millis(50));
+            assert!(eval(resolver, &fifo.to_string_lossy())
+                .unwrap_err()
+                .to_string()
+                .contains("Timeout("));
+            // Unblock the reading thread.
+            drop(std::fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::TypeError;
+use crate::operations::OpKind;
+use crate::semantics::{type_with, Hir, HirKind, TyEnv};
+use crate::syntax::{ExprKind, Span};
+
+/// Replaces each `let x : T = e in b` of `hir` by `(λ(x : T) → b) e`. A `let` without annotation
+/// is given the type inferred for its value. The imported expressions are inlined and desugared
+/// too.
+///
+/// The result normalizes to the same value as `hir`, but doesn't always typecheck: in a `let`,
+/// the body knows the value of the variable, and a lambda doesn't. For example
+/// `let T = Natural in 1 : T` becomes `(λ(T : Type) → 1 : T) Natural`.
+pub fn desugar_let<'cx>(
+    env: &TyEnv<'cx>,
+    hir: &Hir<'cx>,
+) -> Result<Hir<'cx>, TypeError> {
+    let kind = match hir.kind() {
+        HirKind::Import(import) => {
+            let typed = env.cx()[import].unwrap_result();
+            // The imported expression is located where it was imported.
+            desugar_let(&TyEnv::new(env.cx()), &typed.hir)?
+                .kind()
+                .clone()
+        }
+        HirKind::ImportAlternative(alt, left, right) => {
+            let hir = if env.cx()[alt].unwrap_selected() {
+                left
+            } else {
+                right
+            };
+            return desugar_let(env, hir);
+        }
+        HirKind::Expr(ExprKind::Lam(binder, annot, body)) => {
+            let annot_nf = type_with(env, annot, None)?.eval_to_type(env)?;
+            let body_env = env.insert_type(binder, annot_nf);
+            HirKind::Expr(ExprKind::Lam(
+                binder.clone(),
+                desugar_let(env, annot)?,
+                desugar_let(&body_env, body)?,
+            ))
+        }
+        HirKind::Expr(ExprKind::Pi(binder, annot, body)) => {
+            let annot_nf = type_with(env, annot, None)?.eval_to_type(env)?;
+            let body_env = env.insert_type(binder, annot_nf);
+            HirKind::Expr(ExprKind::Pi(
+                binder.clone(),
+                desugar_let(env, annot)?,
+                desugar_let(&body_env, body)?,
+            ))
+        }
+        HirKind::Expr(ExprKind::Let(binder, annot, val, body)) => {
+            let val_annot = annot
+                .as_ref()
+                .map(|t| type_with(env, t, None)?.eval_to_type(env))
+                .transpose()?;
+            let val_tir = type_with(env, val, val_annot)?;
+            let annot = match annot {
+                Some(t) => desugar_let(env, t)?,
+                None => val_tir.ty().to_hir(env.as_varenv()),
+            };
+            let body_env = env.insert_value(
+                binder,
+                val_tir.eval(env),
+                val_tir.ty().clone(),
+            );
+            let lam = Hir::new(
+                HirKind::Expr(ExprKind::Lam(
+                    binder.clone(),
+                    annot,
+                    desugar_let(&body_env, body)?,
+                )),
+                Span::Artificial,
+            );
+            HirKind::Expr(ExprKind::Op(OpKind::App(
+                lam,
+                desugar_let(env, val)?,
+            )))
+        }
+        HirKind::Expr(e) => {
+            HirKind::Expr(e.traverse_ref(|hir| desugar_let(env, hir))?)
+        }
+        kind => kind.clone(),
+    };
+    Ok(Hir::new(kind, hir.span()))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::operations::{BinOp, OpKind};
+    use crate::syntax::{Expr, ExprKind, NumKind, Span, V};
+    use crate::{Ctxt, Parsed};
+
+    fn desugar(s: &str) -> String {
+        Ctxt::with_new(|cx| {
+            Parsed::parse_str(s)
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap()
+                .desugar_let(cx)
+                .unwrap()
+                .to_string()
+        })
+    }
+
+    #[test]
+    fn desugar_let() {
+        assert_eq!(desugar("let x = 1 in x + x"), "(λ(x : Natural) → x + x) 1");
+        assert_eq!(
+            desugar("let x : Natural = 1 in let y = [x] in y # y"),
+            "(λ(x : Natural) → (λ(y : List Natural) → y # y) [x]) 1"
+        );
+        // The inferred type knows the values of the outer variables.
+        assert_eq!(
+            desugar("let T = Natural in let f = λ(x : T) → x in f 1"),
+            "(λ(T : Type) → (λ(f : ∀(x : Natural) → Natural) → f 1) (λ(x : T) → x)) Natural"
+        );
+        assert_eq!(
+            desugar("λ(x : Natural) → let y = x in { y }"),
+            "λ(x : Natural) → (λ(y : Natural) → { y = y }) x"
+        );
+    }
+
+    fn expr(kind: ExprKind<Expr>) -> Expr {
+        Expr::new(kind, Span::Artificial)
+    }
+
+    /// Expressions of type `Natural` with variables called `x`, `y` and `z`, that are bound
+    /// outside of the expression.
+    fn arb_expr() -> impl Strategy<Value = Expr> {
+        let label = || prop_oneof![Just("x"), Just("y"), Just("z")];
+        let natural =
+            || expr(ExprKind::Builtin(crate::builtins::Builtin::Natural));
+        let leaf = prop_oneof![
+            label().prop_map(|x| expr(ExprKind::Var(V(x.into(), 0)))),
+            (0..3u64).prop_map(|n| expr(ExprKind::Num(NumKind::Natural(n)))),
+        ];
+        leaf.prop_recursive(5, 48, 3, move |inner| {
+            prop_oneof![
+                (label(), any::<bool>(), inner.clone(), inner.clone())
+                    .prop_map(move |(x, annot, v, e)| {
+                        let annot = if annot { Some(natural()) } else { None };
+                        expr(ExprKind::Let(x.into(), annot, v, e))
+                    }),
+                (label(), inner.clone(), inner.clone()).prop_map(
+                    move |(x, e, a)| {
+                        let f = expr(ExprKind::Lam(x.into(), natural(), e));
+                        expr(ExprKind::Op(OpKind::App(f, a)))
+                    }
+                ),
+                (inner.clone(), inner).prop_map(|(a, b)| expr(ExprKind::Op(
+                    OpKind::BinOp(BinOp::NaturalPlus, a, b)
+                ))),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn desugar_preserves_normal_form(e in arb_expr()) {
+            let e = ["z", "y", "x"].iter().fold(e, |e, x| {
+                expr(ExprKind::Let(
+                    (*x).into(),
+                    None,
+                    expr(ExprKind::Num(NumKind::Natural(1))),
+                    e,
+                ))
+            });
+            Ctxt::with_new(|cx| {
+                let resolved = Parsed::from_expr_without_imports(e.clone())
+                    .skip_resolve(cx)
+                    .unwrap();
+                let desugared = resolved.desugar_let(cx).unwrap();
+                prop_assert!(!desugared.to_string().contains("let"));
+                let normalize = |e: Expr| {
+                    Parsed::from_expr_without_imports(e)
+                        .skip_resolve(cx)
+                        .unwrap()
+                        .typecheck(cx)
+                        .unwrap()
+                        .normalize(cx)
+                };
+                prop_assert!(normalize(e) == normalize(desugared));
+                Ok(())
+            })?;
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::builtins::Builtin;
 use crate::semantics::{AlphaVar, NameEnv, Nir, NzEnv, NzVar, Type, ValEnv};
 use crate::syntax::Label;
 use crate::Ctxt;
@@ -14,6 +15,37 @@ pub struct TyEnv<'cx> {
     cx: Ctxt<'cx>,
     names: NameEnv,
     items: ValEnv<'cx, Type<'cx>>,
+    max_depth: usize,
+}
+
+/// The types of literals, built once per `Ctxt` instead of once per literal.
+#[derive(Debug)]
+pub(crate) struct LiteralTypes<'cx> {
+    bool: Type<'cx>,
+    natural: Type<'cx>,
+    integer: Type<'cx>,
+    double: Type<'cx>,
+    text: Type<'cx>,
+}
+
+/// The default maximum nesting depth of expressions accepted by the parser, the import resolver
+/// and the typechecker. They all recurse, and grow the stack as needed to go this deep.
+pub const DEFAULT_MAX_DEPTH: usize = 10000;
+/// The maximum nesting depth of evaluations in `normalize_bounded`. Evaluating an expression
+/// nests a few times deeper than the expression, e.g. three levels for each `let` that uses the
+/// previous one, with smaller stack frames.
+pub const MAX_EVAL_DEPTH: usize = 4 * DEFAULT_MAX_DEPTH;
+
+impl<'cx> LiteralTypes<'cx> {
+    pub(crate) fn new(cx: Ctxt<'cx>) -> Self {
+        LiteralTypes {
+            bool: Type::from_builtin(cx, Builtin::Bool),
+            natural: Type::from_builtin(cx, Builtin::Natural),
+            integer: Type::from_builtin(cx, Builtin::Integer),
+            double: Type::from_builtin(cx, Builtin::Double),
+            text: Type::from_builtin(cx, Builtin::Text),
+        }
+    }
 }
 
 impl VarEnv {
@@ -46,8 +78,16 @@ impl<'cx> TyEnv<'cx> {
             cx,
             names: NameEnv::new(),
             items: ValEnv::new(cx),
+            max_depth: DEFAULT_MAX_DEPTH,
         }
     }
+    /// Sets the maximum nesting depth of expressions; typechecking deeper expressions fails.
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        TyEnv { max_depth, ..self }
+    }
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
     pub fn cx(&self) -> Ctxt<'cx> {
         self.cx
     }
@@ -66,6 +106,7 @@ impl<'cx> TyEnv<'cx> {
             cx: self.cx,
             names: self.names.insert(x),
             items: self.items.insert_type(ty),
+            max_depth: self.max_depth,
         }
     }
     pub fn insert_value(&self, x: &Label, e: Nir<'cx>, ty: Type<'cx>) -> Self {
@@ -73,11 +114,25 @@ impl<'cx> TyEnv<'cx> {
             cx: self.cx,
             names: self.names.insert(x),
             items: self.items.insert_value(e, ty),
+            max_depth: self.max_depth,
         }
     }
     pub fn lookup(&self, var: AlphaVar) -> Type<'cx> {
         self.items.lookup_ty(var)
     }
+    /// The type of the literals of type `b`, which must be one of `Bool`, `Natural`, `Integer`,
+    /// `Double` or `Text`.
+    pub fn literal_type(&self, b: Builtin) -> Type<'cx> {
+        let types = self.cx.literal_types();
+        match b {
+            Builtin::Bool => types.bool.clone(),
+            Builtin::Natural => types.natural.clone(),
+            Builtin::Integer => types.integer.clone(),
+            Builtin::Double => types.double.clone(),
+            Builtin::Text => types.text.clone(),
+            _ => unreachable!("this builtin is not a type of literals: {}", b),
+        }
+    }
 }
 
 impl<'a, 'cx> From<&'a TyEnv<'cx>> for NzEnv<'cx> {
@@ -1,6 +1,8 @@
+pub mod desugar;
 pub mod env;
 pub mod tir;
 pub mod typecheck;
+pub use desugar::*;
 pub use env::*;
 pub use tir::*;
 pub use typecheck::*;
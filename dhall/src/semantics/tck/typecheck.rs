@@ -5,8 +5,11 @@ use crate::error::{ErrorBuilder, TypeError, TypeMessage};
 use crate::operations::typecheck_operation;
 use crate::semantics::{Hir, HirKind, Nir, NirKind, Tir, TyEnv, Type};
 use crate::syntax::{Const, ExprKind, InterpolatedTextContents, NumKind, Span};
+use crate::utils::with_stack;
 use crate::Ctxt;
 
+/// The universe of a function type whose input lives in `a` and output in `b`. Any combination is
+/// allowed, including types that depend on terms like `∀(x : Natural) → Type`.
 fn function_check(a: Const, b: Const) -> Const {
     if b == Const::Type {
         Const::Type
@@ -41,6 +44,7 @@ fn type_one_layer<'cx>(
         ExprKind::Import(..) => {
             unreachable!("This case should have been handled in resolution")
         }
+        ExprKind::Error(..) => return span_err("SyntaxError"),
         ExprKind::Var(..)
         | ExprKind::Const(Const::Sort)
         | ExprKind::Lam(..)
@@ -52,21 +56,19 @@ fn type_one_layer<'cx>(
 
         ExprKind::Const(Const::Type) => Type::from_const(Const::Kind),
         ExprKind::Const(Const::Kind) => Type::from_const(Const::Sort),
-        ExprKind::Num(num) => Type::from_builtin(
-            cx,
-            match num {
-                NumKind::Bool(_) => Builtin::Bool,
-                NumKind::Natural(_) => Builtin::Natural,
-                NumKind::Integer(_) => Builtin::Integer,
-                NumKind::Double(_) => Builtin::Double,
-            },
-        ),
-        ExprKind::Builtin(b) => {
+        ExprKind::Num(num) => env.literal_type(match num {
+            NumKind::Bool(_) => Builtin::Bool,
+            NumKind::Natural(_) => Builtin::Natural,
+            NumKind::Integer(_) => Builtin::Integer,
+            NumKind::Double(_) => Builtin::Double,
+        }),
+        // The types of builtins are closed, so they don't depend on `env`.
+        ExprKind::Builtin(b) => cx.builtin_type(b, || {
             let t_hir = type_of_builtin(cx, b);
-            typecheck(cx, &t_hir)?.eval_to_type(env)?
-        }
+            typecheck(cx, &t_hir)?.eval_to_type(env)
+        })?,
         ExprKind::TextLit(interpolated) => {
-            let text_type = Type::from_builtin(cx, Builtin::Text);
+            let text_type = env.literal_type(Builtin::Text);
             for contents in interpolated.iter() {
                 use InterpolatedTextContents::Expr;
                 if let Expr(x) = contents {
@@ -181,6 +183,32 @@ pub fn type_with<'cx, 'hir>(
     env: &TyEnv<'cx>,
     hir: &'hir Hir<'cx>,
     annot: Option<Type<'cx>>,
+) -> Result<Tir<'cx, 'hir>, TypeError> {
+    type_with_depth(env, hir, annot, 0)
+}
+
+/// Like `type_with`, but keeps track of how deeply nested `hir` is, to fail cleanly instead of
+/// overflowing the stack on very deep expressions.
+fn type_with_depth<'cx, 'hir>(
+    env: &TyEnv<'cx>,
+    hir: &'hir Hir<'cx>,
+    annot: Option<Type<'cx>>,
+    depth: usize,
+) -> Result<Tir<'cx, 'hir>, TypeError> {
+    if depth >= env.max_depth() {
+        return Err(TypeError::new(TypeMessage::ExpressionTooDeep {
+            depth: env.max_depth(),
+        }));
+    }
+    with_stack(|| type_nested(env, hir, annot, depth + 1))
+}
+
+/// The body of `type_with_depth`, once it has checked the depth and made room on the stack.
+fn type_nested<'cx, 'hir>(
+    env: &TyEnv<'cx>,
+    hir: &'hir Hir<'cx>,
+    annot: Option<Type<'cx>>,
+    depth: usize,
 ) -> Result<Tir<'cx, 'hir>, TypeError> {
     let tir = match hir.kind() {
         HirKind::Var(var) => Tir::from_hir(hir, env.lookup(*var)),
@@ -199,7 +227,7 @@ pub fn type_with<'cx, 'hir>(
             } else {
                 right
             };
-            return type_with(env, hir, annot);
+            return type_with_depth(env, hir, annot, depth);
         }
         HirKind::Expr(ExprKind::Var(_)) => {
             unreachable!("Hir should contain no unresolved variables")
@@ -212,16 +240,16 @@ pub fn type_with<'cx, 'hir>(
                 HirKind::Expr(ExprKind::Const(Const::Sort)) => {
                     Type::from_const(Const::Sort)
                 }
-                _ => type_with(env, t, None)?.eval_to_type(env)?,
+                _ => type_with_depth(env, t, None, depth)?.eval_to_type(env)?,
             };
-            type_with(env, x, Some(t))?
+            type_with_depth(env, x, Some(t), depth)?
         }
 
         HirKind::Expr(ExprKind::Lam(binder, annot, body)) => {
-            let annot = type_with(env, annot, None)?;
+            let annot = type_with_depth(env, annot, None, depth)?;
             let annot_nf = annot.eval_to_type(env)?;
             let body_env = env.insert_type(binder, annot_nf);
-            let body = type_with(&body_env, body, None)?;
+            let body = type_with_depth(&body_env, body, None, depth)?;
 
             let u_annot = annot.ty().as_const().unwrap();
             let u_body = match body.ty().ty().as_const() {
@@ -242,10 +270,10 @@ pub fn type_with<'cx, 'hir>(
             Tir::from_hir(hir, ty)
         }
         HirKind::Expr(ExprKind::Pi(binder, annot, body)) => {
-            let annot = type_with(env, annot, None)?;
+            let annot = type_with_depth(env, annot, None, depth)?;
             let annot_val = annot.eval_to_type(env)?;
             let body_env = env.insert_type(binder, annot_val);
-            let body = type_with(&body_env, body, None)?;
+            let body = type_with_depth(&body_env, body, None, depth)?;
             body.ensure_is_type(env)?;
 
             let ks = annot.ty().as_const().unwrap();
@@ -256,17 +284,20 @@ pub fn type_with<'cx, 'hir>(
         HirKind::Expr(ExprKind::Let(binder, annot, val, body)) => {
             let val_annot = annot
                 .as_ref()
-                .map(|t| type_with(env, t, None)?.eval_to_type(env))
+                .map(|t| {
+                    type_with_depth(env, t, None, depth)?.eval_to_type(env)
+                })
                 .transpose()?;
-            let val = type_with(env, &val, val_annot)?;
+            let val = type_with_depth(env, val, val_annot, depth)?;
             let val_nf = val.eval(env);
             let body_env = env.insert_value(&binder, val_nf, val.ty().clone());
-            let body = type_with(&body_env, body, None)?;
+            let body = type_with_depth(&body_env, body, None, depth)?;
             let ty = body.ty().clone();
             Tir::from_hir(hir, ty)
         }
         HirKind::Expr(ekind) => {
-            let ekind = ekind.traverse_ref(|e| type_with(env, e, None))?;
+            let ekind =
+                ekind.traverse_ref(|e| type_with_depth(env, e, None, depth))?;
             let ty = type_one_layer(env, ekind, hir.span())?;
             Tir::from_hir(hir, ty)
         }
@@ -306,3 +337,412 @@ pub fn typecheck_with<'cx, 'hir>(
     let ty = typecheck(cx, ty)?.eval_to_type(&TyEnv::new(cx))?;
     type_with(&TyEnv::new(cx), hir, Some(ty))
 }
+
+/// Looks for obvious type errors without typechecking, like unbound variables, applying a literal
+/// that is not a function, or accessing a field that a record literal doesn't have.
+///
+/// This doesn't normalize anything, so it is much cheaper than `typecheck`. It is meant to reject
+/// bad expressions early: an expression that passes may still fail to typecheck.
+pub fn quick_check<'cx>(
+    cx: Ctxt<'cx>,
+    hir: &Hir<'cx>,
+) -> Result<(), TypeError> {
+    use crate::operations::OpKind;
+
+    let mut stack = vec![hir];
+    while let Some(hir) = stack.pop() {
+        let ekind = match hir.kind() {
+            HirKind::Var(_) | HirKind::Import(_) => continue,
+            HirKind::MissingVar(var) => {
+                return mkerr(
+                    ErrorBuilder::new(format!("unbound variable `{}`", var))
+                        .span_err(hir.span(), "not found in this scope")
+                        .format(),
+                )
+            }
+            HirKind::ImportAlternative(alt, left, right) => {
+                stack.push(if cx[alt].unwrap_selected() {
+                    left
+                } else {
+                    right
+                });
+                continue;
+            }
+            HirKind::Expr(ekind) => ekind,
+        };
+        match ekind {
+            ExprKind::Op(OpKind::App(f, _)) if is_literal_non_function(f) => {
+                return mkerr(
+                    ErrorBuilder::new("expected function, found a literal")
+                        .span_err(
+                            f.span(),
+                            "function application requires a function",
+                        )
+                        .format(),
+                )
+            }
+            ExprKind::Op(OpKind::Field(scrut, x)) => match scrut.kind() {
+                HirKind::Expr(ExprKind::RecordLit(kvs))
+                    if !kvs.contains_key(x) =>
+                {
+                    return mk_span_err(hir.span(), "MissingRecordField")
+                }
+                HirKind::Expr(ExprKind::UnionType(kts))
+                    if !kts.contains_key(x) =>
+                {
+                    return mk_span_err(hir.span(), "MissingUnionField")
+                }
+                _ if is_literal_non_function(scrut) => {
+                    return mk_span_err(hir.span(), "NotARecord")
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        ekind.map_ref(|e| stack.push(e));
+    }
+    Ok(())
+}
+
+/// Whether `hir` is a literal that can be neither applied nor have fields accessed, whatever its
+/// contents.
+fn is_literal_non_function(hir: &Hir<'_>) -> bool {
+    matches!(
+        hir.kind(),
+        HirKind::Expr(
+            ExprKind::Num(_)
+                | ExprKind::TextLit(_)
+                | ExprKind::SomeLit(_)
+                | ExprKind::EmptyListLit(_)
+                | ExprKind::NEListLit(_)
+                | ExprKind::RecordType(_)
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::type_with;
+    use crate::error::Error;
+    use crate::semantics::TyEnv;
+    use crate::syntax::{Expr, ExprKind, NumKind, Span};
+    use crate::{Ctxt, Parsed};
+
+    #[test]
+    fn expression_too_deep() {
+        let nested = |depth| {
+            let mut expr =
+                Expr::new(ExprKind::Num(NumKind::Bool(true)), Span::Artificial);
+            for _ in 1..depth {
+                expr = Expr::new(ExprKind::SomeLit(expr), Span::Artificial);
+            }
+            Parsed::from_expr_without_imports(expr)
+        };
+        // Resolution stops at the limit, so the rest of the expression is never recursed into.
+        // Dropping the expression still recurses, so this runs with the stack of a main thread.
+        let too_deep = std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn(move || {
+                Ctxt::with_new(|cx| {
+                    nested(20000).skip_resolve(cx).unwrap_err().to_string()
+                })
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(
+            too_deep,
+            "Type error: expression is nested too deeply (more than 10000 levels)"
+        );
+
+        Ctxt::with_new(|cx| {
+            // Deeper than the stack of a test thread allows without growing it.
+            let resolved = nested(3000).skip_resolve(cx).unwrap();
+            assert!(resolved.typecheck(cx).is_ok());
+
+            let resolved = nested(20).skip_resolve(cx).unwrap();
+            let env = TyEnv::new(cx).with_max_depth(10);
+            assert_eq!(
+                Error::from(
+                    type_with(&env, resolved.as_hir(), None).unwrap_err()
+                )
+                .to_string(),
+                "Type error: expression is nested too deeply (more than 10 levels)"
+            );
+        });
+    }
+
+    fn typecheck_str(s: &str) -> Result<String, String> {
+        Ctxt::with_new(|cx| {
+            let resolved =
+                Parsed::parse_str(s).unwrap().skip_resolve(cx).unwrap();
+            match resolved.typecheck(cx) {
+                Ok(tck) => Ok(tck.get_type().unwrap().to_expr(cx).to_string()),
+                Err(e) => Err(Error::from(e).to_string()),
+            }
+        })
+    }
+
+    #[test]
+    fn builtin_types_are_shared() {
+        // The type of `List/length` is computed under a binder and reused at the top level.
+        assert_eq!(
+            typecheck_str(
+                "{ f = λ(x : Natural) → List/length Natural [x], \
+                 g = List/length, n = List/length Bool [True] }"
+            ),
+            Ok("{ f : ∀(x : Natural) → Natural, \
+                g : ∀(a : Type) → List a → Natural, \
+                n : Natural }"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn union_constructor_application() {
+        assert_eq!(
+            typecheck_str("< A : Natural | B >.A 1"),
+            Ok("< A: Natural | B >".to_string())
+        );
+        assert_eq!(
+            typecheck_str("< A : Natural | B >.A True"),
+            Err("Type error: wrong type of payload for alternative `A`: \
+                 expected `Natural`, found `Bool`"
+                .to_string())
+        );
+        // Other functions still get the generic error.
+        assert!(typecheck_str("(λ(x : Natural) → x) True")
+            .unwrap_err()
+            .contains("wrong type of function argument"));
+    }
+
+    #[test]
+    fn type_error_source() {
+        use crate::error::{TypeError, TypeMessage};
+        use std::error::Error;
+
+        let msg = "wrong type of payload for alternative `A`: \
+                   expected `Natural`, found `Bool`";
+        Ctxt::with_new(|cx| {
+            let resolved = Parsed::parse_str("< A : Natural | B >.A True")
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap();
+            let err = resolved.typecheck(cx).unwrap_err();
+            assert!(matches!(
+                err.message(),
+                TypeMessage::ConstructorArgMismatch(..)
+            ));
+            {
+                let err: &dyn Error = &err;
+                assert_eq!(err.to_string(), "Type error");
+                assert_eq!(err.source().unwrap().to_string(), msg);
+                assert!(err.source().unwrap().source().is_none());
+            }
+            // `dhall::Error` prints the message along with it.
+            assert_eq!(
+                crate::error::Error::from(err).to_string(),
+                format!("Type error: {}", msg)
+            );
+        });
+
+        // It converts to a boxed error with `?`, and error reporters that follow the chain of
+        // sources print each part once.
+        fn check() -> Result<(), Box<dyn Error>> {
+            Err(TypeError::new(TypeMessage::ExpressionTooDeep { depth: 3 }))?
+        }
+        let err = check().unwrap_err();
+        let mut chain = vec![err.to_string()];
+        let mut source = err.source();
+        while let Some(err) = source {
+            chain.push(err.to_string());
+            source = err.source();
+        }
+        assert_eq!(
+            chain.join(": "),
+            "Type error: expression is nested too deeply (more than 3 levels)"
+        );
+    }
+
+    #[test]
+    fn type_and_normalize() {
+        Ctxt::with_new(|cx| {
+            let resolved = Parsed::parse_str(r#"{ a = 1 + 1, b = "x" }"#)
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap();
+            let (ty, nf) = resolved.type_and_normalize(cx).unwrap();
+            assert_eq!(ty.to_expr(cx).to_string(), "{ a : Natural, b : Text }");
+            assert_eq!(nf.to_expr(cx).to_string(), r#"{ a = 2, b = "x" }"#);
+
+            let resolved = Parsed::parse_str("1 + True")
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap();
+            assert!(resolved.type_and_normalize(cx).is_err());
+        });
+    }
+
+    #[test]
+    fn empty_merge() {
+        // Merging an empty union needs no handler, and has the annotated type.
+        assert_eq!(
+            typecheck_str("λ(x : <>) → merge {=} x : Natural"),
+            Ok("∀(x : <  >) → Natural".to_string())
+        );
+        assert_eq!(
+            typecheck_str("λ(T : Type) → λ(x : <>) → merge {=} x : T"),
+            Ok("∀(T : Type) → ∀(x : <  >) → T".to_string())
+        );
+        // It stays stuck on the variable when normalized.
+        Ctxt::with_new(|cx| {
+            let nf = Parsed::parse_str("λ(x : <>) → merge {=} x : Natural")
+                .unwrap()
+                .skip_resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap()
+                .normalize(cx);
+            assert_eq!(
+                nf.to_expr(cx).to_string(),
+                "λ(x : <  >) → merge {=} x : Natural"
+            );
+        });
+        assert!(typecheck_str("λ(x : <>) → merge {=} x").is_err());
+        assert!(
+            typecheck_str("λ(x : <>) → merge { A = 1 } x : Natural").is_err()
+        );
+    }
+
+    #[test]
+    fn dependent_types() {
+        // Types may depend on terms.
+        assert_eq!(
+            typecheck_str("∀(x : Natural) → Type"),
+            Ok("Kind".to_string())
+        );
+        assert_eq!(
+            typecheck_str("λ(x : Natural) → Type"),
+            Ok("∀(x : Natural) → Kind".to_string())
+        );
+        assert_eq!(
+            typecheck_str(
+                "let T = λ(b : Bool) → if b then Natural else Text in 1 : T True"
+            ),
+            Ok("Natural".to_string())
+        );
+    }
+
+    #[test]
+    fn text_replace() {
+        assert_eq!(
+            typecheck_str("Text/replace"),
+            Ok("∀(needle : Text) → ∀(replacement : Text) → ∀(haystack : Text) → Text"
+                .to_string())
+        );
+        assert_eq!(
+            typecheck_str(r#"Text/replace "a" "X" "banana""#),
+            Ok("Text".to_string())
+        );
+        assert!(typecheck_str(r#"Text/replace "a" 1 "banana""#)
+            .unwrap_err()
+            .contains("wrong type of function argument"));
+    }
+
+    #[test]
+    fn merge_without_annotation() {
+        let handlers = "{ Left = λ(n : Natural) → n + 1, Right = 0 }";
+        assert_eq!(
+            typecheck_str(&format!(
+                "merge {} (< Left : Natural | Right >.Left 1)",
+                handlers
+            )),
+            Ok("Natural".to_string())
+        );
+        assert_eq!(
+            typecheck_str(&format!(
+                "let U = < Left : Natural | Right > in (λ(x : U) → merge {} x) U.Right",
+                handlers
+            )),
+            Ok("Natural".to_string())
+        );
+        assert_eq!(
+            typecheck_str(
+                "merge { None = 0, Some = λ(n : Natural) → n } (Some 1)"
+            ),
+            Ok("Natural".to_string())
+        );
+        // The handlers must agree.
+        assert!(typecheck_str(
+            "merge { Left = λ(n : Natural) → n, Right = True } (< Left : Natural | Right >.Right)"
+        )
+        .unwrap_err()
+        .contains("MergeHandlerTypeMismatch"));
+        // The type of an empty merge can't be inferred.
+        assert!(typecheck_str("λ(x : <>) → merge {=} x")
+            .unwrap_err()
+            .contains("MergeEmptyNeedsAnnotation"));
+        assert!(typecheck_str("λ(x : <>) → merge {=} x : Bool").is_ok());
+    }
+
+    #[test]
+    fn quick_check() {
+        let check = |s: &str| {
+            Ctxt::with_new(|cx| {
+                let resolved =
+                    Parsed::parse_str(s).unwrap().skip_resolve(cx).unwrap();
+                let quick = resolved
+                    .quick_check(cx)
+                    .map_err(|e| Error::from(e).to_string());
+                // Whatever the quick check rejects, the full check rejects too.
+                if quick.is_err() {
+                    assert!(resolved.typecheck(cx).is_err());
+                }
+                quick
+            })
+        };
+        assert!(check("λ(x : Natural) → [x, x + 1]").is_ok());
+        assert!(check("{ a = 1 }.a").is_ok());
+        assert!(check("< A | B >.B").is_ok());
+        // Real type errors that need the full check.
+        assert!(check("1 + True").is_ok());
+
+        assert!(check("λ(x : Natural) → y")
+            .unwrap_err()
+            .contains("unbound variable `y`"));
+        assert!(check("[1, (\"f\" 2)]")
+            .unwrap_err()
+            .contains("expected function"));
+        assert!(check("{ a = 1 }.b")
+            .unwrap_err()
+            .contains("MissingRecordField"));
+        assert!(check("< A | B >.C")
+            .unwrap_err()
+            .contains("MissingUnionField"));
+        assert!(check("[1].a").unwrap_err().contains("NotARecord"));
+    }
+
+    #[test]
+    fn with_record_update() {
+        let r = "{ a = { b = 1, c = True }, d = \"x\" }";
+        // Updating an existing nested field.
+        assert_eq!(
+            typecheck_str(&format!("{} with a.b = 2", r)),
+            Ok("{ a : { b : Natural, c : Bool }, d : Text }".to_string())
+        );
+        // The update may change the type of the field.
+        assert_eq!(
+            typecheck_str(&format!("{} with a.b = \"two\"", r)),
+            Ok("{ a : { b : Text, c : Bool }, d : Text }".to_string())
+        );
+        // Missing fields are added, along with the records that contain them.
+        assert_eq!(
+            typecheck_str(&format!("{} with e.f = 3", r)),
+            Ok("{ a : { b : Natural, c : Bool }, d : Text, e : { f : Natural } }"
+                .to_string())
+        );
+        assert!(typecheck_str(&format!("{} with d.e = 3", r))
+            .unwrap_err()
+            .contains("WithMustBeRecord"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::error::Error;
+use crate::semantics::{resolve_with_names, type_with, Nir, TyEnv, Type};
+use crate::syntax::{Expr, ExprKind, Label, Span};
+use crate::{Ctxt, Normalized, Parsed};
+
+/// Evaluates expressions one after the other in a growing context, like a REPL does: each
+/// expression can refer to the values bound by the previous calls to `eval_let`.
+#[derive(Debug, Clone)]
+pub struct Session<'cx> {
+    env: TyEnv<'cx>,
+}
+
+impl<'cx> Session<'cx> {
+    pub fn new(cx: Ctxt<'cx>) -> Self {
+        Session {
+            env: TyEnv::new(cx),
+        }
+    }
+
+    /// A session where the `let` bindings are bound in order, as in `let x : T = e in ...`, so
+    /// that each can refer to the previous ones. This lets many expressions share a preamble
+    /// without typechecking it again for each. The expressions can't contain imports.
+    pub fn from_let_bindings(
+        cx: Ctxt<'cx>,
+        bindings: impl IntoIterator<Item = (Label, Option<Expr>, Expr)>,
+    ) -> Result<Self, Error> {
+        let mut session = Session::new(cx);
+        for (name, annot, value) in bindings {
+            let value = match annot {
+                Some(ty) => {
+                    Expr::new(ExprKind::Annot(value, ty), Span::Artificial)
+                }
+                None => value,
+            };
+            session.eval_let(name, Parsed::from_expr_without_imports(value))?;
+        }
+        Ok(session)
+    }
+
+    pub fn cx(&self) -> Ctxt<'cx> {
+        self.env.cx()
+    }
+
+    /// Typechecks and normalizes `expr` in the current context.
+    pub fn eval(&self, expr: Parsed) -> Result<Normalized<'cx>, Error> {
+        let (val, _) = self.eval_with_type(expr)?;
+        Ok(Normalized(val))
+    }
+
+    /// The type of `expr` in the current context.
+    pub fn type_of(&self, expr: Parsed) -> Result<Normalized<'cx>, Error> {
+        let (_, ty) = self.eval_with_type(expr)?;
+        Ok(Normalized(ty.into_nir()))
+    }
+
+    /// The types of each of `exprs` in the current context.
+    pub fn type_of_all(
+        &self,
+        exprs: impl IntoIterator<Item = Parsed>,
+    ) -> Vec<Result<Normalized<'cx>, Error>> {
+        exprs.into_iter().map(|expr| self.type_of(expr)).collect()
+    }
+
+    /// Typechecks and normalizes `expr`, and binds `name` to the result for the following
+    /// expressions, like `let name = expr in ...` would. A later binding of the same name shadows
+    /// this one; it is still available as `name@1`.
+    pub fn eval_let(
+        &mut self,
+        name: Label,
+        expr: Parsed,
+    ) -> Result<Normalized<'cx>, Error> {
+        let (val, ty) = self.eval_with_type(expr)?;
+        self.env = self.env.insert_value(&name, val.clone(), ty);
+        Ok(Normalized(val))
+    }
+
+    fn eval_with_type(
+        &self,
+        expr: Parsed,
+    ) -> Result<(Nir<'cx>, Type<'cx>), Error> {
+        let resolved =
+            resolve_with_names(self.cx(), expr, self.env.as_nameenv())?;
+        let tir = type_with(&self.env, &resolved.0, None)?;
+        Ok((tir.eval(&self.env), tir.ty().clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Parsed {
+        Parsed::parse_str(s).unwrap()
+    }
+
+    fn eval(session: &Session, s: &str) -> Result<String, Error> {
+        Ok(session.eval(parse(s))?.to_expr(session.cx()).to_string())
+    }
+
+    #[test]
+    fn session() {
+        Ctxt::with_new(|cx| {
+            let mut session = Session::new(cx);
+
+            let x = session.eval_let("x".into(), parse("1 + 2")).unwrap();
+            assert_eq!(x.to_expr(cx).to_string(), "3");
+            session
+                .eval_let("f".into(), parse("λ(n : Natural) → n * x"))
+                .unwrap();
+            assert_eq!(eval(&session, "f 5").unwrap(), "15");
+            assert_eq!(
+                session.type_of(parse("f")).unwrap().to_expr(cx).to_string(),
+                "∀(n : Natural) → Natural"
+            );
+
+            // Shadowing.
+            session.eval_let("x".into(), parse("\"a\"")).unwrap();
+            assert_eq!(eval(&session, "[x@1]").unwrap(), "[3]");
+            assert_eq!(eval(&session, "f 2").unwrap(), "6");
+
+            // Errors leave the context unchanged.
+            assert!(session.eval_let("y".into(), parse("x + 1")).is_err());
+            assert!(eval(&session, "y").is_err());
+            assert_eq!(eval(&session, "x ++ \"b\"").unwrap(), "\"ab\"");
+        })
+    }
+
+    #[test]
+    fn from_let_bindings() {
+        let expr = |s: &str| parse(s).to_expr();
+        Ctxt::with_new(|cx| {
+            let bindings = vec![
+                ("Port".into(), None, expr("Natural")),
+                ("default".into(), Some(expr("Port")), expr("8080")),
+                ("Server".into(), None, expr("{ host : Text, port : Port }")),
+            ];
+            let session = Session::from_let_bindings(cx, bindings).unwrap();
+            let types: Vec<_> = session
+                .type_of_all(vec![
+                    parse("{ host = \"a\", port = default }"),
+                    parse("λ(s : Server) → s.port + default"),
+                    parse("default ++ \"b\""),
+                ])
+                .into_iter()
+                .map(|ty| ty.map(|ty| ty.to_expr(cx).to_string()))
+                .collect();
+            assert_eq!(
+                types[0].as_ref().unwrap(),
+                "{ host : Text, port : Natural }"
+            );
+            assert_eq!(
+                types[1].as_ref().unwrap(),
+                "∀(s : { host : Text, port : Natural }) → Natural"
+            );
+            assert!(types[2].is_err());
+
+            // Bindings are checked against their annotation.
+            let bindings = vec![("x".into(), Some(expr("Text")), expr("1"))];
+            assert!(Session::from_let_bindings(cx, bindings).is_err());
+        })
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::syntax::{Expr, ExprKind, Label};
+
+/// The structural difference between two expressions, as computed by [`structural_diff`].
+///
+/// Record literals, record types and non-empty lists are compared element by element; any other
+/// pair of differing expressions is reported as a whole as `Changed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprDiff {
+    /// The expression is the same on both sides.
+    Unchanged(Expr),
+    /// The expression only exists in the new version.
+    Added(Expr),
+    /// The expression only exists in the old version.
+    Removed(Expr),
+    /// The expression was replaced.
+    Changed(Expr, Expr),
+    /// A record literal, compared field by field.
+    RecordLit(BTreeMap<Label, ExprDiff>),
+    /// A record type, compared field by field.
+    RecordType(BTreeMap<Label, ExprDiff>),
+    /// A non-empty list literal, compared element by element.
+    List(Vec<ExprDiff>),
+}
+
+/// Compute the structural difference between two expressions.
+///
+/// Expressions are compared syntactically, ignoring source locations.
+pub fn structural_diff(before: &Expr, after: &Expr) -> ExprDiff {
+    if before == after {
+        return ExprDiff::Unchanged(after.clone());
+    }
+    match (before.kind(), after.kind()) {
+        (ExprKind::RecordLit(kvs1), ExprKind::RecordLit(kvs2)) => {
+            ExprDiff::RecordLit(diff_maps(kvs1, kvs2))
+        }
+        (ExprKind::RecordType(kts1), ExprKind::RecordType(kts2)) => {
+            ExprDiff::RecordType(diff_maps(kts1, kts2))
+        }
+        (ExprKind::NEListLit(xs), ExprKind::NEListLit(ys)) => {
+            ExprDiff::List(diff_lists(xs, ys))
+        }
+        _ => ExprDiff::Changed(before.clone(), after.clone()),
+    }
+}
+
+impl ExprDiff {
+    /// Whether the two compared expressions were equal.
+    pub fn is_unchanged(&self) -> bool {
+        match self {
+            ExprDiff::Unchanged(_) => true,
+            ExprDiff::Added(_)
+            | ExprDiff::Removed(_)
+            | ExprDiff::Changed(..) => false,
+            ExprDiff::RecordLit(fields) | ExprDiff::RecordType(fields) => {
+                fields.values().all(ExprDiff::is_unchanged)
+            }
+            ExprDiff::List(elts) => elts.iter().all(ExprDiff::is_unchanged),
+        }
+    }
+}
+
+fn diff_maps(
+    before: &BTreeMap<Label, Expr>,
+    after: &BTreeMap<Label, Expr>,
+) -> BTreeMap<Label, ExprDiff> {
+    let mut fields = BTreeMap::new();
+    for (k, x) in before {
+        let diff = match after.get(k) {
+            Some(y) => structural_diff(x, y),
+            None => ExprDiff::Removed(x.clone()),
+        };
+        fields.insert(k.clone(), diff);
+    }
+    for (k, y) in after {
+        if !before.contains_key(k) {
+            fields.insert(k.clone(), ExprDiff::Added(y.clone()));
+        }
+    }
+    fields
+}
+
+fn diff_lists(before: &[Expr], after: &[Expr]) -> Vec<ExprDiff> {
+    myers_diff(before, after)
+        .into_iter()
+        .map(|edit| match edit {
+            Edit::Keep(_, j) => ExprDiff::Unchanged(after[j].clone()),
+            Edit::Delete(i) => ExprDiff::Removed(before[i].clone()),
+            Edit::Insert(j) => ExprDiff::Added(after[j].clone()),
+        })
+        .collect()
+}
+
+/// One step of an edit script between two sequences `a` and `b`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    /// `a[i]` and `b[j]` are equal.
+    Keep(usize, usize),
+    /// `a[i]` was deleted.
+    Delete(usize),
+    /// `b[j]` was inserted.
+    Insert(usize),
+}
+
+/// Compute a shortest edit script from `a` to `b`, using Myers' O(ND) algorithm.
+fn myers_diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Edit> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // `v[k + offset]` is the furthest-reaching `x` on diagonal `k`.
+    let mut v = vec![0isize; 2 * max + 3];
+    // Snapshots of `v` before each step, to backtrack through.
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    // Walk back from the end, recovering the edits in reverse order.
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d
+            || (k != d
+                && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize])
+        {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Keep(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+/// Renders the diff in a unified-diff-like format: one line per field or element, prefixed with
+/// `+` or `-` when it was added or removed.
+impl fmt::Display for ExprDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_diff(self, f, 0, None)
+    }
+}
+
+/// Prints `diff` with the given indentation level. `field` is the label and separator that
+/// precede the expression, if it is the field of a record.
+fn fmt_diff(
+    diff: &ExprDiff,
+    f: &mut fmt::Formatter,
+    indent: usize,
+    field: Option<(&Label, &str)>,
+) -> fmt::Result {
+    let line = |f: &mut fmt::Formatter, prefix: char| -> fmt::Result {
+        write!(f, "{} {:indent$}", prefix, "", indent = indent)?;
+        if let Some((label, sep)) = field {
+            write!(f, "{} {} ", label, sep)?;
+        }
+        Ok(())
+    };
+    match diff {
+        ExprDiff::Unchanged(e) => {
+            line(f, ' ')?;
+            writeln!(f, "{}", e)
+        }
+        ExprDiff::Added(e) => {
+            line(f, '+')?;
+            writeln!(f, "{}", e)
+        }
+        ExprDiff::Removed(e) => {
+            line(f, '-')?;
+            writeln!(f, "{}", e)
+        }
+        ExprDiff::Changed(before, after) => {
+            line(f, '-')?;
+            writeln!(f, "{}", before)?;
+            line(f, '+')?;
+            writeln!(f, "{}", after)
+        }
+        ExprDiff::RecordLit(fields) | ExprDiff::RecordType(fields) => {
+            let sep = match diff {
+                ExprDiff::RecordLit(_) => "=",
+                _ => ":",
+            };
+            line(f, ' ')?;
+            writeln!(f, "{{")?;
+            for (k, d) in fields {
+                fmt_diff(d, f, indent + 2, Some((k, sep)))?;
+            }
+            writeln!(f, "  {:indent$}}}", "", indent = indent)
+        }
+        ExprDiff::List(elts) => {
+            line(f, ' ')?;
+            writeln!(f, "[")?;
+            for d in elts {
+                fmt_diff(d, f, indent + 2, None)?;
+            }
+            writeln!(f, "  {:indent$}]", "", indent = indent)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_expr;
+
+    fn diff(before: &str, after: &str) -> ExprDiff {
+        structural_diff(
+            &parse_expr(before).unwrap(),
+            &parse_expr(after).unwrap(),
+        )
+    }
+
+    #[test]
+    fn myers() {
+        use Edit::*;
+        assert_eq!(myers_diff::<u8>(&[], &[]), vec![]);
+        assert_eq!(
+            myers_diff(&[1, 2, 3], &[1, 3, 4]),
+            vec![Keep(0, 0), Delete(1), Keep(2, 1), Insert(2)]
+        );
+        let a: Vec<_> = "ABCABBA".chars().collect();
+        let b: Vec<_> = "CBABAC".chars().collect();
+        let edits = myers_diff(&a, &b);
+        // The shortest edit script has 5 insertions and deletions.
+        assert_eq!(edits.iter().filter(|e| !matches!(e, Keep(..))).count(), 5);
+    }
+
+    #[test]
+    fn record_type() {
+        let d = diff(
+            "{ a : Natural, b : Bool, c : Text }",
+            "{ a : Natural, c : Bool, d : Double }",
+        );
+        assert!(!d.is_unchanged());
+        assert_eq!(
+            d.to_string(),
+            "  {\n\
+            \x20   a : Natural\n\
+            -   b : Bool\n\
+            -   c : Text\n\
+            +   c : Bool\n\
+            +   d : Double\n\
+            \x20 }\n"
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let d = diff("{ x = { y = [1, 2, 3] } }", "{ x = { y = [1, 3, 4] } }");
+        assert_eq!(
+            d.to_string(),
+            "  {\n\
+            \x20   x = {\n\
+            \x20     y = [\n\
+            \x20       1\n\
+            -       2\n\
+            \x20       3\n\
+            +       4\n\
+            \x20     ]\n\
+            \x20   }\n\
+            \x20 }\n"
+        );
+    }
+
+    #[test]
+    fn unchanged() {
+        let d = diff("λ(x : Bool) → x", "λ(x : Bool) → x");
+        assert!(d.is_unchanged());
+        assert_eq!(d.to_string(), "  λ(x : Bool) → x\n");
+        assert_eq!(
+            diff("1", "2"),
+            ExprDiff::Changed(
+                parse_expr("1").unwrap(),
+                parse_expr("2").unwrap()
+            )
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::syntax::{Expr, ExprKind, Label, V};
+
+/// Whether two expressions are the same up to alpha-equivalence, i.e. up to the names of bound
+/// variables.
+///
+/// Unlike the equality used by the typechecker, this compares the expressions as written,
+/// without normalizing them: `1 + 1` is not syntactically equal to `2`. This makes it much
+/// cheaper, and it gives the same answer for expressions already in normal form. Source locations
+/// are ignored.
+pub fn syntactic_equal(e1: &Expr, e2: &Expr) -> bool {
+    go(e1, e2, &mut Vec::new())
+}
+
+/// `ctx` holds the pairs of variables bound on the way down, innermost last.
+fn go<'a>(
+    e1: &'a Expr,
+    e2: &'a Expr,
+    ctx: &mut Vec<(&'a Label, &'a Label)>,
+) -> bool {
+    if let (ExprKind::Var(v1), ExprKind::Var(v2)) = (e1.kind(), e2.kind()) {
+        return match_vars(v1, v2, ctx);
+    }
+    if erase_binder(e1.kind().map_ref(|_| ()))
+        != erase_binder(e2.kind().map_ref(|_| ()))
+    {
+        return false;
+    }
+    // The shapes are equal, so the subexpressions come in the same order.
+    let mut children1 = Vec::new();
+    e1.kind()
+        .map_ref_maybe_binder(|l, e| children1.push((l, e)));
+    let mut children2 = Vec::new();
+    e2.kind()
+        .map_ref_maybe_binder(|l, e| children2.push((l, e)));
+    children1
+        .into_iter()
+        .zip(children2)
+        .all(|((l1, e1), (l2, e2))| match (l1, l2) {
+            (Some(l1), Some(l2)) => {
+                ctx.push((l1, l2));
+                let eq = go(e1, e2, ctx);
+                ctx.pop();
+                eq
+            }
+            _ => go(e1, e2, ctx),
+        })
+}
+
+/// Whether the variables refer to the same binder, or are the same free variable.
+fn match_vars(v1: &V, v2: &V, ctx: &[(&Label, &Label)]) -> bool {
+    let (V(x1, mut n1), V(x2, mut n2)) = (v1, v2);
+    for (l1, l2) in ctx.iter().rev() {
+        match (x1 == *l1, x2 == *l2) {
+            (true, true) if n1 == 0 && n2 == 0 => return true,
+            // One variable refers to this binder but not the other.
+            (true, _) if n1 == 0 => return false,
+            (_, true) if n2 == 0 => return false,
+            (b1, b2) => {
+                n1 -= b1 as usize;
+                n2 -= b2 as usize;
+            }
+        }
+    }
+    x1 == x2 && n1 == n2
+}
+
+fn erase_binder(kind: ExprKind<()>) -> ExprKind<()> {
+    let anon = || Label::from("_");
+    match kind {
+        ExprKind::Lam(_, t, e) => ExprKind::Lam(anon(), t, e),
+        ExprKind::Pi(_, t, e) => ExprKind::Pi(anon(), t, e),
+        ExprKind::Let(_, t, v, e) => ExprKind::Let(anon(), t, v, e),
+        kind => kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_expr;
+
+    fn eq(s1: &str, s2: &str) -> bool {
+        syntactic_equal(&parse_expr(s1).unwrap(), &parse_expr(s2).unwrap())
+    }
+
+    #[test]
+    fn alpha_equivalence() {
+        assert!(eq("{ x = [1, 2], y = \"a\" }", "{ y = \"a\", x = [1, 2] }"));
+        assert!(!eq("1 + 1", "2"));
+        assert!(!eq("[1, 2]", "[1, 3]"));
+
+        // Bound variables may be renamed.
+        assert!(eq("λ(x : Natural) → x + 1", "λ(y : Natural) → y + 1"));
+        assert!(eq(
+            "λ(x : Type) → λ(y : x) → let z = y in x",
+            "λ(a : Type) → λ(b : a) → let c = b in a"
+        ));
+        assert!(eq(
+            "λ(x : Bool) → λ(x : Bool) → x@1",
+            "λ(a : Bool) → λ(b : Bool) → a"
+        ));
+        assert!(!eq(
+            "λ(x : Bool) → λ(y : Bool) → x",
+            "λ(x : Bool) → λ(y : Bool) → y"
+        ));
+        assert!(!eq(
+            "λ(x : Bool) → λ(x : Bool) → x",
+            "λ(a : Bool) → λ(b : Bool) → a"
+        ));
+        // Free variables may not.
+        assert!(eq("λ(x : Bool) → y", "λ(z : Bool) → y"));
+        assert!(!eq("λ(x : Bool) → y", "λ(x : Bool) → z"));
+        assert!(!eq("λ(y : Bool) → y@1", "λ(x : Bool) → y@1"));
+        assert!(eq("λ(y : Bool) → y@1", "λ(x : Bool) → y@0"));
+        // Record fields are not binders.
+        assert!(!eq("{ x = 1 }", "{ y = 1 }"));
+    }
+}
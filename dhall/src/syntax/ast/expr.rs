@@ -105,6 +105,16 @@ pub enum ExprKind<SubExpr> {
 
     /// `./some/path`
     Import(Import<SubExpr>),
+
+    /// A part of the source that failed to parse. Only found in the result of
+    /// `Parsed::parse_str_recovering`.
+    Error(ErrorNode),
+}
+
+/// The text that was skipped when recovering from a syntax error.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ErrorNode {
+    pub skipped: String,
 }
 
 impl<SE> ExprKind<SE> {
@@ -177,6 +187,28 @@ impl Expr {
         }
     }
 
+    /// Attaches comments to the expression, to be printed before it.
+    pub fn with_comments(self, comments: Vec<Comment>) -> Self {
+        Expr {
+            kind: self.kind,
+            span: self.span.with_comments(comments),
+        }
+    }
+
+    /// Replaces the location of the expression, keeping its comments and the order of its
+    /// fields.
+    pub fn with_span(self, span: Span) -> Self {
+        let comments = self.span.comments().to_vec();
+        let span = match self.span.field_order() {
+            Some(labels) => span.with_field_order(labels.to_vec()),
+            None => span,
+        };
+        Expr {
+            kind: self.kind,
+            span: span.with_comments(comments),
+        }
+    }
+
     // Compute the sha256 hash of the binary form of the expression.
     pub fn sha256_hash(&self) -> Result<Box<[u8]>, Error> {
         let data = binary::encode(self)?;
@@ -187,6 +219,67 @@ impl Expr {
     pub fn add_let_binding(self, label: Label, value: Expr) -> Expr {
         Expr::new(ExprKind::Let(label, None, value, self), Span::Artificial)
     }
+
+    /// Apply `f` to every label in the expression: variables and their binders, record fields,
+    /// union alternatives and field accesses.
+    pub fn map_labels(&self, f: impl Fn(&Label) -> Label) -> Expr {
+        fn rename_keys<T>(
+            kvs: BTreeMap<Label, T>,
+            f: &impl Fn(&Label) -> Label,
+        ) -> BTreeMap<Label, T> {
+            kvs.into_iter().map(|(k, v)| (f(&k), v)).collect()
+        }
+        fn go(e: &Expr, f: &impl Fn(&Label) -> Label) -> Expr {
+            use crate::operations::OpKind::{Field, Projection, With};
+            use ExprKind::*;
+            let kind = match e.kind().map_ref(|e| go(e, f)) {
+                Var(V(l, n)) => Var(V(f(&l), n)),
+                Lam(l, t, e) => Lam(f(&l), t, e),
+                Pi(l, t, e) => Pi(f(&l), t, e),
+                Let(l, t, v, e) => Let(f(&l), t, v, e),
+                RecordType(kts) => RecordType(rename_keys(kts, f)),
+                RecordLit(kvs) => RecordLit(rename_keys(kvs, f)),
+                UnionType(kts) => UnionType(rename_keys(kts, f)),
+                Op(Field(e, l)) => Op(Field(e, f(&l))),
+                Op(Projection(e, ls)) => {
+                    Op(Projection(e, ls.iter().map(f).collect()))
+                }
+                Op(With(e, ls, v)) => {
+                    Op(With(e, ls.iter().map(f).collect(), v))
+                }
+                kind => kind,
+            };
+            Expr::new(kind, e.span())
+        }
+        go(self, &f)
+    }
+
+    /// Rebuild the expression bottom-up, replacing each node with the result of `f`. `f` is
+    /// called on a node after its sub-expressions have been replaced.
+    pub fn map_expr(&self, f: impl Fn(&Expr) -> Expr) -> Expr {
+        fn go(e: &Expr, f: &impl Fn(&Expr) -> Expr) -> Expr {
+            f(&Expr::new(e.kind().map_ref(|e| go(e, f)), e.span()))
+        }
+        go(self, &f)
+    }
+
+    /// Accumulate a value over every node of the expression, visiting each node before its
+    /// sub-expressions.
+    pub fn fold_expr<T>(&self, init: T, f: impl Fn(T, &Expr) -> T) -> T {
+        struct Fold<T, F> {
+            acc: Option<T>,
+            f: F,
+        }
+        impl<T, F: Fn(T, &Expr) -> T> visitor::ExprVisitor for Fold<T, F> {
+            fn visit_expr(&mut self, e: &Expr) {
+                self.acc = Some((self.f)(self.acc.take().unwrap(), e));
+                visitor::walk_expr(self, e);
+            }
+        }
+        let mut fold = Fold { acc: Some(init), f };
+        visitor::ExprVisitor::visit_expr(&mut fold, self);
+        fold.acc.unwrap()
+    }
 }
 
 // Empty enum to indicate that no error can occur
@@ -249,3 +342,49 @@ impl std::hash::Hash for Expr {
         self.kind.hash(state)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::syntax::{parse_expr, Expr, ExprKind, Label, NumKind};
+
+    #[test]
+    fn map_labels() {
+        let e = parse_expr("λ(x : { x : Bool }) → x.x ∧ { y = x }").unwrap();
+        let renamed = e.map_labels(|l| {
+            if l == &Label::from("x") {
+                Label::from("z")
+            } else {
+                l.clone()
+            }
+        });
+        assert_eq!(
+            renamed.to_string(),
+            "λ(z : { z : Bool }) → z.z ∧ { y = z }"
+        );
+    }
+
+    #[test]
+    fn map_expr() {
+        let e = parse_expr("[True, False && True]").unwrap();
+        let negated = e.map_expr(|e| match e.kind() {
+            ExprKind::Num(NumKind::Bool(b)) => {
+                Expr::new(ExprKind::Num(NumKind::Bool(!b)), e.span())
+            }
+            _ => e.clone(),
+        });
+        assert_eq!(negated.to_string(), "[False, True && False]");
+    }
+
+    #[test]
+    fn fold_expr() {
+        let e = parse_expr("let x = 1 in x + x").unwrap();
+        assert_eq!(e.fold_expr(0, |n, _| n + 1), 5);
+        let vars = e.fold_expr(Vec::new(), |mut vars, e| {
+            if let ExprKind::Var(v) = e.kind() {
+                vars.push(v.0.clone());
+            }
+            vars
+        });
+        assert_eq!(vars, vec![Label::from("x"), Label::from("x")]);
+    }
+}
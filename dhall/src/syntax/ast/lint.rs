@@ -0,0 +1,436 @@
+use crate::builtins::Builtin;
+use crate::operations::OpKind;
+use crate::syntax::visitor::{walk_expr, ExprVisitor};
+use crate::syntax::{Expr, ExprKind, Label, ParsedSpan, V};
+
+use super::shake::occurs;
+
+/// Chains of at least this many independent `let`s get a warning.
+const LONG_LET_CHAIN: usize = 10;
+
+/// The kind of pattern a [`Warning`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WarningKind {
+    /// A `let` binding whose variable is never used.
+    UnusedLet,
+    /// `List/build` applied to a function that builds a fixed list, which could be a list
+    /// literal.
+    ListBuild,
+    /// A long chain of `let` bindings that don't refer to each other, which could be a record.
+    LongLetChain,
+    /// The `Optional` literals from old versions of the standard, `[] : Optional T` and
+    /// `[x] : Optional T`, which are now `None T` and `Some x`.
+    OptionalListLiteral,
+    /// The `constructors` keyword from old versions of the standard. Unions can now be used
+    /// directly.
+    Constructors,
+}
+
+/// A pattern that is valid Dhall but could be written better, as found by [`lint`], or a construct
+/// that was removed from the standard, as found by [`deprecations`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    /// The range of characters of the source text the warning is about, if known.
+    pub span: Option<(usize, usize)>,
+}
+
+impl Warning {
+    fn new(kind: WarningKind, message: String, e: &Expr) -> Self {
+        let span = e.span().parsed().map(ParsedSpan::as_char_range);
+        Warning {
+            kind,
+            message,
+            span,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "warning: {}", self.message)
+    }
+}
+
+/// Look for patterns that are valid but could be written better, like unused `let` bindings.
+///
+/// This is meant to run on a file as written, i.e. before import resolution, so that the spans of
+/// the warnings point into it. The warnings are returned in the order they appear in the source.
+pub fn lint(e: &Expr) -> Vec<Warning> {
+    let mut visitor = LintVisitor::default();
+    visitor.visit_expr(e);
+    visitor.warnings
+}
+
+/// Look for constructs that were removed from the standard, like the `[] : Optional T` literals.
+/// They still parse, but fail to typecheck; the warnings say what to write instead.
+///
+/// Like [`lint`], this is meant to run on a file as written. The warnings are returned in the
+/// order they appear in the source.
+pub fn deprecations(e: &Expr) -> Vec<Warning> {
+    let mut visitor = DeprecationVisitor::default();
+    visitor.visit_expr(e);
+    visitor.warnings
+}
+
+/// Finds the constructs of [`deprecations`].
+#[derive(Debug, Default)]
+struct DeprecationVisitor {
+    /// The variables in scope, to tell a free `constructors` from a variable with that name.
+    bound: Vec<Label>,
+    warnings: Vec<Warning>,
+}
+
+impl DeprecationVisitor {
+    fn check(&mut self, e: &Expr) {
+        let is_optional = |t: &Expr| match t.kind() {
+            ExprKind::Op(OpKind::App(f, _)) => {
+                matches!(f.kind(), ExprKind::Builtin(Builtin::Optional))
+            }
+            _ => false,
+        };
+        let is_free_constructors = |f: &Expr| match f.kind() {
+            ExprKind::Var(V(x, n)) => {
+                x.as_ref() == "constructors"
+                    && self.bound.iter().filter(|y| *y == x).count() <= *n
+            }
+            _ => false,
+        };
+        match e.kind() {
+            ExprKind::EmptyListLit(t) if is_optional(t) => {
+                self.warnings.push(Warning::new(
+                    WarningKind::OptionalListLiteral,
+                    format!(
+                        "`[] : Optional T` was removed from the language; write `None {}`",
+                        optional_argument(t)
+                    ),
+                    e,
+                ));
+            }
+            ExprKind::Annot(l, t) if is_optional(t) => {
+                if let ExprKind::NEListLit(xs) = l.kind() {
+                    if xs.len() == 1 {
+                        self.warnings.push(Warning::new(
+                            WarningKind::OptionalListLiteral,
+                            format!(
+                                "`[x] : Optional T` was removed from the language; write `Some {}`",
+                                xs[0]
+                            ),
+                            e,
+                        ));
+                    }
+                }
+            }
+            ExprKind::Op(OpKind::App(f, _)) if is_free_constructors(f) => {
+                self.warnings.push(Warning::new(
+                    WarningKind::Constructors,
+                    "`constructors` was removed from the language; use the union type directly"
+                        .to_owned(),
+                    e,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_under_binder(&mut self, l: &Label, body: &Expr) {
+        self.bound.push(l.clone());
+        self.visit_expr(body);
+        self.bound.pop();
+    }
+}
+
+impl ExprVisitor for DeprecationVisitor {
+    fn visit_expr(&mut self, e: &Expr) {
+        self.check(e);
+        walk_expr(self, e)
+    }
+    fn visit_lam(&mut self, l: &Label, annot: &Expr, body: &Expr) {
+        self.visit_expr(annot);
+        self.visit_under_binder(l, body);
+    }
+    fn visit_pi(&mut self, l: &Label, annot: &Expr, body: &Expr) {
+        self.visit_expr(annot);
+        self.visit_under_binder(l, body);
+    }
+    fn visit_let(
+        &mut self,
+        l: &Label,
+        annot: Option<&Expr>,
+        val: &Expr,
+        body: &Expr,
+    ) {
+        if let Some(annot) = annot {
+            self.visit_expr(annot);
+        }
+        self.visit_expr(val);
+        self.visit_under_binder(l, body);
+    }
+}
+
+/// The `T` of `Optional T`.
+fn optional_argument(t: &Expr) -> &Expr {
+    match t.kind() {
+        ExprKind::Op(OpKind::App(_, a)) => a,
+        _ => t,
+    }
+}
+
+/// Finds the patterns of [`lint`].
+#[derive(Debug, Default)]
+struct LintVisitor {
+    warnings: Vec<Warning>,
+}
+
+impl LintVisitor {
+    /// Lints a chain of consecutive `let`s, starting at `e`.
+    fn visit_let_chain(&mut self, e: &Expr) {
+        let mut bindings: Vec<(&Label, Option<&Expr>, &Expr)> = Vec::new();
+        let mut rest = e;
+        while let ExprKind::Let(x, t, v, body) = rest.kind() {
+            if let Some(t) = t {
+                self.visit_expr(t);
+            }
+            self.visit_expr(v);
+            if x.as_ref() != "_" && !occurs(x, 0, body) {
+                self.warnings.push(Warning::new(
+                    WarningKind::UnusedLet,
+                    format!("unused let binding `{}`", x),
+                    rest,
+                ));
+            }
+            bindings.push((x, t.as_ref(), v));
+            rest = body;
+        }
+
+        if bindings.len() >= LONG_LET_CHAIN && are_independent(&bindings) {
+            self.warnings.push(Warning::new(
+                WarningKind::LongLetChain,
+                format!(
+                    "these {} `let` bindings don't refer to each other; they could be grouped \
+                     in a record",
+                    bindings.len()
+                ),
+                e,
+            ));
+        }
+        self.visit_expr(rest);
+    }
+}
+
+impl ExprVisitor for LintVisitor {
+    fn visit_expr(&mut self, e: &Expr) {
+        match e.kind() {
+            ExprKind::Let(..) => return self.visit_let_chain(e),
+            ExprKind::Op(OpKind::App(f, builder))
+                if is_list_build(f) && builds_fixed_list(builder) =>
+            {
+                self.warnings.push(Warning::new(
+                    WarningKind::ListBuild,
+                    "this `List/build` could be a list literal".to_owned(),
+                    e,
+                ));
+            }
+            _ => {}
+        }
+        walk_expr(self, e)
+    }
+}
+
+/// Whether none of the bindings refers to an earlier one.
+fn are_independent(bindings: &[(&Label, Option<&Expr>, &Expr)]) -> bool {
+    bindings.iter().enumerate().all(|(i, (_, t, v))| {
+        bindings[..i].iter().enumerate().all(|(j, (x, _, _))| {
+            // Account for the shadowing by the bindings in between.
+            let n =
+                bindings[j + 1..i].iter().filter(|(y, _, _)| y == x).count();
+            !occurs(x, n, v) && !matches!(t, Some(t) if occurs(x, n, t))
+        })
+    })
+}
+
+fn is_list_build(e: &Expr) -> bool {
+    match e.kind() {
+        ExprKind::Op(OpKind::App(f, _)) => {
+            matches!(f.kind(), ExprKind::Builtin(Builtin::ListBuild))
+        }
+        _ => false,
+    }
+}
+
+/// Whether `e` has the form `λ(list : Type) → λ(cons : ..) → λ(nil : list) → cons a (cons b nil)`
+/// where the elements don't depend on the bound variables.
+fn builds_fixed_list(e: &Expr) -> bool {
+    let (list, cons, nil, mut body) = match e.kind() {
+        ExprKind::Lam(list, _, e) => match e.kind() {
+            ExprKind::Lam(cons, _, e) => match e.kind() {
+                ExprKind::Lam(nil, _, body) => (list, cons, nil, body),
+                _ => return false,
+            },
+            _ => return false,
+        },
+        _ => return false,
+    };
+    if list == cons || list == nil || cons == nil {
+        return false;
+    }
+    let is_var = |e: &Expr, x: &Label| match e.kind() {
+        ExprKind::Var(V(y, 0)) => x == y,
+        _ => false,
+    };
+    loop {
+        if is_var(body, nil) {
+            return true;
+        }
+        match body.kind() {
+            ExprKind::Op(OpKind::App(f, tail)) => match f.kind() {
+                ExprKind::Op(OpKind::App(c, x))
+                    if is_var(c, cons)
+                        && !occurs(list, 0, x)
+                        && !occurs(cons, 0, x)
+                        && !occurs(nil, 0, x) =>
+                {
+                    body = tail
+                }
+                _ => return false,
+            },
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_expr;
+
+    fn lint_str(s: &str) -> Vec<(WarningKind, String)> {
+        lint(&parse_expr(s).unwrap())
+            .into_iter()
+            .map(|w| (w.kind, w.message))
+            .collect()
+    }
+
+    #[test]
+    fn unused_let() {
+        assert_eq!(
+            lint_str("let x = 1 in let y = 2 in y"),
+            vec![(WarningKind::UnusedLet, "unused let binding `x`".to_owned())]
+        );
+        assert_eq!(lint_str("let x = 1 in let x = x + 1 in x"), vec![]);
+        assert_eq!(lint_str("let _ = assert : 1 ≡ 1 in 2"), vec![]);
+
+        let w = lint(&parse_expr("[let x = 1 in 2]").unwrap());
+        assert_eq!(w[0].span, Some((1, 15)));
+    }
+
+    #[test]
+    fn deprecated() {
+        let messages = |s| {
+            deprecations(&parse_expr(s).unwrap())
+                .into_iter()
+                .map(|w| (w.kind, w.message))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            messages("[[] : Optional Natural, [1] : Optional Natural]"),
+            vec![
+                (
+                    WarningKind::OptionalListLiteral,
+                    "`[] : Optional T` was removed from the language; write `None Natural`"
+                        .to_owned()
+                ),
+                (
+                    WarningKind::OptionalListLiteral,
+                    "`[x] : Optional T` was removed from the language; write `Some 1`"
+                        .to_owned()
+                )
+            ]
+        );
+        assert_eq!(
+            messages("let T = < A | B > in (constructors T).A"),
+            vec![(
+                WarningKind::Constructors,
+                "`constructors` was removed from the language; use the union type directly"
+                    .to_owned()
+            )]
+        );
+        // A variable that happens to be called `constructors`.
+        assert_eq!(
+            messages("λ(constructors : Natural → Natural) → constructors 1"),
+            vec![]
+        );
+        assert_eq!(
+            messages("[None Natural, Some 1, [] : List Natural]"),
+            vec![]
+        );
+
+        // They still parse.
+        let parsed =
+            crate::Parsed::parse_str("{ x = [] : Optional Natural }").unwrap();
+        let w = parsed.warnings();
+        assert_eq!(w[0].kind, WarningKind::OptionalListLiteral);
+        assert_eq!(w[0].span, Some((6, 27)));
+    }
+
+    #[test]
+    fn list_build() {
+        let kinds =
+            |s| lint_str(s).into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+        assert_eq!(
+            kinds(
+                "List/build Natural (λ(list : Type) → λ(cons : Natural → list → list) → \
+                 λ(nil : list) → cons 1 (cons 2 nil))"
+            ),
+            vec![WarningKind::ListBuild]
+        );
+        // Not a fixed list.
+        assert_eq!(
+            kinds(
+                "λ(f : ∀(list : Type) → ∀(cons : Natural → list → list) → ∀(nil : list) → list) \
+                 → List/build Natural f"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            kinds(
+                "List/build Natural (λ(list : Type) → λ(cons : Natural → list → list) → \
+                 λ(nil : list) → cons 1 (cons 2 (cons 3 nil)) : list)"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn long_let_chain() {
+        let chain = |n: usize, dependent: bool| {
+            let mut s = String::new();
+            for i in 0..n {
+                if dependent && i > 0 {
+                    s += &format!("let x{} = x{} in ", i, i - 1);
+                } else {
+                    s += &format!("let x{} = {} in ", i, i);
+                }
+            }
+            s += "[";
+            s += &(0..n)
+                .map(|i| format!("x{}", i))
+                .collect::<Vec<_>>()
+                .join(", ");
+            s += "]";
+            lint_str(&s)
+        };
+        assert_eq!(
+            chain(10, false),
+            vec![(
+                WarningKind::LongLetChain,
+                "these 10 `let` bindings don't refer to each other; they could be grouped in \
+                 a record"
+                    .to_owned()
+            )]
+        );
+        assert_eq!(chain(9, false), vec![]);
+        assert_eq!(chain(10, true), vec![]);
+    }
+}
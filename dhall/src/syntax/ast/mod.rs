@@ -1,9 +1,19 @@
+mod diff;
+pub use diff::*;
+mod equal;
+pub use equal::*;
 mod expr;
 pub use expr::*;
 mod import;
 pub use import::*;
 mod label;
 pub use label::*;
+mod lint;
+pub use lint::*;
+mod rewrite;
+pub use rewrite::*;
+mod shake;
+pub use shake::*;
 mod span;
 pub use span::*;
 mod text;
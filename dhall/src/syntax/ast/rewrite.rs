@@ -0,0 +1,640 @@
+use crate::syntax::visitor::{fold_subexprs, ExprFolder, LabelCollector};
+use crate::syntax::{trivial_result, Expr, ExprKind, Label, Span, V, X};
+
+/// Rewrites an expression for automated refactoring. Rewrites can be chained; each one takes the
+/// result of the previous one.
+///
+/// ```
+/// # use dhall::syntax::{parse_expr, Rewriter};
+/// let expr = parse_expr("let x = 1 in λ(y : Natural) → x + y + 1").unwrap();
+/// let expr = Rewriter::new(expr)
+///     .inline_let(&"x".into())
+///     .rename_label(&"y".into(), "x".into())
+///     .unwrap()
+///     .into_expr();
+/// assert_eq!(expr.to_string(), "λ(x : Natural) → 1 + x + 1");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rewriter {
+    expr: Expr,
+}
+
+/// An error from a [`Rewriter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteError {
+    /// Both labels are used by free variables, which renaming would merge.
+    FreeVariableClash(Label, Label),
+}
+
+impl std::fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RewriteError::FreeVariableClash(old, new) => write!(
+                f,
+                "can't rename `{}` to `{}`: both are free variables",
+                String::from(old),
+                String::from(new)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RewriteError {}
+
+impl Rewriter {
+    pub fn new(expr: Expr) -> Self {
+        Rewriter { expr }
+    }
+
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    pub fn into_expr(self) -> Expr {
+        self.expr
+    }
+
+    /// Renames the variables called `old` to `new`, both their binders and their uses, including
+    /// free variables. Record fields and union alternatives are left alone.
+    ///
+    /// The result is alpha-equivalent to the input when `old` is not free in it: when a renamed
+    /// variable would be shadowed by a binder called `new`, it is referred to as `new@n`.
+    pub fn rename_label(
+        self,
+        old: &Label,
+        new: Label,
+    ) -> Result<Self, RewriteError> {
+        if old == &new {
+            return Ok(self);
+        }
+        if occurs_free(old, &self.expr) && occurs_free(&new, &self.expr) {
+            return Err(RewriteError::FreeVariableClash(old.clone(), new));
+        }
+        let rename = |l: &Label| if l == old { new.clone() } else { l.clone() };
+        let expr = rename_vars(&self.expr, &rename);
+        Ok(Rewriter { expr })
+    }
+
+    /// Removes the `let` bindings of `name`, replacing the uses of each with its value. The value
+    /// keeps its type annotation, if any.
+    pub fn inline_let(self, name: &Label) -> Self {
+        Rewriter {
+            expr: inline_let(name, &self.expr),
+        }
+    }
+
+    /// Binds a sub-expression to `name` with a `let` around the whole expression, and replaces it
+    /// with `name`.
+    ///
+    /// The extracted expression is the first one, from the outside in, for which `pred` holds and
+    /// that doesn't use variables bound in the expression. Its other occurrences for which `pred`
+    /// holds are replaced too. Nothing changes if there is no such expression.
+    pub fn extract_subexpr(
+        self,
+        pred: impl Fn(&Expr) -> bool,
+        name: Label,
+    ) -> Self {
+        let mut extract = Extract::new(&pred, &name);
+        let body = trivial_result(extract.fold_expr(&self.expr));
+        match extract.extracted {
+            Some(value) => Rewriter {
+                expr: Expr::new(
+                    ExprKind::Let(name, None, value, body),
+                    Span::Artificial,
+                ),
+            },
+            None => self,
+        }
+    }
+}
+
+/// Alpha-normalizes `e`, as the standard requires before hashing: the bound variables are renamed
+/// to `_`, so that alpha-equivalent expressions have the same binary encoding. Free variables
+/// keep their name, with their index counted from the top of the expression. A free `_` also
+/// skips all the binders around it, since they are all called `_` in the result.
+///
+/// ```
+/// # use dhall::syntax::{alpha_normalize, parse_expr};
+/// let e = parse_expr("λ(x : Natural) → λ(y : Natural) → x + y + z").unwrap();
+/// assert_eq!(
+///     alpha_normalize(&e).to_string(),
+///     "λ(_ : Natural) → λ(_ : Natural) → _@1 + _ + z"
+/// );
+/// ```
+pub fn alpha_normalize(e: &Expr) -> Expr {
+    struct AlphaNormalize {
+        binders: Vec<Label>,
+    }
+    impl ExprFolder for AlphaNormalize {
+        type Error = X;
+        fn fold_var(&mut self, e: &Expr, V(x, n): &V) -> Result<Expr, X> {
+            let binders = &self.binders;
+            let target = binders
+                .iter()
+                .rev()
+                .enumerate()
+                .filter(|(_, l)| *l == x)
+                .nth(*n);
+            let v = match target {
+                Some((i, _)) => V("_".into(), i),
+                None => {
+                    let depth = binders.iter().filter(|l| *l == x).count();
+                    let n = if x == &Label::from("_") {
+                        n - depth + binders.len()
+                    } else {
+                        n - depth
+                    };
+                    V(x.clone(), n)
+                }
+            };
+            Ok(Expr::new(ExprKind::Var(v), e.span()))
+        }
+        fn fold_under_binder(
+            &mut self,
+            l: &Label,
+            body: &Expr,
+        ) -> Result<Expr, X> {
+            self.binders.push(l.clone());
+            let body = self.fold_expr(body);
+            self.binders.pop();
+            body
+        }
+        fn fold_binder_label(&mut self, _l: &Label) -> Label {
+            "_".into()
+        }
+    }
+    trivial_result(
+        AlphaNormalize {
+            binders: Vec::new(),
+        }
+        .fold_expr(e),
+    )
+}
+
+/// Whether a variable called `x` is free in `e`.
+fn occurs_free(x: &Label, e: &Expr) -> bool {
+    LabelCollector::collect(e).contains(x)
+}
+
+/// Renames the binders and variables of `e` with `rename`.
+fn rename_vars(e: &Expr, rename: &impl Fn(&Label) -> Label) -> Expr {
+    struct RenameVars<'a, R> {
+        rename: &'a R,
+        /// The names of the variables bound around the current node, innermost last.
+        binders: Vec<Label>,
+    }
+    impl<R: Fn(&Label) -> Label> ExprFolder for RenameVars<'_, R> {
+        type Error = X;
+        fn fold_var(&mut self, e: &Expr, V(x, n): &V) -> Result<Expr, X> {
+            let (rename, binders) = (self.rename, &self.binders);
+            let new_x = rename(x);
+            // The position of the binder of the variable, if it is bound.
+            let target = binders
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, l)| *l == x)
+                .nth(*n)
+                .map(|(i, _)| i);
+            let new_n = match target {
+                Some(i) => binders[i + 1..]
+                    .iter()
+                    .filter(|l| rename(l) == new_x)
+                    .count(),
+                None => {
+                    let free = n - binders.iter().filter(|l| *l == x).count();
+                    binders.iter().filter(|l| rename(l) == new_x).count() + free
+                }
+            };
+            Ok(Expr::new(ExprKind::Var(V(new_x, new_n)), e.span()))
+        }
+        fn fold_under_binder(
+            &mut self,
+            l: &Label,
+            body: &Expr,
+        ) -> Result<Expr, X> {
+            self.binders.push(l.clone());
+            let body = self.fold_expr(body);
+            self.binders.pop();
+            body
+        }
+        fn fold_binder_label(&mut self, l: &Label) -> Label {
+            (self.rename)(l)
+        }
+    }
+    let mut folder = RenameVars {
+        rename,
+        binders: Vec::new(),
+    };
+    trivial_result(folder.fold_expr(e))
+}
+
+fn inline_let(name: &Label, e: &Expr) -> Expr {
+    struct InlineLet<'a> {
+        name: &'a Label,
+    }
+    impl ExprFolder for InlineLet<'_> {
+        type Error = X;
+        fn fold_expr(&mut self, e: &Expr) -> Result<Expr, X> {
+            let e = fold_subexprs(self, e)?;
+            Ok(match e.kind() {
+                ExprKind::Let(x, t, v, body) if x == self.name => {
+                    let v = match t {
+                        Some(t) => Expr::new(
+                            ExprKind::Annot(v.clone(), t.clone()),
+                            Span::Artificial,
+                        ),
+                        None => v.clone(),
+                    };
+                    let body = subst(x, 0, &shift(1, x, 0, &v), body);
+                    shift(-1, x, 0, &body)
+                }
+                _ => e,
+            })
+        }
+    }
+    trivial_result(InlineLet { name }.fold_expr(e))
+}
+
+/// Adds `d` to the indices of the variables `x@m` of `e` with `m >= min`.
+pub(crate) fn shift(d: isize, x: &Label, min: usize, e: &Expr) -> Expr {
+    struct Shift<'a> {
+        d: isize,
+        x: &'a Label,
+        min: usize,
+    }
+    impl ExprFolder for Shift<'_> {
+        type Error = X;
+        fn fold_var(&mut self, e: &Expr, V(y, m): &V) -> Result<Expr, X> {
+            Ok(if self.x == y && *m >= self.min {
+                let v = V(y.clone(), (*m as isize + self.d) as usize);
+                Expr::new(ExprKind::Var(v), e.span())
+            } else {
+                e.clone()
+            })
+        }
+        fn fold_under_binder(
+            &mut self,
+            l: &Label,
+            body: &Expr,
+        ) -> Result<Expr, X> {
+            if l != self.x {
+                return self.fold_expr(body);
+            }
+            self.min += 1;
+            let body = self.fold_expr(body);
+            self.min -= 1;
+            body
+        }
+    }
+    trivial_result(Shift { d, x, min }.fold_expr(e))
+}
+
+/// Replaces the variable `x@n` with `v` in `e`.
+pub(crate) fn subst(x: &Label, n: usize, v: &Expr, e: &Expr) -> Expr {
+    struct Subst<'a> {
+        x: &'a Label,
+        n: usize,
+        v: Expr,
+    }
+    impl ExprFolder for Subst<'_> {
+        type Error = X;
+        fn fold_var(&mut self, e: &Expr, V(y, m): &V) -> Result<Expr, X> {
+            Ok(if self.x == y && self.n == *m {
+                self.v.clone()
+            } else {
+                e.clone()
+            })
+        }
+        fn fold_under_binder(
+            &mut self,
+            l: &Label,
+            body: &Expr,
+        ) -> Result<Expr, X> {
+            let n = self.n;
+            let v = shift(1, l, 0, &self.v);
+            let v = std::mem::replace(&mut self.v, v);
+            if l == self.x {
+                self.n += 1;
+            }
+            let body = self.fold_expr(body);
+            self.n = n;
+            self.v = v;
+            body
+        }
+    }
+    let mut folder = Subst { x, n, v: v.clone() };
+    trivial_result(folder.fold_expr(e))
+}
+
+/// Replaces the expressions to extract with `name`, and shifts the free variables called `name` to
+/// make room for its binder. `extracted` is the extracted expression, set at the first match.
+struct Extract<'a, P> {
+    pred: &'a P,
+    name: &'a Label,
+    extracted: Option<Expr>,
+    /// The names of the variables bound around the current node, innermost last.
+    binders: Vec<Label>,
+}
+
+impl<'a, P: Fn(&Expr) -> bool> Extract<'a, P> {
+    fn new(pred: &'a P, name: &'a Label) -> Self {
+        Extract {
+            pred,
+            name,
+            extracted: None,
+            binders: Vec::new(),
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.binders.iter().filter(|l| *l == self.name).count()
+    }
+}
+
+impl<P: Fn(&Expr) -> bool> ExprFolder for Extract<'_, P> {
+    type Error = X;
+    fn fold_expr(&mut self, e: &Expr) -> Result<Expr, X> {
+        if (self.pred)(e) {
+            if let Some(lifted) = lift(e, &self.binders) {
+                if self.extracted.get_or_insert_with(|| lifted.clone())
+                    == &lifted
+                {
+                    let var = V(self.name.clone(), self.depth());
+                    return Ok(Expr::new(ExprKind::Var(var), e.span()));
+                }
+            }
+        }
+        fold_subexprs(self, e)
+    }
+    fn fold_var(&mut self, e: &Expr, V(x, n): &V) -> Result<Expr, X> {
+        Ok(if x == self.name && *n >= self.depth() {
+            Expr::new(ExprKind::Var(V(x.clone(), n + 1)), e.span())
+        } else {
+            e.clone()
+        })
+    }
+    fn fold_under_binder(&mut self, l: &Label, body: &Expr) -> Result<Expr, X> {
+        self.binders.push(l.clone());
+        let body = self.fold_expr(body);
+        self.binders.pop();
+        body
+    }
+}
+
+/// Moves `e` out of the `outer` binders around it, by adjusting the indices of its free variables.
+/// Fails if it uses one of these binders.
+fn lift(e: &Expr, outer: &[Label]) -> Option<Expr> {
+    struct Lift<'a> {
+        outer: &'a [Label],
+        /// The binders inside `e` around the current node.
+        inner: Vec<Label>,
+    }
+    impl ExprFolder for Lift<'_> {
+        type Error = ();
+        fn fold_var(&mut self, e: &Expr, V(x, n): &V) -> Result<Expr, ()> {
+            let count =
+                |binders: &[Label]| binders.iter().filter(|l| *l == x).count();
+            let n_inner = count(&self.inner);
+            let n_outer = count(self.outer);
+            if *n < n_inner {
+                Ok(e.clone())
+            } else if *n < n_inner + n_outer {
+                Err(())
+            } else {
+                let v = V(x.clone(), n - n_outer);
+                Ok(Expr::new(ExprKind::Var(v), e.span()))
+            }
+        }
+        fn fold_under_binder(
+            &mut self,
+            l: &Label,
+            body: &Expr,
+        ) -> Result<Expr, ()> {
+            self.inner.push(l.clone());
+            let body = self.fold_expr(body);
+            self.inner.pop();
+            body
+        }
+    }
+    let mut folder = Lift {
+        outer,
+        inner: Vec::new(),
+    };
+    folder.fold_expr(e).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::operations::OpKind;
+    use crate::syntax::{binary, parse_expr, Hash, NumKind};
+
+    fn rewrite(s: &str) -> Rewriter {
+        Rewriter::new(parse_expr(s).unwrap())
+    }
+
+    fn expr(kind: ExprKind<Expr>) -> Expr {
+        Expr::new(kind, Span::Artificial)
+    }
+
+    /// Expressions made of variables called `x`, `y`, `z` and `_`, functions, `let`s and
+    /// applications.
+    fn arb_expr() -> impl Strategy<Value = Expr> {
+        let label = || prop_oneof![Just("x"), Just("y"), Just("z"), Just("_")];
+        let leaf = prop_oneof![
+            (label(), 0..3usize)
+                .prop_map(|(x, n)| expr(ExprKind::Var(V(x.into(), n)))),
+            (0..3u64).prop_map(|n| expr(ExprKind::Num(NumKind::Natural(n)))),
+        ];
+        leaf.prop_recursive(5, 48, 3, move |inner| {
+            prop_oneof![
+                (label(), inner.clone(), inner.clone())
+                    .prop_map(|(x, t, e)| expr(ExprKind::Lam(x.into(), t, e))),
+                (label(), inner.clone(), inner.clone()).prop_map(
+                    |(x, v, e)| expr(ExprKind::Let(x.into(), None, v, e))
+                ),
+                (inner.clone(), inner)
+                    .prop_map(|(f, a)| expr(ExprKind::Op(OpKind::App(f, a)))),
+            ]
+        })
+    }
+
+    /// The free variables of `e`, in order, with their index counted from the top of `e`.
+    fn free_vars(e: &Expr, binders: &mut Vec<Label>, out: &mut Vec<V>) {
+        match e.kind() {
+            ExprKind::Var(V(x, n)) => {
+                let depth = binders.iter().filter(|l| *l == x).count();
+                if *n >= depth {
+                    out.push(V(x.clone(), n - depth));
+                }
+            }
+            kind => {
+                kind.map_ref_maybe_binder(|l, e| match l {
+                    Some(l) => {
+                        binders.push(l.clone());
+                        free_vars(e, binders, out);
+                        binders.pop();
+                    }
+                    None => free_vars(e, binders, out),
+                });
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn alpha_normalize_keeps_free_variables(e in arb_expr()) {
+            let (mut before, mut after) = (Vec::new(), Vec::new());
+            free_vars(&e, &mut Vec::new(), &mut before);
+            free_vars(&alpha_normalize(&e), &mut Vec::new(), &mut after);
+            prop_assert_eq!(before, after);
+        }
+
+        #[test]
+        fn rename_is_alpha_equivalent(e in arb_expr()) {
+            let (x, y) = (Label::from("x"), Label::from("y"));
+            match Rewriter::new(e.clone()).rename_label(&x, y.clone()) {
+                Ok(renamed) => {
+                    // Only the free `x`s are renamed in the alpha-normal form.
+                    let expected = alpha_normalize(&e).map_expr(|e| match e.kind() {
+                        ExprKind::Var(V(l, n)) if l == &x => {
+                            expr(ExprKind::Var(V(y.clone(), *n)))
+                        }
+                        _ => e.clone(),
+                    });
+                    prop_assert_eq!(alpha_normalize(renamed.expr()), expected);
+                }
+                Err(_) => {
+                    prop_assert!(occurs_free(&x, &e) && occurs_free(&y, &e))
+                }
+            }
+        }
+
+        #[test]
+        fn alpha_equivalent_exprs_hash_equal(e in arb_expr()) {
+            // Renaming the labels that aren't free gives an alpha-equivalent expression.
+            let mut renamed = Rewriter::new(e.clone());
+            for (old, new) in [("x", "a"), ("y", "b"), ("z", "c")].iter() {
+                let old = Label::from(*old);
+                if !occurs_free(&old, &e) {
+                    renamed = renamed.rename_label(&old, (*new).into()).unwrap();
+                }
+            }
+            let (e, renamed) = (alpha_normalize(&e), alpha_normalize(renamed.expr()));
+            prop_assert_eq!(binary::encode(&e).unwrap(), binary::encode(&renamed).unwrap());
+            prop_assert_eq!(e.sha256_hash().unwrap(), renamed.sha256_hash().unwrap());
+            prop_assert_eq!(alpha_normalize(&e), e);
+        }
+
+        #[test]
+        fn extract_then_inline(e in arb_expr()) {
+            let is_app = |e: &Expr| matches!(e.kind(), ExprKind::Op(OpKind::App(..)));
+            let w = Label::from("w");
+            let roundtrip = Rewriter::new(e.clone())
+                .extract_subexpr(is_app, w.clone())
+                .inline_let(&w);
+            prop_assert_eq!(alpha_normalize(roundtrip.expr()), alpha_normalize(&e));
+
+            // With a name that is already bound in the expression.
+            let x = Label::from("x");
+            let roundtrip = Rewriter::new(e.clone())
+                .extract_subexpr(is_app, x.clone())
+                .inline_let(&x);
+            let inlined = Rewriter::new(e).inline_let(&x);
+            prop_assert_eq!(alpha_normalize(roundtrip.expr()), alpha_normalize(inlined.expr()));
+        }
+    }
+
+    #[test]
+    fn alpha_normalize_before_hashing() {
+        let a = parse_expr("λ(x : Natural) → λ(y : Natural) → x + y").unwrap();
+        let b = parse_expr("λ(a : Natural) → λ(x : Natural) → a + x").unwrap();
+        assert_ne!(a.sha256_hash().unwrap(), b.sha256_hash().unwrap());
+        let hash = alpha_normalize(&a).sha256_hash().unwrap();
+        assert_eq!(alpha_normalize(&b).sha256_hash().unwrap(), hash);
+        // Free variables called `_` are not captured by the renamed binders.
+        let normalized =
+            |s: &str| alpha_normalize(&parse_expr(s).unwrap()).to_string();
+        assert_eq!(normalized("λ(x : Natural) → _"), "λ(_ : Natural) → _@1");
+        assert_eq!(
+            normalized("λ(_ : Natural) → λ(x : Natural) → _@1"),
+            "λ(_ : Natural) → λ(_ : Natural) → _@2"
+        );
+        // Fingerprints hash the alpha-normal form of the normal form.
+        let hash = Hash::SHA256(hash).to_string();
+        assert_eq!(crate::fingerprint(&a).unwrap(), hash);
+        assert_eq!(crate::fingerprint(&b).unwrap(), hash);
+    }
+
+    #[test]
+    fn rename_label() {
+        let renamed = |s: &str| {
+            rewrite(s)
+                .rename_label(&"x".into(), "y".into())
+                .map(|r| r.expr().to_string())
+        };
+        assert_eq!(
+            renamed("λ(x : Natural) → { x = x }").unwrap(),
+            "λ(y : Natural) → { x = y }"
+        );
+        // A renamed variable that would be captured gets an index.
+        assert_eq!(
+            renamed("λ(x : Natural) → λ(y : Natural) → x + y").unwrap(),
+            "λ(y : Natural) → λ(y : Natural) → y@1 + y"
+        );
+        assert_eq!(renamed("x + z").unwrap(), "y + z");
+        assert_eq!(
+            renamed("x + y"),
+            Err(RewriteError::FreeVariableClash("x".into(), "y".into()))
+        );
+    }
+
+    #[test]
+    fn inline_let() {
+        let inlined =
+            |s: &str| rewrite(s).inline_let(&"x".into()).expr().to_string();
+        assert_eq!(
+            inlined("let x = 1 in let y = x in x + y"),
+            "let y = 1 in 1 + y"
+        );
+        assert_eq!(inlined("let x : Natural = 1 in x"), "1 : Natural");
+        // The value doesn't get captured.
+        assert_eq!(
+            inlined("let x = y in λ(y : Natural) → x + y"),
+            "λ(y : Natural) → y@1 + y"
+        );
+        // Shadowed bindings are inlined too.
+        assert_eq!(
+            inlined("let x = 1 in let x = x + 1 in x@1 + x"),
+            "1 + (1 + 1)"
+        );
+    }
+
+    #[test]
+    fn extract_subexpr() {
+        let extracted = |s: &str, sub: &str| {
+            let sub = parse_expr(sub).unwrap();
+            rewrite(s)
+                .extract_subexpr(|e| e == &sub, "three".into())
+                .expr()
+                .to_string()
+        };
+        assert_eq!(
+            extracted("λ(n : Natural) → [ 1 + 2, n + (1 + 2) ]", "1 + 2"),
+            "let three = 1 + 2 in λ(n : Natural) → [three, n + three]"
+        );
+        // Expressions that use bound variables can't be extracted.
+        assert_eq!(
+            extracted("λ(n : Natural) → n + 1", "n + 1"),
+            "λ(n : Natural) → n + 1"
+        );
+        // Free variables called like the new binding are shifted.
+        assert_eq!(
+            extracted("λ(three : Natural) → three + (1 + 2) + three@1", "1 + 2"),
+            "let three = 1 + 2 in λ(three : Natural) → three + three@1 + three@2"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::syntax::{Expr, ExprKind, Label, V};
+
+/// Remove the `let` bindings whose variable is never used.
+///
+/// The result is equivalent to the input, but can be much smaller, e.g. when only a few of the
+/// names bound by a large file are actually used. Sub-expressions are shaken before their
+/// enclosing `let` is inspected, so bindings that only become dead once another binding has been
+/// removed are removed too.
+pub fn shake(e: &Expr) -> Expr {
+    let kind = e.kind().map_ref(shake);
+    match kind {
+        ExprKind::Let(x, _, _, body) if !occurs(&x, 0, &body) => {
+            shift_down(&x, 0, &body)
+        }
+        kind => Expr::new(kind, e.span()),
+    }
+}
+
+/// Whether the variable `x@n` appears free in `e`.
+pub(super) fn occurs(x: &Label, n: usize, e: &Expr) -> bool {
+    match e.kind() {
+        ExprKind::Var(V(y, m)) => x == y && n == *m,
+        kind => {
+            let mut found = false;
+            kind.map_ref_maybe_binder(|l, e| {
+                let n = if l == Some(x) { n + 1 } else { n };
+                found = found || occurs(x, n, e);
+            });
+            found
+        }
+    }
+}
+
+/// Decrement the indices of the free variables `x@m` in `e` that have `m > n`. This is what's
+/// needed after removing the binder for `x@n` when it doesn't occur in `e`.
+fn shift_down(x: &Label, n: usize, e: &Expr) -> Expr {
+    let kind = match e.kind() {
+        ExprKind::Var(V(y, m)) if x == y && *m > n => {
+            ExprKind::Var(V(y.clone(), m - 1))
+        }
+        kind => kind.map_ref_maybe_binder(|l, e| {
+            let n = if l == Some(x) { n + 1 } else { n };
+            shift_down(x, n, e)
+        }),
+    };
+    Expr::new(kind, e.span())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_expr;
+
+    fn shaken(s: &str) -> String {
+        shake(&parse_expr(s).unwrap()).to_string()
+    }
+
+    #[test]
+    fn unused_lets() {
+        assert_eq!(shaken("let x = 1 in 2"), "2");
+        assert_eq!(shaken("let x = 1 in x"), "let x = 1 in x");
+        // Removing `y` makes `x` dead.
+        assert_eq!(shaken("let x = 1 in let y = x in 2"), "2");
+        // Unused bindings are also removed in nested expressions.
+        assert_eq!(
+            shaken("λ(a : Bool) → [let x = 1 in a, let y = 2 in y]"),
+            "λ(a : Bool) → [a, let y = 2 in y]"
+        );
+    }
+
+    #[test]
+    fn shifts_indices() {
+        assert_eq!(shaken("let x = 1 in let x = 2 in x@1"), "let x = 1 in x");
+        assert_eq!(
+            shaken("λ(x : Bool) → let x = 1 in λ(x : Text) → x@2"),
+            "λ(x : Bool) → λ(x : Text) → x@1"
+        );
+    }
+
+    #[test]
+    fn long_chain() {
+        let n = 20;
+        let mut s = "let first = 0 in ".to_string();
+        for i in 0..n {
+            s += &format!("let x{} = first + {} in ", i, i);
+        }
+        s += "let last = 1 in first + last";
+        assert_eq!(shaken(&s), "let first = 0 in let last = 1 in first + last");
+    }
+}
@@ -1,5 +1,7 @@
 use std::rc::Rc;
 
+use crate::syntax::Label;
+
 /// A location in the source text
 #[derive(Debug, Clone)]
 pub struct ParsedSpan {
@@ -14,10 +16,24 @@ pub struct ParsedSpan {
     end: usize,
 }
 
+/// A comment found in the source text, kept so that it can be printed back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Comment {
+    /// `--text`, up to the end of the line.
+    Line(String),
+    /// `{-text-}`
+    Block(String),
+}
+
 #[derive(Debug, Clone)]
 pub enum Span {
     /// A location in the source text
     Parsed(ParsedSpan),
+    /// Another span, with the comments that preceded the expression in the source.
+    Commented(Rc<[Comment]>, Box<Span>),
+    /// Another span, for a record whose fields were written in this order instead of the sorted
+    /// order.
+    FieldOrder(Rc<[Label]>, Box<Span>),
     /// Desugarings
     DuplicateRecordFieldsSugar,
     DottedFieldSugar,
@@ -32,6 +48,18 @@ impl ParsedSpan {
     pub fn to_input(&self) -> String {
         self.input.to_string()
     }
+    /// The text the span points into.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+    /// The byte offset of the start of the span in the input.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+    /// The byte offset of the end of the span in the input.
+    pub fn end(&self) -> usize {
+        self.end
+    }
     /// Convert to a char range for consumption by annotate_snippets.
     /// This compensates for  https://github.com/rust-lang/annotate-snippets-rs/issues/24
     pub fn as_char_range(&self) -> (usize, usize) {
@@ -51,6 +79,72 @@ impl Span {
         })
     }
 
+    /// Attaches comments to the span, after the ones it already has.
+    pub fn with_comments(self, comments: Vec<Comment>) -> Self {
+        if comments.is_empty() {
+            return self;
+        }
+        match self {
+            Span::Commented(old, span) => {
+                let all: Vec<_> = old.iter().cloned().chain(comments).collect();
+                Span::Commented(all.into(), span)
+            }
+            span => Span::Commented(comments.into(), Box::new(span)),
+        }
+    }
+
+    /// Records that the fields of the record at this span were written in the order `labels`.
+    /// Nothing is recorded when that is the sorted order.
+    pub fn with_field_order(self, labels: Vec<Label>) -> Self {
+        if labels.windows(2).all(|w| w[0] < w[1]) {
+            return self;
+        }
+        match self {
+            Span::Commented(comments, span) => Span::Commented(
+                comments,
+                Box::new(span.with_field_order(labels)),
+            ),
+            span => Span::FieldOrder(labels.into(), Box::new(span)),
+        }
+    }
+
+    /// The order in which the fields of the record at this span were written, if it isn't the
+    /// sorted order.
+    pub fn field_order(&self) -> Option<&Rc<[Label]>> {
+        match self {
+            Span::FieldOrder(labels, _) => Some(labels),
+            Span::Commented(_, span) => span.field_order(),
+            _ => None,
+        }
+    }
+
+    /// The comments attached to the span.
+    pub fn comments(&self) -> &[Comment] {
+        match self {
+            Span::Commented(comments, _) => comments,
+            _ => &[],
+        }
+    }
+
+    /// The location in the source text, if any.
+    pub fn parsed(&self) -> Option<&ParsedSpan> {
+        match self {
+            Span::Parsed(span) => Some(span),
+            Span::Commented(_, span) | Span::FieldOrder(_, span) => {
+                span.parsed()
+            }
+            _ => None,
+        }
+    }
+
+    /// The span without its comments.
+    pub fn without_comments(self) -> Self {
+        match self {
+            Span::Commented(_, span) => *span,
+            span => span,
+        }
+    }
+
     /// Takes the union of the two spans, i.e. the range of input covered by the two spans plus any
     /// input between them. Assumes that the spans come from the same input. Fails if one of the
     /// spans does not point to an input location.
@@ -58,6 +152,10 @@ impl Span {
         use std::cmp::{max, min};
         use Span::*;
         match (self, other) {
+            (Commented(_, x), y)
+            | (y, Commented(_, x))
+            | (FieldOrder(_, x), y)
+            | (y, FieldOrder(_, x)) => x.union(y),
             (Parsed(x), Parsed(y)) if Rc::ptr_eq(&x.input, &y.input) => {
                 Parsed(ParsedSpan {
                     input: x.input.clone(),
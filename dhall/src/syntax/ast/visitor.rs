@@ -1,6 +1,9 @@
 use itertools::Itertools;
+use std::collections::{BTreeMap, BTreeSet};
 use std::iter::FromIterator;
 
+use crate::builtins::Builtin;
+use crate::operations::OpKind;
 use crate::syntax::*;
 
 fn opt<'a, T, U, Err>(
@@ -69,5 +72,238 @@ where
         Annot(x, t) => Annot(expr!(x), expr!(t)),
         Assert(e) => Assert(expr!(e)),
         Import(i) => Import(i.traverse_ref(expr!())?),
+        Error(e) => Error(e.clone()),
     })
 }
+
+/// A read-only traversal of an [`Expr`], with one method per kind of node.
+///
+/// The default implementations just visit the sub-expressions, so implementors only need to
+/// override the cases they care about. To do something on every node, override [`visit_expr`]
+/// and call [`walk_expr`] from it to keep recursing.
+///
+/// [`visit_expr`]: ExprVisitor::visit_expr
+pub trait ExprVisitor {
+    /// Called on every node; dispatches to the method for the kind of node.
+    fn visit_expr(&mut self, e: &Expr) {
+        walk_expr(self, e)
+    }
+
+    fn visit_const(&mut self, _c: Const) {}
+    fn visit_num(&mut self, _n: &NumKind) {}
+    fn visit_builtin(&mut self, _b: Builtin) {}
+    fn visit_var(&mut self, _v: &V) {}
+    fn visit_error(&mut self, _e: &ErrorNode) {}
+    fn visit_text_lit(&mut self, t: &InterpolatedText<Expr>) {
+        for e in t.iter() {
+            if let InterpolatedTextContents::Expr(e) = e {
+                self.visit_expr(e);
+            }
+        }
+    }
+    fn visit_some_lit(&mut self, e: &Expr) {
+        self.visit_expr(e);
+    }
+    fn visit_empty_list_lit(&mut self, t: &Expr) {
+        self.visit_expr(t);
+    }
+    fn visit_ne_list_lit(&mut self, es: &[Expr]) {
+        for e in es {
+            self.visit_expr(e);
+        }
+    }
+    fn visit_record_type(&mut self, kts: &BTreeMap<Label, Expr>) {
+        for t in kts.values() {
+            self.visit_expr(t);
+        }
+    }
+    fn visit_record_lit(&mut self, kvs: &BTreeMap<Label, Expr>) {
+        for v in kvs.values() {
+            self.visit_expr(v);
+        }
+    }
+    fn visit_union_type(&mut self, kts: &BTreeMap<Label, Option<Expr>>) {
+        for t in kts.values().flatten() {
+            self.visit_expr(t);
+        }
+    }
+    fn visit_lam(&mut self, _l: &Label, annot: &Expr, body: &Expr) {
+        self.visit_expr(annot);
+        self.visit_expr(body);
+    }
+    fn visit_pi(&mut self, _l: &Label, annot: &Expr, body: &Expr) {
+        self.visit_expr(annot);
+        self.visit_expr(body);
+    }
+    fn visit_let(
+        &mut self,
+        _l: &Label,
+        annot: Option<&Expr>,
+        val: &Expr,
+        body: &Expr,
+    ) {
+        if let Some(annot) = annot {
+            self.visit_expr(annot);
+        }
+        self.visit_expr(val);
+        self.visit_expr(body);
+    }
+    fn visit_op(&mut self, op: &OpKind<Expr>) {
+        op.map_ref(|e| self.visit_expr(e));
+    }
+    fn visit_annot(&mut self, x: &Expr, t: &Expr) {
+        self.visit_expr(x);
+        self.visit_expr(t);
+    }
+    fn visit_assert(&mut self, t: &Expr) {
+        self.visit_expr(t);
+    }
+    fn visit_import(&mut self, i: &Import<Expr>) {
+        i.map_ref(|e| self.visit_expr(e));
+    }
+}
+
+/// Calls the method of `visitor` corresponding to the kind of `e`.
+pub fn walk_expr<Vis: ExprVisitor + ?Sized>(visitor: &mut Vis, e: &Expr) {
+    use crate::syntax::ExprKind::*;
+    match e.kind() {
+        Const(c) => visitor.visit_const(*c),
+        Num(n) => visitor.visit_num(n),
+        Builtin(b) => visitor.visit_builtin(*b),
+        Var(v) => visitor.visit_var(v),
+        TextLit(t) => visitor.visit_text_lit(t),
+        SomeLit(e) => visitor.visit_some_lit(e),
+        EmptyListLit(t) => visitor.visit_empty_list_lit(t),
+        NEListLit(es) => visitor.visit_ne_list_lit(es),
+        RecordType(kts) => visitor.visit_record_type(kts),
+        RecordLit(kvs) => visitor.visit_record_lit(kvs),
+        UnionType(kts) => visitor.visit_union_type(kts),
+        Lam(l, t, e) => visitor.visit_lam(l, t, e),
+        Pi(l, t, e) => visitor.visit_pi(l, t, e),
+        Let(l, t, v, e) => visitor.visit_let(l, t.as_ref(), v, e),
+        Op(op) => visitor.visit_op(op),
+        Annot(x, t) => visitor.visit_annot(x, t),
+        Assert(t) => visitor.visit_assert(t),
+        Import(i) => visitor.visit_import(i),
+        Error(e) => visitor.visit_error(e),
+    }
+}
+
+/// Collects the names of the free variables of an expression.
+///
+/// ```
+/// # use dhall::syntax::{parse_expr, visitor::LabelCollector, Label};
+/// let e = parse_expr("λ(x : A) → x y@0 (λ(y : B) → y@1)").unwrap();
+/// let labels: Vec<_> = LabelCollector::collect(&e).into_iter().collect();
+/// assert_eq!(labels, vec![Label::from("A"), Label::from("B"), Label::from("y")]);
+/// ```
+#[derive(Debug, Default)]
+pub struct LabelCollector {
+    /// The binders we're currently under.
+    bound: Vec<Label>,
+    labels: BTreeSet<Label>,
+}
+
+impl LabelCollector {
+    pub fn collect(e: &Expr) -> BTreeSet<Label> {
+        let mut collector = LabelCollector::default();
+        collector.visit_expr(e);
+        collector.labels
+    }
+
+    fn visit_under_binder(&mut self, l: &Label, body: &Expr) {
+        self.bound.push(l.clone());
+        self.visit_expr(body);
+        self.bound.pop();
+    }
+}
+
+impl ExprVisitor for LabelCollector {
+    fn visit_var(&mut self, V(l, n): &V) {
+        let shadowing = self.bound.iter().filter(|b| *b == l).count();
+        if *n >= shadowing {
+            self.labels.insert(l.clone());
+        }
+    }
+    fn visit_lam(&mut self, l: &Label, annot: &Expr, body: &Expr) {
+        self.visit_expr(annot);
+        self.visit_under_binder(l, body);
+    }
+    fn visit_pi(&mut self, l: &Label, annot: &Expr, body: &Expr) {
+        self.visit_expr(annot);
+        self.visit_under_binder(l, body);
+    }
+    fn visit_let(
+        &mut self,
+        l: &Label,
+        annot: Option<&Expr>,
+        val: &Expr,
+        body: &Expr,
+    ) {
+        if let Some(annot) = annot {
+            self.visit_expr(annot);
+        }
+        self.visit_expr(val);
+        self.visit_under_binder(l, body);
+    }
+}
+
+/// A traversal that rebuilds an [`Expr`], for rewrites that need to know where each variable is
+/// bound.
+///
+/// The default implementations rebuild the expression unchanged, so implementors only need to
+/// override the cases they care about: usually [`fold_var`], and [`fold_under_binder`] to keep
+/// track of the binders around the current node. To do something on every node, override
+/// [`fold_expr`] and call [`fold_subexprs`] from it to keep recursing. The fold stops at the first
+/// error.
+///
+/// [`fold_expr`]: ExprFolder::fold_expr
+/// [`fold_var`]: ExprFolder::fold_var
+/// [`fold_under_binder`]: ExprFolder::fold_under_binder
+pub trait ExprFolder {
+    type Error;
+
+    /// Called on every node; rebuilds it from its folded sub-expressions.
+    fn fold_expr(&mut self, e: &Expr) -> Result<Expr, Self::Error> {
+        fold_subexprs(self, e)
+    }
+    /// Called on the variables; `e` is the node of `v`.
+    fn fold_var(&mut self, e: &Expr, _v: &V) -> Result<Expr, Self::Error> {
+        Ok(e.clone())
+    }
+    /// Called on the bodies of `λ`, `∀` and `let`, where `l` is bound.
+    fn fold_under_binder(
+        &mut self,
+        _l: &Label,
+        body: &Expr,
+    ) -> Result<Expr, Self::Error> {
+        self.fold_expr(body)
+    }
+    /// The new name of a binder, once its body is folded.
+    fn fold_binder_label(&mut self, l: &Label) -> Label {
+        l.clone()
+    }
+}
+
+/// Rebuilds `e` with the results of `folder` on its sub-expressions. Variables are passed to
+/// [`ExprFolder::fold_var`] instead.
+pub fn fold_subexprs<F: ExprFolder + ?Sized>(
+    folder: &mut F,
+    e: &Expr,
+) -> Result<Expr, F::Error> {
+    use crate::syntax::ExprKind::*;
+    let kind = match e.kind() {
+        Var(v) => return folder.fold_var(e, v),
+        kind => kind.traverse_ref_maybe_binder(|l, e| match l {
+            Some(l) => folder.fold_under_binder(l, e),
+            None => folder.fold_expr(e),
+        })?,
+    };
+    let kind = match kind {
+        Lam(l, t, e) => Lam(folder.fold_binder_label(&l), t, e),
+        Pi(l, t, e) => Pi(folder.fold_binder_label(&l), t, e),
+        Let(l, t, v, e) => Let(folder.fold_binder_label(&l), t, v, e),
+        kind => kind,
+    };
+    Ok(Expr::new(kind, e.span()))
+}
@@ -0,0 +1,80 @@
+use std::fmt::Write;
+
+use serde_cbor::Value;
+
+use crate::error::DecodeError;
+
+/// Decodes CBOR into its diagnostic notation (RFC 8949, section 8), without interpreting it as a
+/// dhall expression. This helps finding what is wrong with a binary file that fails to decode.
+pub fn diagnostic(data: &[u8]) -> Result<String, DecodeError> {
+    let value: Value =
+        serde_cbor::de::from_slice(data).map_err(DecodeError::CBORError)?;
+    let mut out = String::new();
+    write_value(&mut out, &value);
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => write!(out, "{}", b).unwrap(),
+        Value::Integer(n) => write!(out, "{}", n).unwrap(),
+        Value::Float(x) if x.is_nan() => out.push_str("NaN"),
+        Value::Float(x) if x.is_infinite() => {
+            out.push_str(if *x > 0.0 { "Infinity" } else { "-Infinity" })
+        }
+        Value::Float(x) => write!(out, "{:?}", x).unwrap(),
+        Value::Bytes(bytes) => {
+            out.push_str("h'");
+            for b in bytes {
+                write!(out, "{:02x}", b).unwrap();
+            }
+            out.push('\'');
+        }
+        Value::Text(s) => write_text(out, s),
+        Value::Array(values) => {
+            out.push('[');
+            for (i, v) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, v);
+            }
+            out.push(']');
+        }
+        Value::Map(map) => {
+            out.push('{');
+            for (i, (k, v)) in map.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_value(out, k);
+                out.push_str(": ");
+                write_value(out, v);
+            }
+            out.push('}');
+        }
+        Value::Tag(tag, v) => {
+            write!(out, "{}(", tag).unwrap();
+            write_value(out, v);
+            out.push(')');
+        }
+        _ => out.push_str("undefined"),
+    }
+}
+
+/// Writes a text string with the escapes of JSON.
+fn write_text(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
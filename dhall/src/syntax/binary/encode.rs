@@ -9,6 +9,7 @@ use crate::syntax::{
     Expr, ExprKind, FilePrefix, Hash, Import, ImportMode, ImportTarget, Label,
     Scheme, V,
 };
+use crate::utils::with_stack;
 
 pub fn encode(expr: &Expr) -> Result<Vec<u8>, EncodeError> {
     serde_cbor::ser::to_vec(&Serialize::Expr(expr))
@@ -114,6 +115,9 @@ where
         }
         Annot(x, y) => ser_seq!(ser; tag(26), expr(x), expr(y)),
         Assert(x) => ser_seq!(ser; tag(19), expr(x)),
+        Error(_) => Err(serde::ser::Error::custom(
+            "cannot encode an expression with syntax errors",
+        )),
         SomeLit(x) => ser_seq!(ser; tag(5), null(), expr(x)),
         EmptyListLit(x) => match x.as_ref() {
             Op(App(f, a)) => match f.as_ref() {
@@ -275,7 +279,7 @@ impl<'a> serde::ser::Serialize for Serialize<'a> {
             Text(v) => ser.serialize_str(v),
             Bytes(v) => ser.serialize_bytes(v),
 
-            Expr(e) => serialize_subexpr(ser, e),
+            Expr(e) => with_stack(|| serialize_subexpr(ser, e)),
             RecordMap(map) => {
                 ser.collect_map(map.iter().map(|(k, v)| (Label(k), Expr(v))))
             }
@@ -1,4 +1,6 @@
 mod decode;
+mod diagnostic;
 mod encode;
 pub use decode::decode;
+pub use diagnostic::diagnostic;
 pub use encode::encode;
@@ -8,7 +8,9 @@
 mod ast;
 pub use crate::syntax::ast::visitor;
 pub use crate::syntax::ast::*;
+pub use crate::syntax::text::error::*;
 pub use crate::syntax::text::parser::*;
 pub use crate::syntax::text::printer::*;
+pub use crate::syntax::text::recover::*;
 pub mod binary;
 pub mod text;
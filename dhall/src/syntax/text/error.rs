@@ -0,0 +1,254 @@
+use std::fmt;
+
+use pest::error::{ErrorVariant, InputLocation, LineColLocation};
+
+use crate::syntax::text::parser::{PestError, Rule};
+use crate::syntax::text::recover::closers;
+
+/// A syntax error, with where it happened and what the parser was looking for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The byte offset of the error in the input.
+    pub offset: usize,
+    /// The line of the error, starting at 1.
+    pub line: u32,
+    /// The column of the error in characters, starting at 1.
+    pub column: u32,
+    /// Descriptions of the tokens that would have been valid at the error, like "`λ`" or
+    /// "identifier".
+    pub expected: Vec<String>,
+    /// The token found at the error, or `None` at the end of the input.
+    pub found: Option<String>,
+    /// A suggestion to fix the error, like "did you forget to close a text literal?".
+    pub hint: Option<String>,
+    /// The error from pest, which `Display` shows with the line of the error annotated.
+    pest: Box<PestError>,
+}
+
+impl ParseError {
+    pub(crate) fn new(err: PestError, input: &str) -> Self {
+        let offset = match err.location {
+            InputLocation::Pos(pos) => pos,
+            InputLocation::Span((start, _)) => start,
+        };
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(pos) => pos,
+            LineColLocation::Span(start, _) => start,
+        };
+        let found = token_at(&input[offset..]);
+        let mut expected: Vec<String> = Vec::new();
+        if let ErrorVariant::ParsingError { positives, .. } = &err.variant {
+            for desc in positives.iter().copied().map(describe_rule) {
+                if !expected.contains(&desc) {
+                    expected.push(desc);
+                }
+            }
+        }
+        let hint = hint(input, offset, found, &expected);
+        ParseError {
+            offset,
+            line: line as u32,
+            column: column as u32,
+            expected,
+            found: found.map(str::to_owned),
+            hint,
+            pest: Box::new(err),
+        }
+    }
+
+    /// For errors that are not about an unexpected token, like a duplicate field, describes the
+    /// error.
+    pub fn message(&self) -> Option<&str> {
+        match &self.pest.variant {
+            ErrorVariant::CustomError { message } => Some(message),
+            ErrorVariant::ParsingError { .. } => None,
+        }
+    }
+
+    /// Describes the error on one line, like "error: expected `λ` or `forall`, found `{` at
+    /// line 5, column 12", followed by the hint on a second line if there is one.
+    pub fn summary(&self) -> String {
+        let mut summary = String::from("error: ");
+        match self.message() {
+            Some(message) => summary.push_str(message),
+            None => {
+                if !self.expected.is_empty() {
+                    summary.push_str(&format!(
+                        "expected {}, found ",
+                        self.expected.join(" or ")
+                    ));
+                } else {
+                    summary.push_str("unexpected ");
+                }
+                match self.found.as_deref() {
+                    None => summary.push_str("end of input"),
+                    Some("\n") => summary.push_str("end of line"),
+                    Some(found) => summary.push_str(&format!("`{}`", found)),
+                }
+            }
+        }
+        summary.push_str(&format!(
+            " at line {}, column {}",
+            self.line, self.column
+        ));
+        if let Some(hint) = &self.hint {
+            summary.push_str(&format!("\nhint: {}", hint));
+        }
+        summary
+    }
+}
+
+/// Shows the line of the error with the position annotated, and what was expected there.
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pest)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The word or character at the start of `rest`.
+fn token_at(rest: &str) -> Option<&str> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_' || c == '/';
+    let first = rest.chars().next()?;
+    let len = if is_word_char(first) {
+        rest.find(|c| !is_word_char(c)).unwrap_or(rest.len())
+    } else {
+        first.len_utf8()
+    };
+    Some(&rest[..len])
+}
+
+fn describe_rule(rule: Rule) -> String {
+    use Rule::*;
+    let token = match rule {
+        EOI => return "end of input".to_owned(),
+        import_alt | bool_or | natural_plus | text_append | list_append
+        | bool_and | natural_times | bool_eq | bool_ne | combine
+        | combine_types | equivalent | prefer | operator => {
+            return "operator".to_owned()
+        }
+        expression
+        | final_expression
+        | annotated_expression
+        | first_application_expression
+        | primitive_expression
+        | import_hashed
+        | import_expression
+        | application_expression => return "expression".to_owned(),
+        label | any_label | any_label_or_some | simple_label
+        | nonreserved_label | quoted_label => return "label".to_owned(),
+        double_quote_char
+        | double_quote_chunk
+        | single_quote_char
+        | single_quote_continue => return "text".to_owned(),
+        lambda => "λ",
+        forall | forall_symbol | forall_keyword => "forall",
+        arrow => "→",
+        complete => "::",
+        if_ => "if",
+        then => "then",
+        else_ => "else",
+        let_ | let_binding => "let",
+        in_ => "in",
+        as_ => "as",
+        using => "using",
+        merge => "merge",
+        missing => "missing",
+        Some_ => "Some",
+        toMap => "toMap",
+        assert => "assert",
+        with | with_clause => "with",
+        NaN => "NaN",
+        empty_record_literal => "=",
+        _ => {
+            return format!("{:?}", rule)
+                .trim_end_matches('_')
+                .replace('_', " ")
+        }
+    };
+    format!("`{}`", token)
+}
+
+/// Suggests a fix for common mistakes: unclosed text literals and brackets, and `let` without
+/// `in`.
+fn hint(
+    input: &str,
+    offset: usize,
+    found: Option<&str>,
+    expected: &[String],
+) -> Option<String> {
+    if closers(input).iter().any(|c| *c == "\"" || *c == "''") {
+        return Some("did you forget to close a text literal?".to_owned());
+    }
+    let is_closer =
+        matches!(found, None | Some(")") | Some("]") | Some("}") | Some(">"));
+    if is_closer {
+        if let Some(closer) = closers(&input[..offset]).first() {
+            return Some(format!("did you forget a closing `{}`?", closer));
+        }
+    }
+    if found.is_none() && expected.iter().any(|e| e == "`let`") {
+        return Some("did you forget the `in` of a `let`?".to_owned());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::syntax::parse_expr;
+
+    fn error(input: &str) -> String {
+        parse_expr(input).unwrap_err().summary()
+    }
+
+    #[test]
+    fn fields() {
+        let err = parse_expr("{ x = 1 }\n  }").unwrap_err();
+        assert_eq!(err.offset, 12);
+        assert_eq!((err.line, err.column), (2, 3));
+        assert_eq!(err.found.as_deref(), Some("}"));
+        assert_eq!(err.expected[0], "end of input");
+        assert!(err.expected.contains(&"operator".to_owned()));
+        assert_eq!(err.hint, None);
+        // `Display` shows the line of the error, like the snapshots of the spec tests.
+        assert_eq!(
+            err.to_string().lines().take(4).collect::<Vec<_>>(),
+            vec![" --> 2:3", "  |", "2 |   }", "  |   ^---"]
+        );
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(
+            error("λ(x : Bool) → "),
+            "error: expected expression, found end of input at line 1, column 15"
+        );
+        assert_eq!(
+            error("{ x : Bool, x : Natural }"),
+            "error: Duplicate field in record type at line 1, column 3"
+        );
+        // Deep inputs are rejected before parsing, which would need too much stack.
+        assert_eq!(
+            error(&format!("{}1{}", "[".repeat(20000), "]".repeat(20000))),
+            "error: expression is nested too deeply (more than 10000 levels) \
+             at line 1, column 10001"
+        );
+    }
+
+    #[test]
+    fn hints() {
+        assert_eq!(
+            error("\"abc").lines().nth(1),
+            Some("hint: did you forget to close a text literal?")
+        );
+        assert_eq!(
+            error("[1, f (2]").lines().nth(1),
+            Some("hint: did you forget a closing `)`?")
+        );
+        assert_eq!(
+            error("let x = 1").lines().nth(1),
+            Some("hint: did you forget the `in` of a `let`?")
+        );
+    }
+}
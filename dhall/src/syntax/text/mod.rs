@@ -1,2 +1,4 @@
+pub mod error;
 pub mod parser;
 pub mod printer;
+pub mod recover;
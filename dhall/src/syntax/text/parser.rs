@@ -1,19 +1,23 @@
 use itertools::Itertools;
+use pest::error::ErrorVariant;
 use pest::prec_climber as pcl;
 use pest::prec_climber::PrecClimber;
-use std::collections::{BTreeMap, BTreeSet};
+use pest::Position;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::iter::once;
 use std::rc::Rc;
 
-use pest_consume::{match_nodes, Parser};
+use pest_consume::match_nodes;
 
 use crate::operations::OpKind::*;
+use crate::semantics::DEFAULT_MAX_DEPTH;
+use crate::syntax::text::recover::nesting_depth;
 use crate::syntax::ExprKind::*;
 use crate::syntax::NumKind::*;
 use crate::syntax::{
-    Double, Expr, FilePath, FilePrefix, Hash, ImportMode, ImportTarget,
-    Integer, InterpolatedText, InterpolatedTextContents, Label, NaiveDouble,
-    Natural, Scheme, Span, UnspannedExpr, URL, V,
+    Comment, Double, Expr, FilePath, FilePrefix, Hash, ImportMode,
+    ImportTarget, Integer, InterpolatedText, InterpolatedTextContents, Label,
+    NaiveDouble, Natural, ParseError, Scheme, Span, UnspannedExpr, URL, V,
 };
 
 // This file consumes the parse tree generated by pest and turns it into
@@ -23,10 +27,10 @@ use crate::syntax::{
 
 type ParsedText = InterpolatedText<Expr>;
 type ParsedTextContents = InterpolatedTextContents<Expr>;
-type ParseInput<'input> = pest_consume::Node<'input, Rule, Rc<str>>;
+type ParseInput<'input> = pest_consume::Node<'input, Rule, Rc<ParseData>>;
 
-pub type ParseError = pest::error::Error<Rule>;
-pub type ParseResult<T> = Result<T, ParseError>;
+pub(crate) type PestError = pest::error::Error<Rule>;
+pub(crate) type ParseResult<T> = Result<T, PestError>;
 
 #[derive(Debug)]
 enum Selector {
@@ -36,7 +40,7 @@ enum Selector {
 }
 
 fn input_to_span(input: ParseInput) -> Span {
-    Span::make(input.user_data().clone(), input.as_pair().as_span())
+    Span::make(input.user_data().input.clone(), input.as_pair().as_span())
 }
 fn spanned(input: ParseInput, x: UnspannedExpr) -> Expr {
     Expr::new(x, input_to_span(input))
@@ -45,6 +49,146 @@ fn spanned_union(span1: Span, span2: Span, x: UnspannedExpr) -> Expr {
     Expr::new(x, span1.union(&span2))
 }
 
+/// Data shared by all the nodes of a parse.
+struct ParseData {
+    input: Rc<str>,
+    /// The comments in the input, by start offset, with their end offset.
+    comments: BTreeMap<usize, (usize, Comment)>,
+}
+
+impl ParseData {
+    /// The comments just before `pos`, with only whitespace between them, in order. Each comes
+    /// with the offset where it starts.
+    fn comments_before(&self, pos: usize) -> Vec<(usize, Comment)> {
+        let mut comments = Vec::new();
+        let mut cursor = pos;
+        for (&start, (end, comment)) in self.comments.range(..pos).rev() {
+            // Commas are skipped too, for comments before `, x` in a list or record.
+            let between = &self.input[*end..cursor];
+            if !between.chars().all(|c| c.is_whitespace() || c == ',') {
+                break;
+            }
+            comments.push((start, comment.clone()));
+            cursor = start;
+        }
+        comments.reverse();
+        comments
+    }
+}
+
+/// Finds the comments in the input. They are part of the whitespace, which is hidden from the
+/// parse tree, so they are looked for in the gaps between the tokens of the parse tree. Other
+/// than comments, these gaps only contain keywords and punctuation that can't look like comments.
+fn find_comments(
+    input: &str,
+    pairs: pest::iterators::Pairs<Rule>,
+) -> BTreeMap<usize, (usize, Comment)> {
+    let mut comments = BTreeMap::new();
+    let mut pos = 0;
+    for pair in pairs.flatten() {
+        if pair.clone().into_inner().next().is_some() {
+            continue;
+        }
+        let span = pair.as_span();
+        if span.start() > pos {
+            find_comments_in_gap(input, pos, span.start(), &mut comments);
+        }
+        pos = pos.max(span.end());
+    }
+    find_comments_in_gap(input, pos, input.len(), &mut comments);
+    comments
+}
+
+fn find_comments_in_gap(
+    input: &str,
+    from: usize,
+    to: usize,
+    comments: &mut BTreeMap<usize, (usize, Comment)>,
+) {
+    let mut i = from;
+    while let Some(offset) = input[i..to].find(&['-', '{'][..]) {
+        let start = i + offset;
+        let rest = &input[start..];
+        let comment = if rest.starts_with("--") {
+            let end = start + rest.find('\n').unwrap_or(rest.len());
+            let text = input[start + 2..end].trim_end_matches('\r');
+            Some((end, Comment::Line(text.to_owned())))
+        } else if rest.starts_with("{-") {
+            block_comment_len(rest).map(|len| {
+                let text = &rest[2..len - 2];
+                (start + len, Comment::Block(text.to_owned()))
+            })
+        } else {
+            None
+        };
+        match comment {
+            // A comment is always whole in a gap; this excludes e.g. `{-` in `${-1}`, where `-`
+            // is the sign of the integer.
+            Some((end, comment)) if end <= to => {
+                comments.insert(start, (end, comment));
+                i = end;
+            }
+            _ => i = start + 1,
+        }
+    }
+}
+
+/// The length of the block comment at the start of `s`, which may contain nested comments, or
+/// `None` if it isn't closed.
+fn block_comment_len(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i + 1 < s.len() {
+        match &s.as_bytes()[i..i + 2] {
+            b"{-" => {
+                depth += 1;
+                i += 2;
+            }
+            b"-}" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// The comments before a record field, which are attached to its value.
+fn entry_comments(input: &ParseInput) -> Vec<Comment> {
+    let start = input.as_pair().as_span().start();
+    input
+        .user_data()
+        .comments_before(start)
+        .into_iter()
+        .map(|(_, c)| c)
+        .collect()
+}
+
+/// Attaches to each expression the comments that precede it. Each comment goes to the outermost
+/// expression it precedes; `claimed` holds the offsets of the comments already attached.
+fn attach_comments(
+    e: &Expr,
+    data: &ParseData,
+    claimed: &mut HashSet<usize>,
+) -> Expr {
+    let span = e.span();
+    let comments = match span.parsed() {
+        Some(parsed) => data
+            .comments_before(parsed.start())
+            .into_iter()
+            .filter(|(start, _)| claimed.insert(*start))
+            .map(|(_, c)| c)
+            .collect(),
+        None => Vec::new(),
+    };
+    let kind = e.kind().map_ref(|e| attach_comments(e, data, claimed));
+    Expr::new(kind, span.with_comments(comments))
+}
+
 // Trim the shared indent off of a vec of lines, as defined by the Dhall semantics of multiline
 // literals.
 fn trim_indent(lines: &mut Vec<ParsedText>) {
@@ -143,6 +287,8 @@ lazy_static::lazy_static! {
 // ```
 include!(concat!(env!("OUT_DIR"), "/dhall_parser.rs"));
 
+// pest_consume needs the rules to return pest's error, which is large.
+#[allow(clippy::result_large_err)]
 #[pest_consume::parser(parser = DhallParser, rule = Rule)]
 impl DhallParser {
     fn EOI(_input: ParseInput) -> ParseResult<()> {
@@ -884,18 +1030,24 @@ impl DhallParser {
             [integer_literal(n)] => spanned(input, Num(Integer(n))),
             [double_quote_literal(s)] => spanned(input, TextLit(s)),
             [single_quote_literal(s)] => spanned(input, TextLit(s)),
-            [record_type_or_literal(e)] => spanned(input, e),
+            [record_type_or_literal((e, labels))] => {
+                let span = input_to_span(input).with_field_order(labels);
+                Expr::new(e, span)
+            },
             [union_type(e)] => spanned(input, e),
             [expression(e)] => e,
         ))
     }
 
-    fn record_type_or_literal(input: ParseInput) -> ParseResult<UnspannedExpr> {
+    /// The record, with its labels in the order they are written.
+    fn record_type_or_literal(
+        input: ParseInput,
+    ) -> ParseResult<(UnspannedExpr, Vec<Label>)> {
         Ok(match_nodes!(input.children();
-            [empty_record_literal(_)] => RecordLit(Default::default()),
-            [non_empty_record_type(map)] => RecordType(map),
-            [non_empty_record_literal(map)] => RecordLit(map),
-            [] => RecordType(Default::default()),
+            [empty_record_literal(_)] => (RecordLit(Default::default()), Vec::new()),
+            [non_empty_record_type((map, labels))] => (RecordType(map), labels),
+            [non_empty_record_literal((map, labels))] => (RecordLit(map), labels),
+            [] => (RecordType(Default::default()), Vec::new()),
         ))
     }
 
@@ -905,12 +1057,14 @@ impl DhallParser {
 
     fn non_empty_record_type(
         input: ParseInput,
-    ) -> ParseResult<BTreeMap<Label, Expr>> {
+    ) -> ParseResult<(BTreeMap<Label, Expr>, Vec<Label>)> {
         Ok(match_nodes!(input.children();
             [record_type_entry(entries)..] => {
                 let mut map = BTreeMap::default();
+                let mut labels = Vec::new();
                 for (l, t) in entries {
                     use std::collections::btree_map::Entry;
+                    labels.push(l.clone());
                     match map.entry(l) {
                         Entry::Occupied(_) => {
                             return Err(input.error(
@@ -923,33 +1077,40 @@ impl DhallParser {
                         }
                     }
                 }
-                map
+                (map, labels)
             },
         ))
     }
 
     fn record_type_entry(input: ParseInput) -> ParseResult<(Label, Expr)> {
+        let comments = entry_comments(&input);
         Ok(match_nodes!(input.into_children();
-            [label(name), expression(expr)] => (name, expr)
+            [label(name), expression(expr)] => (name, expr.with_comments(comments))
         ))
     }
 
     fn non_empty_record_literal(
         input: ParseInput,
-    ) -> ParseResult<BTreeMap<Label, Expr>> {
+    ) -> ParseResult<(BTreeMap<Label, Expr>, Vec<Label>)> {
         Ok(match_nodes!(input.into_children();
             [record_literal_entry(entries)..] => {
                 let mut map = BTreeMap::new();
+                let mut labels = Vec::new();
                 for (l, e) in entries {
+                    // Duplicate fields are merged, in the place of the first one.
+                    if !map.contains_key(&l) {
+                        labels.push(l.clone());
+                    }
                     insert_recordlit_entry(&mut map, l, e);
                 }
-                map
+                (map, labels)
             }
         ))
     }
 
     fn record_literal_entry(input: ParseInput) -> ParseResult<(Label, Expr)> {
-        Ok(match_nodes!(input.into_children();
+        let comments = entry_comments(&input);
+        let (name, expr) = match_nodes!(input.into_children();
             [label(name)] => {
                 // Desugar record pun into a variable
                 let expr = Expr::new(Var(name.clone().into()), Span::RecordPunSugar);
@@ -967,7 +1128,8 @@ impl DhallParser {
                 });
                 (first_name, expr)
             },
-        ))
+        );
+        Ok((name, expr.with_comments(comments)))
     }
 
     fn union_type(input: ParseInput) -> ParseResult<UnspannedExpr> {
@@ -1020,16 +1182,70 @@ impl DhallParser {
     }
 }
 
-pub fn parse_expr(input_str: &str) -> ParseResult<Expr> {
-    let rc_input_str = input_str.to_string().into();
-    let inputs = DhallParser::parse_with_userdata(
+pub fn parse_expr(input_str: &str) -> Result<Expr, ParseError> {
+    parse_expr_pest(input_str).map_err(|err| ParseError::new(err, input_str))
+}
+
+/// Like `parse_expr`, but keeps the error from pest.
+#[allow(clippy::result_large_err)]
+pub(crate) fn parse_expr_pest(input_str: &str) -> ParseResult<Expr> {
+    // pest recurses without a way to grow the stack in between, so the stack it needs is
+    // allocated up front, from how deep the input nests. Brackets, text literals, functions,
+    // `if`s and `let`s each count as a level.
+    let depth = match nesting_depth(input_str, DEFAULT_MAX_DEPTH) {
+        Ok(depth) => depth,
+        Err(offset) => {
+            return Err(PestError::new_from_pos(
+                ErrorVariant::CustomError {
+                    message: format!(
+                        "expression is nested too deeply (more than {} levels)",
+                        DEFAULT_MAX_DEPTH
+                    ),
+                },
+                Position::new(input_str, offset).unwrap(),
+            ))
+        }
+    };
+    let stack_size = (depth + 1) * PARSE_STACK_PER_LEVEL;
+    stacker::maybe_grow(stack_size, stack_size, || {
+        parse_expr_unchecked(input_str)
+    })
+}
+
+/// The stack that parsing needs for each level of nesting. pest's stack frames are much larger
+/// without optimizations.
+const PARSE_STACK_PER_LEVEL: usize = if cfg!(debug_assertions) {
+    64 * 1024
+} else {
+    4 * 1024
+};
+
+#[allow(clippy::result_large_err)]
+fn parse_expr_unchecked(input_str: &str) -> ParseResult<Expr> {
+    let mut pairs = <DhallParser as pest::Parser<Rule>>::parse(
         Rule::final_expression,
         input_str,
-        rc_input_str,
     )?;
-    Ok(match_nodes!(<DhallParser>; inputs;
-        [expression(e)] => e,
-    ))
+    let has_comments = input_str.contains("--") || input_str.contains("{-");
+    let comments = if has_comments {
+        find_comments(input_str, pairs.clone())
+    } else {
+        BTreeMap::new()
+    };
+    let data = Rc::new(ParseData {
+        input: input_str.into(),
+        comments,
+    });
+    let node = pest_consume::Node::new_with_user_data(
+        pairs.next().unwrap(),
+        data.clone(),
+    );
+    let expr = DhallParser::final_expression(node)?;
+    if has_comments {
+        Ok(attach_comments(&expr, &data, &mut HashSet::new()))
+    } else {
+        Ok(expr)
+    }
 }
 
 #[test]
@@ -1061,3 +1277,55 @@ fn test_grammar_files_in_sync() {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiline_text() {
+        let text = |s: &str| parse_expr(s).unwrap().to_string();
+
+        // From the standard's `parser/success/text` tests.
+        assert_eq!(
+            text("''\n\thai\n\t\tthere\n\t   lol\n\t''"),
+            r#""hai\n\tthere\n   lol\n""#
+        );
+        assert_eq!(
+            text("''\n  hello\n\n  there\n  ''"),
+            r#""hello\n\nthere\n""#
+        );
+        assert_eq!(
+            text("''\r\n  hello\r\n\r\n  there\r\n  ''"),
+            r#""hello\n\nthere\n""#
+        );
+        // Tabs and spaces are allowed in the same indent: only the longest common prefix is
+        // stripped.
+        assert_eq!(text("''\n  \ta\n   b\n  ''"), r#""\ta\n b\n""#);
+        assert_eq!(
+            text("''\n\t\thai\n\t\tthere\n\t ok\n\t\t''"),
+            r#""\thai\n\tthere\n ok\n\t""#
+        );
+
+        // The newline after the opening quotes isn't part of the text, but the last one is.
+        assert_eq!(text("''\n''"), r#""""#);
+        assert_eq!(text("''\nfoo''"), r#""foo""#);
+        assert_eq!(text("''\nfoo\n''"), r#""foo\n""#);
+        // The indent of the closing quotes counts.
+        assert_eq!(text("''\n  a\n    ''"), r#""a\n  ""#);
+        assert_eq!(text("''\n  a\n''"), r#""  a\n""#);
+        // Trailing whitespace is kept.
+        assert_eq!(text("''\n  foo  \n  bar\t\n  ''"), r#""foo  \nbar\t\n""#);
+        // Empty lines are ignored, but not lines with only whitespace.
+        assert_eq!(text("''\n  a\n \n  b\n  ''"), r#"" a\n\n b\n ""#);
+        // Interpolations end the indent.
+        assert_eq!(
+            text("''\n    a ${\"x\"}\n      ${\"y\"} b\n    ''"),
+            r#""a ${ "x" }\n  ${ "y" } b\n""#
+        );
+        assert_eq!(text("''\n  ${\"x\"}\n  a''"), r#""${ "x" }\na""#);
+        assert_eq!(text("''\n  a\n${\"x\"}''"), r#""  a\n${ "x" }""#);
+        // Escapes aren't indent.
+        assert_eq!(text("''\n  '''a\n  ''${b}\n  ''"), r#""''a\n\u0024{b}\n""#);
+    }
+}
@@ -2,6 +2,7 @@ use crate::builtins::Builtin;
 use crate::operations::{BinOp, OpKind};
 use crate::syntax::*;
 use itertools::Itertools;
+use std::collections::BTreeMap;
 use std::fmt::{self, Display};
 
 // There is a one-to-one correspondence between the formatter and the grammar. Each phase is
@@ -15,8 +16,9 @@ enum PrintPhase {
     Base,
     // `operator-expression`
     Operator,
-    // All the `<operator>-expression`s
-    BinOp(self::BinOp),
+    // All the `<operator>-expression`s. The flag is set for the right operand: operators are
+    // left-associative, so `a + (b + c)` needs parentheses but `(a + b) + c` doesn't.
+    BinOp(self::BinOp, bool),
     // `application-expression`
     App,
     // `import-expression`
@@ -63,10 +65,11 @@ impl UnspannedExpr {
             Annot(a, b) => Annot(a.phase(Operator), b),
             Op(OpKind::BinOp(op, a, b)) => Op(OpKind::BinOp(
                 op,
-                a.phase(PrintPhase::BinOp(op)),
-                b.phase(PrintPhase::BinOp(op)),
+                a.phase(PrintPhase::BinOp(op, false)),
+                b.phase(PrintPhase::BinOp(op, true)),
             )),
             SomeLit(e) => SomeLit(e.phase(PrintPhase::Import)),
+            EmptyListLit(t) => EmptyListLit(t.phase(PrintPhase::App)),
             Op(OpKind::App(f, a)) => Op(OpKind::App(
                 f.phase(PrintPhase::App),
                 a.phase(PrintPhase::Import),
@@ -79,6 +82,9 @@ impl UnspannedExpr {
             Op(Completion(a, b)) => {
                 Op(Completion(a.phase(Primitive), b.phase(Primitive)))
             }
+            Op(With(a, ls, b)) => {
+                Op(With(a.phase(PrintPhase::Import), ls, b.phase(Operator)))
+            }
             ExprKind::Import(a) => {
                 ExprKind::Import(a.map_ref(|x| x.phase(PrintPhase::Import)))
             }
@@ -86,15 +92,11 @@ impl UnspannedExpr {
         }
     }
 
-    fn fmt_phase(
-        &self,
-        f: &mut fmt::Formatter,
-        phase: PrintPhase,
-    ) -> Result<(), fmt::Error> {
+    // Whether the expression needs parentheses when printed in the given phase.
+    fn needs_paren(&self, phase: PrintPhase) -> bool {
         use ExprKind::*;
         use OpKind::*;
-
-        let needs_paren = match self {
+        match self {
             Lam(_, _, _)
             | Pi(_, _, _)
             | Let(_, _, _, _)
@@ -103,19 +105,34 @@ impl UnspannedExpr {
             | Op(BoolIf(_, _, _))
             | Op(Merge(_, _, _))
             | Op(ToMap(_, _))
+            | Op(With(_, _, _))
+            | Assert(_)
             | Annot(_, _) => phase > PrintPhase::Base,
             // Precedence is magically handled by the ordering of BinOps. This is reverse Pratt
             // parsing.
-            Op(BinOp(op, _, _)) => phase > PrintPhase::BinOp(*op),
+            Op(BinOp(op, _, _)) => phase > PrintPhase::BinOp(*op, false),
             Op(App(_, _)) => phase > PrintPhase::App,
-            Op(Completion(_, _)) => phase > PrintPhase::Import,
+            Op(Completion(_, _)) | Import(_) => phase > PrintPhase::Import,
             _ => false,
-        };
+        }
+    }
 
+    fn fmt_phase(
+        &self,
+        f: &mut fmt::Formatter,
+        phase: PrintPhase,
+    ) -> Result<(), fmt::Error> {
+        use ExprKind::*;
+        let needs_paren = self.needs_paren(phase);
         if needs_paren {
             f.write_str("(")?;
         }
-        self.annotate_with_phases().fmt(f)?;
+        match self.annotate_with_phases() {
+            // The comments of a field are printed before its name.
+            RecordLit(a) if !a.is_empty() => fmt_record(&a, " = ", f)?,
+            RecordType(a) if !a.is_empty() => fmt_record(&a, " : ", f)?,
+            kind => kind.fmt(f)?,
+        }
         if needs_paren {
             f.write_str(")")?;
         }
@@ -146,12 +163,41 @@ where
     f.write_str(close)
 }
 
+fn fmt_record(
+    entries: &BTreeMap<Label, PhasedExpr<'_>>,
+    sep: &str,
+    f: &mut fmt::Formatter,
+) -> Result<(), fmt::Error> {
+    fmt_list("{ ", ", ", " }", entries, f, |(k, v), f| {
+        fmt_comments(v.0.span().comments(), f)?;
+        fmt_label(k, f)?;
+        f.write_str(sep)?;
+        v.0.as_ref().fmt_phase(f, v.1)
+    })
+}
+
+fn fmt_comments(
+    comments: &[Comment],
+    f: &mut fmt::Formatter,
+) -> Result<(), fmt::Error> {
+    for comment in comments {
+        match comment {
+            Comment::Line(text) => writeln!(f, "--{}", text)?,
+            Comment::Block(text) => write!(f, "{{-{}-}} ", text)?,
+        }
+    }
+    Ok(())
+}
+
 fn fmt_label(label: &Label, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
     // TODO: distinguish between reserved and nonreserved locations for quoting builtins
     let s = String::from(label);
     let is_reserved = match s.as_str() {
-        "let" | "in" | "if" | "then" | "else" | "Type" | "Kind" | "Sort"
-        | "True" | "False" | "Some" => true,
+        "if" | "then" | "else" | "let" | "in" | "using" | "missing"
+        | "assert" | "as" | "Infinity" | "NaN" | "merge" | "Some" | "toMap"
+        | "forall" | "with" | "Type" | "Kind" | "Sort" | "True" | "False" => {
+            true
+        }
         _ => Builtin::parse(&s).is_some(),
     };
     if s.is_empty() {
@@ -232,6 +278,8 @@ impl<SE: Display + Clone> Display for ExprKind<SE> {
                 write!(f, "assert : {}", a)?;
             }
             Import(a) => a.fmt(f)?,
+            // Printed as written, so that the rest of the source is kept.
+            Error(e) => f.write_str(&e.skipped)?,
         }
         Ok(())
     }
@@ -288,7 +336,7 @@ impl<SE: Display + Clone> Display for OpKind<SE> {
 
 impl Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        self.kind().fmt_phase(f, PrintPhase::Base)
+        PhasedExpr(self, PrintPhase::Base).fmt(f)
     }
 }
 
@@ -312,40 +360,46 @@ impl Display for NumKind {
 
 impl<'a> Display for PhasedExpr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt_comments(self.0.span().comments(), f)?;
         self.0.as_ref().fmt_phase(f, self.1)
     }
 }
 
+// The contents of a chunk of a double-quoted text literal.
+struct EscapedText<'a>(&'a str);
+
+impl<'a> Display for EscapedText<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        for c in self.0.chars() {
+            match c {
+                '\\' => f.write_str("\\\\"),
+                '"' => f.write_str("\\\""),
+                '$' => f.write_str("\\u0024"),
+                '\u{0008}' => f.write_str("\\b"),
+                '\u{000C}' => f.write_str("\\f"),
+                '\n' => f.write_str("\\n"),
+                '\r' => f.write_str("\\r"),
+                '\t' => f.write_str("\\t"),
+                '\u{0000}'..='\u{001F}' => {
+                    // Escape to an explicit "\u{XXXX}" form
+                    let escaped: String = c.escape_default().collect();
+                    // Print as "\uXXXX"
+                    write!(f, "\\u{:0>4}", &escaped[3..escaped.len() - 1])
+                }
+                c => write!(f, "{}", c),
+            }?;
+        }
+        Ok(())
+    }
+}
+
 impl<SubExpr: Display> Display for InterpolatedText<SubExpr> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         f.write_str("\"")?;
         for x in self.iter() {
             match x {
                 InterpolatedTextContents::Text(a) => {
-                    for c in a.chars() {
-                        match c {
-                            '\\' => f.write_str("\\\\"),
-                            '"' => f.write_str("\\\""),
-                            '$' => f.write_str("\\u0024"),
-                            '\u{0008}' => f.write_str("\\b"),
-                            '\u{000C}' => f.write_str("\\f"),
-                            '\n' => f.write_str("\\n"),
-                            '\r' => f.write_str("\\r"),
-                            '\t' => f.write_str("\\t"),
-                            '\u{0000}'..='\u{001F}' => {
-                                // Escape to an explicit "\u{XXXX}" form
-                                let escaped: String =
-                                    c.escape_default().collect();
-                                // Print as "\uXXXX"
-                                write!(
-                                    f,
-                                    "\\u{:0>4}",
-                                    &escaped[3..escaped.len() - 1]
-                                )
-                            }
-                            c => write!(f, "{}", c),
-                        }?;
-                    }
+                    EscapedText(&a).fmt(f)?;
                 }
                 InterpolatedTextContents::Expr(e) => {
                     f.write_str("${ ")?;
@@ -398,11 +452,17 @@ impl Display for NaiveDouble {
         } else if v == 0.0 && v.is_sign_negative() {
             f.write_str("-0.0")
         } else {
-            let s = format!("{}", v);
-            if s.contains('e') || s.contains('.') {
-                f.write_str(&s)
+            // Both notations give the shortest digits that parse back to the same value. Very
+            // large and very small numbers would take hundreds of digits without an exponent.
+            let s = if v == 0.0 || (1e-7..1e21).contains(&v.abs()) {
+                format!("{}", v)
             } else {
-                write!(f, "{}.0", s)
+                format!("{:e}", v)
+            };
+            match s.find('e') {
+                _ if s.contains('.') => f.write_str(&s),
+                Some(i) => write!(f, "{}.0{}", &s[..i], &s[i..]),
+                None => write!(f, "{}.0", s),
             }
         }
     }
@@ -414,6 +474,14 @@ impl Display for Label {
     }
 }
 
+impl Label {
+    /// The label as written in Dhall code, quoted with backticks if it is a keyword or a builtin
+    /// or if it has characters that a plain label can't have, like `` `log level` ``.
+    pub fn to_quoted_string(&self) -> String {
+        PrettyLabel(self).to_string()
+    }
+}
+
 impl Display for Hash {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
@@ -522,3 +590,883 @@ impl Display for V {
         Ok(())
     }
 }
+
+/// Print an expression as compactly as possible, e.g. to pass it in a command-line argument or an
+/// environment variable.
+///
+/// Whitespace is only kept where the grammar requires it, parentheses only where precedence
+/// requires them, and the shortest of the ASCII and Unicode spellings of each symbol is used
+/// (e.g. `\` for `λ`, `->` for `→`, but `∀` for `forall`). Consecutive `let`s share a single `in`.
+/// The result parses back to the same expression.
+pub fn minify(e: &Expr) -> String {
+    let mut m = Minifier {
+        out: String::new(),
+        needs_space: false,
+        ends_with_label: false,
+        ends_with_path: false,
+    };
+    m.expr(PhasedExpr(e, PrintPhase::Base));
+    m.out
+}
+
+struct Minifier {
+    out: String,
+    // Whether the next token must be separated from the previous one by whitespace.
+    needs_space: bool,
+    // Whether the last token ended like a label or a number.
+    ends_with_label: bool,
+    // Whether the last token ended with the path of a local import.
+    ends_with_path: bool,
+}
+
+impl Minifier {
+    /// Write a token, separating it from the previous one if they would otherwise merge into a
+    /// single token, like two labels, or a label and `->`.
+    fn token(&mut self, s: &str) {
+        // Labels can contain `-` and `/`, e.g. `Natural/fold`.
+        let is_label_char = |c: char| {
+            c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '/'
+        };
+        // Local paths end at whitespace or at one of these characters.
+        let is_path_char =
+            |c: char| c.is_ascii_graphic() && !"\"#(),<>?[\\]{}".contains(c);
+        let merges = match s.chars().next() {
+            Some(c) => {
+                (self.ends_with_label && is_label_char(c))
+                    || (self.ends_with_path && is_path_char(c))
+            }
+            None => false,
+        };
+        if self.needs_space || merges {
+            self.out.push(' ');
+        }
+        self.needs_space = false;
+        self.ends_with_path = false;
+        self.ends_with_label = s
+            .chars()
+            .last()
+            .map(|c| c.is_ascii_alphanumeric() || c == '_')
+            .unwrap_or(false);
+        self.out.push_str(s);
+    }
+
+    /// Require whitespace before the next token.
+    fn space(&mut self) {
+        self.needs_space = true;
+    }
+
+    fn label(&mut self, l: &Label) {
+        self.token(&V(l.clone(), 0).to_string());
+    }
+
+    fn list<T>(
+        &mut self,
+        open: &str,
+        sep: &str,
+        close: &str,
+        it: impl IntoIterator<Item = T>,
+        mut func: impl FnMut(&mut Self, T),
+    ) {
+        self.token(open);
+        for (i, x) in it.into_iter().enumerate() {
+            if i > 0 {
+                self.token(sep);
+            }
+            func(self, x);
+        }
+        self.token(close);
+    }
+
+    fn expr(&mut self, e: PhasedExpr<'_>) {
+        use ExprKind::*;
+        let PhasedExpr(e, phase) = e;
+        let needs_paren = e.as_ref().needs_paren(phase);
+        if needs_paren {
+            self.token("(");
+        }
+        match e.as_ref().annotate_with_phases() {
+            Var(v) => self.token(&v.to_string()),
+            Lam(a, b, c) => {
+                self.token("\\(");
+                self.label(&a);
+                self.token(":");
+                self.space();
+                self.expr(b);
+                self.token(")->");
+                self.expr(c);
+            }
+            Pi(a, b, c) if &String::from(&a) == "_" => {
+                self.expr(b);
+                self.token("->");
+                self.expr(c);
+            }
+            Pi(a, b, c) => {
+                self.token("∀(");
+                self.label(&a);
+                self.token(":");
+                self.space();
+                self.expr(b);
+                self.token(")->");
+                self.expr(c);
+            }
+            Let(a, b, c, d) => {
+                self.token("let");
+                self.space();
+                self.label(&a);
+                if let Some(b) = b {
+                    self.token(":");
+                    self.space();
+                    self.expr(b);
+                }
+                self.token("=");
+                self.expr(c);
+                // `let x = a in let y = b in c` can be written `let x = a let y = b in c`.
+                if !matches!(d.0.kind(), Let(..)) {
+                    self.token("in");
+                    self.space();
+                }
+                self.expr(d);
+            }
+            Const(k) => self.token(&k.to_string()),
+            Builtin(b) => self.token(&b.to_string()),
+            Num(n) => self.token(&n.to_string()),
+            TextLit(t) => {
+                self.token("\"");
+                for x in t.iter() {
+                    match x {
+                        InterpolatedTextContents::Text(a) => {
+                            self.out.push_str(&EscapedText(&a).to_string())
+                        }
+                        InterpolatedTextContents::Expr(e) => {
+                            self.out.push_str("${");
+                            self.expr(*e);
+                            self.token("}");
+                        }
+                    }
+                }
+                self.out.push('"');
+                self.ends_with_label = false;
+            }
+            SomeLit(e) => {
+                self.token("Some");
+                self.space();
+                self.expr(e);
+            }
+            EmptyListLit(t) => {
+                self.token("[]:");
+                self.space();
+                self.expr(t);
+            }
+            NEListLit(es) => self.list("[", ",", "]", es, Self::expr),
+            RecordLit(kvs) if kvs.is_empty() => self.token("{=}"),
+            RecordLit(kvs) => self.list("{", ",", "}", kvs, |m, (k, v)| {
+                m.label(&k);
+                m.token("=");
+                m.expr(v);
+            }),
+            RecordType(kts) => self.list("{", ",", "}", kts, |m, (k, t)| {
+                m.label(&k);
+                m.token(":");
+                m.space();
+                m.expr(t);
+            }),
+            UnionType(kts) => self.list("<", "|", ">", kts, |m, (k, t)| {
+                m.label(&k);
+                if let Some(t) = t {
+                    m.token(":");
+                    m.space();
+                    m.expr(t);
+                }
+            }),
+            Op(op) => self.op(op),
+            Annot(a, b) => {
+                self.expr(a);
+                self.token(":");
+                self.space();
+                self.expr(b);
+            }
+            Assert(a) => {
+                self.token("assert:");
+                self.space();
+                self.expr(a);
+            }
+            Import(a) => {
+                self.token(&a.to_string());
+                let ends_with_path =
+                    a.hash.is_none() && a.mode == ImportMode::Code;
+                match a.location {
+                    // URLs can contain almost any character.
+                    ImportTarget::Remote(_) => self.space(),
+                    ImportTarget::Local(..) if ends_with_path => {
+                        self.ends_with_path = true
+                    }
+                    _ => {}
+                }
+            }
+            Error(e) => self.token(&e.skipped),
+        }
+        if needs_paren {
+            self.token(")");
+        }
+    }
+
+    fn op(&mut self, op: OpKind<PhasedExpr<'_>>) {
+        use OpKind::*;
+        match op {
+            App(a, b) => {
+                self.expr(a);
+                self.space();
+                self.expr(b);
+            }
+            BinOp(op, a, b) => {
+                use self::BinOp::*;
+                self.expr(a);
+                self.token(match op {
+                    BoolOr => "||",
+                    TextAppend => "++",
+                    NaturalPlus => "+",
+                    BoolAnd => "&&",
+                    RecursiveRecordMerge => "/\\",
+                    NaturalTimes => "*",
+                    BoolEQ => "==",
+                    BoolNE => "!=",
+                    RecursiveRecordTypeMerge => "⩓",
+                    ImportAlt => "?",
+                    RightBiasedRecordMerge => "//",
+                    ListAppend => "#",
+                    Equivalence => "===",
+                });
+                // Whitespace is required after these to disambiguate e.g. `f +2`.
+                if let NaturalPlus | ImportAlt = op {
+                    self.space();
+                }
+                self.expr(b);
+            }
+            BoolIf(a, b, c) => {
+                self.token("if");
+                self.space();
+                self.expr(a);
+                self.token("then");
+                self.space();
+                self.expr(b);
+                self.token("else");
+                self.space();
+                self.expr(c);
+            }
+            Merge(a, b, c) => {
+                self.token("merge");
+                self.space();
+                self.expr(a);
+                self.space();
+                self.expr(b);
+                if let Some(c) = c {
+                    self.token(":");
+                    self.space();
+                    self.expr(c);
+                }
+            }
+            ToMap(a, b) => {
+                self.token("toMap");
+                self.space();
+                self.expr(a);
+                if let Some(b) = b {
+                    self.token(":");
+                    self.space();
+                    self.expr(b);
+                }
+            }
+            Field(a, b) => {
+                self.expr(a);
+                self.token(".");
+                self.label(&b);
+            }
+            Projection(e, ls) => {
+                self.expr(e);
+                self.token(".");
+                self.list("{", ",", "}", ls, |m, l| m.label(&l));
+            }
+            ProjectionByExpr(a, b) => {
+                self.expr(a);
+                self.token(".(");
+                self.expr(b);
+                self.token(")");
+            }
+            Completion(a, b) => {
+                self.expr(a);
+                self.token("::");
+                self.expr(b);
+            }
+            With(a, ls, b) => {
+                self.expr(a);
+                self.space();
+                self.token("with");
+                self.space();
+                for (i, l) in ls.iter().enumerate() {
+                    if i > 0 {
+                        self.token(".");
+                    }
+                    self.label(l);
+                }
+                self.token("=");
+                self.expr(b);
+            }
+        }
+    }
+}
+
+/// The number of columns that [`pretty`] fits lines in.
+const PRETTY_WIDTH: usize = 80;
+/// The indentation that [`pretty`] adds for the sub-expressions that it moves to their own lines.
+const PRETTY_INDENT: usize = 4;
+
+/// Print an expression over several lines, like `dhall format`.
+///
+/// Each sub-expression that fits on the rest of its line is printed on it; the others are broken
+/// over several lines. Records, unions and lists put one entry per line with the separators in
+/// front, and functions, applications and `let` bindings indent the parts that they move to new
+/// lines by 4 spaces. Lines only exceed 80 columns when a part that can't be broken, like a text
+/// literal, is too long. Comments are dropped from the parts that are broken.
+pub(crate) fn pretty(e: &Expr) -> String {
+    let mut p = Pretty { out: String::new() };
+    p.expr(PhasedExpr(e, PrintPhase::Base), 0);
+    p.out
+}
+
+struct PrettyLabel<'a>(&'a Label);
+
+impl Display for PrettyLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        fmt_label(self.0, f)
+    }
+}
+
+struct Pretty {
+    out: String,
+}
+
+impl Pretty {
+    fn column(&self) -> usize {
+        let line = self.out.rsplit('\n').next().unwrap_or_default();
+        line.chars().count()
+    }
+
+    fn fits(&self, s: &str) -> bool {
+        !s.contains('\n') && self.column() + s.chars().count() <= PRETTY_WIDTH
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    fn write(&mut self, x: impl Display) {
+        use std::fmt::Write;
+        write!(self.out, "{}", x).unwrap();
+    }
+
+    /// Print `e`, breaking it if it doesn't fit. `indent` is the indentation of the current line.
+    fn expr(&mut self, e: PhasedExpr<'_>, indent: usize) {
+        let flat = e.to_string();
+        if self.fits(&flat) {
+            return self.out.push_str(&flat);
+        }
+        let needs_paren = e.0.as_ref().needs_paren(e.1);
+        if needs_paren {
+            self.out.push('(');
+        }
+        self.broken(e.0, indent);
+        if needs_paren {
+            self.out.push(')');
+        }
+    }
+
+    /// Print `e` on the current line if it fits, or else on its own lines, indented.
+    fn expr_or_indent(&mut self, e: PhasedExpr<'_>, indent: usize) {
+        let flat = e.to_string();
+        if self.fits(&format!(" {}", flat)) {
+            self.write(format_args!(" {}", flat));
+        } else {
+            self.newline(indent + PRETTY_INDENT);
+            self.expr(e, indent + PRETTY_INDENT);
+        }
+    }
+
+    /// Print `e` over several lines.
+    fn broken(&mut self, e: &Expr, indent: usize) {
+        use ExprKind::*;
+        use OpKind::*;
+        let col = self.column();
+        let inner = indent + PRETTY_INDENT;
+        match e.as_ref().annotate_with_phases() {
+            RecordLit(kvs) if !kvs.is_empty() => self.record(&kvs, " =", col),
+            RecordType(kts) if !kts.is_empty() => self.record(&kts, " :", col),
+            UnionType(kts) => {
+                for (i, (k, t)) in kts.iter().enumerate() {
+                    self.out.push_str(if i == 0 { "< " } else { "| " });
+                    self.write(PrettyLabel(k));
+                    if let Some(t) = t {
+                        self.out.push(':');
+                        self.expr_or_indent(*t, col);
+                    }
+                    self.newline(col);
+                }
+                self.out.push('>');
+            }
+            NEListLit(es) => {
+                for (i, e) in es.iter().enumerate() {
+                    self.out.push_str(if i == 0 { "[ " } else { ", " });
+                    self.expr(*e, col);
+                    self.newline(col);
+                }
+                self.out.push(']');
+            }
+            Lam(x, t, body) => {
+                self.write(format_args!("λ({} : ", PrettyLabel(&x)));
+                self.expr(t, indent);
+                self.out.push_str(") →");
+                self.body(body, indent);
+            }
+            Pi(x, t, body) if &String::from(&x) == "_" => {
+                self.expr(t, indent);
+                self.out.push_str(" →");
+                self.body(body, indent);
+            }
+            Pi(x, t, body) => {
+                self.write(format_args!("∀({} : ", PrettyLabel(&x)));
+                self.expr(t, indent);
+                self.out.push_str(") →");
+                self.body(body, indent);
+            }
+            Let(x, t, v, body) => {
+                self.write(format_args!("let {}", PrettyLabel(&x)));
+                if let Some(t) = t {
+                    self.out.push_str(" :");
+                    self.expr_or_indent(t, indent);
+                }
+                self.out.push_str(" =");
+                self.expr_or_indent(v, indent);
+                self.newline(indent);
+                // Consecutive `let`s share a single `in`.
+                if let Let(..) = body.0.kind() {
+                    self.broken(body.0, indent);
+                } else {
+                    self.out.push_str("in  ");
+                    self.expr(body, indent);
+                }
+            }
+            Op(BoolIf(c, t, e)) => {
+                self.out.push_str("if ");
+                self.expr(c, indent);
+                self.newline(col);
+                self.out.push_str("then ");
+                self.expr(t, col);
+                self.newline(col);
+                self.out.push_str("else ");
+                self.expr(e, col);
+            }
+            Op(App(..)) => {
+                let mut args = Vec::new();
+                let mut f = e;
+                while let Op(App(g, a)) = f.kind() {
+                    args.push(a);
+                    f = g;
+                }
+                self.expr(PhasedExpr(f, PrintPhase::App), indent);
+                for a in args.into_iter().rev() {
+                    self.newline(inner);
+                    self.expr(PhasedExpr(a, PrintPhase::Import), inner);
+                }
+            }
+            Op(Merge(handlers, x, t)) => {
+                self.out.push_str("merge");
+                self.newline(inner);
+                self.expr(handlers, inner);
+                self.newline(inner);
+                self.expr(x, inner);
+                if let Some(t) = t {
+                    self.newline(indent);
+                    self.out.push_str(": ");
+                    self.expr(t, indent);
+                }
+            }
+            Op(BinOp(op, a, b)) => {
+                self.expr(a, indent);
+                self.newline(col);
+                self.write(format_args!("{} ", op));
+                self.expr(b, col);
+            }
+            Annot(a, t) => {
+                self.expr(a, indent);
+                self.newline(col);
+                self.out.push_str(": ");
+                self.expr(t, col);
+            }
+            SomeLit(x) => {
+                self.out.push_str("Some");
+                self.newline(inner);
+                self.expr(x, inner);
+            }
+            _ => self.write(e),
+        }
+    }
+
+    /// The fields of a record on their own lines, aligned with the opening brace at `col`.
+    fn record(
+        &mut self,
+        entries: &BTreeMap<Label, PhasedExpr<'_>>,
+        sep: &str,
+        col: usize,
+    ) {
+        for (i, (k, v)) in entries.iter().enumerate() {
+            self.out.push_str(if i == 0 { "{ " } else { ", " });
+            self.write(format_args!("{}{}", PrettyLabel(k), sep));
+            self.expr_or_indent(*v, col);
+            self.newline(col);
+        }
+        self.out.push('}');
+    }
+
+    /// The body of a function, on the next line. The bodies of curried functions are not
+    /// indented, so that their parameters line up.
+    fn body(&mut self, body: PhasedExpr<'_>, indent: usize) {
+        let indent = match body.0.kind() {
+            ExprKind::Lam(..) | ExprKind::Pi(..) => indent,
+            _ => indent + PRETTY_INDENT,
+        };
+        self.newline(indent);
+        self.expr(body, indent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn minified(s: &str) -> String {
+        minify(&parse_expr(s).unwrap())
+    }
+
+    #[test]
+    fn minify_examples() {
+        assert_eq!(minified("λ(x : Bool) → x"), "\\(x: Bool)->x");
+        assert_eq!(minified("∀(a : Type) → a → a"), "∀(a: Type)->a ->a");
+        assert_eq!(
+            minified("let x = 1 in let y : Natural = 2 in x + y"),
+            "let x=1 let y: Natural=2 in x+ y"
+        );
+        assert_eq!(minified("{ a = [ 1, 2 ], b = {=} }"), "{a=[1,2],b={=}}");
+        assert_eq!(minified("[] : List Natural"), "[]: List Natural");
+        assert_eq!(minified("f (g x) (+1)"), "f (g x) +1");
+        assert_eq!(minified("(a // b) /\\ c"), "a //b /\\c");
+        assert_eq!(minified("\"a${ x }b\""), "\"a${x}b\"");
+        assert_eq!(minified("(./foo ? ./bar).x"), "(./foo? ./bar).x");
+        assert_eq!(minified("./foo ./bar"), "./foo ./bar");
+        assert_eq!(minified("[./foo, ./bar]"), "[./foo,./bar]");
+    }
+
+    #[test]
+    fn quoted_labels() {
+        let quoted = |s: &str| Label::from(s).to_quoted_string();
+        assert_eq!(quoted("port"), "port");
+        assert_eq!(quoted("log level"), "`log level`");
+        assert_eq!(quoted("merge"), "`merge`");
+        assert_eq!(quoted("Natural"), "`Natural`");
+        assert_eq!(quoted("Date"), "Date");
+        // Printed keywords parse back as labels.
+        assert_eq!(minified("{ `merge` = 1, `if` = 2 }"), "{`if`=2,`merge`=1}");
+    }
+
+    #[test]
+    fn comments() {
+        let printed = |s: &str| parse_expr(s).unwrap().to_string();
+        assert_eq!(
+            printed("-- The configuration\n{- v2 -} f x"),
+            "-- The configuration\n{- v2 -} f x"
+        );
+        assert_eq!(
+            printed(
+                "{ -- The port\n  port = 80\n  {- The host,\n  or {- nested -} an IP -}\n, host = \"a\"\n}"
+            ),
+            "{ {- The host,\n  or {- nested -} an IP -} host = \"a\", -- The port\nport = 80 }"
+        );
+        assert_eq!(
+            printed("{ -- A\n  a : Natural, b : Text }"),
+            "{ -- A\na : Natural, b : Text }"
+        );
+        assert_eq!(
+            printed("let x = 1\n\n-- Uses x\nin  x -- not kept\n"),
+            "let x = 1 in x"
+        );
+        assert_eq!(
+            printed("let x = 1\n-- The second\nlet y = 2 in [ x,\n-- y\ny ]"),
+            "let x = 1 in -- The second\nlet y = 2 in [x, -- y\ny]"
+        );
+        // Comment markers in text and labels are not comments.
+        assert_eq!(printed("\"-}\" ++ \"--\""), "\"-}\" ++ \"--\"");
+        assert_eq!(printed("{ a-- = 1 }"), "{ `a--` = 1 }");
+
+        // The printed expression parses back to the same one, with the same comments.
+        for s in &[
+            "-- a\n{ -- b\nx = { -- c\ny = 1 } }",
+            "{- a -} \\(x : {- b -} Bool) -> {- c -} x",
+            "{ -- a\nx = 1 }.x",
+        ] {
+            let e = parse_expr(s).unwrap();
+            assert_eq!(
+                parse_expr(&e.to_string()).unwrap().to_string(),
+                e.to_string()
+            );
+        }
+        // Comments don't change the expression.
+        assert_eq!(
+            parse_expr("{ -- The port\nport = 80 }").unwrap(),
+            parse_expr("{ port = 80 }").unwrap()
+        );
+    }
+
+    /// Generates a random, fully parenthesized expression.
+    fn gen_expr(rng: &mut StdRng, depth: usize) -> String {
+        const LEAVES: &[&str] = &[
+            "x",
+            "y",
+            "x@1",
+            "`a b`",
+            "1",
+            "+2",
+            "-3",
+            "1.5",
+            "-Infinity",
+            "True",
+            "Natural/fold",
+            "Bool",
+            "Type",
+            "{=}",
+            "{}",
+            "<>",
+            "\"a\\\"$b\"",
+            "./foo.dhall",
+            "env:HOME",
+            "missing",
+        ];
+        const FORMS: &[&str] = &[
+            "(λ(x : A) → B)",
+            "(∀(y : A) → B)",
+            "(A → B)",
+            "(let x = A in B)",
+            "(let x : A = B in let y = C in x)",
+            "(A B)",
+            "(A + B)",
+            "(A ++ B)",
+            "(A || B)",
+            "(A && B)",
+            "(A # B)",
+            "(A ∧ B)",
+            "(A ⩓ B)",
+            "(A ⫽ B)",
+            "(A == B)",
+            "(A != B)",
+            "(A * B)",
+            "(A ? B)",
+            "(A ≡ B)",
+            "(if A then B else C)",
+            "(A : B)",
+            "(Some A)",
+            "([] : A)",
+            "[A, B]",
+            "{ a = A, `b-c` = B }",
+            "{ a : A, b : B }",
+            "< A | B : C >",
+            "(A.a)",
+            "(A.{ a, b })",
+            "(A.(B))",
+            "(A :: B)",
+            "(merge A B)",
+            "(merge A B : C)",
+            "(toMap A)",
+            "(toMap A : B)",
+            "(assert : A)",
+            "(A with a.b = B)",
+            "\"t${A}\"",
+        ];
+        if depth == 0 {
+            return LEAVES[rng.gen_range(0, LEAVES.len())].to_string();
+        }
+        let form = FORMS[rng.gen_range(0, FORMS.len())];
+        let mut out = String::new();
+        for c in form.chars() {
+            match c {
+                'A' | 'B' | 'C' => out += &gen_expr(rng, depth - 1),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn minify_roundtrip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut checked = 0;
+        while checked < 500 {
+            let depth = rng.gen_range(1, 4);
+            let source = gen_expr(&mut rng, depth);
+            let expr = match parse_expr(&source) {
+                Ok(expr) => expr,
+                // Some combinations are not syntactically valid.
+                Err(_) => continue,
+            };
+            let min = minify(&expr);
+            assert_eq!(
+                parse_expr(&min).ok().as_ref(),
+                Some(&expr),
+                "\nsource: {}\nminified: {}",
+                source,
+                min
+            );
+            assert_eq!(
+                parse_expr(&expr.to_string()).ok().as_ref(),
+                Some(&expr),
+                "\nsource: {}\nprinted: {}",
+                source,
+                expr
+            );
+            assert!(
+                min.len() <= expr.to_string().len(),
+                "\nminified: {}\nprinted: {}",
+                min,
+                expr
+            );
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn pretty_examples() {
+        let pretty = |s: &str| pretty(&parse_expr(s).unwrap());
+        assert_eq!(pretty("{ b = 2, a = [ 1, 2 ] }"), "{ a = [1, 2], b = 2 }");
+        let long = |x: &str| format!("\"{}\"", x.repeat(40));
+        assert_eq!(
+            pretty(&format!(
+                "{{ name = {}, nested = {{ x = {}, y = {} }}, tags = [{}, {}] }}",
+                long("a"),
+                long("b"),
+                long("e"),
+                long("c"),
+                long("d")
+            )),
+            format!(
+                "{{ name = {}\n\
+                 , nested =\n    {{ x = {}\n    , y = {}\n    }}\n\
+                 , tags =\n    [ {}\n    , {}\n    ]\n\
+                 }}",
+                long("a"),
+                long("b"),
+                long("e"),
+                long("c"),
+                long("d")
+            )
+        );
+        assert_eq!(
+            pretty(&format!(
+                "λ(x : Text) → λ(y : Text) → if Natural/isZero 0 then f {} {} else y",
+                long("a"),
+                long("b")
+            )),
+            format!(
+                "λ(x : Text) →\n\
+                 λ(y : Text) →\n    \
+                 if Natural/isZero 0\n    \
+                 then f\n        {}\n        {}\n    \
+                 else y",
+                long("a"),
+                long("b")
+            )
+        );
+        assert_eq!(
+            pretty(&format!(
+                "let x = {} in let y = {} in x ++ y",
+                long("a"),
+                long("b")
+            )),
+            format!("let x = {}\nlet y = {}\nin  x ++ y", long("a"), long("b"))
+        );
+    }
+
+    #[test]
+    fn pretty_roundtrip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut checked = 0;
+        // The number of expressions that didn't fit on a line.
+        let mut broken = 0;
+        while checked < 200 {
+            let depth = rng.gen_range(3, 5);
+            let source = gen_expr(&mut rng, depth);
+            let expr = match parse_expr(&source) {
+                Ok(expr) => expr,
+                Err(_) => continue,
+            };
+            let printed = pretty(&expr);
+            if printed.contains('\n') {
+                broken += 1;
+            }
+            let reparsed = parse_expr(&printed).ok();
+            assert_eq!(
+                reparsed.as_ref(),
+                Some(&expr),
+                "\nsource: {}\nprinted:\n{}",
+                source,
+                printed
+            );
+            assert_eq!(pretty(&reparsed.unwrap()), printed);
+            checked += 1;
+        }
+        assert!(broken > 20, "only {} expressions were broken", broken);
+    }
+
+    #[test]
+    fn canonical_string() {
+        let canonical = |s: &str| {
+            crate::to_canonical_string(&parse_expr(s).unwrap()).unwrap()
+        };
+        assert_eq!(
+            canonical("{ b = 1 + 1, a = True }"),
+            "{ a = True, b = 2 }\n"
+        );
+        // Only the meaning matters, not how the expression is written.
+        assert_eq!(
+            canonical(
+                "let f = λ(x : Natural) → x * 2 in { a = f 2, b = [f 3] }"
+            ),
+            canonical("{ b = [ 6 ],\n  a = 4 }")
+        );
+        let big = format!(
+            "{{ hosts = [{}], port = 8080, users = {{ admin = \"root\", guest = None Text }} }}",
+            (0..8).map(|i| format!("\"host-{}.example.com\"", i)).join(", ")
+        );
+        let text = canonical(&big);
+        assert!(text.lines().count() > 1, "{}", text);
+        assert!(text.lines().all(|l| l.chars().count() <= 80), "{}", text);
+        // It is its own canonical string.
+        assert_eq!(canonical(&text), text);
+        assert!(crate::to_canonical_string(&parse_expr("1 + True").unwrap())
+            .is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn double_roundtrip(x in proptest::num::f64::ANY) {
+            let printed = NaiveDouble::from(x).to_string();
+            let parsed = match parse_expr(&printed).unwrap().kind() {
+                ExprKind::Num(NumKind::Double(y)) => f64::from(*y),
+                _ => panic!("not a double: {}", printed),
+            };
+            // All NaNs are printed the same.
+            if x.is_nan() {
+                proptest::prop_assert!(parsed.is_nan());
+            } else {
+                proptest::prop_assert_eq!(parsed.to_bits(), x.to_bits(), "{}", printed);
+            }
+        }
+    }
+}
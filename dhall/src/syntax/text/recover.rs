@@ -0,0 +1,612 @@
+//! Parsing that recovers from syntax errors, for editors that need an AST of broken code.
+//!
+//! The grammar is not changed: when the input fails to parse, the text around the error is
+//! patched and parsed again, until it parses. The part that was skipped is replaced by a
+//! placeholder variable, which becomes an `ExprKind::Error` node once the spans are mapped back
+//! to the original input.
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use pest::error::InputLocation;
+
+use crate::syntax::text::parser::{parse_expr_pest, PestError};
+use crate::syntax::ParseError;
+use crate::syntax::{ErrorNode, Expr, ExprKind, Span, V};
+
+/// The most syntax errors reported before giving up on the rest of the input.
+const MAX_ERRORS: usize = 100;
+/// The name of the variable that stands for a skipped part of the input.
+const PLACEHOLDER_LABEL: &str = "<error>";
+const PLACEHOLDER: &str = " `<error>` ";
+/// The keywords where parsing can resume after an error.
+const KEYWORDS: &[&str] = &["let", "in", "if", "then", "else", "merge"];
+/// The tokens that are tried to complete expressions like `if` or `let`, each followed by a
+/// placeholder.
+const CONTINUATIONS: &[&[&str]] =
+    &[&["→"], &["then", "else"], &["else"], &["in"], &["", "else"]];
+
+/// Parses an expression, replacing the parts that fail to parse with `ExprKind::Error` nodes.
+///
+/// After an error, the parser skips to the next line, closing bracket, comma or keyword, and
+/// inserts missing closing brackets if needed. Returns the partial expression and the errors
+/// found, which are empty if the input is valid. The spans of the expression point into `input`.
+pub fn parse_expr_recovering(input: &str) -> (Expr, Vec<ParseError>) {
+    let mut patched = Patched::new(input);
+    let mut errors = Vec::new();
+    loop {
+        let err = match parse_expr_pest(&patched.text) {
+            Ok(expr) => return (patched.unpatch(&expr), errors),
+            Err(err) => err,
+        };
+        let (start, end) = match err.location {
+            InputLocation::Pos(pos) => {
+                (pos, recovery_point(&patched.text, pos))
+            }
+            InputLocation::Span(span) => span,
+        };
+        errors.push(ParseError::new(patched.unpatch_error(err), input));
+        if errors.len() >= MAX_ERRORS {
+            break;
+        }
+        match patched.recover(start, end) {
+            Some(next) => patched = next,
+            None => break,
+        }
+    }
+    // Give up, and make the whole input an error.
+    let input: Rc<str> = input.into();
+    let span = pest::Span::new(&input, 0, input.len()).unwrap();
+    let expr = Expr::new(
+        ExprKind::Error(ErrorNode {
+            skipped: input.to_string(),
+        }),
+        Span::make(input.clone(), span),
+    );
+    (expr, errors)
+}
+
+/// The input with some parts replaced.
+#[derive(Clone)]
+struct Patched<'a> {
+    input: &'a str,
+    text: String,
+    /// The position in `input` of each position in `text`, including the end.
+    positions: Vec<usize>,
+    /// Whether each position of `text` was inserted by a patch.
+    inserted: Vec<bool>,
+    /// The end in `input` of the part skipped by each placeholder, by its start.
+    skipped: HashMap<usize, usize>,
+}
+
+impl<'a> Patched<'a> {
+    fn new(input: &'a str) -> Self {
+        // The final newline lets keywords at the end of the input be parsed, like the `in` of
+        // `let x = 1 in`, so that the error is found after them.
+        Patched {
+            input,
+            text: format!("{}\n", input),
+            positions: (0..=input.len()).chain(Some(input.len())).collect(),
+            inserted: vec![false; input.len() + 2],
+            skipped: HashMap::new(),
+        }
+    }
+
+    /// Replaces `start..end` of the text with `s`.
+    fn replace(&self, start: usize, end: usize, s: &str) -> Self {
+        let mut new = self.clone();
+        new.text.replace_range(start..end, s);
+        let orig_start = self.positions[start];
+        new.positions.splice(start..end, vec![orig_start; s.len()]);
+        new.inserted.splice(start..end, vec![true; s.len()]);
+        new
+    }
+
+    /// Replaces `start..end` of the text with a placeholder, between `prefix` and `suffix`.
+    fn replace_with_placeholder(
+        &self,
+        start: usize,
+        end: usize,
+        prefix: &str,
+        suffix: &str,
+    ) -> Self {
+        let s = format!("{}{}{}", prefix, PLACEHOLDER, suffix);
+        let mut new = self.replace(start, end, &s);
+        let skipped = new.skipped.entry(self.positions[start]).or_insert(0);
+        *skipped = (*skipped).max(self.positions[end]);
+        new
+    }
+
+    /// Tries the ways to patch the text around an error at `start..end`, and returns the first
+    /// one that parses or fails further in the input.
+    fn recover(&self, start: usize, end: usize) -> Option<Self> {
+        let closers = closers(&self.text[..start]);
+        let innermost = closers.first().cloned().unwrap_or("");
+        let all: String = closers.concat();
+        let skip_end = if end > start {
+            end
+        } else {
+            self.text[start..]
+                .chars()
+                .next()
+                .map_or(start, |c| start + c.len_utf8())
+        };
+        let mut candidates =
+            vec![self.replace_with_placeholder(start, end, "", "")];
+        if !closers.is_empty() {
+            candidates.push(self.replace(start, start, innermost));
+            candidates.push(self.replace(start, start, &all));
+        }
+        if skip_end > start {
+            candidates.push(self.replace(start, skip_end, ""));
+        }
+        for tokens in CONTINUATIONS {
+            let suffix: String = tokens[1..]
+                .iter()
+                .map(|token| format!(" {}{}", token, PLACEHOLDER))
+                .collect();
+            for closers in &["", innermost, &all] {
+                let prefix = format!("{} {}", closers, tokens[0]);
+                candidates.push(
+                    self.replace_with_placeholder(start, end, &prefix, &suffix),
+                );
+            }
+        }
+        candidates.push(self.replace_with_placeholder(start, end, "", &all));
+
+        let error_pos = self.positions[start];
+        let progress = |patched: &Patched| match parse_expr_pest(&patched.text)
+        {
+            Ok(_) => true,
+            Err(err) => {
+                let pos = match err.location {
+                    InputLocation::Pos(pos) => pos,
+                    InputLocation::Span((start, _)) => start,
+                };
+                patched.positions[pos] > error_pos
+            }
+        };
+        candidates.into_iter().find(progress).or_else(|| {
+            let rest =
+                self.replace_with_placeholder(start, self.text.len(), "", &all);
+            Some(rest).filter(|rest| parse_expr_pest(&rest.text).is_ok())
+        })
+    }
+
+    /// Maps the spans of an expression parsed from the text back to the input, and turns
+    /// placeholders into error nodes.
+    fn unpatch(&self, expr: &Expr) -> Expr {
+        let input: Rc<str> = self.input.into();
+        self.unpatch_expr(&input, expr)
+    }
+
+    fn unpatch_expr(&self, input: &Rc<str>, expr: &Expr) -> Expr {
+        let span = expr.span();
+        let parsed = match span.parsed() {
+            Some(parsed) => parsed,
+            None => {
+                return Expr::new(
+                    expr.kind().map_ref(|e| self.unpatch_expr(input, e)),
+                    span,
+                )
+            }
+        };
+        let start = self.positions[parsed.start()];
+        let mut end = self.positions[parsed.end()];
+        let kind = match expr.kind() {
+            ExprKind::Var(V(l, 0))
+                if String::from(l) == PLACEHOLDER_LABEL
+                    && self.inserted[parsed.start()] =>
+            {
+                end = self.skipped[&start];
+                ExprKind::Error(ErrorNode {
+                    skipped: self.input[start..end].to_owned(),
+                })
+            }
+            kind => kind.map_ref(|e| self.unpatch_expr(input, e)),
+        };
+        let new_span = pest::Span::new(input, start, end).unwrap();
+        let comments = span.comments().to_vec();
+        Expr::new(kind, Span::make(input.clone(), new_span))
+            .with_comments(comments)
+    }
+
+    /// Maps the location of an error in the text back to the input.
+    fn unpatch_error(&self, err: PestError) -> PestError {
+        match err.location {
+            InputLocation::Pos(pos) => {
+                let pos = pest::Position::new(self.input, self.positions[pos])
+                    .unwrap();
+                PestError::new_from_pos(err.variant, pos)
+            }
+            InputLocation::Span((start, end)) => {
+                let span = pest::Span::new(
+                    self.input,
+                    self.positions[start],
+                    self.positions[end],
+                )
+                .unwrap();
+                PestError::new_from_span(err.variant, span)
+            }
+        }
+    }
+}
+
+/// The position of the next line, closing bracket, comma or keyword from `pos`.
+fn recovery_point(text: &str, pos: usize) -> usize {
+    let mut prev = text[..pos].chars().next_back();
+    for (i, c) in text[pos..].char_indices() {
+        let i = pos + i;
+        let at_word_start = !is_label_char(prev);
+        match c {
+            '\n' | ',' | ')' | ']' | '}' => return i,
+            '>' if prev != Some('-') => return i,
+            _ if at_word_start => {
+                let rest = &text[i..];
+                let is_keyword = KEYWORDS.iter().any(|kw| {
+                    rest.starts_with(kw)
+                        && !is_label_char(rest[kw.len()..].chars().next())
+                });
+                if is_keyword {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    text.len()
+}
+
+/// Whether `c` is a character of a label or keyword.
+fn is_label_char(c: Option<char>) -> bool {
+    match c {
+        Some(c) => c.is_ascii_alphanumeric() || c == '_',
+        None => false,
+    }
+}
+
+/// The text that closes the brackets and text literals left open in `text`, innermost first.
+pub(crate) fn closers(text: &str) -> Vec<&'static str> {
+    scan(text, |_, _| true)
+        .iter()
+        .rev()
+        .filter_map(|c| match c {
+            '(' => Some(")"),
+            '[' => Some("]"),
+            '{' | '$' => Some("}"),
+            '<' => Some(">"),
+            '"' => Some("\""),
+            '\'' => Some("''"),
+            _ => None,
+        })
+        .collect()
+}
+
+/// How many levels deep `text` nests, or where it nests more than `max_depth` levels deep.
+/// Brackets, text literals, functions, `if`s and `let`s each count as a level. This doesn't
+/// parse, so it tells how much stack parsing needs before starting.
+pub(crate) fn nesting_depth(
+    text: &str,
+    max_depth: usize,
+) -> Result<usize, usize> {
+    let mut depth = 0;
+    let mut too_deep = None;
+    scan(text, |offset, open| {
+        depth = depth.max(open.len());
+        if depth > max_depth {
+            too_deep = Some(offset);
+        }
+        too_deep.is_none()
+    });
+    match too_deep {
+        Some(offset) => Err(offset),
+        None => Ok(depth),
+    }
+}
+
+/// The keywords after which an expression is expected.
+const OPENING_KEYWORDS: &[&str] = &[
+    "if",
+    "then",
+    "else",
+    "let",
+    "in",
+    "as",
+    "using",
+    "merge",
+    "Some",
+    "toMap",
+    "showConstructor",
+    "assert",
+    "forall",
+    "with",
+];
+
+/// Scans `text` for the constructs that nest, and calls `visit` with the start of each token
+/// and what is open after it, until `visit` returns false. Returns what is open at the end.
+///
+/// Brackets are open until their closing bracket. `"` and `'` stand for text literals, and `$`
+/// for interpolations. `l` stands for the bindings of a `let`, and `→` for what nests to the
+/// right without brackets: the bodies of functions and `let`s, and `if`s. They are closed with
+/// the enclosing bracket, or at a `,` or `|`.
+fn scan(
+    text: &str,
+    mut visit: impl FnMut(usize, &[char]) -> bool,
+) -> Vec<char> {
+    /// Closes the `→`s at the top of `open`, and the `let` they are in, if any.
+    fn close_binding(open: &mut Vec<char>) {
+        while open.last() == Some(&'→') {
+            open.pop();
+        }
+        if open.last() == Some(&'l') {
+            open.pop();
+        }
+    }
+    /// Closes the `let`s and `→`s at the top of `open`.
+    fn close_unbracketed(open: &mut Vec<char>) {
+        while matches!(open.last(), Some('→') | Some('l')) {
+            open.pop();
+        }
+    }
+
+    let mut open = Vec::new();
+    // Whether the last token ends an expression. A `let` there starts the next binding of a
+    // `let`, rather than a `let` in the value of the current one.
+    let mut after_expr = false;
+    let mut offset = 0;
+    while let Some(c) = text[offset..].chars().next() {
+        let rest = &text[offset..];
+        let mut skip = c.len_utf8();
+        match (open.last(), c) {
+            (Some('"'), '\\') => {
+                skip += rest[1..].chars().next().map_or(0, char::len_utf8)
+            }
+            (Some('"'), '"') => {
+                open.pop();
+                after_expr = true;
+            }
+            (Some('"'), '$') | (Some('\''), '$') if rest.starts_with("${") => {
+                open.push('$');
+                skip = 2;
+                after_expr = false;
+            }
+            (Some('\''), '\'') if rest.starts_with("'''") => skip = 3,
+            (Some('\''), '\'') if rest.starts_with("''${") => skip = 4,
+            (Some('\''), '\'') if rest.starts_with("''") => {
+                open.pop();
+                skip = 2;
+                after_expr = true;
+            }
+            (Some('"'), _) | (Some('\''), _) => {}
+            (_, '-') if rest.starts_with("--") => {
+                skip = rest.find('\n').unwrap_or(rest.len());
+            }
+            (_, '{') if rest.starts_with("{-") => {
+                let mut depth = 0;
+                let mut i = 0;
+                while i < rest.len() {
+                    if rest[i..].starts_with("{-") {
+                        depth += 1;
+                        i += 2;
+                    } else if rest[i..].starts_with("-}") {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += rest[i..].chars().next().unwrap().len_utf8();
+                    }
+                }
+                skip = i;
+            }
+            (_, '-') if rest.starts_with("->") => {
+                open.push('→');
+                skip = 2;
+                after_expr = false;
+            }
+            (_, '→') => {
+                open.push('→');
+                after_expr = false;
+            }
+            (_, '`') => {
+                skip = rest[1..].find('`').map_or(rest.len(), |i| i + 2);
+                after_expr = true;
+            }
+            (_, '"') => open.push('"'),
+            (_, '\'') if rest.starts_with("''") => {
+                open.push('\'');
+                skip = 2;
+            }
+            (_, '(') | (_, '[') | (_, '{') | (_, '<') => {
+                open.push(c);
+                after_expr = false;
+            }
+            (_, ')') | (_, ']') | (_, '}') | (_, '>') => {
+                close_unbracketed(&mut open);
+                let closes = match open.last() {
+                    Some('(') => c == ')',
+                    Some('[') => c == ']',
+                    Some('{') | Some('$') => c == '}',
+                    Some('<') => c == '>',
+                    _ => false,
+                };
+                if closes {
+                    open.pop();
+                }
+                after_expr = true;
+            }
+            (_, ',') | (_, '|') => {
+                close_unbracketed(&mut open);
+                after_expr = false;
+            }
+            _ if is_label_char(Some(c)) => {
+                skip = rest
+                    .find(|c| !is_label_char(Some(c)) && c != '-' && c != '/')
+                    .unwrap_or(rest.len());
+                let word = &rest[..skip];
+                match word {
+                    "if" => open.push('→'),
+                    "let" => {
+                        if after_expr {
+                            close_binding(&mut open);
+                        }
+                        open.push('l');
+                    }
+                    "in" => {
+                        close_binding(&mut open);
+                        open.push('→');
+                    }
+                    _ => {}
+                }
+                after_expr = !OPENING_KEYWORDS.contains(&word);
+            }
+            _ if c.is_whitespace() => {}
+            _ => after_expr = false,
+        }
+        if !visit(offset, &open) {
+            break;
+        }
+        offset += skip;
+    }
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::parse_expr;
+    use crate::{Ctxt, Parsed};
+
+    /// The printed partial expression, and the positions of the errors.
+    fn recover(input: &str) -> (String, Vec<usize>) {
+        let (expr, errors) = parse_expr_recovering(input);
+        let positions = errors.iter().map(|err| err.offset).collect();
+        (expr.to_string(), positions)
+    }
+
+    /// The spans of the error nodes of `expr`.
+    fn error_spans(expr: &Expr) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        if let ExprKind::Error(_) = expr.kind() {
+            let span = expr.span();
+            let span = span.parsed().unwrap();
+            spans.push((span.start(), span.end()));
+        }
+        expr.kind().map_ref(|e| spans.extend(error_spans(e)));
+        spans
+    }
+
+    #[test]
+    fn valid_input() {
+        let input = "let x = { a = [ 1, 2 ] } in x.a # [ 3 ]";
+        let (expr, errors) = parse_expr_recovering(input);
+        assert!(errors.is_empty());
+        assert_eq!(expr, parse_expr(input).unwrap());
+    }
+
+    #[test]
+    fn recovers() {
+        // An expression is missing.
+        assert_eq!(
+            recover("{ x = , y = 2 }"),
+            ("{ x = , y = 2 }".into(), vec![6])
+        );
+        assert_eq!(recover("let x = 1 in"), ("let x = 1 in ".into(), vec![12]));
+        assert_eq!(
+            recover("{ x = , y = }"),
+            ("{ x = , y =  }".into(), vec![6, 12])
+        );
+        // Brackets and text literals are closed.
+        assert_eq!(
+            recover("{ x = 1, y = [ 2"),
+            ("{ x = 1, y = [2] }".into(), vec![16])
+        );
+        assert_eq!(
+            recover("{ x = \"a${y}"),
+            ("{ x = \"a${ y }\" }".into(), vec![12])
+        );
+        // Expressions are completed.
+        assert_eq!(
+            recover("λ(x : Natural"),
+            ("λ(x : Natural) → ".into(), vec![14])
+        );
+        assert_eq!(
+            recover("if True then 1"),
+            ("if True then 1 else ".into(), vec![14])
+        );
+        assert_eq!(recover("if True"), ("if True then  else ".into(), vec![7]));
+        assert_eq!(
+            recover("if True then"),
+            ("if True then  else ".into(), vec![12])
+        );
+        // Unexpected text is skipped.
+        assert_eq!(recover("{ x = 1 } }"), ("{ x = 1 }".into(), vec![10]));
+        assert_eq!(
+            recover("[ 1, 2 3 4 ] ) ]"),
+            ("[1, 2 3 4]".into(), vec![13, 15])
+        );
+    }
+
+    #[test]
+    fn error_nodes() {
+        let input = "{ x = 1 +, y = λ(z : ) → z }";
+        let (expr, errors) = parse_expr_recovering(input);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(error_spans(&expr), vec![(9, 9), (22, 22)]);
+        // Spans point into the input.
+        let span = expr.span();
+        assert_eq!(span.parsed().unwrap().input(), input);
+
+        // Error nodes don't typecheck.
+        let (partial, errors) = Parsed::parse_str_recovering("{ x = 1 + }");
+        assert_eq!(errors.len(), 1);
+        assert!(!partial.is_complete());
+        assert!(Parsed::parse_str_recovering("1").0.is_complete());
+        let err = Ctxt::with_new(|cx| {
+            Parsed::from_expr_without_imports(partial.to_expr())
+                .skip_resolve(cx)
+                .unwrap()
+                .typecheck(cx)
+                .unwrap_err()
+                .message()
+                .to_string()
+        });
+        assert!(err.contains("SyntaxError"));
+
+        // Without a way to recover, the whole input is an error.
+        let (expr, errors) = parse_expr_recovering("merge");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            expr.kind(),
+            &ExprKind::Error(ErrorNode {
+                skipped: "merge".into()
+            })
+        );
+    }
+
+    #[test]
+    fn nesting_depths() {
+        let depth = |s: &str| nesting_depth(s, usize::MAX).ok();
+        assert_eq!(depth("1"), Some(0));
+        assert_eq!(depth("[[[1]]]"), Some(3));
+        assert_eq!(nesting_depth("[[[1]]]", 2), Err(2));
+        // Brackets in text and comments don't count, but the text literal does.
+        assert_eq!(depth("\"(((\" -- (((\n"), Some(1));
+        assert_eq!(depth(r#""${"${"a"}"}""#), Some(5));
+        // Functions and `if`s nest without brackets.
+        assert_eq!(depth("λ(x : Bool) → λ(y : Bool) → x"), Some(2));
+        assert_eq!(depth("Bool → Bool → Bool"), Some(2));
+        assert_eq!(
+            depth("if True then if False then 1 else 2 else 3"),
+            Some(2)
+        );
+        // Siblings don't add up, in lists or in the bindings of a `let`.
+        assert_eq!(depth("[λ(x : Bool) → x, λ(y : Bool) → y]"), Some(2));
+        assert_eq!(
+            depth("let f = λ(x : Bool) → x let g = λ(x : Bool) → x in f"),
+            Some(2)
+        );
+        assert_eq!(depth("let a = 1 in let b = 2 in a"), Some(2));
+    }
+}
@@ -4,6 +4,19 @@ use std::path::Path;
 
 use crate::error::Error;
 
+/// How much stack the recursions over expressions need left at each level. With less left,
+/// `with_stack` continues on a new stack.
+const STACK_RED_ZONE: usize = 1024 * 1024;
+/// The size of the stacks allocated by `with_stack`.
+const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// Runs `f`, one level of a recursion over an expression, on a new stack if the current one is
+/// nearly full. The recursions that follow the nesting of expressions go through this, so that
+/// expressions nested up to `DEFAULT_MAX_DEPTH` levels deep don't overflow the stack.
+pub(crate) fn with_stack<T>(f: impl FnOnce() -> T) -> T {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, f)
+}
+
 // Compute the sha256 hash of a bitstring.
 pub fn sha256_hash(data: &[u8]) -> Box<[u8]> {
     use sha2::Digest;
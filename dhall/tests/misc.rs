@@ -48,3 +48,348 @@ fn manual_function_application() {
     // The crate uses essentially a global context, created here.
     Ctxt::with_new(run).unwrap();
 }
+
+#[test]
+fn parse_binary() {
+    use dhall::error::{DecodeError, ErrorKind};
+
+    fn parse(data: &[u8]) -> Result<String, Error> {
+        Ok(Parsed::parse_binary(data)?.to_expr().to_string())
+    }
+
+    // `True`
+    assert_eq!(parse(&[0xf5]).unwrap(), "True");
+    // `[15, 1]`, i.e. `1`
+    assert_eq!(parse(&[0x82, 0x0f, 0x01]).unwrap(), "1");
+    // `[0, ["x", 0], [15, 1]]`, i.e. `x 1`
+    assert_eq!(
+        parse(&[0x83, 0x00, 0x82, 0x61, b'x', 0x00, 0x82, 0x0f, 0x01]).unwrap(),
+        "x 1"
+    );
+
+    // Truncated array.
+    let err = parse(&[0x82, 0x0f]).unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Decode(DecodeError::CBORError(_))
+    ));
+    // Well-formed CBOR, but not a dhall expression.
+    let err = parse(&[0x82, 0x18, 0x63, 0x01]).unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Decode(DecodeError::WrongFormatError(_))
+    ));
+    let err = parse(&[0x40]).unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        ErrorKind::Decode(DecodeError::WrongFormatError(_))
+    ));
+}
+
+#[test]
+fn add_let_bindings() {
+    fn run(cx: Ctxt<'_>) -> Result<(), Error> {
+        let eval = |parsed: Parsed| -> Result<String, Error> {
+            Ok(parsed
+                .skip_resolve(cx)?
+                .typecheck(cx)?
+                .normalize(cx)
+                .to_expr(cx)
+                .to_string())
+        };
+        let x = Parsed::parse_str("1")?.to_expr();
+        let y = Parsed::parse_str("x + 1")?.to_expr();
+
+        let parsed =
+            Parsed::parse_str("x * 10")?.add_let_binding("x".into(), x.clone());
+        assert_eq!(eval(parsed)?, "10");
+
+        // `y` refers to `x`, which was bound before it.
+        let parsed = Parsed::parse_str("[x, y]")?
+            .add_let_bindings(vec![("x".into(), x), ("y".into(), y)]);
+        assert_eq!(eval(parsed)?, "[1, 2]");
+        Ok(())
+    }
+
+    Ctxt::with_new(run).unwrap();
+}
+
+#[test]
+fn pipeline_stages() {
+    let dir = std::env::temp_dir()
+        .join(format!("dhall_pipeline_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("port.dhall"), "let p = 8000 in p + 80").unwrap();
+    std::fs::write(dir.join("host.dhall"), "\"localhost\"").unwrap();
+    std::fs::write(dir.join("main.dhall"), "{ port = ./port.dhall }").unwrap();
+
+    let run = |cx: Ctxt<'_>| -> Result<(), Error> {
+        // Parsing keeps the imports.
+        let parsed = Parsed::parse_file(&dir.join("main.dhall"))?;
+        assert_eq!(parsed.to_expr().to_string(), "{ port = ./\"port.dhall\" }");
+
+        // Resolving replaces them by their contents, normalized.
+        let resolved = parsed.resolve(cx)?;
+        assert_eq!(resolved.to_expr(cx).to_string(), "{ port = 8080 }");
+
+        // Typechecking infers the type, or checks a given one.
+        let typed = resolved.typecheck(cx)?;
+        assert_eq!(
+            typed.get_type()?.to_expr(cx).to_string(),
+            "{ port : Natural }"
+        );
+        let ty = |ty: &str| -> Result<_, Error> {
+            Ok(Parsed::parse_str(ty)?
+                .skip_resolve(cx)?
+                .typecheck(cx)?
+                .normalize(cx))
+        };
+        let good = ty("{ port : Natural }")?;
+        assert!(resolved.clone().typecheck_with(cx, &good.to_hir()).is_ok());
+        let bad = ty("{ port : Text }")?;
+        assert!(resolved.typecheck_with(cx, &bad.to_hir()).is_err());
+
+        // Normalizing computes the value.
+        let nf = Parsed::parse_str("let x = 1 in x + 1")?
+            .skip_resolve(cx)?
+            .typecheck(cx)?
+            .normalize(cx);
+        assert_eq!(nf.to_expr(cx).to_string(), "2");
+
+        // Imports can be resolved separately, and the results put together before typechecking.
+        let port = Parsed::parse_file(&dir.join("port.dhall"))?.resolve(cx)?;
+        let host = Parsed::parse_file(&dir.join("host.dhall"))?.resolve(cx)?;
+        let merged =
+            Parsed::parse_str("{ host, port }")?.add_let_bindings(vec![
+                ("port".into(), port.to_expr(cx)),
+                ("host".into(), host.to_expr(cx)),
+            ]);
+        let value = merged.skip_resolve(cx)?.typecheck(cx)?.normalize(cx);
+        assert_eq!(
+            value.to_expr(cx).to_string(),
+            "{ host = \"localhost\", port = 8080 }"
+        );
+        Ok(())
+    };
+    let result = Ctxt::with_new(run);
+    std::fs::remove_dir_all(&dir).unwrap();
+    result.unwrap();
+}
+
+#[test]
+fn normalize_with_trace() {
+    use dhall::builtins::Builtin;
+
+    let mut events = Vec::new();
+    let expr =
+        Parsed::parse_str("Natural/fold 3 Natural (λ(x : Natural) → x + 2) 0")
+            .unwrap()
+            .to_expr();
+    let nf =
+        dhall::normalize_with_trace(&expr, &mut |e| events.push(*e)).unwrap();
+    assert_eq!(nf.to_string(), "6");
+    let folds = events
+        .iter()
+        .filter(|e| {
+            matches!(
+                e,
+                TraceEvent::Builtin {
+                    builtin: Builtin::NaturalFold,
+                    ..
+                }
+            )
+        })
+        .count();
+    // One step per iteration, and one for `Natural/fold 0` that returns the zero.
+    assert_eq!(folds, 4);
+    // The function is applied once per iteration.
+    let betas = events
+        .iter()
+        .filter(|e| matches!(e, TraceEvent::Beta { .. }))
+        .count();
+    assert_eq!(betas, 3);
+
+    // Nothing is traced in a context created without a hook.
+    let mut events = Vec::new();
+    Ctxt::with_new_traced(
+        |e| events.push(*e),
+        |_| {
+            Ctxt::with_new(|cx| {
+                let typed = Parsed::from_expr_without_imports(expr)
+                    .skip_resolve(cx)
+                    .unwrap()
+                    .typecheck(cx)
+                    .unwrap();
+                typed.normalize(cx).to_expr(cx)
+            })
+        },
+    );
+    assert!(events.is_empty());
+}
+
+#[test]
+fn freeze() {
+    let dir = std::env::temp_dir()
+        .join(format!("dhall_freeze_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("a.dhall"), "1").unwrap();
+    std::fs::write(dir.join("b.dhall"), "./a.dhall + 1").unwrap();
+    std::fs::write(
+        dir.join("main.dhall"),
+        "{ xs = [./a.dhall, ./b.dhall, missing ? ./a.dhall], t = ./a.dhall as Text }",
+    )
+    .unwrap();
+
+    let run = |cx: Ctxt<'_>| -> Result<(String, String), Error> {
+        let main = dir.join("main.dhall");
+        let remote = Parsed::parse_file(&main)?.freeze(cx, false)?.to_string();
+        let all = Parsed::parse_file(&main)?.freeze(cx, true)?.to_string();
+        // The hashes must match when the frozen file is resolved again.
+        std::fs::write(&main, &all).unwrap();
+        let value = Parsed::parse_file(&main)?
+            .resolve(cx)?
+            .typecheck(cx)?
+            .normalize(cx)
+            .to_expr(cx)
+            .to_string();
+        assert_eq!(value, "{ t = \"1\", xs = [1, 2, 1] }");
+        Ok((remote, all))
+    };
+    let result = Ctxt::with_new(run);
+    std::fs::remove_dir_all(&dir).unwrap();
+    let (remote, all) = result.unwrap();
+
+    // There are no remote imports.
+    assert_eq!(
+        remote,
+        "{ t = ./\"a.dhall\" as Text, xs = [./\"a.dhall\", ./\"b.dhall\", missing ? ./\"a.dhall\"] }"
+    );
+    let hash_1 = "sha256:d60d8415e36e86dae7f42933d3b0c4fe3ca238f057fba206c7e9fbf5d784fe15";
+    let hash_2 = "sha256:4caf97e8c445d4d4b5c5b992973e098ed4ae88a355915f5a59db640a589bc9cb";
+    assert_eq!(
+        all,
+        format!(
+            "{{ t = ./\"a.dhall\" as Text, xs = [./\"a.dhall\" {}, ./\"b.dhall\" {}, missing ? ./\"a.dhall\" {}] }}",
+            hash_1, hash_2, hash_1
+        )
+    );
+}
+
+/// The `parallel-imports` feature prefetches local imports too: these must give the same results
+/// and callbacks with or without it, and with or without a parse cache.
+#[test]
+fn independent_imports() {
+    use dhall::semantics::parse::ParseCache;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let dir = std::env::temp_dir()
+        .join(format!("dhall_independent_imports_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for i in 0..10 {
+        std::fs::write(dir.join(format!("{}.dhall", i)), format!("{}", i))
+            .unwrap();
+    }
+    std::fs::write(
+        dir.join("main.dhall"),
+        "{ xs = [./0.dhall, ./1.dhall, ./2.dhall, ./3.dhall, ./4.dhall, ./5.dhall, ./6.dhall, \
+         ./7.dhall, ./8.dhall, ./9.dhall, ./0.dhall], \
+         text = ./9.dhall as Text, \
+         fallback = ./missing.dhall ? 42 }",
+    )
+    .unwrap();
+
+    let run = |parse_cache: Option<Rc<ParseCache>>| {
+        let imports = Rc::new(RefCell::new(Vec::new()));
+        let result = Ctxt::with_new(|cx| -> Result<String, Error> {
+            let callback_imports = imports.clone();
+            let mut env = ImportEnv::new(cx).with_callback(move |_, meta| {
+                callback_imports
+                    .borrow_mut()
+                    .push((meta.size, meta.from_cache))
+            });
+            if let Some(cache) = parse_cache {
+                env = env.with_parse_cache(cache);
+            }
+            Ok(Parsed::parse_file(&dir.join("main.dhall"))?
+                .resolve_with_env(&mut env)?
+                .typecheck(cx)?
+                .normalize(cx)
+                .to_expr(cx)
+                .to_string())
+        });
+        let imports = imports.borrow().clone();
+        (result, imports)
+    };
+    let cache = Rc::new(ParseCache::new(16));
+    let results = vec![run(None), run(Some(cache.clone())), run(Some(cache))];
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Record fields are resolved in order, so `./9.dhall as Text` comes first. Then each file
+    // is fetched once, and `./0.dhall` comes from the cache the second time. The parse cache
+    // doesn't change that, even when the files are already in it.
+    let mut expected = vec![(Some(1), false); 11];
+    expected.push((None, true));
+    for (result, imports) in results {
+        assert_eq!(
+            result.unwrap(),
+            "{ fallback = 42, text = \"9\", xs = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0] }"
+        );
+        assert_eq!(imports, expected);
+    }
+}
+
+/// With the `parallel-imports` feature, independent local imports are read by the threads of the
+/// rayon pool, unless `with_parallel_imports(false)` keeps them on the resolving thread.
+#[cfg(feature = "parallel-imports")]
+#[test]
+fn parallel_imports_read_from_several_threads() {
+    use std::collections::HashSet;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, ThreadId};
+    use std::time::Duration;
+
+    /// Serves `/0.dhall` to `/9.dhall`, and records which threads read them.
+    struct RecordingResolver(Arc<Mutex<Vec<ThreadId>>>);
+
+    impl ImportResolver for RecordingResolver {
+        fn read_file(&self, path: &Path) -> Result<String, Error> {
+            self.0.lock().unwrap().push(thread::current().id());
+            // Slow reads leave time for the other threads to pick up work.
+            thread::sleep(Duration::from_millis(20));
+            Ok(path.file_stem().unwrap().to_str().unwrap().to_owned())
+        }
+    }
+
+    let run = |parallel_imports: bool| {
+        let readers = Arc::new(Mutex::new(Vec::new()));
+        let result = Ctxt::with_new(|cx| -> Result<String, Error> {
+            let mut env = ImportEnv::new(cx)
+                .with_parallel_imports(parallel_imports)
+                .with_resolver(RecordingResolver(readers.clone()));
+            Ok(Parsed::parse_str(
+                "[/0.dhall, /1.dhall, /2.dhall, /3.dhall, /4.dhall, /5.dhall, /6.dhall, \
+                 /7.dhall, /8.dhall, /9.dhall]",
+            )?
+            .resolve_with_env(&mut env)?
+            .typecheck(cx)?
+            .normalize(cx)
+            .to_expr(cx)
+            .to_string())
+        });
+        assert_eq!(result.unwrap(), "[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]");
+        let readers = readers.lock().unwrap();
+        assert_eq!(readers.len(), 10);
+        readers.iter().copied().collect::<HashSet<_>>()
+    };
+
+    let sequential = run(false);
+    assert_eq!(sequential.len(), 1);
+    assert!(sequential.contains(&thread::current().id()));
+    let parallel = run(true);
+    assert!(!parallel.contains(&thread::current().id()));
+    if rayon::current_num_threads() > 1 {
+        assert!(parallel.len() > 1);
+    }
+}
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use std::collections::BTreeMap;
 use std::env;
 use std::ffi::OsString;
 use std::fmt::{Debug, Display};
@@ -8,6 +9,7 @@ use std::fs::{create_dir_all, read_to_string, File};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 
 use libtest_mimic::{Arguments, Outcome, Test};
 use walkdir::WalkDir;
@@ -501,6 +503,9 @@ fn discover_tests_for_feature(feature: TestFeature) -> Vec<Test<SpecTest>> {
 
 /// Ignore some tests if they are known to be failing or not meant to pass.
 /// `path` must be relative to the test directorie(s).
+///
+/// Every test that isn't ignored must pass, so this list is the watermark for spec conformance:
+/// the pass rate can only go up, by removing entries from it.
 #[allow(clippy::nonminimal_bool)]
 fn ignore_test(variant: SpecTestKind, path: &str) -> bool {
     use SpecTestKind::*;
@@ -685,13 +690,33 @@ fn run_test(test: &SpecTest) -> Result<()> {
     })
 }
 
+/// Counts of the tests of a feature, to track how much of the spec we implement.
+#[derive(Default)]
+struct FeatureSummary {
+    /// Tests that are known to fail, see `ignore_test`.
+    ignored: usize,
+    run: usize,
+    passed: usize,
+}
+
+fn feature_name(test: &Test<SpecTest>) -> String {
+    test.name.split("::").next().unwrap().to_owned()
+}
+
 fn main() {
-    let tests = FEATURES
+    let tests: Vec<_> = FEATURES
         .iter()
         .copied()
         .flat_map(discover_tests_for_feature)
         .collect();
 
+    let summary: Arc<Mutex<BTreeMap<String, FeatureSummary>>> =
+        Default::default();
+    for test in tests.iter().filter(|test| test.is_ignored) {
+        let mut summary = summary.lock().unwrap();
+        summary.entry(feature_name(test)).or_default().ignored += 1;
+    }
+
     // Setup current directory to the root of the repository. Important for `as Location` tests.
     let root_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .parent()
@@ -728,10 +753,18 @@ fn main() {
     UPDATE_TEST_FILES.store(bless, Ordering::Release);
 
     let args = Arguments::from_iter(env::args().filter(|arg| arg != "--bless"));
-    let res = libtest_mimic::run_tests(&args, tests, |test| {
+    let run_summary = summary.clone();
+    let res = libtest_mimic::run_tests(&args, tests, move |test| {
         let result = std::panic::catch_unwind(move || {
             run_test_stringy_error(&test.data)
         });
+        let passed = matches!(result, Ok(Ok(_)));
+        {
+            let mut summary = run_summary.lock().unwrap();
+            let entry = summary.entry(feature_name(test)).or_default();
+            entry.run += 1;
+            entry.passed += passed as usize;
+        }
         match result {
             Ok(Ok(_)) => Outcome::Passed,
             Ok(Err(e)) => Outcome::Failed { msg: Some(e) },
@@ -741,6 +774,24 @@ fn main() {
         }
     });
 
+    // Set `DHALL_SPEC_SUMMARY=1` to see how much of each feature of the spec passes.
+    if env::var("DHALL_SPEC_SUMMARY") == Ok("1".to_string()) {
+        println!("pass rate per feature (ignored tests are known failures):");
+        for (name, s) in summary.lock().unwrap().iter() {
+            if s.run == 0 {
+                continue;
+            }
+            println!(
+                "    {}: {}/{} passed ({:.1}%), {} ignored",
+                name,
+                s.passed,
+                s.run,
+                100.0 * s.passed as f64 / s.run as f64,
+                s.ignored,
+            );
+        }
+    }
+
     std::fs::remove_dir_all(&cache_dir).unwrap();
 
     res.exit();
@@ -0,0 +1,228 @@
+#![doc(html_root_url = "https://docs.rs/dhall_lint/0.1.0")]
+//! Configurable style checks for Dhall code.
+//!
+//! Pick the rules to check with [`Linter::rule`], then run the [`Linter`] on some Dhall source.
+//! Each violation gives a [`LintDiagnostic`]. The source is checked as written: imports are not
+//! followed, and the code is not typechecked.
+//!
+//! # Example
+//!
+//! ```
+//! use dhall_lint::{LintRule, Linter};
+//!
+//! let diagnostics = Linter::new()
+//!     .rule(LintRule::RequireTypeAnnotation)
+//!     .rule(LintRule::PreferLetOverLambda)
+//!     .lint_str("(λ(x : Natural) → x + 1) 2")
+//!     .unwrap();
+//! assert_eq!(diagnostics.len(), 2);
+//! assert_eq!(diagnostics[0].rule, "require-type-annotation");
+//! assert_eq!(diagnostics[1].rule, "prefer-let-over-lambda");
+//! ```
+
+use std::fmt;
+use std::path::Path;
+
+use dhall::error::Error;
+use dhall::operations::OpKind;
+use dhall::syntax::visitor::{walk_expr, ExprVisitor};
+use dhall::syntax::{Expr, ExprKind, Label, NumKind, Span};
+use dhall::Parsed;
+
+/// A style rule that the [`Linter`] can check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// Disallow `_` as the name of a record field or union alternative.
+    NoAnonymousFields,
+    /// Require the file to end in a type annotation, like `let x = 1 in x : Natural`.
+    RequireTypeAnnotation,
+    /// Reject expressions nested more than this many levels deep.
+    MaxNestingDepth(usize),
+    /// Disallow boolean literals as function arguments, since `f True` doesn't say what `True`
+    /// means. A union or a record field conveys that better.
+    NoImplicitBoolean,
+    /// Flag lambdas that are applied immediately, like `(λ(x : T) → e) v`, which read better as
+    /// `let x : T = v in e`.
+    PreferLetOverLambda,
+}
+
+impl LintRule {
+    /// The name of the rule, as found in [`LintDiagnostic::rule`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintRule::NoAnonymousFields => "no-anonymous-fields",
+            LintRule::RequireTypeAnnotation => "require-type-annotation",
+            LintRule::MaxNestingDepth(_) => "max-nesting-depth",
+            LintRule::NoImplicitBoolean => "no-implicit-boolean",
+            LintRule::PreferLetOverLambda => "prefer-let-over-lambda",
+        }
+    }
+}
+
+/// A violation of a [`LintRule`].
+#[derive(Debug, Clone)]
+pub struct LintDiagnostic {
+    /// The name of the rule that was violated.
+    pub rule: &'static str,
+    pub message: String,
+    /// The location of the offending expression in the source, if known.
+    pub span: Option<Span>,
+}
+
+impl LintDiagnostic {
+    fn new(rule: LintRule, message: String, e: &Expr) -> Self {
+        let span = e.span().parsed().cloned().map(Span::Parsed);
+        LintDiagnostic {
+            rule: rule.name(),
+            message,
+            span,
+        }
+    }
+}
+
+impl fmt::Display for LintDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.rule, self.message)
+    }
+}
+
+/// Checks Dhall code against a set of [`LintRule`]s.
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    rules: Vec<LintRule>,
+}
+
+impl Linter {
+    /// A linter that doesn't check any rule yet.
+    pub fn new() -> Self {
+        Linter::default()
+    }
+
+    /// Adds a rule to check.
+    pub fn rule(mut self, rule: LintRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Checks an expression. The diagnostics are grouped by rule, in the order the rules were
+    /// added, and then in the order they appear in the expression.
+    pub fn lint_expr(&self, e: &Expr) -> Vec<LintDiagnostic> {
+        let mut diagnostics = Vec::new();
+        for &rule in &self.rules {
+            check_rule(rule, e, &mut diagnostics);
+        }
+        diagnostics
+    }
+
+    /// Parses and checks some Dhall source.
+    pub fn lint_str(&self, s: &str) -> Result<Vec<LintDiagnostic>, Error> {
+        Ok(self.lint_expr(&Parsed::parse_str(s)?.to_expr()))
+    }
+
+    /// Parses and checks a Dhall file. The files it imports are not checked.
+    pub fn lint_file(&self, path: &Path) -> Result<Vec<LintDiagnostic>, Error> {
+        Ok(self.lint_expr(&Parsed::parse_file(path)?.to_expr()))
+    }
+}
+
+fn check_rule(rule: LintRule, e: &Expr, diagnostics: &mut Vec<LintDiagnostic>) {
+    match rule {
+        LintRule::RequireTypeAnnotation => {
+            let mut body = e;
+            while let ExprKind::Let(_, _, _, b) = body.kind() {
+                body = b;
+            }
+            if !matches!(body.kind(), ExprKind::Annot(..)) {
+                diagnostics.push(LintDiagnostic::new(
+                    rule,
+                    "the file should end in a type annotation".to_owned(),
+                    body,
+                ));
+            }
+        }
+        LintRule::MaxNestingDepth(max) => walk(e, |e, depth| {
+            if depth > max {
+                diagnostics.push(LintDiagnostic::new(
+                    rule,
+                    format!(
+                        "this expression is nested more than {} levels deep",
+                        max
+                    ),
+                    e,
+                ));
+                false
+            } else {
+                true
+            }
+        }),
+        LintRule::NoAnonymousFields => walk(e, |e, _| {
+            let anonymous = Label::from_str("_");
+            let has_anonymous_field = match e.kind() {
+                ExprKind::RecordType(kts) | ExprKind::RecordLit(kts) => {
+                    kts.contains_key(&anonymous)
+                }
+                ExprKind::UnionType(kts) => kts.contains_key(&anonymous),
+                _ => false,
+            };
+            if has_anonymous_field {
+                diagnostics.push(LintDiagnostic::new(
+                    rule,
+                    "`_` should not be used as a field name".to_owned(),
+                    e,
+                ));
+            }
+            true
+        }),
+        LintRule::NoImplicitBoolean => walk(e, |e, _| {
+            if let ExprKind::Op(OpKind::App(_, arg)) = e.kind() {
+                if let ExprKind::Num(NumKind::Bool(b)) = arg.kind() {
+                    diagnostics.push(LintDiagnostic::new(
+                        rule,
+                        format!(
+                            "`{}` is passed as an argument without saying what it means; \
+                             consider using a union or a record instead",
+                            if *b { "True" } else { "False" }
+                        ),
+                        e,
+                    ));
+                }
+            }
+            true
+        }),
+        LintRule::PreferLetOverLambda => walk(e, |e, _| {
+            if let ExprKind::Op(OpKind::App(f, _)) = e.kind() {
+                if let ExprKind::Lam(x, _, _) = f.kind() {
+                    diagnostics.push(LintDiagnostic::new(
+                        rule,
+                        format!(
+                            "this lambda is applied immediately; consider using \
+                             `let {} = ...` instead",
+                            x
+                        ),
+                        e,
+                    ));
+                }
+            }
+            true
+        }),
+    }
+}
+
+/// Calls `f` on `e` and its subexpressions, parents first, along with their nesting depth.
+/// Subexpressions are skipped when `f` returns `false`.
+fn walk(e: &Expr, f: impl FnMut(&Expr, usize) -> bool) {
+    struct Walk<F> {
+        f: F,
+        depth: usize,
+    }
+    impl<F: FnMut(&Expr, usize) -> bool> ExprVisitor for Walk<F> {
+        fn visit_expr(&mut self, e: &Expr) {
+            self.depth += 1;
+            if (self.f)(e, self.depth) {
+                walk_expr(self, e);
+            }
+            self.depth -= 1;
+        }
+    }
+    Walk { f, depth: 0 }.visit_expr(e)
+}
@@ -0,0 +1,104 @@
+use dhall_lint::{LintRule, Linter};
+
+fn lint(rule: LintRule, s: &str) -> Vec<String> {
+    Linter::new()
+        .rule(rule)
+        .lint_str(s)
+        .unwrap()
+        .into_iter()
+        .map(|d| d.to_string())
+        .collect()
+}
+
+#[test]
+fn no_anonymous_fields() {
+    let rule = LintRule::NoAnonymousFields;
+    assert_eq!(
+        lint(rule, "{ _ = 1, x = { _ : Natural } }"),
+        vec![
+            "no-anonymous-fields: `_` should not be used as a field name",
+            "no-anonymous-fields: `_` should not be used as a field name",
+        ]
+    );
+    assert_eq!(lint(rule, "< _ | A >").len(), 1);
+    assert!(lint(rule, "let _ = 1 in { x = 1 }").is_empty());
+}
+
+#[test]
+fn diagnostics_on_records_have_a_span() {
+    // Records keep the order of their fields in their span.
+    let diagnostics = Linter::new()
+        .rule(LintRule::NoAnonymousFields)
+        .lint_str("{ y = 2, _ = 1 }")
+        .unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    let span = diagnostics[0].span.as_ref().unwrap();
+    assert_eq!(span.parsed().unwrap().as_char_range(), (0, 16));
+}
+
+#[test]
+fn require_type_annotation() {
+    let rule = LintRule::RequireTypeAnnotation;
+    assert_eq!(
+        lint(rule, "let x = 1 in x"),
+        vec![
+            "require-type-annotation: the file should end in a type annotation"
+        ]
+    );
+    assert!(lint(rule, "let x = 1 in x : Natural").is_empty());
+    assert!(lint(rule, "1 : Natural").is_empty());
+}
+
+#[test]
+fn max_nesting_depth() {
+    let rule = LintRule::MaxNestingDepth(3);
+    assert!(lint(rule, "[[1]]").is_empty());
+    assert_eq!(
+        lint(rule, "[[[1]], [[2]]]"),
+        vec![
+            "max-nesting-depth: this expression is nested more than 3 levels deep",
+            "max-nesting-depth: this expression is nested more than 3 levels deep",
+        ]
+    );
+}
+
+#[test]
+fn no_implicit_boolean() {
+    let rule = LintRule::NoImplicitBoolean;
+    assert_eq!(
+        lint(rule, "λ(f : Bool → Natural) → f True"),
+        vec![
+            "no-implicit-boolean: `True` is passed as an argument without saying what it means; \
+             consider using a union or a record instead"
+        ]
+    );
+    assert!(lint(rule, "{ enabled = True }").is_empty());
+}
+
+#[test]
+fn prefer_let_over_lambda() {
+    let rule = LintRule::PreferLetOverLambda;
+    assert_eq!(
+        lint(rule, "(λ(x : Natural) → x + 1) 2"),
+        vec![
+            "prefer-let-over-lambda: this lambda is applied immediately; consider using \
+             `let x = ...` instead"
+        ]
+    );
+    assert!(lint(rule, "let x = 2 in x + 1").is_empty());
+    assert!(lint(rule, "λ(f : Natural → Natural) → f 2").is_empty());
+}
+
+#[test]
+fn several_rules() {
+    let diagnostics = Linter::new()
+        .rule(LintRule::PreferLetOverLambda)
+        .rule(LintRule::NoImplicitBoolean)
+        .lint_str("(λ(b : Bool) → b) True")
+        .unwrap();
+    let rules: Vec<_> = diagnostics.iter().map(|d| d.rule).collect();
+    assert_eq!(rules, vec!["prefer-let-over-lambda", "no-implicit-boolean"]);
+    assert!(diagnostics.iter().all(|d| d.span.is_some()));
+
+    assert!(Linter::new().lint_str("(").is_err());
+}
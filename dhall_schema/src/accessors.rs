@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_dhall::SimpleType;
+
+use crate::migrate::escape_label;
+
+/// Writes Dhall functions to inspect a value of the union type `ty`.
+///
+/// For each alternative `A`, the map has an accessor at key `A`, which returns the payload as
+/// `Some` if the value is an `A` and `None` otherwise, and a predicate `is_A` which returns
+/// whether the value is an `A`. The accessor of an alternative without payload returns
+/// `Some {=}` or `None {}`. The map is empty when `ty` is not a union.
+///
+/// Fails if a predicate would have the same key as another alternative, like `is_A` for the
+/// union `< A | is_A >`.
+///
+/// # Example
+///
+/// ```
+/// use dhall_schema::generate_accessors;
+/// use serde_dhall::SimpleType;
+///
+/// let ty = SimpleType::union(vec![
+///     ("Port", Some(SimpleType::Natural)),
+///     ("Auto", None),
+/// ]);
+/// let accessors = generate_accessors(&ty).unwrap();
+/// assert_eq!(
+///     accessors["Port"],
+///     "λ(x : < Auto | Port: Natural >) → \
+///      merge { Auto = None Natural, Port = λ(v : Natural) → Some v } x"
+/// );
+/// assert_eq!(
+///     accessors["is_Auto"],
+///     "λ(x : < Auto | Port: Natural >) → \
+///      merge { Auto = True, Port = λ(_ : Natural) → False } x"
+/// );
+/// ```
+pub fn generate_accessors(
+    ty: &SimpleType,
+) -> Result<BTreeMap<String, String>, AccessorError> {
+    let alternatives: BTreeMap<&String, &Option<SimpleType>> = match ty {
+        SimpleType::Union(kts) => kts.iter().collect(),
+        _ => return Ok(BTreeMap::new()),
+    };
+    for name in alternatives.keys() {
+        let predicate = format!("is_{}", name);
+        if alternatives.contains_key(&predicate) {
+            return Err(AccessorError::NameCollision(
+                (*name).clone(),
+                predicate,
+            ));
+        }
+    }
+    let merge = |handler: &dyn Fn(&String, &Option<SimpleType>) -> String| {
+        let handlers: Vec<String> = alternatives
+            .iter()
+            .map(|(k, t)| format!("{} = {}", escape_label(k), handler(k, t)))
+            .collect();
+        format!("λ(x : {}) → merge {{ {} }} x", ty, handlers.join(", "))
+    };
+
+    let mut accessors = BTreeMap::new();
+    for (name, payload) in &alternatives {
+        let payload_ty = match payload {
+            Some(t) => t.to_string(),
+            None => "{}".to_owned(),
+        };
+        let accessor = merge(&|k, t| match t {
+            Some(t) if k == *name => format!("λ(v : {}) → Some v", t),
+            None if k == *name => "Some {=}".to_owned(),
+            Some(t) => format!("λ(_ : {}) → None {}", t, payload_ty),
+            None => format!("None {}", payload_ty),
+        });
+        let predicate = merge(&|k, t| {
+            let is_name = if k == *name { "True" } else { "False" };
+            match t {
+                Some(t) => format!("λ(_ : {}) → {}", t, is_name),
+                None => is_name.to_owned(),
+            }
+        });
+        accessors.insert((*name).clone(), accessor);
+        accessors.insert(format!("is_{}", name), predicate);
+    }
+    Ok(accessors)
+}
+
+/// Why [`generate_accessors`] can't write the functions of a union.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessorError {
+    /// The predicate of the first alternative would have the same key as the second one, which
+    /// is called `is_` followed by the name of the first.
+    NameCollision(String, String),
+}
+
+impl fmt::Display for AccessorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessorError::NameCollision(name, predicate) => write!(
+                f,
+                "the predicate of `{}` would have the same name as the alternative `{}`",
+                name, predicate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AccessorError {}
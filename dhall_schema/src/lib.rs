@@ -0,0 +1,362 @@
+#![doc(html_root_url = "https://docs.rs/dhall_schema/0.1.0")]
+//! Typed, documented schemas for Dhall configuration files.
+//!
+//! A [`Schema`] ties a Rust type to the Dhall type of the configuration it is read from. It
+//! validates configuration written by users, reporting all the mismatches with the expected type
+//! at once, and renders that type as Dhall or as a JSON Schema so it can be documented.
+//!
+//! # Example
+//!
+//! ```
+//! use dhall_schema::Schema;
+//! use serde::Deserialize;
+//! use serde_dhall::StaticType;
+//!
+//! #[derive(Debug, Deserialize, StaticType)]
+//! struct Config {
+//!     port: u64,
+//!     hosts: Vec<String>,
+//! }
+//!
+//! let schema = Schema::<Config>::new().description("The server configuration");
+//! assert_eq!(
+//!     schema.to_dhall_type_string(),
+//!     "-- The server configuration\n{ hosts : List Text, port : Natural }"
+//! );
+//!
+//! let config = schema.validate(r#"{ port = 8080, hosts = ["localhost"] }"#).unwrap();
+//! assert_eq!(config.port, 8080);
+//!
+//! let errors = schema.validate("{ port = -1, debug = True }").unwrap_err();
+//! assert_eq!(
+//!     errors.to_string(),
+//!     "`debug`: unexpected field\n\
+//!      `hosts`: missing field of type `List Text`\n\
+//!      `port`: expected a value of type `Natural`, found `-1`"
+//! );
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use serde_dhall::{FromDhall, NumKind, SimpleType, SimpleValue, StaticType};
+use serde_json::{json, Value as JsonValue};
+
+mod accessors;
+mod migrate;
+mod proto;
+pub use accessors::{generate_accessors, AccessorError};
+pub use migrate::{generate_migration, record_diff, SchemaDiff};
+pub use proto::{to_proto3_schema, ProtoError};
+
+/// The expected type of a Dhall configuration, along with the Rust type it is read into.
+pub struct Schema<T> {
+    ty: SimpleType,
+    description: Option<String>,
+    example: Option<fn() -> T>,
+}
+
+impl<T: FromDhall + StaticType> Schema<T> {
+    /// The schema of `T`, with the Dhall type given by its [`StaticType`] implementation.
+    pub fn new() -> Self {
+        Schema {
+            ty: T::static_type(),
+            description: None,
+            example: None,
+        }
+    }
+
+    /// Sets a description of the configuration, used in the generated documentation.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Uses `T::default()` as the example returned by [`Schema::example`].
+    pub fn with_default_example(mut self) -> Self
+    where
+        T: Default,
+    {
+        self.example = Some(T::default);
+        self
+    }
+
+    /// The expected Dhall type.
+    pub fn ty(&self) -> &SimpleType {
+        &self.ty
+    }
+
+    /// The description set with [`Schema::description`], if any.
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// An example configuration, if one was set with [`Schema::with_default_example`].
+    pub fn example(&self) -> Option<T> {
+        self.example.map(|example| example())
+    }
+
+    /// Reads some Dhall configuration, checking that it has the expected type.
+    ///
+    /// Dhall stops at the first type error. When the configuration doesn't have the expected
+    /// type, it is read again without the type to report every field that doesn't match.
+    pub fn validate(&self, s: &str) -> Result<T, ValidationErrors> {
+        let error = match serde_dhall::from_str(s)
+            .type_annotation(&self.ty)
+            .parse::<T>()
+        {
+            Ok(x) => return Ok(x),
+            Err(e) => e,
+        };
+        let mut errors = Vec::new();
+        if let Ok(val) = serde_dhall::from_str(s).parse::<SimpleValue>() {
+            check_value(&val, &self.ty, "", &mut errors);
+        }
+        // The mismatch couldn't be located, e.g. because it is in an empty list.
+        if errors.is_empty() {
+            errors.push(ValidationError {
+                path: String::new(),
+                message: error.to_string(),
+            });
+        }
+        Err(ValidationErrors(errors))
+    }
+
+    /// Renders the expected type as Dhall, preceded by the description as a comment. Users can
+    /// annotate their configuration with it.
+    pub fn to_dhall_type_string(&self) -> String {
+        let mut s = String::new();
+        if let Some(description) = &self.description {
+            for line in description.lines() {
+                s += &format!("-- {}\n", line);
+            }
+        }
+        s += &self.ty.to_string();
+        s
+    }
+
+    /// Renders the expected type as a JSON Schema.
+    ///
+    /// The JSON it accepts is the JSON that [`serde_dhall::from_json_value`] can read with this
+    /// type: `Optional` fields may be missing or `null`, and a union value is either the name of
+    /// an alternative without payload or the payload of one of the alternatives.
+    pub fn to_json_schema(&self) -> JsonValue {
+        let mut schema = json_schema(&self.ty);
+        if let (Some(description), JsonValue::Object(map)) =
+            (&self.description, &mut schema)
+        {
+            map.insert("description".to_owned(), json!(description));
+        }
+        schema
+    }
+}
+
+impl<T: FromDhall + StaticType> Default for Schema<T> {
+    fn default() -> Self {
+        Schema::new()
+    }
+}
+
+impl<T> Clone for Schema<T> {
+    fn clone(&self) -> Self {
+        Schema {
+            ty: self.ty.clone(),
+            description: self.description.clone(),
+            example: self.example,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Schema<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Schema")
+            .field("ty", &self.ty)
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+/// A place where a configuration doesn't match its [`Schema`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The path to the offending value, like `servers[0].port`. It is empty when the error is
+    /// about the whole configuration.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "`{}`: {}", self.path, self.message)
+        }
+    }
+}
+
+/// The errors found by [`Schema::validate`]. There is at least one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationErrors(Vec<ValidationError>);
+
+impl ValidationErrors {
+    pub fn errors(&self) -> &[ValidationError] {
+        &self.0
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Collects the places where `val` doesn't have type `ty`.
+fn check_value(
+    val: &SimpleValue,
+    ty: &SimpleType,
+    path: &str,
+    errors: &mut Vec<ValidationError>,
+) {
+    let mut push = |message: String| {
+        errors.push(ValidationError {
+            path: path.to_owned(),
+            message,
+        })
+    };
+    match (val, ty) {
+        (SimpleValue::Num(NumKind::Bool(_)), SimpleType::Bool)
+        | (SimpleValue::Num(NumKind::Natural(_)), SimpleType::Natural)
+        | (SimpleValue::Num(NumKind::Integer(_)), SimpleType::Integer)
+        | (SimpleValue::Num(NumKind::Double(_)), SimpleType::Double)
+        | (SimpleValue::Text(_), SimpleType::Text)
+        | (SimpleValue::Optional(None), SimpleType::Optional(_)) => {}
+        (SimpleValue::Optional(Some(val)), SimpleType::Optional(ty)) => {
+            check_value(val, ty, path, errors)
+        }
+        (SimpleValue::List(vals), SimpleType::List(ty)) => {
+            for (i, val) in vals.iter().enumerate() {
+                check_value(val, ty, &format!("{}[{}]", path, i), errors)
+            }
+        }
+        (SimpleValue::Record(kvs), SimpleType::Record(kts)) => {
+            let fields: BTreeSet<&String> =
+                kvs.keys().chain(kts.keys()).collect();
+            for k in fields {
+                let path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                match (kvs.get(k), kts.get(k)) {
+                    (Some(val), Some(ty)) => {
+                        check_value(val, ty, &path, errors)
+                    }
+                    (None, Some(ty)) => errors.push(ValidationError {
+                        path,
+                        message: format!("missing field of type `{}`", ty),
+                    }),
+                    _ => errors.push(ValidationError {
+                        path,
+                        message: "unexpected field".to_owned(),
+                    }),
+                }
+            }
+        }
+        (SimpleValue::Union(k, val), SimpleType::Union(kts)) => {
+            match (kts.get(k), val) {
+                (Some(Some(ty)), Some(val)) => {
+                    check_value(val, ty, path, errors)
+                }
+                (Some(None), None) => {}
+                _ => push(format!(
+                    "expected a value of type `{}`, found the alternative `{}`",
+                    ty, k
+                )),
+            }
+        }
+        _ => push(format!(
+            "expected a value of type `{}`, found {}",
+            ty,
+            describe(val)
+        )),
+    }
+}
+
+fn describe(val: &SimpleValue) -> String {
+    match val {
+        SimpleValue::Num(NumKind::Bool(b)) => {
+            format!("`{}`", if *b { "True" } else { "False" })
+        }
+        SimpleValue::Num(NumKind::Natural(n)) => format!("`{}`", n),
+        SimpleValue::Num(NumKind::Integer(n)) => format!("`{:+}`", n),
+        SimpleValue::Num(NumKind::Double(d)) => {
+            format!("`{:?}`", f64::from(*d))
+        }
+        SimpleValue::Text(s) => format!("`{:?}`", s),
+        SimpleValue::Optional(_) => "an `Optional` value".to_owned(),
+        SimpleValue::List(_) => "a list".to_owned(),
+        SimpleValue::Record(_) => "a record".to_owned(),
+        SimpleValue::Union(k, _) => format!("the alternative `{}`", k),
+    }
+}
+
+fn json_schema(ty: &SimpleType) -> JsonValue {
+    match ty {
+        SimpleType::Bool => json!({ "type": "boolean" }),
+        SimpleType::Natural => json!({ "type": "integer", "minimum": 0 }),
+        SimpleType::Integer => json!({ "type": "integer" }),
+        SimpleType::Double => json!({ "type": "number" }),
+        SimpleType::Text => json!({ "type": "string" }),
+        SimpleType::Optional(ty) => {
+            json!({ "anyOf": [json_schema(ty), { "type": "null" }] })
+        }
+        SimpleType::List(ty) => {
+            json!({ "type": "array", "items": json_schema(ty) })
+        }
+        SimpleType::Record(kts) => {
+            let kts: BTreeMap<_, _> = kts.iter().collect();
+            let properties: serde_json::Map<_, _> = kts
+                .iter()
+                .map(|(k, ty)| ((*k).clone(), json_schema(ty)))
+                .collect();
+            let required: Vec<_> = kts
+                .iter()
+                .filter(|(_, ty)| !matches!(ty, SimpleType::Optional(_)))
+                .map(|(k, _)| json!(k))
+                .collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                "additionalProperties": false,
+            })
+        }
+        SimpleType::Union(kts) => {
+            let kts: BTreeMap<_, _> = kts.iter().collect();
+            let names: Vec<_> = kts
+                .iter()
+                .filter(|(_, ty)| ty.is_none())
+                .map(|(k, _)| json!(k))
+                .collect();
+            let mut alternatives = Vec::new();
+            if !names.is_empty() {
+                alternatives.push(json!({ "enum": names }));
+            }
+            alternatives.extend(
+                kts.values().filter_map(|ty| ty.as_ref()).map(json_schema),
+            );
+            json!({ "anyOf": alternatives })
+        }
+        // Types and functions can't be written in JSON: the schema `false` matches nothing.
+        _ => json!(false),
+    }
+}
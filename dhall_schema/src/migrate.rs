@@ -0,0 +1,196 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use dhall::syntax::Label;
+use serde_dhall::SimpleType;
+
+/// The differences between two versions of a record type, computed by [`record_diff`].
+///
+/// Fields are named by their path, like `db.port`: the fields of a nested record that is in both
+/// types are compared one by one. When one of the two types is not a record, the whole type has
+/// changed, at the empty path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaDiff {
+    old_type: SimpleType,
+    new_type: SimpleType,
+    added: BTreeMap<String, SimpleType>,
+    removed: BTreeMap<String, SimpleType>,
+    changed: BTreeMap<String, (SimpleType, SimpleType)>,
+}
+
+impl SchemaDiff {
+    pub fn old_type(&self) -> &SimpleType {
+        &self.old_type
+    }
+
+    pub fn new_type(&self) -> &SimpleType {
+        &self.new_type
+    }
+
+    /// The fields of the new type that are not in the old one, with their type.
+    pub fn added(&self) -> &BTreeMap<String, SimpleType> {
+        &self.added
+    }
+
+    /// The fields of the old type that are not in the new one, with their type.
+    pub fn removed(&self) -> &BTreeMap<String, SimpleType> {
+        &self.removed
+    }
+
+    /// The fields that are in both types with different types, with their old and new type.
+    pub fn changed(&self) -> &BTreeMap<String, (SimpleType, SimpleType)> {
+        &self.changed
+    }
+
+    /// Whether the two types are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// Compares two versions of the type of a configuration.
+///
+/// # Example
+///
+/// ```
+/// use dhall_schema::record_diff;
+/// use serde_dhall::SimpleType;
+///
+/// let old = SimpleType::record(vec![
+///     ("host", SimpleType::Text),
+///     ("port", SimpleType::Natural),
+///     ("debug", SimpleType::Bool),
+/// ]);
+/// let new = SimpleType::record(vec![
+///     ("host", SimpleType::Text),
+///     ("port", SimpleType::Integer),
+///     ("timeout", SimpleType::Natural),
+/// ]);
+/// let diff = record_diff(&old, &new);
+/// assert_eq!(diff.added().keys().collect::<Vec<_>>(), ["timeout"]);
+/// assert_eq!(diff.removed().keys().collect::<Vec<_>>(), ["debug"]);
+/// assert_eq!(diff.changed().keys().collect::<Vec<_>>(), ["port"]);
+/// ```
+pub fn record_diff(old_type: &SimpleType, new_type: &SimpleType) -> SchemaDiff {
+    let mut diff = SchemaDiff {
+        old_type: old_type.clone(),
+        new_type: new_type.clone(),
+        added: BTreeMap::new(),
+        removed: BTreeMap::new(),
+        changed: BTreeMap::new(),
+    };
+    diff_types(old_type, new_type, "", &mut diff);
+    diff
+}
+
+/// Collects the differences between `old` and `new`, the types of the fields at `path`.
+fn diff_types(
+    old: &SimpleType,
+    new: &SimpleType,
+    path: &str,
+    diff: &mut SchemaDiff,
+) {
+    match (old, new) {
+        (SimpleType::Record(old_kts), SimpleType::Record(new_kts)) => {
+            let fields: BTreeSet<&String> =
+                old_kts.keys().chain(new_kts.keys()).collect();
+            for k in fields {
+                let path = if path.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", path, k)
+                };
+                match (old_kts.get(k), new_kts.get(k)) {
+                    (Some(old), Some(new)) => diff_types(old, new, &path, diff),
+                    (None, Some(new)) => {
+                        diff.added.insert(path, new.clone());
+                    }
+                    (Some(old), None) => {
+                        diff.removed.insert(path, old.clone());
+                    }
+                    (None, None) => unreachable!(),
+                }
+            }
+        }
+        _ if old == new => {}
+        _ => {
+            diff.changed
+                .insert(path.to_owned(), (old.clone(), new.clone()));
+        }
+    }
+}
+
+/// Writes a Dhall function that turns a configuration of the old type of `diff` into one of the
+/// new type.
+///
+/// The fields that didn't change are copied, and the removed fields are dropped. The value of an
+/// added or changed field is left for the user to fill in: it is written `(TODO : T)`, which
+/// doesn't typecheck until `TODO` is replaced.
+///
+/// # Example
+///
+/// ```
+/// use dhall_schema::{generate_migration, record_diff};
+/// use serde_dhall::SimpleType;
+///
+/// let old = SimpleType::record(vec![
+///     ("host", SimpleType::Text),
+///     ("debug", SimpleType::Bool),
+/// ]);
+/// let new = SimpleType::record(vec![
+///     ("host", SimpleType::Text),
+///     ("timeout", SimpleType::Natural),
+/// ]);
+/// assert_eq!(
+///     generate_migration(&record_diff(&old, &new)),
+///     "λ(old : { debug : Bool, host : Text }) → \
+///      { host = old.host, timeout = (TODO : Natural) }"
+/// );
+/// ```
+pub fn generate_migration(diff: &SchemaDiff) -> String {
+    format!(
+        "λ(old : {}) → {}",
+        diff.old_type,
+        migrate_value(&diff.old_type, &diff.new_type, "old")
+    )
+}
+
+/// A Dhall expression of type `new`, computed from `value`, an expression of type `old`.
+fn migrate_value(old: &SimpleType, new: &SimpleType, value: &str) -> String {
+    match (old, new) {
+        _ if old == new => value.to_owned(),
+        (SimpleType::Record(old_kts), SimpleType::Record(new_kts)) => {
+            if new_kts.is_empty() {
+                return "{=}".to_owned();
+            }
+            let new_kts: BTreeMap<_, _> = new_kts.iter().collect();
+            let fields: Vec<String> = new_kts
+                .into_iter()
+                .map(|(k, new)| {
+                    let label = escape_label(k);
+                    let v = match old_kts.get(k) {
+                        Some(old) => migrate_value(
+                            old,
+                            new,
+                            &format!("{}.{}", value, label),
+                        ),
+                        None => placeholder(new),
+                    };
+                    format!("{} = {}", label, v)
+                })
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+        _ => placeholder(new),
+    }
+}
+
+fn placeholder(ty: &SimpleType) -> String {
+    format!("(TODO : {})", ty)
+}
+
+/// Quotes a field name with backticks when it can't be written as is, like the printer does.
+pub(crate) fn escape_label(label: &str) -> String {
+    Label::from(label).to_quoted_string()
+}
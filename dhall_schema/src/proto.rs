@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+
+use serde_dhall::SimpleType;
+
+/// Writes a Protocol Buffers (proto3) schema with a message `name` for the record type `ty`.
+///
+/// Record fields become message fields, numbered in the order of their names. `Optional` fields
+/// become `optional`, lists become `repeated`, and records become nested messages. A union
+/// becomes a nested message with a `oneof value` that has a field for each alternative;
+/// alternatives without payload are empty messages. When `ty` is a union, the message is just
+/// its `oneof`, and when it is another type, the message has a single field `value`.
+///
+/// The field numbers are not stable: adding or removing a field renumbers the fields whose names
+/// come after it. Data encoded with the schema of one version of `ty` can't be decoded with the
+/// schema of another version, so regenerate the schema on both sides when the type changes.
+///
+/// Protobuf can't express every Dhall type:
+/// - `Optional (List T)` is just `repeated`, so `None` and `[]` are the same;
+/// - lists of lists or of optional values have their elements wrapped in a message;
+/// - fields of types that have no equivalent, like functions, are left out with a comment.
+///
+/// Fails if two fields of a record or two alternatives of a union get the same name in
+/// Protobuf, like `fooBar` and `foo_bar`, or if a type is `Optional (Optional T)`, whose `None`
+/// and `Some None` can't be told apart.
+///
+/// # Example
+///
+/// ```
+/// use dhall_schema::to_proto3_schema;
+/// use serde_dhall::SimpleType;
+///
+/// let ty = SimpleType::record(vec![
+///     ("host", SimpleType::Text),
+///     ("port", SimpleType::Optional(Box::new(SimpleType::Natural))),
+/// ]);
+/// assert_eq!(
+///     to_proto3_schema("Server", &ty).unwrap(),
+///     "syntax = \"proto3\";\n\
+///      \n\
+///      message Server {\n\
+///      \x20 string host = 1;\n\
+///      \x20 optional uint64 port = 2;\n\
+///      }\n"
+/// );
+/// ```
+pub fn to_proto3_schema(
+    name: &str,
+    ty: &SimpleType,
+) -> Result<String, ProtoError> {
+    let mut out = String::from("syntax = \"proto3\";\n\n");
+    write_message(&mut out, 0, &identifier(name), "", ty)?;
+    Ok(out)
+}
+
+/// Why [`to_proto3_schema`] can't write a schema for a type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtoError {
+    /// The path to the offending field, like `servers.port`. It is empty when the error is about
+    /// the whole type.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "`{}`: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+/// Writes a message for `ty`, the type at `path`, at nesting level `depth`.
+fn write_message(
+    out: &mut String,
+    depth: usize,
+    name: &str,
+    path: &str,
+    ty: &SimpleType,
+) -> Result<(), ProtoError> {
+    let indent = "  ".repeat(depth);
+    let mut body = MessageBody {
+        depth: depth + 1,
+        path: path.to_owned(),
+        messages: Vec::new(),
+        fields: Vec::new(),
+        field_names: HashMap::new(),
+        message_names: HashMap::new(),
+        next_number: 1,
+    };
+    match ty {
+        SimpleType::Record(kts) => {
+            let kts: BTreeMap<_, _> = kts.iter().collect();
+            for (label, ty) in kts {
+                body.field(label, &body.path_of(label), ty)?;
+            }
+        }
+        SimpleType::Union(kts) => body.oneof(kts)?,
+        _ => body.field("value", path, ty)?,
+    }
+    if body.messages.is_empty() && body.fields.is_empty() {
+        writeln!(out, "{}message {} {{}}", indent, name).unwrap();
+        return Ok(());
+    }
+    writeln!(out, "{}message {} {{", indent, name).unwrap();
+    for (i, message) in body.messages.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        out.push_str(message);
+    }
+    if !body.messages.is_empty() && !body.fields.is_empty() {
+        out.push('\n');
+    }
+    for field in &body.fields {
+        writeln!(out, "{}  {}", indent, field).unwrap();
+    }
+    writeln!(out, "{}}}", indent).unwrap();
+    Ok(())
+}
+
+struct MessageBody {
+    depth: usize,
+    /// The path of the type of the message.
+    path: String,
+    /// The nested messages, already written.
+    messages: Vec<String>,
+    /// The lines of the fields, without indentation.
+    fields: Vec<String>,
+    /// What each field name given so far is for, like "`fooBar`".
+    field_names: HashMap<String, String>,
+    /// What each nested message name given so far is for.
+    message_names: HashMap<String, String>,
+    next_number: u32,
+}
+
+impl MessageBody {
+    fn field(
+        &mut self,
+        label: &str,
+        path: &str,
+        ty: &SimpleType,
+    ) -> Result<(), ProtoError> {
+        let line = match self.field_type(label, path, ty)? {
+            Some((modifier, ty)) => {
+                let name = self.field_name(label)?;
+                let number = self.number();
+                let modifier = modifier.map(|m| m + " ").unwrap_or_default();
+                format!("{}{} {} = {};", modifier, ty, name, number)
+            }
+            None => {
+                format!("// `{}` has a type with no equivalent: {}", label, ty)
+            }
+        };
+        self.fields.push(line);
+        Ok(())
+    }
+
+    fn oneof(
+        &mut self,
+        kts: &HashMap<String, Option<SimpleType>>,
+    ) -> Result<(), ProtoError> {
+        let kts: BTreeMap<_, _> = kts.iter().collect();
+        let mut lines = Vec::new();
+        for (label, ty) in kts {
+            let path = self.path_of(label);
+            let ty = match ty {
+                // Fields of a `oneof` can't be `optional` or `repeated`.
+                Some(ty @ SimpleType::Optional(_))
+                | Some(ty @ SimpleType::List(_)) => {
+                    Some(self.message(label, &path, ty)?)
+                }
+                Some(ty) => {
+                    self.field_type(label, &path, ty)?.map(|(_, ty)| ty)
+                }
+                None => Some(self.message(
+                    label,
+                    &path,
+                    &SimpleType::Record(Default::default()),
+                )?),
+            };
+            lines.push(match ty {
+                Some(ty) => {
+                    let name = self.field_name(label)?;
+                    format!("  {} {} = {};", ty, name, self.number())
+                }
+                None => {
+                    format!("  // `{}` has a type with no equivalent", label)
+                }
+            });
+        }
+        self.fields.push("oneof value {".to_owned());
+        self.fields.extend(lines);
+        self.fields.push("}".to_owned());
+        Ok(())
+    }
+
+    /// The modifier and the type of a field of type `ty`, or `None` if Protobuf can't express
+    /// `ty`.
+    fn field_type(
+        &mut self,
+        label: &str,
+        path: &str,
+        ty: &SimpleType,
+    ) -> Result<Option<(Option<String>, String)>, ProtoError> {
+        let scalar = |ty: &str| Ok(Some((None, ty.to_owned())));
+        match ty {
+            SimpleType::Bool => scalar("bool"),
+            SimpleType::Natural => scalar("uint64"),
+            SimpleType::Integer => scalar("int64"),
+            SimpleType::Double => scalar("double"),
+            SimpleType::Text => scalar("string"),
+            SimpleType::Record(_) | SimpleType::Union(_) => {
+                Ok(Some((None, self.message(label, path, ty)?)))
+            }
+            SimpleType::Optional(inner) => match **inner {
+                SimpleType::Optional(_) => Err(ProtoError {
+                    path: path.to_owned(),
+                    message: format!(
+                        "`{}` has no equivalent: `None` and `Some None` would be the same",
+                        ty
+                    ),
+                }),
+                SimpleType::List(_) => self.field_type(label, path, inner),
+                _ => Ok(self
+                    .field_type(label, path, inner)?
+                    .map(|(_, ty)| (Some("optional".to_owned()), ty))),
+            },
+            SimpleType::List(inner) => {
+                let ty = match **inner {
+                    SimpleType::Optional(_) | SimpleType::List(_) => self
+                        .write_nested(
+                            message_name(&format!("{}_item", label)),
+                            format!("the items of `{}`", label),
+                            path,
+                            inner,
+                        )?,
+                    _ => match self.field_type(label, path, inner)? {
+                        Some((_, ty)) => ty,
+                        None => return Ok(None),
+                    },
+                };
+                Ok(Some((Some("repeated".to_owned()), ty)))
+            }
+            // Types and functions have no equivalent in Protobuf.
+            _ => Ok(None),
+        }
+    }
+
+    /// Writes a nested message for the type `ty` of the field `label`, and returns its name.
+    fn message(
+        &mut self,
+        label: &str,
+        path: &str,
+        ty: &SimpleType,
+    ) -> Result<String, ProtoError> {
+        self.write_nested(message_name(label), format!("`{}`", label), path, ty)
+    }
+
+    /// Writes a nested message `name` for the type `ty` of `what`, and returns its name.
+    fn write_nested(
+        &mut self,
+        name: String,
+        what: String,
+        path: &str,
+        ty: &SimpleType,
+    ) -> Result<String, ProtoError> {
+        check_unique(&mut self.message_names, &name, what, &self.path)?;
+        let mut message = String::new();
+        write_message(&mut message, self.depth, &name, path, ty)?;
+        self.messages.push(message);
+        Ok(name)
+    }
+
+    /// The name of the field `label`, which must not be the name of another field.
+    fn field_name(&mut self, label: &str) -> Result<String, ProtoError> {
+        let name = field_name(label);
+        check_unique(
+            &mut self.field_names,
+            &name,
+            format!("`{}`", label),
+            &self.path,
+        )?;
+        Ok(name)
+    }
+
+    /// The path of the field `label` of the type of the message.
+    fn path_of(&self, label: &str) -> String {
+        if self.path.is_empty() {
+            label.to_owned()
+        } else {
+            format!("{}.{}", self.path, label)
+        }
+    }
+
+    fn number(&mut self) -> u32 {
+        self.next_number += 1;
+        self.next_number - 1
+    }
+}
+
+/// Records that `what` is named `name`, failing if something else in the type at `path` already
+/// has this name.
+fn check_unique(
+    names: &mut HashMap<String, String>,
+    name: &str,
+    what: String,
+    path: &str,
+) -> Result<(), ProtoError> {
+    match names.insert(name.to_owned(), what.clone()) {
+        Some(other) => Err(ProtoError {
+            path: path.to_owned(),
+            message: format!(
+                "{} and {} would both be named `{}`",
+                other, what, name
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Replaces the characters that can't be part of a Protobuf identifier with `_`.
+fn identifier(label: &str) -> String {
+    let mut name: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// The name of a field in `snake_case`, so that it can't clash with the name of its message.
+fn field_name(label: &str) -> String {
+    let mut name = String::new();
+    let mut prev_lower = false;
+    for c in identifier(label).chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            name.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+/// The name of a message in `CamelCase`.
+fn message_name(label: &str) -> String {
+    let mut name = String::new();
+    let mut upper = true;
+    for c in identifier(label).chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            name.push(c.to_ascii_uppercase());
+            upper = false;
+        } else {
+            name.push(c);
+        }
+    }
+    identifier(&name)
+}
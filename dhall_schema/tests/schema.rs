@@ -0,0 +1,385 @@
+use dhall_schema::{
+    generate_accessors, generate_migration, record_diff, to_proto3_schema,
+    Schema,
+};
+use serde::Deserialize;
+use serde_dhall::{SimpleType, SimpleValue, StaticType};
+use serde_json::json;
+
+#[derive(Debug, Default, PartialEq, Deserialize, StaticType)]
+struct Server {
+    host: String,
+    port: Option<u16>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, StaticType)]
+struct Config {
+    servers: Vec<Server>,
+    retries: u64,
+}
+
+fn errors(s: &str) -> Vec<String> {
+    Schema::<Config>::new()
+        .validate(s)
+        .unwrap_err()
+        .errors()
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+#[test]
+fn validate() {
+    let config = Schema::<Config>::new()
+        .validate(
+            r#"
+            let server = λ(host : Text) → { host = host, port = None Natural }
+            in { servers = [server "a", (server "b") with port = Some 80], retries = 3 }
+            "#,
+        )
+        .unwrap();
+    assert_eq!(config.servers[1].port, Some(80));
+
+    assert_eq!(
+        errors(
+            r#"{ servers = [{ host = 1, port = Some -1 }], retries = 1.5 }"#
+        ),
+        vec![
+            "`retries`: expected a value of type `Natural`, found `1.5`",
+            "`servers[0].host`: expected a value of type `Text`, found `1`",
+            "`servers[0].port`: expected a value of type `Natural`, found `-1`",
+        ]
+    );
+    assert_eq!(
+        errors(r#"{ servers = "a" }"#),
+        vec![
+            "`retries`: missing field of type `Natural`",
+            "`servers`: expected a value of type `List { host : Text, port : Optional Natural }`, \
+             found `\"a\"`",
+        ]
+    );
+
+    // Errors that aren't about the type of a field are reported as they are.
+    let e = errors(
+        r#"{ servers = [{ host = "a", port = None Natural }], retries = 1 + True }"#,
+    );
+    assert_eq!(e.len(), 1);
+    assert!(e[0].contains("Type error"));
+    // So are errors that happen after typechecking, when reading the value into Rust.
+    let e = errors(
+        r#"{ servers = [{ host = "a", port = Some 100000 }], retries = 1 }"#,
+    );
+    assert_eq!(e.len(), 1);
+}
+
+#[test]
+fn to_dhall_type_string() {
+    let schema = Schema::<Server>::new()
+        .description("A server.\nThe port defaults to 80.");
+    assert_eq!(
+        schema.to_dhall_type_string(),
+        "-- A server.\n-- The port defaults to 80.\n{ host : Text, port : Optional Natural }"
+    );
+    assert_eq!(
+        schema.get_description(),
+        Some("A server.\nThe port defaults to 80.")
+    );
+
+    // The type can be used to annotate a configuration.
+    let config = format!(
+        r#"{{ host = "a", port = None Natural }} : {}"#,
+        schema.to_dhall_type_string()
+    );
+    assert!(schema.validate(&config).is_ok());
+}
+
+#[test]
+fn to_json_schema() {
+    let schema = Schema::<Config>::new().description("The configuration");
+    assert_eq!(
+        schema.to_json_schema(),
+        json!({
+            "description": "The configuration",
+            "type": "object",
+            "properties": {
+                "retries": { "type": "integer", "minimum": 0 },
+                "servers": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "host": { "type": "string" },
+                            "port": {
+                                "anyOf": [
+                                    { "type": "integer", "minimum": 0 },
+                                    { "type": "null" },
+                                ]
+                            },
+                        },
+                        "required": ["host"],
+                        "additionalProperties": false,
+                    },
+                },
+            },
+            "required": ["retries", "servers"],
+            "additionalProperties": false,
+        })
+    );
+
+    #[derive(Deserialize, StaticType)]
+    #[allow(dead_code)]
+    enum Mode {
+        Fast,
+        Slow,
+        Custom(u64),
+    }
+    assert_eq!(
+        Schema::<Mode>::new().to_json_schema(),
+        json!({
+            "anyOf": [
+                { "enum": ["Fast", "Slow"] },
+                { "type": "integer", "minimum": 0 },
+            ]
+        })
+    );
+}
+
+#[test]
+fn example() {
+    assert_eq!(Schema::<Config>::new().example(), None);
+    assert_eq!(
+        Schema::<Config>::new().with_default_example().example(),
+        Some(Config::default())
+    );
+}
+
+#[test]
+fn migration() {
+    let old = SimpleType::record(vec![
+        ("name", SimpleType::Text),
+        ("debug", SimpleType::Bool),
+        (
+            "db",
+            SimpleType::record(vec![
+                ("host", SimpleType::Text),
+                ("port", SimpleType::Natural),
+            ]),
+        ),
+    ]);
+    let new = SimpleType::record(vec![
+        ("name", SimpleType::Text),
+        ("if", SimpleType::optional(SimpleType::Text)),
+        (
+            "db",
+            SimpleType::record(vec![
+                ("host", SimpleType::Text),
+                ("port", SimpleType::Integer),
+            ]),
+        ),
+    ]);
+    let diff = record_diff(&old, &new);
+    assert_eq!(
+        diff.added().iter().collect::<Vec<_>>(),
+        [(&"if".to_owned(), &SimpleType::optional(SimpleType::Text))]
+    );
+    assert_eq!(
+        diff.removed().iter().collect::<Vec<_>>(),
+        [(&"debug".to_owned(), &SimpleType::Bool)]
+    );
+    assert_eq!(
+        diff.changed().iter().collect::<Vec<_>>(),
+        [(
+            &"db.port".to_owned(),
+            &(SimpleType::Natural, SimpleType::Integer)
+        )]
+    );
+    assert!(!diff.is_empty());
+    assert!(record_diff(&old, &old).is_empty());
+
+    let migration = generate_migration(&diff);
+    assert_eq!(
+        migration,
+        "λ(old : { db : { host : Text, port : Natural }, debug : Bool, name : Text }) → \
+         { db = { host = old.db.host, port = (TODO : Integer) }, \
+         `if` = (TODO : Optional Text), name = old.name }"
+    );
+
+    // Once the placeholders are filled in, the migration reads an old configuration.
+    let migration = migration
+        .replace("(TODO : Integer)", "Natural/toInteger old.db.port")
+        .replace("(TODO : Optional Text)", "None Text");
+    let config: SimpleValue = serde_dhall::from_str(&format!(
+        r#"({}) {{ name = "a", debug = True, db = {{ host = "h", port = 5432 }} }}"#,
+        migration
+    ))
+    .type_annotation(&new)
+    .parse()
+    .unwrap();
+    let expected: SimpleValue = serde_dhall::from_str(
+        r#"{ name = "a", `if` = None Text, db = { host = "h", port = +5432 } }"#,
+    )
+    .parse()
+    .unwrap();
+    assert_eq!(config, expected);
+}
+
+#[test]
+fn accessors() {
+    #[derive(Debug, PartialEq, Deserialize, StaticType)]
+    enum Listen {
+        Port(u64),
+        Socket { path: String },
+        Auto,
+    }
+
+    let ty = Listen::static_type();
+    let accessors = generate_accessors(&ty).unwrap();
+    assert_eq!(
+        accessors.keys().collect::<Vec<_>>(),
+        ["Auto", "Port", "Socket", "is_Auto", "is_Port", "is_Socket"]
+    );
+    assert!(generate_accessors(&SimpleType::Natural).unwrap().is_empty());
+    // A predicate can't have the name of another alternative.
+    let clash = SimpleType::union(vec![("A", None), ("is_A", None)]);
+    assert_eq!(
+        generate_accessors(&clash).map_err(|e| e.to_string()),
+        Err("the predicate of `A` would have the same name as the alternative `is_A`"
+            .to_owned())
+    );
+
+    let apply =
+        |f: &str, x: &str| format!("({}) (({}).{})", accessors[f], ty, x);
+    let eval =
+        |s: String| serde_dhall::from_str(&s).parse::<SimpleValue>().unwrap();
+    assert_eq!(
+        serde_dhall::from_str(&apply("Port", "Port 80"))
+            .parse::<Option<u64>>()
+            .unwrap(),
+        Some(80)
+    );
+    assert_eq!(
+        serde_dhall::from_str(&apply("Port", "Auto"))
+            .parse::<Option<u64>>()
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        eval(apply("Socket", "Socket { path = \"/run/app\" }")),
+        eval("Some { path = \"/run/app\" }".to_owned())
+    );
+    assert_eq!(eval(apply("Auto", "Auto")), eval("Some {=}".to_owned()));
+    for (predicate, expected) in
+        &[("is_Port", true), ("is_Socket", false), ("is_Auto", false)]
+    {
+        assert_eq!(
+            serde_dhall::from_str(&apply(predicate, "Port 1"))
+                .parse::<bool>()
+                .unwrap(),
+            *expected
+        );
+    }
+}
+
+#[test]
+fn proto3_schema() {
+    let ty = serde_dhall::from_str(
+        "{ name : Text, \
+           replicas : Natural, \
+           offset : Integer, \
+           debug : Optional Bool, \
+           labels : List Text, \
+           limits : { cpu : Double, memory : Optional Natural }, \
+           servers : List { host : Text, port : Natural }, \
+           matrix : List (List Double), \
+           listen : < Auto | Port : Natural | Socket : { path : Text } >, \
+           check : Natural → Bool \
+         }",
+    )
+    .parse::<SimpleType>()
+    .unwrap();
+    let schema = to_proto3_schema("Deployment", &ty).unwrap();
+    assert_eq!(
+        schema,
+        r#"syntax = "proto3";
+
+message Deployment {
+  message Limits {
+    double cpu = 1;
+    optional uint64 memory = 2;
+  }
+
+  message Listen {
+    message Auto {}
+
+    message Socket {
+      string path = 1;
+    }
+
+    oneof value {
+      Auto auto = 1;
+      uint64 port = 2;
+      Socket socket = 3;
+    }
+  }
+
+  message MatrixItem {
+    repeated double value = 1;
+  }
+
+  message Servers {
+    string host = 1;
+    uint64 port = 2;
+  }
+
+  // `check` has a type with no equivalent: Natural → Bool
+  optional bool debug = 1;
+  repeated string labels = 2;
+  Limits limits = 3;
+  Listen listen = 4;
+  repeated MatrixItem matrix = 5;
+  string name = 6;
+  int64 offset = 7;
+  uint64 replicas = 8;
+  repeated Servers servers = 9;
+}
+"#
+    );
+
+    // Check the schema with `protoc` when it is installed.
+    let dir = std::env::temp_dir()
+        .join(format!("dhall_schema_proto_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("deployment.proto"), &schema).unwrap();
+    let status = std::process::Command::new("protoc")
+        .arg("--proto_path")
+        .arg(&dir)
+        .arg("--descriptor_set_out")
+        .arg(dir.join("deployment.pb"))
+        .arg("deployment.proto")
+        .status();
+    std::fs::remove_dir_all(&dir).unwrap();
+    if let Ok(status) = status {
+        assert!(status.success());
+    }
+    let err = |s: &str| {
+        let ty = serde_dhall::from_str(s).parse::<SimpleType>().unwrap();
+        to_proto3_schema("Config", &ty).unwrap_err().to_string()
+    };
+    assert_eq!(
+        err("{ db : { fooBar : Text, foo_bar : Natural } }"),
+        "`db`: `fooBar` and `foo_bar` would both be named `foo_bar`"
+    );
+    assert_eq!(
+        err("< a_b : Text | aB >"),
+        "`aB` and `a_b` would both be named `a_b`"
+    );
+    assert_eq!(
+        err("{ x : List (List Text), x_item : { y : Text } }"),
+        "the items of `x` and `x_item` would both be named `XItem`"
+    );
+    assert_eq!(
+        err("{ db : { port : Optional (Optional Natural) } }"),
+        "`db.port`: `Optional (Optional Natural)` has no equivalent: \
+         `None` and `Some None` would be the same"
+    );
+}
@@ -0,0 +1,139 @@
+#![doc(html_root_url = "https://docs.rs/dhall_schema_registry/0.1.0")]
+//! Share named Dhall types between Rust crates.
+//!
+//! When several crates read their configuration from Dhall, they often have types in common,
+//! like a `LogLevel` union. A [`SchemaRegistry`] stores such types by name, and writes them to a
+//! Dhall file, a record of types that configuration files can import. The crates that use the
+//! types load the file back to check that they agree with the crate that defined them.
+//!
+//! # Example
+//!
+//! ```
+//! use dhall_schema_registry::SchemaRegistry;
+//! use serde::Deserialize;
+//! use serde_dhall::StaticType;
+//!
+//! #[derive(Debug, PartialEq, Deserialize, StaticType)]
+//! enum LogLevel {
+//!     Debug,
+//!     Info,
+//! }
+//!
+//! let mut registry = SchemaRegistry::new();
+//! registry.add("LogLevel", LogLevel::static_type());
+//! let text = registry.to_dhall_string().unwrap();
+//! assert_eq!(text, "{ LogLevel = < Debug | Info > }\n");
+//!
+//! let registry = SchemaRegistry::from_dhall_str(&text).unwrap();
+//! assert_eq!(registry.get("LogLevel"), Some(&LogLevel::static_type()));
+//!
+//! // Configurations use the types of the registry.
+//! let config = format!("let Registry = {} in Registry.LogLevel.Info", text);
+//! let level: LogLevel = serde_dhall::from_str(&config).parse().unwrap();
+//! assert_eq!(level, LogLevel::Info);
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use dhall::syntax::{Expr, ExprKind, Span};
+use dhall::{Ctxt, Parsed};
+use serde::de::Error as _;
+use serde_dhall::{Error, Result, SimpleType};
+
+/// Dhall types stored by name, to be shared through a Dhall file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaRegistry {
+    types: BTreeMap<String, SimpleType>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        SchemaRegistry::default()
+    }
+
+    /// Stores `ty` as `name`, replacing the type that had this name, if any.
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        ty: SimpleType,
+    ) -> &mut Self {
+        self.types.insert(name.into(), ty);
+        self
+    }
+
+    /// The type stored as `name`.
+    pub fn get(&self, name: &str) -> Option<&SimpleType> {
+        self.types.get(name)
+    }
+
+    /// The names of the stored types, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.types.keys().map(String::as_str)
+    }
+
+    /// Renders the registry as a Dhall record of types, like `{ LogLevel = < Debug | Info > }`.
+    ///
+    /// Fails if a type isn't closed, like `SimpleType::var("a")` outside of a `∀`, since the
+    /// file couldn't be read back.
+    pub fn to_dhall_string(&self) -> Result<String> {
+        let fields = self
+            .types
+            .iter()
+            .map(|(name, ty)| {
+                let ty = dhall::syntax::parse_expr(&ty.to_string())
+                    .map_err(Error::custom)?;
+                Ok((name.as_str().into(), ty))
+            })
+            .collect::<Result<_>>()?;
+        let expr = Expr::new(ExprKind::RecordLit(fields), Span::Artificial);
+        dhall::to_canonical_string(&expr).map_err(|e| {
+            Error::custom(format!("the registry is not valid Dhall: {}", e))
+        })
+    }
+
+    /// Writes the registry to the file at `path`, as rendered by
+    /// [`to_dhall_string()`](SchemaRegistry::to_dhall_string).
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, self.to_dhall_string()?).map_err(Error::custom)
+    }
+
+    /// Reads a registry from Dhall source that evaluates to a record of types. The source can't
+    /// have imports; use [`load()`](SchemaRegistry::load) to read a file that does.
+    pub fn from_dhall_str(s: &str) -> Result<Self> {
+        let parsed = Parsed::parse_str(s)
+            .map(|parsed| Parsed::from_expr_without_imports(parsed.to_expr()));
+        SchemaRegistry::from_parsed(parsed)
+    }
+
+    /// Reads a registry from the file at `path`, which must evaluate to a record of types. It
+    /// can import other files, e.g. to put together the registries of several crates.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        SchemaRegistry::from_parsed(Parsed::parse_file(path.as_ref()))
+    }
+
+    fn from_parsed(
+        parsed: std::result::Result<Parsed, dhall::error::Error>,
+    ) -> Result<Self> {
+        let expr = Ctxt::with_new(|cx| {
+            let typed = parsed?.resolve(cx)?.typecheck(cx)?;
+            Ok(typed.normalize(cx).to_expr(cx))
+        })
+        .map_err(|e: dhall::error::Error| Error::custom(e))?;
+        let fields = match expr.kind() {
+            ExprKind::RecordLit(fields) => fields,
+            _ => return Err(Error::custom("the registry is not a record")),
+        };
+        let mut registry = SchemaRegistry::new();
+        for (name, ty) in fields {
+            let name = String::from(name);
+            let ty = serde_dhall::from_str(&ty.to_string())
+                .parse::<SimpleType>()
+                .map_err(|e| {
+                    Error::custom(format!("`{}` is not a type: {}", name, e))
+                })?;
+            registry.add(name, ty);
+        }
+        Ok(registry)
+    }
+}
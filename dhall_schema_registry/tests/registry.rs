@@ -0,0 +1,135 @@
+use dhall_schema_registry::SchemaRegistry;
+use serde_dhall::{SimpleType, StaticType};
+
+/// The crate that defines the shared types.
+mod logging {
+    use serde::Deserialize;
+    use serde_dhall::StaticType;
+
+    #[derive(Debug, PartialEq, Deserialize, StaticType)]
+    pub enum LogLevel {
+        Debug,
+        Info,
+        Error,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, StaticType)]
+    pub struct Duration {
+        pub secs: u64,
+    }
+}
+
+/// A crate that uses them in its configuration, with its own copy of `LogLevel`.
+mod server {
+    use serde::Deserialize;
+    use serde_dhall::StaticType;
+
+    #[derive(Debug, PartialEq, Deserialize, StaticType)]
+    pub enum LogLevel {
+        Debug,
+        Info,
+        Error,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, StaticType)]
+    pub struct Config {
+        pub level: LogLevel,
+        pub timeout: super::logging::Duration,
+    }
+}
+
+#[test]
+fn share_types() {
+    let dir = std::env::temp_dir()
+        .join(format!("dhall_schema_registry_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let run = || -> Result<(), String> {
+        // The first crate writes its types.
+        let mut registry = SchemaRegistry::new();
+        registry
+            .add("LogLevel", logging::LogLevel::static_type())
+            .add("Duration", logging::Duration::static_type());
+        registry
+            .save(dir.join("registry.dhall"))
+            .map_err(|e| e.to_string())?;
+
+        // The other one checks that its copy is the same.
+        let loaded = SchemaRegistry::load(dir.join("registry.dhall"))
+            .map_err(|e| e.to_string())?;
+        assert_eq!(loaded, registry);
+        assert_eq!(
+            loaded.names().collect::<Vec<_>>(),
+            ["Duration", "LogLevel"]
+        );
+        assert_eq!(
+            loaded.get("LogLevel"),
+            Some(&server::LogLevel::static_type())
+        );
+
+        // Users write configurations with the types of the registry.
+        std::fs::write(
+            dir.join("config.dhall"),
+            "let Registry = ./registry.dhall
+             in  { level = Registry.LogLevel.Info
+                 , timeout = { secs = 30 } : Registry.Duration
+                 }",
+        )
+        .unwrap();
+        let config = serde_dhall::from_file(dir.join("config.dhall"))
+            .static_type_annotation()
+            .parse::<server::Config>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(
+            config,
+            server::Config {
+                level: server::LogLevel::Info,
+                timeout: logging::Duration { secs: 30 },
+            }
+        );
+        Ok(())
+    };
+    let result = run();
+    std::fs::remove_dir_all(&dir).unwrap();
+    result.unwrap();
+}
+
+#[test]
+fn registry_files() {
+    // Names that aren't plain labels are quoted.
+    let mut registry = SchemaRegistry::new();
+    registry.add("log level", SimpleType::Text);
+    let text = registry.to_dhall_string().unwrap();
+    assert_eq!(text, "{ `log level` = Text }\n");
+    assert_eq!(SchemaRegistry::from_dhall_str(&text).unwrap(), registry);
+
+    // Registries can be computed.
+    let registry = SchemaRegistry::from_dhall_str(
+        "let Id = Natural in { Id, Ids = List Id }",
+    )
+    .unwrap();
+    assert_eq!(
+        registry.get("Ids"),
+        Some(&SimpleType::list(SimpleType::Natural))
+    );
+
+    let err =
+        |s: &str| SchemaRegistry::from_dhall_str(s).unwrap_err().to_string();
+    assert!(err("{ x = 1 }").starts_with("`x` is not a type"));
+    assert_eq!(err("Natural"), "the registry is not a record");
+
+    // Types with free variables can't be written.
+    let mut registry = SchemaRegistry::new();
+    registry.add("T", SimpleType::var("a"));
+    let err = registry.to_dhall_string().unwrap_err().to_string();
+    assert!(
+        err.starts_with("the registry is not valid Dhall"),
+        "{}",
+        err
+    );
+    let path = std::env::temp_dir().join(format!(
+        "dhall_schema_registry_free_{}.dhall",
+        std::process::id()
+    ));
+    assert!(registry.save(&path).is_err());
+    assert!(!path.exists());
+}
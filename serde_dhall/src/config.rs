@@ -0,0 +1,167 @@
+use std::path::{Component, Path};
+
+use dhall::operations::{BinOp, OpKind};
+use dhall::syntax::{
+    Expr, ExprKind, FilePath, FilePrefix, Import, ImportMode, ImportTarget,
+    Label, Span,
+};
+
+use crate::env::screaming_snake_case;
+use crate::{
+    Error, ErrorKind, FromDhall, Result, SimpleType, StaticType, ToDhall,
+};
+
+/// Loads a configuration from the Dhall file at `path`, with `defaults` for the fields the file
+/// doesn't set and overrides from environment variables.
+///
+/// The file is overlaid on `defaults` with the `⫽` operator, so a field of the file replaces the
+/// whole field of `defaults`. Then each field of `T` can be overridden by an environment
+/// variable, whose name is `env_prefix` followed by the field name in `SCREAMING_SNAKE_CASE`,
+/// with the fields of nested records joined by `_`, like [`to_env_string()`] writes them: with
+/// the prefix `APP_`, `db.logLevel` is read from `APP_DB_LOG_LEVEL`. Unlike the file, this only
+/// replaces the field it names.
+///
+/// Environment variables are read as Dhall `env:` imports: `Text` fields take the contents of
+/// the variable as is, and other fields parse it as a Dhall expression, like `8080`, `+1` or
+/// `True`. An `Optional` field is set to `Some` of the value. Only fields of type `Bool`,
+/// `Natural`, `Integer`, `Double` and `Text`, or `Optional` of these, are read from the
+/// environment.
+///
+/// The result is checked against the type of `T`.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::{Deserialize, Serialize};
+/// use serde_dhall::StaticType;
+///
+/// #[derive(Deserialize, Serialize, StaticType)]
+/// struct Config {
+///     host: String,
+///     port: u64,
+/// }
+///
+/// let defaults = Config { host: "localhost".to_owned(), port: 80 };
+/// // `APP_PORT=8080` overrides the port of the file and of the defaults.
+/// let config: Config = serde_dhall::load_config("config.dhall", defaults, "APP_")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`to_env_string()`]: crate::to_env_string()
+pub fn load_config<T>(
+    path: impl AsRef<Path>,
+    defaults: T,
+    env_prefix: &str,
+) -> Result<T>
+where
+    T: FromDhall + ToDhall + StaticType,
+{
+    // The prefix ends up in variable names, which can't contain these.
+    if env_prefix.contains(|c| c == '=' || c == '\0') {
+        return Err(Error(ErrorKind::Deserialize(format!(
+            "`{}` is not a valid prefix for environment variables",
+            env_prefix.escape_debug()
+        ))));
+    }
+    let ty = T::static_type();
+    let defaults = defaults.to_dhall(Some(&ty))?.to_expr();
+    let mut expr = expr(ExprKind::Op(OpKind::BinOp(
+        BinOp::RightBiasedRecordMerge,
+        defaults,
+        import_path(path.as_ref())?,
+    )));
+    if let SimpleType::Record(kts) = &ty {
+        let mut overrides = Vec::new();
+        collect_overrides(kts, &[], env_prefix, &mut overrides);
+        for (path, value) in overrides {
+            expr = self::expr(ExprKind::Op(OpKind::With(expr, path, value)));
+        }
+    }
+    crate::Deserializer::from_expr(expr)
+        .type_annotation(&ty)
+        .parse()
+}
+
+fn expr(kind: ExprKind<Expr>) -> Expr {
+    Expr::new(kind, Span::Artificial)
+}
+
+/// Adds a `field = value` override for each field that has its environment variable set, with the
+/// path and variable name of the record as prefixes.
+fn collect_overrides(
+    kts: &std::collections::HashMap<String, SimpleType>,
+    path_prefix: &[Label],
+    name_prefix: &str,
+    overrides: &mut Vec<(Vec<Label>, Expr)>,
+) {
+    let mut kts: Vec<_> = kts.iter().collect();
+    kts.sort_by_key(|(field, _)| *field);
+    for (field, ty) in kts {
+        let name = match screaming_snake_case(field) {
+            Some(name) => format!("{}{}", name_prefix, name),
+            None => continue,
+        };
+        let mut path = path_prefix.to_vec();
+        path.push(Label::from_str(field));
+        if let SimpleType::Record(kts) = ty {
+            collect_overrides(kts, &path, &format!("{}_", name), overrides);
+        } else if std::env::var_os(&name).is_some() {
+            if let Some(value) = env_import(&name, ty) {
+                overrides.push((path, value));
+            }
+        }
+    }
+}
+
+/// An expression of type `ty` that reads the environment variable `name`.
+fn env_import(name: &str, ty: &SimpleType) -> Option<Expr> {
+    let import = |mode| {
+        expr(ExprKind::Import(Import {
+            mode,
+            location: ImportTarget::Env(name.to_owned()),
+            hash: None,
+        }))
+    };
+    match ty {
+        SimpleType::Text => Some(import(ImportMode::RawText)),
+        SimpleType::Bool
+        | SimpleType::Natural
+        | SimpleType::Integer
+        | SimpleType::Double => Some(import(ImportMode::Code)),
+        SimpleType::Optional(ty) => {
+            Some(expr(ExprKind::SomeLit(env_import(name, ty)?)))
+        }
+        _ => None,
+    }
+}
+
+/// An import of the file at `path`, by its absolute path.
+fn import_path(path: &Path) -> Result<Expr> {
+    let path = path
+        .canonicalize()
+        .map_err(|e| ErrorKind::Dhall(e.into()))?;
+    let mut file_path = Vec::new();
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            match name.to_str() {
+                Some(name) => file_path.push(name.to_owned()),
+                None => {
+                    return Err(Error(ErrorKind::Deserialize(format!(
+                        "the path `{}` can't be imported",
+                        path.display()
+                    ))))
+                }
+            }
+        }
+    }
+    Ok(expr(ExprKind::Import(Import {
+        mode: ImportMode::Code,
+        location: ImportTarget::Local(
+            FilePrefix::Absolute,
+            FilePath { file_path },
+        ),
+        hash: None,
+    })))
+}
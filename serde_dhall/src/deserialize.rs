@@ -5,11 +5,12 @@ use std::fmt;
 use serde::de::value::{
     MapAccessDeserializer, MapDeserializer, SeqDeserializer,
 };
-use serde::de::VariantAccess as _;
+use serde::de::{IntoDeserializer as _, VariantAccess as _};
 
-use dhall::syntax::NumKind;
+use dhall::syntax::{Label, NumKind};
 
 use crate::value::SimpleValue;
+use crate::SimpleType;
 use crate::{Error, ErrorKind, Value};
 
 pub trait Sealed {}
@@ -43,10 +44,77 @@ pub trait Sealed {}
 pub trait FromDhall: Sealed + Sized {
     #[doc(hidden)]
     fn from_dhall(v: &Value) -> crate::Result<Self>;
+
+    /// Like `from_dhall`, but with the options of [`Deserializer::coerce_numbers()`],
+    /// [`Deserializer::union_as_tagged()`] and [`Deserializer::preserve_field_order()`].
+    ///
+    /// [`Deserializer::coerce_numbers()`]: crate::Deserializer::coerce_numbers()
+    /// [`Deserializer::union_as_tagged()`]: crate::Deserializer::union_as_tagged()
+    /// [`Deserializer::preserve_field_order()`]: crate::Deserializer::preserve_field_order()
+    #[doc(hidden)]
+    fn from_dhall_with(
+        v: &Value,
+        _coerce_numbers: bool,
+        _union_tag: Option<&str>,
+        _field_orders: Option<&FieldOrders>,
+    ) -> crate::Result<Self> {
+        Self::from_dhall(v)
+    }
 }
 
 impl<T> Sealed for T where T: serde::de::DeserializeOwned {}
 
+/// The order in which the fields of the records of a config are written, for
+/// [`Deserializer::preserve_field_order()`]. Since the order doesn't survive normalization, a
+/// record is given the order of the record literal at the same path in the source, or else of the
+/// records of the source with the same fields if they agree on one. See
+/// [`dhall::semantics::FieldOrders`].
+///
+/// [`Deserializer::preserve_field_order()`]: crate::Deserializer::preserve_field_order()
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct FieldOrders {
+    orders: dhall::semantics::FieldOrders,
+    /// The path in the source of the value that is deserialized.
+    root: Vec<Label>,
+}
+
+impl FieldOrders {
+    /// The field orders of `orders`, for the value at the path of fields `root`.
+    pub(crate) fn new(
+        orders: dhall::semantics::FieldOrders,
+        root: &[String],
+    ) -> Self {
+        FieldOrders {
+            orders,
+            root: root
+                .iter()
+                .map(|field| Label::from(field.as_str()))
+                .collect(),
+        }
+    }
+
+    /// The fields of `m`, found at `path`, in the order they were written if it is known.
+    fn fields<'b>(
+        orders: Option<&FieldOrders>,
+        path: Option<&[Label]>,
+        m: &'b BTreeMap<String, SimpleValue>,
+    ) -> Vec<(&'b String, &'b SimpleValue)> {
+        let order = orders.and_then(|orders| {
+            let labels: Vec<Label> =
+                m.keys().map(|k| Label::from(k.as_str())).collect();
+            orders.orders.get(path, &labels)
+        });
+        match order {
+            Some(order) => order
+                .iter()
+                .filter_map(|k| m.get_key_value(&String::from(k)))
+                .collect(),
+            None => m.iter().collect(),
+        }
+    }
+}
+
 /// Deserialize a Rust value from a Dhall [`SimpleValue`].
 ///
 /// # Example
@@ -88,7 +156,7 @@ pub fn from_simple_value<T>(v: SimpleValue) -> crate::Result<T>
 where
     T: serde::de::DeserializeOwned,
 {
-    T::deserialize(Deserializer(Cow::Owned(v)))
+    T::deserialize(Deserializer::new(Cow::Owned(v), false, None, None))
 }
 
 impl<T> FromDhall for T
@@ -96,37 +164,286 @@ where
     T: serde::de::DeserializeOwned,
 {
     fn from_dhall(v: &Value) -> crate::Result<Self> {
-        let sval = v.to_simple_value().ok_or_else(|| {
-            Error(ErrorKind::Deserialize(format!(
-                "this cannot be deserialized into the serde data model: {}",
-                v
-            )))
-        })?;
-        from_simple_value(sval)
+        from_value(v, false, None, None)
+    }
+    fn from_dhall_with(
+        v: &Value,
+        coerce_numbers: bool,
+        union_tag: Option<&str>,
+        field_orders: Option<&FieldOrders>,
+    ) -> crate::Result<Self> {
+        from_value(v, coerce_numbers, union_tag, field_orders)
     }
 }
 
-struct Deserializer<'a>(Cow<'a, SimpleValue>);
+fn from_value<T>(
+    v: &Value,
+    coerce_numbers: bool,
+    union_tag: Option<&str>,
+    field_orders: Option<&FieldOrders>,
+) -> crate::Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let sval = v.to_simple_value().ok_or_else(|| {
+        Error(ErrorKind::Deserialize(format!(
+            "this cannot be deserialized into the serde data model: {}",
+            v
+        )))
+    })?;
+    let mut de = Deserializer::new(
+        Cow::Owned(sval),
+        coerce_numbers,
+        union_tag,
+        field_orders,
+    );
+    de.path = field_orders.map(|orders| orders.root.clone());
+    T::deserialize(de).map_err(|e| {
+        match (expected_scalar_type::<T>(), v.ty()) {
+            // Integral `Double`s are accepted for integers in that case, so the serde error is
+            // more precise.
+            _ if coerce_numbers => e,
+            (Some(expected), Some(found)) if expected != *found => {
+                Error(ErrorKind::TopLevelTypeMismatch {
+                    expected,
+                    found: found.clone(),
+                })
+            }
+            _ => e,
+        }
+    })
+}
 
-impl<'de: 'a, 'a> serde::de::IntoDeserializer<'de, Error> for Deserializer<'a> {
-    type Deserializer = Deserializer<'a>;
-    fn into_deserializer(self) -> Self::Deserializer {
-        self
+/// The Dhall type that `T` deserializes from, if `T` is a scalar type like `u64` or `String`. This
+/// is found by checking which method of the deserializer `T` calls.
+fn expected_scalar_type<T>() -> Option<SimpleType>
+where
+    T: serde::de::DeserializeOwned,
+{
+    match T::deserialize(ScalarProbe) {
+        Err(ProbeResult(ty)) => ty,
+        Ok(_) => None,
     }
 }
 
-impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
-    type Error = Error;
+/// A deserializer that fails right away, reporting which scalar type was asked for.
+struct ScalarProbe;
 
-    fn deserialize_any<V>(self, visitor: V) -> crate::Result<V::Value>
+#[derive(Debug)]
+struct ProbeResult(Option<SimpleType>);
+
+impl fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl std::error::Error for ProbeResult {}
+
+impl serde::de::Error for ProbeResult {
+    fn custom<T: fmt::Display>(_: T) -> Self {
+        ProbeResult(None)
+    }
+}
+
+macro_rules! probe_scalars {
+    ($($method:ident => $ty:ident,)*) => {
+        $(
+            fn $method<V>(self, _: V) -> Result<V::Value, ProbeResult>
+            where
+                V: serde::de::Visitor<'de>,
+            {
+                Err(ProbeResult(Some(SimpleType::$ty)))
+            }
+        )*
+    };
+}
+
+impl<'de> serde::Deserializer<'de> for ScalarProbe {
+    type Error = ProbeResult;
+
+    fn deserialize_any<V>(self, _: V) -> Result<V::Value, ProbeResult>
     where
         V: serde::de::Visitor<'de>,
+    {
+        Err(ProbeResult(None))
+    }
+
+    probe_scalars! {
+        deserialize_bool => Bool,
+        deserialize_i8 => Integer,
+        deserialize_i16 => Integer,
+        deserialize_i32 => Integer,
+        deserialize_i64 => Integer,
+        deserialize_i128 => Integer,
+        deserialize_u8 => Natural,
+        deserialize_u16 => Natural,
+        deserialize_u32 => Natural,
+        deserialize_u64 => Natural,
+        deserialize_u128 => Natural,
+        deserialize_f32 => Double,
+        deserialize_f64 => Double,
+        deserialize_char => Text,
+        deserialize_str => Text,
+        deserialize_string => Text,
+    }
+
+    serde::forward_to_deserialize_any! {
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct Deserializer<'a> {
+    val: Cow<'a, SimpleValue>,
+    /// Whether to accept integral `Double`s for integer targets.
+    coerce_numbers: bool,
+    /// The field to give the name of a union alternative in, for internally tagged enums.
+    union_tag: Option<&'a str>,
+    /// The order in which to give the fields of records, if not sorted.
+    field_orders: Option<&'a FieldOrders>,
+    /// The path of fields in the source that leads to this value, when it is known and needed to
+    /// find the order of its fields.
+    path: Option<Vec<Label>>,
+}
+
+impl<'a> Deserializer<'a> {
+    fn new(
+        val: Cow<'a, SimpleValue>,
+        coerce_numbers: bool,
+        union_tag: Option<&'a str>,
+        field_orders: Option<&'a FieldOrders>,
+    ) -> Self {
+        Deserializer {
+            val,
+            coerce_numbers,
+            union_tag,
+            field_orders,
+            path: None,
+        }
+    }
+
+    /// A deserializer for `x`, the field `k` of this value.
+    fn field<'b>(&self, k: &str, x: &'b SimpleValue) -> Deserializer<'b>
+    where
+        'a: 'b,
+    {
+        let mut de = Deserializer::new(
+            Cow::Borrowed(x),
+            self.coerce_numbers,
+            self.union_tag,
+            self.field_orders,
+        );
+        de.path = self.path.as_ref().map(|path| {
+            let mut path = path.clone();
+            path.push(Label::from(k));
+            path
+        });
+        de
+    }
+
+    /// Deserializes into an integer type: `Integer`s go into signed types and `Natural`s into
+    /// unsigned ones. With `coerce_numbers`, either can go into any integer type, and a `Double`
+    /// with an integral value is passed to the visitor as an integer; the visitor then checks that
+    /// it fits in the target type.
+    fn deserialize_integer<'de, V>(
+        self,
+        visitor: V,
+        signed: bool,
+    ) -> crate::Result<V::Value>
+    where
+        'de: 'a,
+        V: serde::de::Visitor<'de>,
+    {
+        use serde::Deserializer as _;
+        match self.val.as_ref() {
+            SimpleValue::Num(NumKind::Natural(x))
+                if signed && !self.coerce_numbers =>
+            {
+                Err(Error(ErrorKind::Deserialize(format!(
+                    "expected an `Integer` like `+{}`, found the `Natural` `{}`",
+                    x, x
+                ))))
+            }
+            SimpleValue::Num(NumKind::Integer(x))
+                if !signed && !self.coerce_numbers =>
+            {
+                Err(Error(ErrorKind::Deserialize(format!(
+                    "expected a `Natural`, found the `Integer` `{:+}`",
+                    x
+                ))))
+            }
+            SimpleValue::Num(NumKind::Double(x)) if self.coerce_numbers => {
+                let x: f64 = (*x).into();
+                if x.fract() != 0.0 || !x.is_finite() {
+                    Err(Error(ErrorKind::Deserialize(format!(
+                        "cannot convert non-integral Double `{}` to an integer",
+                        x
+                    ))))
+                } else if x >= 0.0 && x < u64::MAX as f64 {
+                    visitor.visit_u64(x as u64)
+                } else if x < 0.0 && x >= i64::MIN as f64 {
+                    visitor.visit_i64(x as i64)
+                } else {
+                    Err(Error(ErrorKind::Deserialize(format!(
+                        "Double `{}` is too large to convert to an integer",
+                        x
+                    ))))
+                }
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    /// Passes the value to `visitor`. Unions are given as enums if `unions_as_enums`, and
+    /// otherwise as externally tagged data like in JSON: an alternative without payload is its
+    /// name, and one with a payload is a map with a single entry. Serde can buffer the latter, which
+    /// it needs to do for `#[serde(flatten)]` fields and untagged enums. With a `union_tag`, an
+    /// alternative with a record payload is given as that record with the name of the
+    /// alternative in the tag field instead, which is what internally tagged enums expect.
+    fn deserialize_value<'de, V>(
+        self,
+        visitor: V,
+        unions_as_enums: bool,
+    ) -> crate::Result<V::Value>
+    where
+        'de: 'a,
+        V: serde::de::Visitor<'de>,
     {
         use NumKind::*;
         use SimpleValue::*;
 
-        let val = |x| Deserializer(Cow::Borrowed(x));
-        match self.0.as_ref() {
+        let (coerce_numbers, union_tag, field_orders) =
+            (self.coerce_numbers, self.union_tag, self.field_orders);
+        let val = |x| {
+            Deserializer::new(
+                Cow::Borrowed(x),
+                coerce_numbers,
+                union_tag,
+                field_orders,
+            )
+        };
+        if let (Some(tag_field), Union(field_name, Some(x))) =
+            (union_tag, self.val.as_ref())
+        {
+            if let (false, Record(m)) = (unions_as_enums, x.as_ref()) {
+                let tag = Deserializer::new(
+                    Cow::Owned(Text(field_name.clone())),
+                    coerce_numbers,
+                    union_tag,
+                    field_orders,
+                );
+                let fields = FieldOrders::fields(field_orders, None, m)
+                    .into_iter()
+                    .map(|(k, v)| (KeyDeserializer(k), val(v)));
+                return visitor.visit_map(MapDeserializer::new(
+                    Some((KeyDeserializer(tag_field), tag))
+                        .into_iter()
+                        .chain(fields),
+                ));
+            }
+        }
+        match self.val.as_ref() {
             Num(Bool(x)) => visitor.visit_bool(*x),
             Num(Natural(x)) => visitor.visit_u64(*x),
             Num(Integer(x)) => visitor.visit_i64(*x),
@@ -138,8 +455,17 @@ impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
             Optional(None) => visitor.visit_none(),
             Optional(Some(x)) => visitor.visit_some(val(x)),
             Record(m) => visitor.visit_map(MapDeserializer::new(
-                m.iter().map(|(k, v)| (k.as_str(), val(v))),
+                FieldOrders::fields(field_orders, self.path.as_deref(), m)
+                    .into_iter()
+                    .map(|(k, v)| (KeyDeserializer(k), self.field(k, v))),
             )),
+            Union(field_name, Some(x)) if !unions_as_enums => visitor
+                .visit_map(MapDeserializer::new(
+                    Some((field_name.as_str(), val(x))).into_iter(),
+                )),
+            Union(field_name, None) if !unions_as_enums => {
+                visitor.visit_str(field_name)
+            }
             Union(field_name, Some(x)) => visitor.visit_enum(
                 MapAccessDeserializer::new(MapDeserializer::new(
                     Some((field_name.as_str(), val(x))).into_iter(),
@@ -152,6 +478,69 @@ impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
             ),
         }
     }
+}
+
+macro_rules! deserialize_integers {
+    ($($method:ident => $signed:expr,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> crate::Result<V::Value>
+            where
+                V: serde::de::Visitor<'de>,
+            {
+                self.deserialize_integer(visitor, $signed)
+            }
+        )*
+    };
+}
+
+impl<'de: 'a, 'a> serde::de::IntoDeserializer<'de, Error> for Deserializer<'a> {
+    type Deserializer = Deserializer<'a>;
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.deserialize_value(visitor, false)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        // Text is read as the name of a variant without payload, so that simple choices don't
+        // need a union.
+        if let SimpleValue::Text(x) = self.val.as_ref() {
+            return visitor.visit_enum(x.as_str().into_deserializer());
+        }
+        self.deserialize_value(visitor, true)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        if name == SIMPLE_VALUE_TOKEN {
+            self.deserialize_value(visitor, true)
+        } else {
+            self.deserialize_any(visitor)
+        }
+    }
 
     fn deserialize_tuple<V>(
         self,
@@ -161,11 +550,13 @@ impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
     where
         V: serde::de::Visitor<'de>,
     {
-        let val = |x| Deserializer(Cow::Borrowed(x));
-        match self.0.as_ref() {
-            // Blindly takes keys in sorted order.
-            SimpleValue::Record(m) => visitor
-                .visit_seq(SeqDeserializer::new(m.iter().map(|(_, v)| val(v)))),
+        match self.val.as_ref() {
+            // Blindly takes the fields in sorted order, or in the order they were written.
+            SimpleValue::Record(m) => visitor.visit_seq(SeqDeserializer::new(
+                FieldOrders::fields(self.field_orders, self.path.as_deref(), m)
+                    .into_iter()
+                    .map(|(k, v)| self.field(k, v)),
+            )),
             _ => self.deserialize_any(visitor),
         }
     }
@@ -174,19 +565,106 @@ impl<'de: 'a, 'a> serde::Deserializer<'de> for Deserializer<'a> {
     where
         V: serde::de::Visitor<'de>,
     {
-        match self.0.as_ref() {
+        match self.val.as_ref() {
             SimpleValue::Record(m) if m.is_empty() => visitor.visit_unit(),
             _ => self.deserialize_any(visitor),
         }
     }
 
+    deserialize_integers! {
+        deserialize_i8 => true,
+        deserialize_i16 => true,
+        deserialize_i32 => true,
+        deserialize_i64 => true,
+        deserialize_i128 => true,
+        deserialize_u8 => false,
+        deserialize_u16 => false,
+        deserialize_u32 => false,
+        deserialize_u64 => false,
+        deserialize_u128 => false,
+    }
+
     serde::forward_to_deserialize_any! {
-        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf option unit_struct newtype_struct seq
-        tuple_struct map struct enum identifier ignored_any
+        bool f32 f64 char str string
+        bytes byte_buf option unit_struct seq
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// Deserializes the key of a record or of a `toMap`-style list. Dhall keys are always text, but
+/// they are parsed when the target is a number so that maps like `HashMap<u64, T>` can be read.
+struct KeyDeserializer<'a>(&'a str);
+
+macro_rules! deserialize_parsed_keys {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> crate::Result<V::Value>
+            where
+                V: serde::de::Visitor<'de>,
+            {
+                match self.0.parse() {
+                    Ok(x) => visitor.$visit(x),
+                    // Let the visitor report the mismatch.
+                    Err(_) => visitor.visit_str(self.0),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> serde::de::IntoDeserializer<'de, Error> for KeyDeserializer<'a> {
+    type Deserializer = Self;
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de, 'a> serde::Deserializer<'de> for KeyDeserializer<'a> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        visitor.visit_str(self.0)
+    }
+
+    deserialize_parsed_keys! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> crate::Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool char str string bytes byte_buf option unit unit_struct seq
+        tuple tuple_struct map struct enum identifier ignored_any
     }
 }
 
+/// The name of the newtype struct that `SimpleValue` asks for, so that our deserializer gives
+/// it unions as enums rather than as externally tagged data.
+const SIMPLE_VALUE_TOKEN: &str = "$serde_dhall::private::SimpleValue";
+
 struct SimpleValueVisitor;
 
 impl<'de> serde::de::Visitor<'de> for SimpleValueVisitor {
@@ -228,7 +706,7 @@ impl<'de> serde::de::Visitor<'de> for SimpleValueVisitor {
     where
         D: serde::Deserializer<'de>,
     {
-        let val = val.deserialize_any(SimpleValueVisitor)?;
+        let val = serde::Deserialize::deserialize(val)?;
         Ok(SimpleValue::Optional(Some(Box::new(val))))
     }
 
@@ -245,6 +723,13 @@ impl<'de> serde::de::Visitor<'de> for SimpleValueVisitor {
         Ok(SimpleValue::Union(name, val))
     }
 
+    fn visit_newtype_struct<D>(self, val: D) -> Result<SimpleValue, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        val.deserialize_any(SimpleValueVisitor)
+    }
+
     fn visit_seq<V>(self, mut visitor: V) -> Result<SimpleValue, V::Error>
     where
         V: serde::de::SeqAccess<'de>,
@@ -273,6 +758,7 @@ impl<'de> serde::de::Deserialize<'de> for SimpleValue {
     where
         D: serde::Deserializer<'de>,
     {
-        deserializer.deserialize_any(SimpleValueVisitor)
+        deserializer
+            .deserialize_newtype_struct(SIMPLE_VALUE_TOKEN, SimpleValueVisitor)
     }
 }
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use crate::{NumKind, SimpleType, SimpleValue, Value};
+
+/// Renders a record as environment variable assignments, one `NAME=value` per line, that a POSIX
+/// shell can `eval` or `source`.
+///
+/// Each field of the record gives a variable, whose name is the field name in
+/// `SCREAMING_SNAKE_CASE`: `logLevel` and `log-level` both become `LOG_LEVEL`. The fields of
+/// nested records are flattened, with their names joined by `_`: `{ db = { port = 5432 } }`
+/// gives `DB_PORT=5432`.
+///
+/// `Text` is single-quoted for the shell, numbers are written in decimal, `Bool`s as `true` or
+/// `false`, and `Some x` like `x`. Fields that are `None` are left out. Other values, like lists
+/// and unions, can't be written as environment variables and give an error. So do the fields of
+/// a record type like `{ port : Natural }`, which are types.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::Value;
+///
+/// let config: Value = serde_dhall::from_str(
+///     r#"{ logLevel = "it's fine", db = { port = 5432, ssl = True } }"#,
+/// )
+/// .parse()?;
+/// assert_eq!(
+///     serde_dhall::to_env_string(&config).unwrap(),
+///     "DB_PORT=5432\nDB_SSL=true\nLOG_LEVEL='it'\\''s fine'\n"
+/// );
+/// # Ok(())
+/// # }
+/// ```
+pub fn to_env_string(value: &Value) -> Result<String, ToEnvError> {
+    let fields = match (value.to_simple_value(), value.to_simple_type()) {
+        (Some(SimpleValue::Record(fields)), _) => fields,
+        (_, Some(SimpleType::Record(kts))) if !kts.is_empty() => {
+            let first = kts.keys().min().unwrap();
+            return Err(ToEnvError::UnsupportedType(first.clone()));
+        }
+        _ => return Err(ToEnvError::NotARecord),
+    };
+    let mut vars = BTreeMap::new();
+    collect_vars(&fields, "", "", &mut vars)?;
+    Ok(vars
+        .into_iter()
+        .map(|(name, (_, value))| format!("{}={}\n", name, value))
+        .collect())
+}
+
+/// An error from [`to_env_string()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToEnvError {
+    /// The value is not a record.
+    NotARecord,
+    /// The field at this path, like `a.b`, has a value that can't be written as an environment
+    /// variable.
+    UnsupportedType(String),
+    /// The name of the field at this path doesn't give a valid variable name, e.g. because it
+    /// starts with a digit.
+    InvalidName(String),
+    /// The fields at these two paths give the same variable name, like `logLevel` and
+    /// `log_level`.
+    DuplicateName(String, String),
+}
+
+impl std::fmt::Display for ToEnvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ToEnvError::NotARecord => f.write_str(
+                "only records can be written as environment variables",
+            ),
+            ToEnvError::UnsupportedType(path) => write!(
+                f,
+                "the value of `{}` can't be written as an environment variable",
+                path
+            ),
+            ToEnvError::InvalidName(path) => write!(
+                f,
+                "`{}` can't be turned into an environment variable name",
+                path
+            ),
+            ToEnvError::DuplicateName(first, second) => write!(
+                f,
+                "`{}` and `{}` give the same environment variable name",
+                first, second
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ToEnvError {}
+
+/// Adds the variables for the fields of a record, with the path and variable name of the record
+/// as prefixes. `vars` maps variable names to the path of the field and the shell value.
+fn collect_vars(
+    fields: &BTreeMap<String, SimpleValue>,
+    path_prefix: &str,
+    name_prefix: &str,
+    vars: &mut BTreeMap<String, (String, String)>,
+) -> Result<(), ToEnvError> {
+    for (field, value) in fields {
+        let path = format!("{}{}", path_prefix, field);
+        let name = match screaming_snake_case(field) {
+            Some(name) if name_prefix.is_empty() => name,
+            Some(name) => format!("{}_{}", name_prefix, name),
+            None => return Err(ToEnvError::InvalidName(path)),
+        };
+        let mut value = value;
+        while let SimpleValue::Optional(Some(x)) = value {
+            value = x;
+        }
+        let shell_value = match value {
+            SimpleValue::Record(fields) => {
+                collect_vars(fields, &format!("{}.", path), &name, vars)?;
+                continue;
+            }
+            SimpleValue::Optional(None) => continue,
+            SimpleValue::Num(NumKind::Bool(b)) => b.to_string(),
+            SimpleValue::Num(NumKind::Natural(n)) => n.to_string(),
+            SimpleValue::Num(NumKind::Integer(n)) => n.to_string(),
+            SimpleValue::Num(NumKind::Double(x)) => f64::from(*x).to_string(),
+            SimpleValue::Text(s) => shell_quote(s),
+            SimpleValue::Optional(Some(_))
+            | SimpleValue::List(_)
+            | SimpleValue::Union(..) => {
+                return Err(ToEnvError::UnsupportedType(path))
+            }
+        };
+        if let Some((other, _)) = vars.get(&name) {
+            return Err(ToEnvError::DuplicateName(other.clone(), path));
+        }
+        vars.insert(name, (path, shell_value));
+    }
+    Ok(())
+}
+
+/// Converts a field name like `logLevel` or `log-level` to `LOG_LEVEL`. Returns `None` if the
+/// result is not a valid variable name.
+pub(crate) fn screaming_snake_case(field: &str) -> Option<String> {
+    let mut name = String::new();
+    let mut prev: Option<char> = None;
+    for c in field.chars() {
+        if c.is_ascii_uppercase()
+            && matches!(prev, Some(p) if p.is_ascii_lowercase() || p.is_ascii_digit())
+        {
+            name.push('_');
+        }
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_uppercase());
+        } else if c == '_' || c == '-' || c == '/' {
+            name.push('_');
+        } else {
+            return None;
+        }
+        prev = Some(c);
+    }
+    match name.chars().next() {
+        Some(c) if !c.is_ascii_digit() => Some(name),
+        _ => None,
+    }
+}
+
+/// Quotes a string for a POSIX shell: everything is literal between single quotes, and a single
+/// quote is written `'\''`.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
@@ -1,5 +1,7 @@
 use dhall::error::Error as DhallError;
 
+use crate::SimpleType;
+
 /// Alias for a `Result` with the error type `serde_dhall::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -12,6 +14,11 @@ pub(crate) enum ErrorKind {
     Dhall(DhallError),
     Deserialize(String),
     Serialize(String),
+    /// The parsed value doesn't have the type that the Rust type needs.
+    TopLevelTypeMismatch {
+        expected: SimpleType,
+        found: SimpleType,
+    },
 }
 
 impl From<ErrorKind> for Error {
@@ -26,6 +33,11 @@ impl std::fmt::Display for Error {
             ErrorKind::Dhall(err) => write!(f, "{}", err),
             ErrorKind::Deserialize(err) => write!(f, "{}", err),
             ErrorKind::Serialize(err) => write!(f, "{}", err),
+            ErrorKind::TopLevelTypeMismatch { expected, found } => write!(
+                f,
+                "expected a value of type `{}`, found a value of type `{}`",
+                expected, found
+            ),
         }
     }
 }
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+
+use dhall::builtins::Builtin;
+use dhall::operations::OpKind;
+use dhall::syntax::{Expr, ExprKind, Label, Span as DhallSpan};
+use serde_json::Value as JsonValue;
+
+use crate::{
+    Error, ErrorKind, FromDhall, NumKind, Result, SimpleType, SimpleValue,
+};
+
+/// Deserialize a Rust value from JSON data, interpreted as Dhall data of the given type.
+///
+/// This does the same conversion as `json-to-dhall`: the JSON value is converted into a Dhall
+/// value of type `schema`, which then gets type-checked and deserialized like any other Dhall
+/// value. In particular:
+/// - JSON numbers become `Natural`, `Integer` or `Double` depending on the expected type;
+/// - `null` and missing record fields become `None` when an `Optional` is expected;
+/// - a union is expected to be either a string naming an alternative without payload, or the
+///   payload of one of the alternatives. Alternatives are tried in order of their names.
+///
+/// Record fields that are not part of the expected type are rejected.
+///
+/// This requires the `json` feature.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::Deserialize;
+/// use serde_dhall::SimpleType;
+///
+/// #[derive(Deserialize)]
+/// struct Point {
+///     x: u64,
+///     y: Option<i64>,
+/// }
+///
+/// let ty: SimpleType =
+///     serde_dhall::from_str("{ x : Natural, y : Optional Integer }").parse()?;
+/// let json = serde_json::json!({ "x": 1 });
+///
+/// let point: Point = serde_dhall::from_json_value(&json, &ty)?;
+/// assert_eq!(point.x, 1);
+/// assert_eq!(point.y, None);
+/// # Ok(())
+/// # }
+/// ```
+pub fn from_json_value<T>(v: &JsonValue, schema: &SimpleType) -> Result<T>
+where
+    T: FromDhall,
+{
+    let val = json_to_simple_value(v, schema)
+        .map_err(|e| Error(ErrorKind::Deserialize(e)))?;
+    let expr = val.to_expr(Some(schema))?;
+    crate::from_str(&expr.to_string())
+        .imports(false)
+        .type_annotation(schema)
+        .parse()
+}
+
+fn json_to_simple_value(
+    v: &JsonValue,
+    ty: &SimpleType,
+) -> std::result::Result<SimpleValue, String> {
+    let mismatch =
+        || format!("expected a value of type `{}`, found: {}", ty, v);
+    Ok(match (ty, v) {
+        (SimpleType::Bool, JsonValue::Bool(b)) => {
+            SimpleValue::Num(NumKind::Bool(*b))
+        }
+        (SimpleType::Natural, JsonValue::Number(n)) => {
+            SimpleValue::Num(NumKind::Natural(n.as_u64().ok_or_else(mismatch)?))
+        }
+        (SimpleType::Integer, JsonValue::Number(n)) => {
+            SimpleValue::Num(NumKind::Integer(n.as_i64().ok_or_else(mismatch)?))
+        }
+        (SimpleType::Double, JsonValue::Number(n)) => SimpleValue::Num(
+            NumKind::Double(n.as_f64().ok_or_else(mismatch)?.into()),
+        ),
+        (SimpleType::Text, JsonValue::String(s)) => {
+            SimpleValue::Text(s.clone())
+        }
+        (SimpleType::Optional(_), JsonValue::Null) => {
+            SimpleValue::Optional(None)
+        }
+        (SimpleType::Optional(t), v) => {
+            SimpleValue::Optional(Some(Box::new(json_to_simple_value(v, t)?)))
+        }
+        (SimpleType::List(t), JsonValue::Array(xs)) => SimpleValue::List(
+            xs.iter()
+                .map(|x| json_to_simple_value(x, t))
+                .collect::<std::result::Result<_, _>>()?,
+        ),
+        (SimpleType::Record(kts), JsonValue::Object(kvs)) => {
+            if let Some(k) = kvs.keys().find(|k| !kts.contains_key(*k)) {
+                return Err(format!(
+                    "unexpected field `{}` for a record of type `{}`",
+                    k, ty
+                ));
+            }
+            let mut fields = BTreeMap::new();
+            for (k, t) in kts {
+                let val = match (kvs.get(k), t) {
+                    (Some(v), t) => json_to_simple_value(v, t)?,
+                    (None, SimpleType::Optional(_)) => {
+                        SimpleValue::Optional(None)
+                    }
+                    (None, _) => {
+                        return Err(format!(
+                            "missing field `{}` for a record of type `{}`",
+                            k, ty
+                        ))
+                    }
+                };
+                fields.insert(k.clone(), val);
+            }
+            SimpleValue::Record(fields)
+        }
+        (SimpleType::Union(kts), v) => {
+            if let JsonValue::String(s) = v {
+                if let Some(None) = kts.get(s) {
+                    return Ok(SimpleValue::Union(s.clone(), None));
+                }
+            }
+            let alternatives: BTreeMap<_, _> = kts.iter().collect();
+            alternatives
+                .into_iter()
+                .find_map(|(k, t)| {
+                    let val = json_to_simple_value(v, t.as_ref()?).ok()?;
+                    Some(SimpleValue::Union(k.clone(), Some(Box::new(val))))
+                })
+                .ok_or_else(mismatch)?
+        }
+        _ => return Err(mismatch()),
+    })
+}
+
+/// A location in the Dhall source, given by [`to_json_with_source_map()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// The byte offset of the start of the location.
+    pub start: usize,
+    /// The byte offset of the end of the location.
+    pub end: usize,
+    /// The line of the start of the location, starting at 1.
+    pub line: usize,
+    /// The column of the start of the location, in characters, starting at 1.
+    pub column: usize,
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}, col {}", self.line, self.column)
+    }
+}
+
+/// Maps the JSON pointers of the values in a JSON document, like `/users/0/name`, to the place in
+/// the Dhall source they come from. The empty pointer is the whole document.
+pub type SourceMap = BTreeMap<String, Span>;
+
+/// Converts a Dhall expression to JSON like `dhall-to-json` does, and tells where each JSON value
+/// comes from in `source`, the Dhall text the expression was parsed from.
+///
+/// Records, lists, `Optional`s, numbers and text are converted as they are written, so each of
+/// their JSON values maps to the literal that gives it. Any other expression, like `x + 1` or a
+/// variable bound by a `let`, is evaluated, and all the JSON values that come from it map to it.
+/// Values that come from an import map to the import. A union value becomes the name of its
+/// alternative if it has no payload, or its payload otherwise.
+///
+/// The expression should come from [`Deserializer::parse_ast()`], which resolves imports and
+/// keeps the locations of the source. Functions and types can't be converted to JSON, nor can
+/// the `Double`s `NaN` and `Infinity`.
+///
+/// This requires the `json` feature.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// let source = "let port = 8000\nin  { users = [ { name = \"alice\", port = port + 1 } ] }";
+/// let expr = serde_dhall::from_str(source).parse_ast()?;
+/// let (json, source_map) = serde_dhall::to_json_with_source_map(&expr, source)?;
+/// assert_eq!(json, serde_json::json!({ "users": [{ "name": "alice", "port": 8001 }] }));
+/// assert_eq!(source_map["/users/0/name"].to_string(), "line 2, col 26");
+/// assert_eq!(source_map["/users/0/port"].to_string(), "line 2, col 42");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Deserializer::parse_ast()`]: crate::Deserializer::parse_ast()
+pub fn to_json_with_source_map(
+    expr: &Expr,
+    source: &str,
+) -> Result<(JsonValue, SourceMap)> {
+    let mut mapper = SourceMapper {
+        source,
+        line_starts: std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect(),
+        inputs: HashMap::new(),
+        lets: Vec::new(),
+        map: SourceMap::new(),
+    };
+    let json = mapper.convert(expr, "", None)?;
+    Ok((json, mapper.map))
+}
+
+struct SourceMapper<'a> {
+    source: &'a str,
+    /// The byte offsets of the starts of the lines of `source`.
+    line_starts: Vec<usize>,
+    /// Whether the input with this address is `source`, for the inputs seen so far.
+    inputs: HashMap<usize, bool>,
+    /// The `let` bindings around the current expression.
+    lets: Vec<(Label, Option<Expr>, Expr)>,
+    map: SourceMap,
+}
+
+impl SourceMapper<'_> {
+    /// Converts `expr`, at `pointer` in the JSON document. `span` is the location of the closest
+    /// enclosing expression in the source, used if `expr` is not in the source itself.
+    fn convert(
+        &mut self,
+        expr: &Expr,
+        pointer: &str,
+        span: Option<Span>,
+    ) -> Result<JsonValue> {
+        let span = self.span(expr).or(span);
+        let json = match expr.kind() {
+            ExprKind::Let(l, t, v, body) => {
+                self.lets.push((l.clone(), t.clone(), v.clone()));
+                let json = self.convert(body, pointer, span);
+                self.lets.pop();
+                return json;
+            }
+            ExprKind::Annot(e, _) | ExprKind::SomeLit(e) => {
+                return self.convert(e, pointer, span)
+            }
+            ExprKind::RecordLit(kvs) => {
+                let mut fields = serde_json::Map::new();
+                for (k, v) in kvs {
+                    let k = String::from(k);
+                    let pointer = format!(
+                        "{}/{}",
+                        pointer,
+                        k.replace('~', "~0").replace('/', "~1")
+                    );
+                    fields.insert(k, self.convert(v, &pointer, span)?);
+                }
+                JsonValue::Object(fields)
+            }
+            ExprKind::NEListLit(xs) => JsonValue::Array(
+                xs.iter()
+                    .enumerate()
+                    .map(|(i, x)| {
+                        self.convert(x, &format!("{}/{}", pointer, i), span)
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            ExprKind::EmptyListLit(_) => JsonValue::Array(Vec::new()),
+            ExprKind::Op(OpKind::App(f, _))
+                if matches!(
+                    f.kind(),
+                    ExprKind::Builtin(Builtin::OptionalNone)
+                ) =>
+            {
+                JsonValue::Null
+            }
+            ExprKind::Num(n) => num_to_json(n)?,
+            ExprKind::TextLit(t) if t.tail().is_empty() => {
+                JsonValue::String(t.head().to_owned())
+            }
+            _ => {
+                let json = simple_value_to_json(&self.eval(expr)?)?;
+                if let Some(span) = span {
+                    insert_all(&mut self.map, pointer, &json, span);
+                }
+                return Ok(json);
+            }
+        };
+        if let Some(span) = span {
+            self.map.insert(pointer.to_owned(), span);
+        }
+        Ok(json)
+    }
+
+    /// The location of `expr` in the source, if it comes from the source.
+    fn span(&mut self, expr: &Expr) -> Option<Span> {
+        let span = expr.span();
+        let span = span.parsed()?;
+        let source = self.source;
+        let is_source = *self
+            .inputs
+            .entry(span.input().as_ptr() as usize)
+            .or_insert_with(|| span.input() == source);
+        if !is_source {
+            return None;
+        }
+        let line = match self.line_starts.binary_search(&span.start()) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let line_start = self.line_starts[line];
+        Some(Span {
+            start: span.start(),
+            end: span.end(),
+            line: line + 1,
+            column: source[line_start..span.start()].chars().count() + 1,
+        })
+    }
+
+    /// Evaluates `expr` with the enclosing `let` bindings.
+    fn eval(&self, expr: &Expr) -> Result<SimpleValue> {
+        let expr =
+            self.lets
+                .iter()
+                .rev()
+                .fold(expr.clone(), |body, (l, t, v)| {
+                    Expr::new(
+                        ExprKind::Let(l.clone(), t.clone(), v.clone(), body),
+                        DhallSpan::Artificial,
+                    )
+                });
+        crate::from_str(&expr.to_string()).imports(false).parse()
+    }
+}
+
+/// Maps `pointer` and the pointers of all the values inside `json` to `span`.
+fn insert_all(
+    map: &mut SourceMap,
+    pointer: &str,
+    json: &JsonValue,
+    span: Span,
+) {
+    map.insert(pointer.to_owned(), span);
+    match json {
+        JsonValue::Array(xs) => {
+            for (i, x) in xs.iter().enumerate() {
+                insert_all(map, &format!("{}/{}", pointer, i), x, span)
+            }
+        }
+        JsonValue::Object(kvs) => {
+            for (k, v) in kvs {
+                let pointer = format!(
+                    "{}/{}",
+                    pointer,
+                    k.replace('~', "~0").replace('/', "~1")
+                );
+                insert_all(map, &pointer, v, span)
+            }
+        }
+        _ => {}
+    }
+}
+
+fn num_to_json(n: &NumKind) -> Result<JsonValue> {
+    Ok(match n {
+        NumKind::Bool(b) => JsonValue::Bool(*b),
+        NumKind::Natural(n) => JsonValue::from(*n),
+        NumKind::Integer(n) => JsonValue::from(*n),
+        NumKind::Double(x) => {
+            let x = f64::from(*x);
+            match serde_json::Number::from_f64(x) {
+                Some(x) => JsonValue::Number(x),
+                None => {
+                    return Err(Error(ErrorKind::Serialize(format!(
+                        "`{}` can't be written as JSON",
+                        x
+                    ))))
+                }
+            }
+        }
+    })
+}
+
+fn simple_value_to_json(val: &SimpleValue) -> Result<JsonValue> {
+    Ok(match val {
+        SimpleValue::Num(n) => num_to_json(n)?,
+        SimpleValue::Text(s) => JsonValue::String(s.clone()),
+        SimpleValue::Optional(None) => JsonValue::Null,
+        SimpleValue::Optional(Some(x)) => simple_value_to_json(x)?,
+        SimpleValue::List(xs) => JsonValue::Array(
+            xs.iter().map(simple_value_to_json).collect::<Result<_>>()?,
+        ),
+        SimpleValue::Record(kvs) => JsonValue::Object(
+            kvs.iter()
+                .map(|(k, v)| Ok((k.clone(), simple_value_to_json(v)?)))
+                .collect::<Result<_>>()?,
+        ),
+        SimpleValue::Union(k, None) => JsonValue::String(k.clone()),
+        SimpleValue::Union(_, Some(x)) => simple_value_to_json(x)?,
+    })
+}
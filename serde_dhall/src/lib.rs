@@ -64,6 +64,30 @@
 //! # }
 //! ```
 //!
+//! Enums are read from unions, but an enum whose variants have no payload can also be read from
+//! `Text` holding the name of a variant, for configs that don't need a union for simple choices:
+//!
+//! ```rust
+//! # fn main() -> serde_dhall::Result<()> {
+//! use serde::Deserialize;
+//!
+//! #[derive(Debug, PartialEq, Deserialize)]
+//! enum LogLevel {
+//!     Debug,
+//!     Info,
+//! }
+//!
+//! let level: LogLevel = serde_dhall::from_str("\"Debug\"").parse()?;
+//! assert_eq!(level, LogLevel::Debug);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Types that take whatever data they are given, like untagged enums, the fields of
+//! `#[serde(flatten)]` and `serde_json::Value`, get unions as externally tagged data, like in
+//! JSON: `< A | B : Natural >.A` is the string `"A"`, and `< A | B : Natural >.B 1` is the map
+//! `{ "B": 1 }`.
+//!
 //! ## Serialization (writing)
 //!
 //! The entrypoint for serialization is the [`serialize()`] function. It takes a serde-compatible
@@ -258,9 +282,14 @@ mod test_readme {
     doc_comment::doctest!("../../README.md");
 }
 
+mod config;
 mod deserialize;
+mod env;
 mod error;
+#[cfg(feature = "json")]
+mod json;
 mod options;
+pub mod prelude;
 mod serialize;
 mod static_type;
 /// Dhall values
@@ -269,11 +298,26 @@ mod value;
 #[doc(hidden)]
 pub use dhall_proc_macros::StaticType;
 
+pub use config::load_config;
 pub use deserialize::{from_simple_value, FromDhall};
+pub use env::{to_env_string, ToEnvError};
 pub(crate) use error::ErrorKind;
 pub use error::{Error, Result};
-pub use options::de::{from_binary_file, from_file, from_str, Deserializer};
+#[cfg(feature = "json")]
+pub use json::{from_json_value, to_json_with_source_map, SourceMap, Span};
+pub use options::de::{
+    binary_diagnostic, from_binary_file, from_dhall_value, from_file, from_str,
+    from_value, merge_sources, Deserializer, MergeStrategy, Source,
+};
 pub use options::ser::{serialize, Serializer};
 pub use serialize::ToDhall;
 pub use static_type::StaticType;
-pub use value::{NumKind, SimpleType, SimpleValue, Value};
+pub use value::{
+    KindType, MergeError, NumKind, SimpleType, SimpleValue, SimpleValueVisitor,
+    Value,
+};
+
+pub use dhall::semantics::{
+    ImportLocation as ImportRef, PersistentCache, ResolvedMeta,
+};
+pub use dhall::syntax::{Warning, WarningKind};
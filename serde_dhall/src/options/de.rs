@@ -1,20 +1,77 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use dhall::{Ctxt, Parsed};
+use dhall::operations::{BinOp, OpKind};
+use dhall::semantics::{ImportEnv, Nir, NirKind, PersistentCache};
+use dhall::syntax::{Expr, ExprKind, Label, Span};
+use dhall::{Ctxt, Normalized, Parsed, Resolved, Typed};
 
-use crate::options::{HasAnnot, ManualAnnot, NoAnnot, StaticAnnot, TypeAnnot};
-use crate::SimpleType;
-use crate::{Error, ErrorKind, FromDhall, Result, Value};
+use crate::deserialize::FieldOrders;
+use crate::options::{
+    FileAnnot, HasAnnot, ManualAnnot, NoAnnot, StaticAnnot, TypeAnnot,
+};
+use crate::{
+    Error, ErrorKind, FromDhall, ImportRef, ResolvedMeta, Result, Value,
+    Warning,
+};
+use crate::{SimpleType, SimpleValue, StaticType};
 
+/// Where to read Dhall code from, for [`merge_sources()`].
 #[derive(Debug, Clone)]
-enum Source<'a> {
+pub enum Source<'a> {
+    /// Dhall code, like [`from_str()`] reads.
     Str(&'a str),
+    /// A file of Dhall code, like [`from_file()`] reads.
     File(PathBuf),
+    /// A file in the binary format, like [`from_binary_file()`] reads.
     BinaryFile(PathBuf),
+    /// A value that was already read, and thus typechecked.
+    Value(Value),
     // Url(&'a str),
 }
 
+/// How [`merge_sources()`] combines its sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// The `⫽` operator: the fields of a source replace the fields of the same name in the
+    /// sources before it, even when they are records.
+    Prefer,
+    /// The `∧` operator: the fields that are records in several sources are merged in turn, and
+    /// any other field set by several sources is an error.
+    Combine,
+}
+
+/// What a [`Deserializer`] reads.
+#[derive(Debug, Clone)]
+enum Input<'a> {
+    Source(Source<'a>),
+    Merged(Vec<Source<'a>>, MergeStrategy),
+    /// An expression built in Rust, whose imports are relative to the current directory.
+    Expr(Expr),
+}
+
+/// The value read, with its lint warnings and, if asked for, the order of its record fields.
+type ParseOutput = (Value, Vec<Warning>, Option<FieldOrders>);
+
+type ImportCallbackFn = dyn Fn(&ImportRef, &ResolvedMeta);
+
+/// The callback set with [`Deserializer::on_import_resolved()`].
+#[derive(Clone)]
+struct ImportCallback(Rc<ImportCallbackFn>);
+
+impl fmt::Debug for ImportCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ImportCallback")
+    }
+}
+
+/// The files read while resolving the imports of a config, or `None` if it reads something that
+/// the cache can't check, like an environment variable.
+type Dependencies = Rc<RefCell<Option<Vec<PathBuf>>>>;
+
 /// Controls how a Dhall value is read.
 ///
 /// This builder exposes the ability to configure how a value is deserialized and what operations
@@ -56,23 +113,53 @@ enum Source<'a> {
 /// ```
 #[derive(Debug, Clone)]
 pub struct Deserializer<'a, A> {
-    source: Source<'a>,
+    source: Input<'a>,
     annot: A,
     allow_imports: bool,
     builtins: HashMap<dhall::syntax::Label, dhall::syntax::Expr>,
+    treat_missing_as_none: bool,
+    max_depth: Option<usize>,
+    expected_list_len: Option<usize>,
+    max_normal_form_nodes: Option<usize>,
+    coerce_numbers: bool,
+    union_tag: Option<String>,
+    on_import_resolved: Option<ImportCallback>,
+    parallel_imports: bool,
+    canonicalize_imports: bool,
+    /// The fields to go through to get to the value to read.
+    path: Vec<String>,
+    substitute_env_vars: bool,
+    normalize: bool,
+    preserve_field_order: bool,
     // allow_remote_imports: bool,
-    // use_cache: bool,
+    cache: Option<PersistentCache>,
 }
 
 impl<'a> Deserializer<'a, NoAnnot> {
     fn default_with_source(source: Source<'a>) -> Self {
+        Self::default_with_input(Input::Source(source))
+    }
+    fn default_with_input(source: Input<'a>) -> Self {
         Deserializer {
             source,
             annot: NoAnnot,
             allow_imports: true,
             builtins: HashMap::new(),
+            treat_missing_as_none: false,
+            max_depth: None,
+            expected_list_len: None,
+            max_normal_form_nodes: None,
+            coerce_numbers: false,
+            union_tag: None,
+            on_import_resolved: None,
+            parallel_imports: true,
+            canonicalize_imports: false,
+            path: Vec::new(),
+            substitute_env_vars: false,
+            normalize: true,
+            preserve_field_order: false,
             // allow_remote_imports: true,
-            // use_cache: true,
+            cache: None,
         }
     }
     fn from_str(s: &'a str) -> Self {
@@ -84,6 +171,12 @@ impl<'a> Deserializer<'a, NoAnnot> {
     fn from_binary_file<P: AsRef<Path>>(path: P) -> Self {
         Self::default_with_source(Source::BinaryFile(path.as_ref().to_owned()))
     }
+    fn from_value(value: Value) -> Self {
+        Self::default_with_source(Source::Value(value))
+    }
+    pub(crate) fn from_expr(expr: Expr) -> Self {
+        Self::default_with_input(Input::Expr(expr))
+    }
     // fn from_url(url: &'a str) -> Self {
     //     Self::default_with_source(Source::Url(url))
     // }
@@ -130,12 +223,36 @@ impl<'a> Deserializer<'a, NoAnnot> {
         self,
         ty: &'ty SimpleType,
     ) -> Deserializer<'a, ManualAnnot<'ty>> {
-        Deserializer {
-            annot: ManualAnnot(ty),
-            source: self.source,
-            allow_imports: self.allow_imports,
-            builtins: self.builtins,
-        }
+        self.with_annot(ManualAnnot(ty))
+    }
+
+    /// Ensures that the parsed value matches the type written in the file at `path`, like
+    /// [`type_annotation()`] with the type read from that file.
+    ///
+    /// The file is read when the value is, with the same options: e.g. it may only have imports
+    /// if [`imports()`] allows them, and relative imports are resolved from the directory of the
+    /// file.
+    ///
+    /// [`type_annotation()`]: Deserializer::type_annotation()
+    /// [`imports()`]: Deserializer::imports()
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use std::collections::HashMap;
+    ///
+    /// let point = serde_dhall::from_file("data.dhall")
+    ///     .type_annotation_from_file("schema.dhall")
+    ///     .parse::<HashMap<String, u64>>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn type_annotation_from_file<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> Deserializer<'a, FileAnnot> {
+        self.with_annot(FileAnnot(path.as_ref().to_owned()))
     }
 
     /// Ensures that the parsed value matches the type of `T`.
@@ -181,16 +298,35 @@ impl<'a> Deserializer<'a, NoAnnot> {
     /// [`StaticType`]: crate::StaticType
     /// [`type_annotation()`]: Deserializer::type_annotation()
     pub fn static_type_annotation(self) -> Deserializer<'a, StaticAnnot> {
+        self.with_annot(StaticAnnot)
+    }
+}
+
+impl<'a, A> Deserializer<'a, A> {
+    /// Keeps the other options, and sets the annotation.
+    fn with_annot<B>(self, annot: B) -> Deserializer<'a, B> {
         Deserializer {
-            annot: StaticAnnot,
+            annot,
             source: self.source,
             allow_imports: self.allow_imports,
             builtins: self.builtins,
+            treat_missing_as_none: self.treat_missing_as_none,
+            max_depth: self.max_depth,
+            expected_list_len: self.expected_list_len,
+            max_normal_form_nodes: self.max_normal_form_nodes,
+            coerce_numbers: self.coerce_numbers,
+            union_tag: self.union_tag,
+            on_import_resolved: self.on_import_resolved,
+            parallel_imports: self.parallel_imports,
+            canonicalize_imports: self.canonicalize_imports,
+            path: self.path,
+            substitute_env_vars: self.substitute_env_vars,
+            normalize: self.normalize,
+            preserve_field_order: self.preserve_field_order,
+            cache: self.cache,
         }
     }
-}
 
-impl<'a, A> Deserializer<'a, A> {
     /// Sets whether to enable imports.
     ///
     /// By default, imports are enabled.
@@ -202,19 +338,502 @@ impl<'a, A> Deserializer<'a, A> {
     /// use serde::Deserialize;
     /// use serde_dhall::SimpleType;
     ///
-    /// let data = "12 + ./other_file.dhall : Natural";
-    /// assert!(
-    ///     serde_dhall::from_str(data)
-    ///         .imports(false)
-    ///         .parse::<u64>()
-    ///         .is_err()
-    /// );
+    /// let data = "12 + ./other_file.dhall : Natural";
+    /// assert!(
+    ///     serde_dhall::from_str(data)
+    ///         .imports(false)
+    ///         .parse::<u64>()
+    ///         .is_err()
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn imports(self, imports: bool) -> Self {
+        Deserializer {
+            allow_imports: imports,
+            ..self
+        }
+    }
+
+    /// Sets whether record fields of `Optional` type may be left out of the parsed value.
+    ///
+    /// When enabled, a type annotation that expects a field of type `Optional T` will accept a
+    /// record that omits that field entirely; the corresponding Rust field will be `None`. Fields
+    /// that are present must still have the expected type.
+    ///
+    /// By default, missing fields are a type error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    /// use serde_dhall::StaticType;
+    ///
+    /// #[derive(Deserialize, StaticType)]
+    /// struct Point {
+    ///     x: u64,
+    ///     y: Option<u64>,
+    /// }
+    ///
+    /// let point = serde_dhall::from_str("{ x = 1 }")
+    ///     .static_type_annotation()
+    ///     .treat_missing_as_none(true)
+    ///     .parse::<Point>()?;
+    /// assert_eq!(point.y, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn treat_missing_as_none(self, treat_missing_as_none: bool) -> Self {
+        Deserializer {
+            treat_missing_as_none,
+            ..self
+        }
+    }
+
+    /// Sets the maximum nesting depth of the parsed value.
+    ///
+    /// Values nested more deeply than this, like records of records of records, will be rejected
+    /// instead of being handed to `serde`. This protects against stack overflows when reading
+    /// untrusted input. Scalars like `1` have depth 1, `[1]` has depth 2, `{ x = [1] }` has depth
+    /// 3, and so on.
+    ///
+    /// By default, there is no limit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use std::collections::HashMap;
+    ///
+    /// let data = "{ x = { y = 1 } }";
+    /// assert!(
+    ///     serde_dhall::from_str(data)
+    ///         .max_depth(2)
+    ///         .parse::<HashMap<String, HashMap<String, u64>>>()
+    ///         .is_err()
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_depth(self, max_depth: usize) -> Self {
+        Deserializer {
+            max_depth: Some(max_depth),
+            ..self
+        }
+    }
+
+    /// Checks that the value is a list of `len` elements before deserializing it.
+    ///
+    /// The length is that of the normalized list, so lists computed with e.g. `List/build` are
+    /// checked too. This catches a wrong number of elements in a config with a clear error,
+    /// instead of when the list is read into a fixed-size collection, or not at all.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = "[1, 2, 3]";
+    /// let xs = serde_dhall::from_str(data).expect_list_len(3).parse::<Vec<u64>>()?;
+    /// assert_eq!(xs, vec![1, 2, 3]);
+    ///
+    /// let err = serde_dhall::from_str(data)
+    ///     .expect_list_len(4)
+    ///     .parse::<Vec<u64>>()
+    ///     .unwrap_err();
+    /// assert_eq!(err.to_string(), "expected a list of 4 elements, found 3 elements");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn expect_list_len(self, len: usize) -> Self {
+        Deserializer {
+            expected_list_len: Some(len),
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of nodes that normalizing the input may evaluate.
+    ///
+    /// Normalization fails with an error as soon as it goes over this limit. This protects
+    /// against expressions like `Natural/fold` that compute huge values from a small input,
+    /// and would otherwise use up all the memory. Each list element, record field and byte of
+    /// text counts as a node. The nodes of intermediate values count too, so the limit should be
+    /// well above the size of the expected value.
+    ///
+    /// By default, there is no limit.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = "Natural/fold 1000000 (List Natural) (λ(xs : List Natural) → xs # [1]) ([] : List Natural)";
+    /// assert!(
+    ///     serde_dhall::from_str(data)
+    ///         .max_normal_form_nodes(100)
+    ///         .parse::<Vec<u64>>()
+    ///         .is_err()
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_normal_form_nodes(self, max_nodes: usize) -> Self {
+        Deserializer {
+            max_normal_form_nodes: Some(max_nodes),
+            ..self
+        }
+    }
+
+    /// Allows numbers to be read into Rust number types that don't match their Dhall type.
+    ///
+    /// `Natural` and `Integer` values can always be read into floating-point fields. Otherwise a
+    /// `Natural` like `1` is only read into unsigned integer fields, and an `Integer` like `+1`
+    /// into signed ones. With this option, either can be read into any integer field that it fits
+    /// in, and a `Double` with an integral value, like `2.0`, can also be read into an integer
+    /// field; a fractional `Double` is still an error. This only affects deserialization: values
+    /// must still match the type annotation, if any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let x = serde_dhall::from_str("2.0").coerce_numbers(true).parse::<u8>()?;
+    /// assert_eq!(x, 2);
+    ///
+    /// assert!(serde_dhall::from_str("2.0").parse::<u8>().is_err());
+    /// assert!(serde_dhall::from_str("2.5")
+    ///     .coerce_numbers(true)
+    ///     .parse::<u8>()
+    ///     .is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn coerce_numbers(self, coerce_numbers: bool) -> Self {
+        Deserializer {
+            coerce_numbers,
+            ..self
+        }
+    }
+
+    /// Reads unions whose alternative has a record payload as that record with an extra
+    /// `tag_field` holding the name of the alternative, for enums that use serde's internal
+    /// tagging, `#[serde(tag = "...")]`.
+    ///
+    /// Records that already have a tag field are read into internally tagged enums without
+    /// this option. With it, the same enum can also be read from a Dhall union, which can be
+    /// typechecked. Alternatives without payload are still read as their name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Debug, PartialEq, Deserialize)]
+    /// #[serde(tag = "kind")]
+    /// enum Server {
+    ///     Web { port: u64 },
+    ///     Db { path: String },
+    /// }
+    ///
+    /// let server: Server =
+    ///     serde_dhall::from_str("< Web : { port : Natural } >.Web { port = 80 }")
+    ///         .union_as_tagged("kind")
+    ///         .parse()?;
+    /// assert_eq!(server, Server::Web { port: 80 });
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn union_as_tagged(self, tag_field: &str) -> Self {
+        Deserializer {
+            union_tag: Some(tag_field.to_owned()),
+            ..self
+        }
+    }
+
+    /// Calls `f` each time an import gets resolved, e.g. to log or audit which files and URLs are
+    /// read. `f` receives the absolute location of the import and some [`ResolvedMeta`] data about
+    /// the result: its size, whether it came from a cache, and its hash. The callback only
+    /// observes the resolution; it cannot change the result.
+    ///
+    /// Imports of the same location are only fetched once; later imports are reported with
+    /// `from_cache` set.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = serde_dhall::from_file("foo.dhall")
+    ///     .on_import_resolved(|import, meta| {
+    ///         eprintln!("imported {} ({:?} bytes)", import, meta.size)
+    ///     })
+    ///     .parse::<u64>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_import_resolved(
+        self,
+        f: impl Fn(&ImportRef, &ResolvedMeta) + 'static,
+    ) -> Self {
+        Deserializer {
+            on_import_resolved: Some(ImportCallback(Rc::new(f))),
+            ..self
+        }
+    }
+
+    /// Sets whether the independent imports of each file, local and remote, are fetched in
+    /// parallel. This is on by default, and only has an effect with the `parallel-imports`
+    /// feature.
+    ///
+    /// Imports are still resolved one after the other once fetched, so import cycles and errors are
+    /// reported the same way whether this is on or not. Turn it off to avoid spawning threads, e.g.
+    /// when the imports are a few small local files, which are read faster than the threads start.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = serde_dhall::from_file("foo.dhall")
+    ///     .parallel_imports(false)
+    ///     .parse::<u64>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parallel_imports(self, parallel_imports: bool) -> Self {
+        Deserializer {
+            parallel_imports,
+            ..self
+        }
+    }
+
+    /// Sets whether the paths of local imports are canonicalized by the filesystem before they
+    /// are read: symlinks are followed and `..` segments collapsed. This is off by default.
+    ///
+    /// Imports of the same file are only read once. Without this, a file imported through a
+    /// symlink and through its real path counts as two files.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = serde_dhall::from_file("foo.dhall")
+    ///     .canonicalize_imports(true)
+    ///     .parse::<u64>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn canonicalize_imports(self, canonicalize_imports: bool) -> Self {
+        Deserializer {
+            canonicalize_imports,
+            ..self
+        }
+    }
+
+    /// Only reads the value at `path` in the config, given as field names separated by dots like
+    /// `services.web.port`. This avoids defining structs just to reach a nested value. Field
+    /// names can be quoted with backticks like in Dhall, e.g. ``hosts.`example.com`.port``, to
+    /// contain dots. An empty path is the whole config.
+    ///
+    /// The whole config is evaluated, then the value is found in the result. A type annotation
+    /// applies to that value rather than to the whole config. Since the value is only found once
+    /// the config is evaluated, [`parse_ast()`] fails when a path is set.
+    ///
+    /// [`parse_ast()`]: Deserializer::parse_ast()
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = "{ services.web = { host = \"example.com\", port = 8080 } }";
+    /// let port = serde_dhall::from_str(data)
+    ///     .at_path("services.web.port")
+    ///     .parse::<u64>()?;
+    /// assert_eq!(port, 8080);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn at_path(self, path: &str) -> Self {
+        Deserializer {
+            path: split_path(path),
+            ..self
+        }
+    }
+
+    /// Replaces each `$ENV{NAME}` in the source with the value of the environment variable `NAME`,
+    /// before parsing. This is off by default.
+    ///
+    /// This is not part of the Dhall standard: it is a textual preprocessing step that runs
+    /// before the Dhall parser, and the source isn't valid Dhall for other tools. It is meant for
+    /// double-quoted text literals like `"http://${host}:$ENV{PORT}"`: values are escaped as
+    /// their contents. Only the source given to the deserializer is preprocessed, not the files
+    /// it imports, and not binary files. Reading a missing variable is an error that lists all
+    /// the missing variables.
+    ///
+    /// Standard Dhall reads environment variables with imports like `env:PORT as Text`, which
+    /// should be preferred when possible.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// std::env::set_var("GREETING", "Hello \"world\"");
+    /// let data = serde_dhall::from_str(r#"{ message = "$ENV{GREETING}!" }"#)
+    ///     .substitute_env_vars(true)
+    ///     .at_path("message")
+    ///     .parse::<String>()?;
+    /// assert_eq!(data, "Hello \"world\"!");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn substitute_env_vars(self, substitute_env_vars: bool) -> Self {
+        Deserializer {
+            substitute_env_vars,
+            ..self
+        }
+    }
+
+    /// Sets whether the value is normalized before it is read. This is on by default.
+    ///
+    /// When this is off, imports are still resolved and the value is still typechecked, but the
+    /// value is read as it is written. This is meant for tools that edit configs and need to keep
+    /// them as written, like formatters. Arithmetic, function applications, `let`s and
+    /// variables are then left unevaluated, and reading them fails for most types: `1 + 1` can't
+    /// be read as a number. Only literals like records, lists, text without interpolation and
+    /// numbers can be read. Types are still normalized, and so is the config when
+    /// [`at_path()`] is used.
+    ///
+    /// [`at_path()`]: Deserializer::at_path()
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = serde_dhall::from_str("{ x = 1, y = [ 2, 3 ] }")
+    ///     .normalize(false)
+    ///     .parse::<std::collections::HashMap<String, serde_dhall::SimpleValue>>()?;
+    /// assert_eq!(data.len(), 2);
+    ///
+    /// let sum = serde_dhall::from_str("1 + 1").normalize(false).parse::<u64>();
+    /// assert!(sum.is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn normalize(self, normalize: bool) -> Self {
+        Deserializer { normalize, ..self }
+    }
+
+    /// Sets whether the fields of records are given to serde in the order they are written in
+    /// the source, instead of sorted by name. This is off by default.
+    ///
+    /// This is for maps that keep their insertion order, like `IndexMap`. Dhall itself doesn't
+    /// keep the order of fields, so it is taken from the record literals and record types of the
+    /// source and its imports: a record gets the order of the first of them that has the same
+    /// fields. A record that only exists after evaluation, like the result of `//`, keeps
+    /// sorted order.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+    ///
+    /// // A map that keeps its insertion order.
+    /// struct Fields(Vec<(String, u64)>);
+    ///
+    /// impl<'de> Deserialize<'de> for Fields {
+    ///     fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    ///         struct FieldsVisitor;
+    ///         impl<'de> Visitor<'de> for FieldsVisitor {
+    ///             type Value = Fields;
+    ///             fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    ///                 write!(f, "a map")
+    ///             }
+    ///             fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Fields, A::Error> {
+    ///                 let mut fields = Vec::new();
+    ///                 while let Some(entry) = map.next_entry()? {
+    ///                     fields.push(entry);
+    ///                 }
+    ///                 Ok(Fields(fields))
+    ///             }
+    ///         }
+    ///         d.deserialize_map(FieldsVisitor)
+    ///     }
+    /// }
+    ///
+    /// let fields = serde_dhall::from_str("{ b = 1, a = 2 }")
+    ///     .preserve_field_order(true)
+    ///     .parse::<Fields>()?;
+    /// assert_eq!(fields.0, vec![("b".to_owned(), 1), ("a".to_owned(), 2)]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn preserve_field_order(self, preserve_field_order: bool) -> Self {
+        Deserializer {
+            preserve_field_order,
+            ..self
+        }
+    }
+
+    /// Sets whether to keep the normal form of the config on disk, so that the next runs of the
+    /// program read it back instead of evaluating the config again. This is off by default.
+    ///
+    /// The entries are stored in the dhall cache directory, next to the cached imports. An entry
+    /// is used as long as the config and the files it imports haven't changed; see
+    /// [`PersistentCache`] for the other cases where it is dropped. Configs that import
+    /// environment variables or remote files aren't cached, since those can change without
+    /// notice, nor are configs where an import alternative falls back on its right side, as in
+    /// `./a.dhall ? ./b.dhall` when `./a.dhall` is missing, since the cache would be stale once
+    /// `./a.dhall` is created. Neither are they with `normalize(false)` or
+    /// `preserve_field_order(true)`.
+    ///
+    /// When the config is read from the cache, its imports aren't resolved, so the callback set
+    /// with [`on_import_resolved()`] isn't called. If there is no cache directory, because
+    /// neither `XDG_CACHE_HOME` nor `HOME` is set, nothing is cached.
+    ///
+    /// [`on_import_resolved()`]: Deserializer::on_import_resolved()
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let data = serde_dhall::from_file("foo.dhall")
+    ///     .use_cache(true)
+    ///     .parse::<u64>()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn use_cache(self, use_cache: bool) -> Self {
+        let cache = if use_cache {
+            PersistentCache::in_default_dir().ok()
+        } else {
+            None
+        };
+        Deserializer { cache, ..self }
+    }
+
+    /// Like [`use_cache(true)`], with the entries kept in `cache`, e.g. to store them in another
+    /// directory or to give them a time-to-live.
+    ///
+    /// [`use_cache(true)`]: Deserializer::use_cache()
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::PersistentCache;
+    /// use std::path::Path;
+    /// use std::time::Duration;
+    ///
+    /// let cache = PersistentCache::new(Path::new("/var/cache/my-server"))
+    ///     .with_ttl(Duration::from_secs(24 * 60 * 60));
+    /// let data = serde_dhall::from_file("foo.dhall")
+    ///     .with_cache(cache)
+    ///     .parse::<u64>()?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn imports(self, imports: bool) -> Self {
+    pub fn with_cache(self, cache: PersistentCache) -> Self {
         Deserializer {
-            allow_imports: imports,
+            cache: Some(cache),
             ..self
         }
     }
@@ -237,8 +856,12 @@ impl<'a, A> Deserializer<'a, A> {
     /// Warning: the new builtins will only be accessible to the current file. If this file has
     /// imports, the imported values will not have access to the builtins.
     ///
+    /// The names of the real builtins, like `Natural` or `List`, cannot be used: [`parse()`], and
+    /// the other methods that read the source, will return an error if one of them is provided.
+    ///
     /// See also [`with_builtin_type()`].
     /// [`with_builtin_type()`]: Deserializer::with_builtin_type()
+    /// [`parse()`]: Deserializer::parse()
     ///
     /// # Example
     /// ```
@@ -285,8 +908,12 @@ impl<'a, A> Deserializer<'a, A> {
     /// Warning: the new builtins will only be accessible to the current file. If this file has
     /// imports, the imported values will not have access to the builtins.
     ///
+    /// The names of the real builtins, like `Natural` or `List`, cannot be used: [`parse()`], and
+    /// the other methods that read the source, will return an error if one of them is provided.
+    ///
     /// See also [`with_builtin_types()`].
     /// [`with_builtin_types()`]: Deserializer::with_builtin_types()
+    /// [`parse()`]: Deserializer::parse()
     ///
     /// # Example
     /// ```
@@ -315,37 +942,608 @@ impl<'a, A> Deserializer<'a, A> {
         self
     }
 
-    fn _parse<T>(&self) -> dhall::error::Result<Result<Value>>
+    fn parse_source(&self) -> Result<Parsed> {
+        match &self.source {
+            Input::Source(source) => self.parse_one(source),
+            Input::Merged(sources, strategy) => {
+                self.parse_merged(sources, *strategy)
+            }
+            Input::Expr(expr) => Ok(Parsed::from_expr(expr.clone())),
+        }
+    }
+
+    fn parse_one(&self, source: &Source<'_>) -> Result<Parsed> {
+        let parsed = match source {
+            Source::Str(s) if self.substitute_env_vars => {
+                Parsed::parse_str(&substitute_env_vars(s)?)
+            }
+            Source::File(p) if self.substitute_env_vars => {
+                let text = std::fs::read_to_string(p)
+                    .map_err(|e| ErrorKind::Dhall(e.into()))?;
+                Parsed::parse_file_contents(p, substitute_env_vars(&text)?)
+            }
+            Source::Str(s) => Parsed::parse_str(s),
+            Source::File(p) => Parsed::parse_file(p.as_ref()),
+            Source::BinaryFile(p) => Parsed::parse_binary_file(p.as_ref()),
+            Source::Value(v) => {
+                Ok(Parsed::from_expr_without_imports(v.to_expr()))
+            }
+        };
+        Ok(parsed.map_err(ErrorKind::Dhall)?)
+    }
+
+    /// Resolves the imports of each source, relative to where the source is, and combines the
+    /// results with `strategy`. The builtins are left to be added around the whole.
+    fn parse_merged(
+        &self,
+        sources: &[Source<'_>],
+        strategy: MergeStrategy,
+    ) -> Result<Parsed> {
+        let op = match strategy {
+            MergeStrategy::Prefer => BinOp::RightBiasedRecordMerge,
+            MergeStrategy::Combine => BinOp::RecursiveRecordMerge,
+        };
+        let empty = Expr::new(
+            ExprKind::RecordLit(Default::default()),
+            Span::Artificial,
+        );
+        let merged =
+            sources.iter().try_fold(None, |acc, source| -> Result<_> {
+                let parsed = self.parse_one(source)?;
+                let expr = Ctxt::with_new(|cx| {
+                    Ok(self.resolve_imports(cx, parsed, None)?.to_expr(cx))
+                })
+                .map_err(ErrorKind::Dhall)?;
+                Ok(Some(match acc {
+                    None => expr,
+                    Some(acc) => Expr::new(
+                        ExprKind::Op(OpKind::BinOp(op, acc, expr)),
+                        Span::Artificial,
+                    ),
+                }))
+            })?;
+        Ok(Parsed::from_expr_without_imports(merged.unwrap_or(empty)))
+    }
+
+    /// Binds the builtin types around `parsed`. Fails if one of them would shadow a real builtin.
+    fn add_builtins(&self, parsed: Parsed) -> Result<Parsed> {
+        if let Some(name) = dhall::builtins::builtin_names()
+            .iter()
+            .find(|name| self.builtins.contains_key(**name))
+        {
+            return Err(Error(ErrorKind::Deserialize(format!(
+                "cannot add builtin type `{}`: it would shadow the builtin of the same name",
+                name
+            ))));
+        }
+        Ok(self.builtins.iter().fold(parsed, |acc, (name, subst)| {
+            acc.add_let_binding(name.clone(), subst.clone())
+        }))
+    }
+
+    /// The key of the normal form of `parsed`, with its builtins, in the cache, if it can be
+    /// cached.
+    fn cache_key(
+        &self,
+        parsed: &Parsed,
+    ) -> Option<(&PersistentCache, [u8; 32])> {
+        let cache = self.cache.as_ref()?;
+        if !self.normalize || self.preserve_field_order {
+            return None;
+        }
+        // Relative imports depend on where the source is.
+        let location = match &self.source {
+            Input::Source(Source::Str(_)) => PathBuf::new(),
+            Input::Source(Source::File(p))
+            | Input::Source(Source::BinaryFile(p)) => p.clone(),
+            Input::Source(Source::Value(_))
+            | Input::Merged(..)
+            | Input::Expr(_) => return None,
+        };
+        let expr = parsed.to_expr();
+        let mut data = dhall::syntax::binary::encode(&expr).ok()?;
+        for path in &[std::env::current_dir().ok()?, location] {
+            data.extend(path.to_string_lossy().as_bytes());
+            data.push(0);
+        }
+        data.push(self.allow_imports as u8);
+        let mut key = [0; 32];
+        key.copy_from_slice(&dhall::utils::sha256_hash(&data));
+        Some((cache, key))
+    }
+
+    /// Resolves imports. The local files that are read are added to `dependencies`, if given.
+    fn resolve_imports<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+        parsed: Parsed,
+        dependencies: Option<&Dependencies>,
+    ) -> dhall::error::Result<Resolved<'cx>> {
+        if !self.allow_imports {
+            return parsed.skip_resolve(cx);
+        }
+        let mut env = ImportEnv::new(cx)
+            .with_parallel_imports(self.parallel_imports)
+            .with_field_order(self.preserve_field_order)
+            .with_canonical_imports(self.canonicalize_imports);
+        let callback = self.on_import_resolved.clone();
+        if callback.is_some() || dependencies.is_some() {
+            let dependencies = dependencies.cloned();
+            env = env.with_callback(move |import, meta| {
+                if let Some(dependencies) = &dependencies {
+                    let mut dependencies = dependencies.borrow_mut();
+                    match import.local_path() {
+                        Some(path) => {
+                            if let Some(paths) = dependencies.as_mut() {
+                                paths.push(path.to_owned());
+                            }
+                        }
+                        None => *dependencies = None,
+                    }
+                }
+                if let Some(ImportCallback(f)) = &callback {
+                    f(import, meta);
+                }
+            });
+        }
+        let resolved = parsed.resolve_with_env(&mut env)?;
+        if env.used_fallback() {
+            // With `./a.dhall ? ./b.dhall`, the result also depends on `./a.dhall` staying
+            // missing, which the cache can't check.
+            if let Some(dependencies) = dependencies {
+                *dependencies.borrow_mut() = None;
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn typecheck<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+        resolved: Resolved<'cx>,
+        annot: Option<SimpleType>,
+    ) -> dhall::error::Result<Typed<'cx>> {
+        Ok(match &annot {
+            None => resolved.typecheck(cx)?,
+            Some(ty) if self.treat_missing_as_none => {
+                // Drop the `Optional` fields that the value doesn't provide from the
+                // annotation, so that only the fields that are present get checked.
+                let inferred = resolved.typecheck(cx)?;
+                let ty = match SimpleType::from_nir(inferred.ty().as_nir()) {
+                    Ok(inferred_ty) => {
+                        ty.without_missing_optionals(&inferred_ty)
+                    }
+                    Err(_) => ty.clone(),
+                };
+                resolved.typecheck_with(cx, &ty.to_hir())?
+            }
+            Some(ty) => resolved.typecheck_with(cx, &ty.to_hir())?,
+        })
+    }
+
+    fn _parse<T>(&self, lint: bool) -> dhall::error::Result<Result<ParseOutput>>
     where
         A: TypeAnnot,
         T: HasAnnot<A>,
     {
+        let annot = match self.annot.annot_file() {
+            Some(path) => match self.load_type(path) {
+                Ok(ty) => Some(ty),
+                Err(e) => return Ok(Err(e)),
+            },
+            None => T::get_annot(self.annot.clone()),
+        };
+        if let (Input::Source(Source::Value(val)), None, true) = (
+            &self.source,
+            &annot,
+            self.path.is_empty() && self.builtins.is_empty(),
+        ) {
+            // The value was typechecked when it was read. With builtin types, the general path
+            // checks that they don't shadow real builtins.
+            return Ok(Ok((val.clone(), Vec::new(), None)));
+        }
+        let parsed = match self.parse_source() {
+            Ok(parsed) => parsed,
+            Err(e) => return Ok(Err(e)),
+        };
+        let warnings = if lint {
+            dhall::syntax::lint(&parsed.to_expr())
+        } else {
+            Vec::new()
+        };
+        let parsed = match self.add_builtins(parsed) {
+            Ok(parsed) => parsed,
+            Err(e) => return Ok(Err(e)),
+        };
+        let cache = self.cache_key(&parsed);
         Ctxt::with_new(|cx| {
-            let parsed = match &self.source {
-                Source::Str(s) => Parsed::parse_str(s)?,
-                Source::File(p) => Parsed::parse_file(p.as_ref())?,
-                Source::BinaryFile(p) => Parsed::parse_binary_file(p.as_ref())?,
+            let cached = cache.and_then(|(cache, key)| cache.get(&key));
+            // Where to store the normal form, if it isn't cached yet.
+            let uncached = if cached.is_none() { cache } else { None };
+            let dependencies: Dependencies =
+                Rc::new(RefCell::new(Some(Vec::new())));
+            let mut resolved = match cached {
+                Some(expr) => {
+                    Parsed::from_expr_without_imports(expr).skip_resolve(cx)?
+                }
+                None => self.resolve_imports(
+                    cx,
+                    parsed,
+                    uncached.map(|_| &dependencies),
+                )?,
+            };
+            let field_orders = if self.preserve_field_order {
+                Some(FieldOrders::new(
+                    dhall::semantics::FieldOrders::collect(
+                        cx,
+                        resolved.as_hir(),
+                    ),
+                    &self.path,
+                ))
+            } else {
+                None
+            };
+            if !self.path.is_empty() {
+                let normalized =
+                    self.normalize_typed(cx, &resolved.typecheck(cx)?)?;
+                store_in_cache(cx, uncached, &dependencies, &normalized);
+                let expr = match self.select_path(normalized.as_nir()) {
+                    Ok(nir) => {
+                        nir.to_hir_noenv().to_expr(cx, Default::default())
+                    }
+                    Err(e) => return Ok(Err(e)),
+                };
+                resolved =
+                    Parsed::from_expr_without_imports(expr).skip_resolve(cx)?;
+            }
+            let typed = self.typecheck(cx, resolved, annot)?;
+            let is_type =
+                matches!(typed.ty().as_nir().kind(), NirKind::Const(_));
+            let val = if self.normalize || is_type {
+                let normalized = self.normalize_typed(cx, &typed)?;
+                if self.path.is_empty() {
+                    store_in_cache(cx, uncached, &dependencies, &normalized);
+                }
+                Value::from_nir_and_ty(
+                    cx,
+                    normalized.as_nir(),
+                    typed.ty().as_nir(),
+                )
+            } else {
+                Value::from_expr_and_ty(
+                    &typed.as_hir().to_expr(cx, Default::default()),
+                    typed.ty().as_nir(),
+                )
             };
+            Ok(val.map(|val| (val, warnings, field_orders)))
+        })
+    }
 
-            let parsed_with_builtins =
-                self.builtins.iter().fold(parsed, |acc, (name, subst)| {
-                    acc.add_let_binding(name.clone(), subst.clone())
-                });
+    /// Reads the type in the file at `path`, with the options of this deserializer.
+    fn load_type(&self, path: &Path) -> Result<SimpleType>
+    where
+        A: Clone,
+    {
+        let mut de = self.clone().with_annot(NoAnnot);
+        de.source = Input::Source(Source::File(path.to_owned()));
+        de.path = Vec::new();
+        de.parse()
+    }
 
-            let resolved = if self.allow_imports {
-                parsed_with_builtins.resolve(cx)?
-            } else {
-                parsed_with_builtins.skip_resolve(cx)?
+    /// The annotation that doesn't depend on the deserialized type, if any.
+    fn manual_annot(&self) -> Result<Option<SimpleType>>
+    where
+        A: TypeAnnot,
+    {
+        match self.annot.annot_file() {
+            Some(path) => self.load_type(path).map(Some),
+            None => Ok(self.annot.clone().manual_annot()),
+        }
+    }
+
+    /// Normalizes `typed`, within the limit set with [`max_normal_form_nodes()`].
+    ///
+    /// [`max_normal_form_nodes()`]: Deserializer::max_normal_form_nodes()
+    fn normalize_typed<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+        typed: &Typed<'cx>,
+    ) -> dhall::error::Result<Normalized<'cx>> {
+        match self.max_normal_form_nodes {
+            Some(max_nodes) => typed.normalize_bounded(cx, max_nodes),
+            None => Ok(typed.normalize(cx)),
+        }
+    }
+
+    /// Finds the value at the path set with [`at_path()`] in the normalized config.
+    ///
+    /// [`at_path()`]: Deserializer::at_path()
+    fn select_path<'cx>(&self, mut nir: &Nir<'cx>) -> Result<Nir<'cx>> {
+        for (i, field) in self.path.iter().enumerate() {
+            let error = |msg: &str| {
+                let parent = show_path(&self.path[..i]);
+                let parent = if parent.is_empty() {
+                    "the config".to_owned()
+                } else {
+                    format!("`{}`", parent)
+                };
+                Error(ErrorKind::Deserialize(format!(
+                    "cannot read `{}`: {} {}",
+                    show_path(&self.path),
+                    parent,
+                    msg
+                )))
+            };
+            nir = match nir.kind() {
+                NirKind::RecordLit(kvs) => {
+                    kvs.get(&Label::from_str(field)).ok_or_else(|| {
+                        error(&format!("has no field `{}`", field))
+                    })?
+                }
+                _ => return Err(error("is not a record")),
+            };
+        }
+        Ok(nir.clone())
+    }
+
+    /// Replaces `resolved` with the value at the path set with [`at_path()`], if any. The value is
+    /// taken from the normal form, so the whole config is typechecked and normalized first.
+    ///
+    /// [`at_path()`]: Deserializer::at_path()
+    fn select_resolved<'cx>(
+        &self,
+        cx: Ctxt<'cx>,
+        resolved: Resolved<'cx>,
+    ) -> dhall::error::Result<Result<Resolved<'cx>>> {
+        if self.path.is_empty() {
+            return Ok(Ok(resolved));
+        }
+        let normalized = self.normalize_typed(cx, &resolved.typecheck(cx)?)?;
+        let expr = match self.select_path(normalized.as_nir()) {
+            Ok(nir) => nir.to_hir_noenv().to_expr(cx, Default::default()),
+            Err(e) => return Ok(Err(e)),
+        };
+        Ok(Ok(Parsed::from_expr_without_imports(expr).skip_resolve(cx)?))
+    }
+
+    /// Parses the chosen dhall expression without evaluating it.
+    ///
+    /// Like [`parse()`], this resolves imports and typechecks the expression. But instead of
+    /// normalizing it into a value, it returns the expression itself, with imports replaced by
+    /// their contents. This preserves the structure that normalization would erase, like `let`
+    /// bindings or function applications, which is useful e.g. for formatting tools.
+    ///
+    /// The expression is checked against a type given with [`type_annotation()`] or
+    /// [`type_annotation_from_file()`]. A static annotation can't be checked here since there is
+    /// no Rust type to take it from. This fails if a path was set with [`at_path()`], which needs
+    /// the config to be evaluated.
+    ///
+    /// [`parse()`]: Deserializer::parse()
+    /// [`at_path()`]: Deserializer::at_path()
+    /// [`type_annotation()`]: Deserializer::type_annotation()
+    /// [`type_annotation_from_file()`]: Deserializer::type_annotation_from_file()
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let expr = serde_dhall::from_str("let x = 1 in x + 1").parse_ast()?;
+    /// assert_eq!(expr.to_string(), "let x = 1 in x + 1");
+    ///
+    /// // `parse()` returns the normalized value instead.
+    /// let value = serde_dhall::from_str("let x = 1 in x + 1").parse::<u64>()?;
+    /// assert_eq!(value, 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse_ast(&self) -> Result<Expr>
+    where
+        A: TypeAnnot,
+    {
+        if !self.path.is_empty() {
+            return Err(Error(ErrorKind::Deserialize(format!(
+                "cannot read the expression at `{}`: paths are only found in the evaluated config",
+                show_path(&self.path)
+            ))));
+        }
+        let parsed = self.add_builtins(self.parse_source()?)?;
+        let annot = self.manual_annot()?;
+        let expr = Ctxt::with_new(|cx| {
+            let resolved = self.resolve_imports(cx, parsed, None)?;
+            let expr = resolved.to_expr(cx);
+            self.typecheck(cx, resolved, annot)?;
+            Ok(expr)
+        })
+        .map_err(ErrorKind::Dhall)
+        .map_err(Error)?;
+        Ok(self.remove_builtins(expr))
+    }
+
+    /// Returns the semantic hash of the chosen dhall value, like `sha256:1f2a...`.
+    ///
+    /// This resolves imports, typechecks and normalizes the expression like [`parse()`], but
+    /// doesn't read the result into Rust. The hash only depends on the normal form of the value,
+    /// not on how it is written or on the names of bound variables, so it can be compared
+    /// between deployments to detect changes in a configuration. It is the hash used to protect
+    /// imports: `./config.dhall sha256:...` accepts the file whose fingerprint is the given hash.
+    ///
+    /// The expression is checked against a type given with [`type_annotation()`] or
+    /// [`type_annotation_from_file()`], but not against a static annotation. Like for
+    /// [`parse()`], normalization stops at the limit set with [`max_normal_form_nodes()`], and
+    /// with [`at_path()`] the fingerprint is that of the value at the path.
+    ///
+    /// [`parse()`]: Deserializer::parse()
+    /// [`max_normal_form_nodes()`]: Deserializer::max_normal_form_nodes()
+    /// [`at_path()`]: Deserializer::at_path()
+    /// [`type_annotation()`]: Deserializer::type_annotation()
+    /// [`type_annotation_from_file()`]: Deserializer::type_annotation_from_file()
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let a = serde_dhall::from_str("{ port = 8000 + 80 }").fingerprint()?;
+    /// let b = serde_dhall::from_str("let p = 8080 in { port = p }").fingerprint()?;
+    /// assert_eq!(a, b);
+    /// assert!(a.starts_with("sha256:"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fingerprint(&self) -> Result<String>
+    where
+        A: TypeAnnot,
+    {
+        let parsed = self.add_builtins(self.parse_source()?)?;
+        let annot = self.manual_annot()?;
+        Ctxt::with_new(|cx| {
+            let resolved = self.resolve_imports(cx, parsed, None)?;
+            let resolved = match self.select_resolved(cx, resolved)? {
+                Ok(resolved) => resolved,
+                Err(e) => return Ok(Err(e)),
+            };
+            let typed = self.typecheck(cx, resolved, annot)?;
+            Ok(Ok(self.normalize_typed(cx, &typed)?.fingerprint(cx)?))
+        })
+        .map_err(ErrorKind::Dhall)
+        .map_err(Error)?
+    }
+
+    /// Checks that the alternatives of the chosen dhall union are exactly the variants of the
+    /// enum `T`, without reading a value.
+    ///
+    /// The union is the type of the chosen value, or the value itself if it is a union type. Only
+    /// the names of the alternatives are compared: this catches a variant that was added to one
+    /// side but not the other once, instead of when a value that uses it is read. Types given
+    /// with [`type_annotation()`] or [`type_annotation_from_file()`] are checked as usual.
+    ///
+    /// [`type_annotation()`]: Deserializer::type_annotation()
+    /// [`type_annotation_from_file()`]: Deserializer::type_annotation_from_file()
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde::Deserialize;
+    /// use serde_dhall::StaticType;
+    ///
+    /// #[derive(Deserialize, StaticType)]
+    /// enum Mode {
+    ///     Debug,
+    ///     Release,
+    /// }
+    ///
+    /// serde_dhall::from_str("< Debug | Release >").check_enum_exhaustive::<Mode>()?;
+    /// serde_dhall::from_str("< Debug | Release >.Debug").check_enum_exhaustive::<Mode>()?;
+    ///
+    /// let err = serde_dhall::from_str("< Debug | Release | Profile >")
+    ///     .check_enum_exhaustive::<Mode>()
+    ///     .unwrap_err();
+    /// assert!(err.to_string().contains("`Profile`"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn check_enum_exhaustive<T: StaticType>(&self) -> Result<()>
+    where
+        A: TypeAnnot,
+    {
+        let variants = match T::static_type() {
+            SimpleType::Union(kts) => kts,
+            ty => {
+                return Err(Error(ErrorKind::Deserialize(format!(
+                    "expected an enum, found a type that reads `{}`",
+                    ty
+                ))))
+            }
+        };
+        let parsed = self.add_builtins(self.parse_source()?)?;
+        let annot = self.manual_annot()?;
+        let ty = Ctxt::with_new(|cx| {
+            let resolved = self.resolve_imports(cx, parsed, None)?;
+            let resolved = match self.select_resolved(cx, resolved)? {
+                Ok(resolved) => resolved,
+                Err(e) => return Ok(Err(e)),
+            };
+            let typed = self.typecheck(cx, resolved, annot)?;
+            let ty = match typed.ty().as_nir().kind() {
+                NirKind::Const(_) => SimpleType::from_nir(
+                    self.normalize_typed(cx, &typed)?.as_nir(),
+                ),
+                _ => SimpleType::from_nir(typed.ty().as_nir()),
             };
-            let typed = match &T::get_annot(self.annot) {
-                None => resolved.typecheck(cx)?,
-                Some(ty) => resolved.typecheck_with(cx, &ty.to_hir())?,
+            Ok(Ok(ty.ok()))
+        })
+        .map_err(ErrorKind::Dhall)
+        .map_err(Error)??;
+        let alternatives = match ty {
+            Some(SimpleType::Union(kts)) => kts,
+            _ => {
+                return Err(Error(ErrorKind::Deserialize(
+                    "the config is not a union".to_owned(),
+                )))
+            }
+        };
+        let names =
+            |kts: &HashMap<String, Option<SimpleType>>,
+             others: &HashMap<String, Option<SimpleType>>| {
+                let mut names: Vec<_> = kts
+                    .keys()
+                    .filter(|k| !others.contains_key(*k))
+                    .map(|k| format!("`{}`", k))
+                    .collect();
+                names.sort();
+                names.join(", ")
             };
-            Ok(Value::from_nir_and_ty(
-                cx,
-                typed.normalize(cx).as_nir(),
-                typed.ty().as_nir(),
-            ))
+        let extra = names(&alternatives, &variants);
+        let missing = names(&variants, &alternatives);
+        let mut problems = Vec::new();
+        if !extra.is_empty() {
+            problems
+                .push(format!("the union has extra alternatives {}", extra));
+        }
+        if !missing.is_empty() {
+            problems
+                .push(format!("the union is missing the variants {}", missing));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error(ErrorKind::Deserialize(format!(
+                "the enum doesn't match the union: {}",
+                problems.join(", and ")
+            ))))
+        }
+    }
+
+    /// Returns the chosen dhall source with the hash of each remote import appended, like `dhall
+    /// freeze`.
+    ///
+    /// This resolves imports to compute their hashes: an import like
+    /// `https://example.com/package.dhall` becomes `https://example.com/package.dhall sha256:...`.
+    /// Local imports, and imports that already have a hash, are left as they are. The source is
+    /// neither typechecked nor normalized, but it gets reformatted and its comments are lost.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// let frozen = serde_dhall::from_str("let x = 1 in x + 1").freeze()?;
+    /// assert_eq!(frozen, "let x = 1 in x + 1");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn freeze(&self) -> Result<String> {
+        let parsed = self.add_builtins(self.parse_source()?)?;
+        let expr = Ctxt::with_new(|cx| parsed.freeze(cx, false))
+            .map_err(ErrorKind::Dhall)
+            .map_err(Error)?;
+        Ok(self.remove_builtins(expr).to_string())
+    }
+
+    /// Removes the let-bindings added for the builtins.
+    fn remove_builtins(&self, expr: Expr) -> Expr {
+        (0..self.builtins.len()).fold(expr, |expr, _| match expr.kind() {
+            ExprKind::Let(_, _, _, body) => body.clone(),
+            _ => expr,
         })
     }
 
@@ -370,11 +1568,177 @@ impl<'a, A> Deserializer<'a, A> {
         A: TypeAnnot,
         T: FromDhall + HasAnnot<A>,
     {
-        let val = self
-            ._parse::<T>()
+        self.parse_and_lint(false).map(|(x, _)| x)
+    }
+
+    /// Parses the chosen dhall value like [`parse()`], and also returns warnings about patterns
+    /// in the source that could be written better, like unused `let` bindings. See [`Warning`]
+    /// for the list of checks.
+    ///
+    /// Only the source itself is checked, not the files it imports. Warnings are only returned
+    /// if the value is successfully parsed.
+    ///
+    /// [`parse()`]: Deserializer::parse()
+    /// [`Warning`]: crate::Warning
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::WarningKind;
+    ///
+    /// let (data, warnings) = serde_dhall::from_str("let x = 1 in 6 * 7")
+    ///     .parse_with_warnings::<u64>()?;
+    /// assert_eq!(data, 42);
+    /// assert_eq!(warnings[0].kind, WarningKind::UnusedLet);
+    /// assert_eq!(warnings[0].message, "unused let binding `x`");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn parse_with_warnings<T>(&self) -> Result<(T, Vec<Warning>)>
+    where
+        A: TypeAnnot,
+        T: FromDhall + HasAnnot<A>,
+    {
+        self.parse_and_lint(true)
+    }
+
+    fn parse_and_lint<T>(&self, lint: bool) -> Result<(T, Vec<Warning>)>
+    where
+        A: TypeAnnot,
+        T: FromDhall + HasAnnot<A>,
+    {
+        let (val, warnings, field_orders) = self
+            ._parse::<T>(lint)
             .map_err(ErrorKind::Dhall)
             .map_err(Error)??;
-        T::from_dhall(&val)
+        if let Some(max_depth) = self.max_depth {
+            if val.exceeds_depth(max_depth) {
+                return Err(Error(ErrorKind::Deserialize(format!(
+                    "the value is nested more than {} levels deep",
+                    max_depth
+                ))));
+            }
+        }
+        if let Some(len) = self.expected_list_len {
+            let elements = |n: usize| {
+                format!("{} element{}", n, if n == 1 { "" } else { "s" })
+            };
+            let found = match val.list_len() {
+                Some(found) if found == len => None,
+                Some(found) => Some(elements(found)),
+                None => Some("a value that is not a list".to_owned()),
+            };
+            if let Some(found) = found {
+                return Err(Error(ErrorKind::Deserialize(format!(
+                    "expected a list of {}, found {}",
+                    elements(len),
+                    found
+                ))));
+            }
+        }
+        let x = T::from_dhall_with(
+            &val,
+            self.coerce_numbers,
+            self.union_tag.as_deref(),
+            field_orders.as_ref(),
+        )?;
+        Ok((x, warnings))
+    }
+}
+
+/// Splits a path given to [`Deserializer::at_path()`] into field names. Dots inside backticks
+/// are part of the field name, and the backticks are removed.
+fn split_path(path: &str) -> Vec<String> {
+    if path.is_empty() {
+        return Vec::new();
+    }
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in path.chars() {
+        match c {
+            '`' => quoted = !quoted,
+            '.' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Writes a path back like it would be given to [`Deserializer::at_path()`], quoting the field
+/// names that need it.
+fn show_path(path: &[String]) -> String {
+    path.iter()
+        .map(|field| Label::from_str(field).to_quoted_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Replaces each `$ENV{NAME}` in `s` with the value of the environment variable `NAME`, escaped
+/// for a double-quoted text literal. See [`Deserializer::substitute_env_vars()`].
+fn substitute_env_vars(s: &str) -> Result<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut missing: Vec<&str> = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("$ENV{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + "$ENV{".len()..];
+        let name = after.find('}').map(|end| &after[..end]).filter(|name| {
+            !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        let name = match name {
+            Some(name) => name,
+            None => {
+                // Not a variable: keep the text as it is.
+                out.push_str("$ENV{");
+                rest = after;
+                continue;
+            }
+        };
+        match std::env::var(name) {
+            Ok(value) => {
+                // Print the value as a text literal and keep what's between the quotes.
+                let literal =
+                    SimpleValue::Text(value).to_expr(None)?.to_string();
+                out.push_str(&literal[1..literal.len() - 1]);
+            }
+            Err(_) => {
+                if !missing.contains(&name) {
+                    missing.push(name);
+                }
+            }
+        }
+        rest = &after[name.len() + 1..];
+    }
+    out.push_str(rest);
+    if !missing.is_empty() {
+        return Err(Error(ErrorKind::Deserialize(format!(
+            "missing environment variables: {}",
+            missing.join(", ")
+        ))));
+    }
+    Ok(out)
+}
+
+/// Stores `normalized` in `cache`, if it's given, unless it depends on something the cache can't
+/// check.
+fn store_in_cache<'cx>(
+    cx: Ctxt<'cx>,
+    cache: Option<(&PersistentCache, [u8; 32])>,
+    dependencies: &Dependencies,
+    normalized: &Normalized<'cx>,
+) {
+    if let (Some((cache, key)), Some(paths)) = (cache, &*dependencies.borrow())
+    {
+        // This is only a cache, so failing to write it is fine.
+        let _ = cache.insert_with_dependencies(
+            &key,
+            &normalized.to_expr(cx),
+            paths,
+        );
     }
 }
 
@@ -481,6 +1845,127 @@ pub fn from_binary_file<'a, P: AsRef<Path>>(
     Deserializer::from_binary_file(path)
 }
 
+/// Reads a CBOR-encoded binary file and renders it in CBOR diagnostic notation, a readable form
+/// close to JSON, without interpreting it as a Dhall expression. This is for debugging binary
+/// files that [`from_binary_file`] fails to decode, like corrupt cache files.
+///
+/// # Example
+///
+/// ```no_run
+/// # fn main() -> serde_dhall::Result<()> {
+/// // `True` is encoded as a plain CBOR boolean.
+/// let diagnostic = serde_dhall::binary_diagnostic("foo.dhallb")?;
+/// assert_eq!(diagnostic, "true");
+/// # Ok(())
+/// # }
+/// ```
+pub fn binary_diagnostic<P: AsRef<Path>>(path: P) -> Result<String> {
+    let data = std::fs::read(path).map_err(|e| ErrorKind::Dhall(e.into()))?;
+    dhall::syntax::binary::diagnostic(&data)
+        .map_err(|e| ErrorKind::Dhall(e.into()).into())
+}
+
+/// Deserialize a value from a [`Value`] that was already read, e.g. to read a configuration once
+/// and then extract different Rust types from it.
+///
+/// This returns a [`Deserializer`] object. Call the [`parse()`] method to get the deserialized
+/// value, or use other [`Deserializer`] methods to control the deserialization process.
+///
+/// The value is not parsed nor typechecked again, unless it is given a type annotation to check.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::Deserialize;
+/// use serde_dhall::{SimpleValue, StaticType, Value};
+///
+/// #[derive(Deserialize, StaticType)]
+/// struct Point {
+///     x: u64,
+///     y: u64,
+/// }
+///
+/// // Look at the value before choosing how to read it.
+/// let value: Value = serde_dhall::from_str("{ x = 1, y = 2 }").parse()?;
+/// if let SimpleValue::Record(fields) = serde_dhall::from_dhall_value(&value)? {
+///     assert!(fields.contains_key("x"));
+/// }
+///
+/// let point: Point = serde_dhall::from_value(value)
+///     .static_type_annotation()
+///     .parse()?;
+/// assert_eq!(point.y, 2);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`parse()`]: Deserializer::parse()
+pub fn from_value(value: Value) -> Deserializer<'static, NoAnnot> {
+    Deserializer::from_value(value)
+}
+
+/// Reads several sources as one, for layered configurations: the results of the sources are
+/// combined in order with `strategy`, so that each one overrides or extends the ones before it.
+/// An empty list of sources gives the empty record.
+///
+/// The imports of each source are resolved relative to where it is, with the options of the
+/// returned [`Deserializer`]. The combined value is then typechecked once, against the type
+/// annotation if there is one. This is like combining the sources with `⫽` or `∧` in Dhall,
+/// without having to write that code.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde::Deserialize;
+/// use serde_dhall::{MergeStrategy, Source, StaticType};
+///
+/// #[derive(Debug, PartialEq, Deserialize, StaticType)]
+/// struct Config {
+///     host: String,
+///     port: u64,
+/// }
+///
+/// let sources = [
+///     Source::Str("{ host = \"localhost\", port = 80 }"),
+///     Source::Str("{ port = 8080 }"),
+/// ];
+/// let config: Config = serde_dhall::merge_sources(&sources, MergeStrategy::Prefer)
+///     .static_type_annotation()
+///     .parse()?;
+/// assert_eq!(config, Config { host: "localhost".to_owned(), port: 8080 });
+/// # Ok(())
+/// # }
+/// ```
+pub fn merge_sources<'a>(
+    sources: &[Source<'a>],
+    strategy: MergeStrategy,
+) -> Deserializer<'a, NoAnnot> {
+    Deserializer::default_with_input(Input::Merged(sources.to_vec(), strategy))
+}
+
+/// Deserializes a [`Value`] that was already read into a Rust type. This is like
+/// `from_value(value.clone()).parse()`, without a clone.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use std::collections::HashMap;
+/// use serde_dhall::Value;
+///
+/// let value: Value = serde_dhall::from_str("{ x = 1, y = 2 }").parse()?;
+///
+/// let map: HashMap<String, u64> = serde_dhall::from_dhall_value(&value)?;
+/// assert_eq!(map["y"], 2);
+/// # Ok(())
+/// # }
+/// ```
+pub fn from_dhall_value<T: FromDhall>(value: &Value) -> Result<T> {
+    T::from_dhall(value)
+}
+
 // pub fn from_url(url: &str) -> Deserializer<'_, NoAnnot> {
 //     Deserializer::from_url(url)
 // }
@@ -1,3 +1,5 @@
+use std::path::{Path, PathBuf};
+
 use crate::{SimpleType, StaticType};
 
 pub(crate) mod de;
@@ -9,15 +11,36 @@ pub struct NoAnnot;
 pub struct ManualAnnot<'ty>(&'ty SimpleType);
 #[derive(Debug, Clone, Copy)]
 pub struct StaticAnnot;
+/// The annotation is the type in the given file.
+#[derive(Debug, Clone)]
+pub struct FileAnnot(PathBuf);
 
-pub trait TypeAnnot: Copy {}
+pub trait TypeAnnot: Clone {
+    /// The annotation when it doesn't depend on the deserialized type.
+    fn manual_annot(self) -> Option<SimpleType> {
+        None
+    }
+    /// The file to read the annotation from, if it has to be read.
+    fn annot_file(&self) -> Option<&Path> {
+        None
+    }
+}
 pub trait HasAnnot<A: TypeAnnot> {
     fn get_annot(a: A) -> Option<SimpleType>;
 }
 
 impl TypeAnnot for NoAnnot {}
-impl TypeAnnot for ManualAnnot<'_> {}
+impl TypeAnnot for ManualAnnot<'_> {
+    fn manual_annot(self) -> Option<SimpleType> {
+        Some(self.0.clone())
+    }
+}
 impl TypeAnnot for StaticAnnot {}
+impl TypeAnnot for FileAnnot {
+    fn annot_file(&self) -> Option<&Path> {
+        Some(&self.0)
+    }
+}
 
 impl<T> HasAnnot<NoAnnot> for T {
     fn get_annot(_: NoAnnot) -> Option<SimpleType> {
@@ -29,6 +52,12 @@ impl<T> HasAnnot<ManualAnnot<'_>> for T {
         Some(a.0.clone())
     }
 }
+/// The file is read by the deserializer, with its options.
+impl<T> HasAnnot<FileAnnot> for T {
+    fn get_annot(_: FileAnnot) -> Option<SimpleType> {
+        None
+    }
+}
 impl<T: StaticType> HasAnnot<StaticAnnot> for T {
     fn get_annot(_: StaticAnnot) -> Option<SimpleType> {
         Some(T::static_type())
@@ -160,7 +160,9 @@ where
     where
         T: ToDhall + HasAnnot<A>,
     {
-        let val = self.data.to_dhall(T::get_annot(self.annot).as_ref())?;
+        let val = self
+            .data
+            .to_dhall(T::get_annot(self.annot.clone()).as_ref())?;
         Ok(val.to_string())
     }
 }
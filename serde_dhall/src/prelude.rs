@@ -0,0 +1,322 @@
+//! Functions of the [Dhall Prelude], to compute with [`Value`]s from Rust.
+//!
+//! Each function is grouped with the others of its Prelude package, like `List/map` which is
+//! [`List::map()`]. The functions are the definitions of the Prelude, shipped with this crate and
+//! applied to the values directly, so they behave exactly like the Prelude. The type arguments of
+//! the Prelude functions are taken from the types of the values, and function arguments are
+//! values too, read like any other.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> serde_dhall::Result<()> {
+//! use serde_dhall::prelude::List;
+//! use serde_dhall::{from_str, Value};
+//!
+//! let xs: Value = from_str("[1, 2, 3]").parse()?;
+//! let double: Value = from_str("λ(n : Natural) → n * 2").parse()?;
+//! let doubled = List::map(&double, &xs)?;
+//! assert_eq!(doubled.to_string(), "[2, 4, 6]");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [Dhall Prelude]: https://prelude.dhall-lang.org
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use dhall::operations::OpKind;
+use dhall::semantics::{
+    typecheck, Hir, HirKind, ImportEnv, ImportResolver, TyEnv,
+};
+use dhall::syntax::{ExprKind, Span};
+use dhall::{Ctxt, Parsed};
+
+use crate::{Error, ErrorKind, Result, SimpleType, Value};
+
+/// The files of the Prelude that the functions below use, by their path in the Prelude. The
+/// imports between them are written without their integrity hashes, since they are read from
+/// here.
+const PRELUDE_FILES: &[(&str, &str)] = &[
+    ("Bool/and.dhall", include_str!("prelude/Bool/and.dhall")),
+    ("Bool/not.dhall", include_str!("prelude/Bool/not.dhall")),
+    ("Bool/or.dhall", include_str!("prelude/Bool/or.dhall")),
+    (
+        "List/concat.dhall",
+        include_str!("prelude/List/concat.dhall"),
+    ),
+    (
+        "List/filter.dhall",
+        include_str!("prelude/List/filter.dhall"),
+    ),
+    ("List/map.dhall", include_str!("prelude/List/map.dhall")),
+    ("List/null.dhall", include_str!("prelude/List/null.dhall")),
+    (
+        "List/replicate.dhall",
+        include_str!("prelude/List/replicate.dhall"),
+    ),
+    (
+        "Natural/greaterThanEqual.dhall",
+        include_str!("prelude/Natural/greaterThanEqual.dhall"),
+    ),
+    (
+        "Natural/lessThan.dhall",
+        include_str!("prelude/Natural/lessThan.dhall"),
+    ),
+    (
+        "Natural/lessThanEqual.dhall",
+        include_str!("prelude/Natural/lessThanEqual.dhall"),
+    ),
+    (
+        "Natural/max.dhall",
+        include_str!("prelude/Natural/max.dhall"),
+    ),
+    (
+        "Natural/min.dhall",
+        include_str!("prelude/Natural/min.dhall"),
+    ),
+    (
+        "Natural/sum.dhall",
+        include_str!("prelude/Natural/sum.dhall"),
+    ),
+    (
+        "Optional/default.dhall",
+        include_str!("prelude/Optional/default.dhall"),
+    ),
+    (
+        "Optional/map.dhall",
+        include_str!("prelude/Optional/map.dhall"),
+    ),
+    (
+        "Optional/null.dhall",
+        include_str!("prelude/Optional/null.dhall"),
+    ),
+    (
+        "Text/concatSep.dhall",
+        include_str!("prelude/Text/concatSep.dhall"),
+    ),
+    (
+        "Text/default.dhall",
+        include_str!("prelude/Text/default.dhall"),
+    ),
+];
+
+/// Where the files of the Prelude are seen to be, for the imports between them.
+const PRELUDE_ROOT: &str = "/Prelude";
+
+/// Functions of the `List` package.
+#[derive(Debug)]
+pub struct List;
+
+/// Functions of the `Optional` package.
+#[derive(Debug)]
+pub struct Optional;
+
+/// Functions of the `Natural` package.
+#[derive(Debug)]
+pub struct Natural;
+
+/// Functions of the `Text` package.
+#[derive(Debug)]
+pub struct Text;
+
+/// Functions of the `Bool` package.
+#[derive(Debug)]
+pub struct Bool;
+
+impl List {
+    /// `List/map`: applies the function `f` to each element of `xs`.
+    pub fn map(f: &Value, xs: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(list_element_type(xs)?);
+        let b = Value::from_simple_type(output_type(f)?);
+        apply("List/map.dhall", &[&a, &b, f, xs])
+    }
+
+    /// `List/filter`: keeps the elements of `xs` for which the function `f` returns `True`.
+    pub fn filter(f: &Value, xs: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(list_element_type(xs)?);
+        apply("List/filter.dhall", &[&a, f, xs])
+    }
+
+    /// `List/concat`: concatenates the lists of `xss`.
+    pub fn concat(xss: &Value) -> Result<Value> {
+        let a = match list_element_type(xss)? {
+            SimpleType::List(a) => Value::from_simple_type(*a),
+            ty => return Err(type_error("a list of lists", &ty)),
+        };
+        apply("List/concat.dhall", &[&a, xss])
+    }
+
+    /// `List/null`: whether `xs` is empty.
+    pub fn null(xs: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(list_element_type(xs)?);
+        apply("List/null.dhall", &[&a, xs])
+    }
+
+    /// `List/replicate`: the list with `n` copies of `x`.
+    pub fn replicate(n: &Value, x: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(value_type(x)?);
+        apply("List/replicate.dhall", &[n, &a, x])
+    }
+}
+
+impl Optional {
+    /// `Optional/default`: the value in `o`, or `default` if there is none.
+    pub fn default(default: &Value, o: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(optional_element_type(o)?);
+        apply("Optional/default.dhall", &[&a, default, o])
+    }
+
+    /// `Optional/map`: applies the function `f` to the value in `o`, if there is one.
+    pub fn map(f: &Value, o: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(optional_element_type(o)?);
+        let b = Value::from_simple_type(output_type(f)?);
+        apply("Optional/map.dhall", &[&a, &b, f, o])
+    }
+
+    /// `Optional/null`: whether `o` is `None`.
+    pub fn null(o: &Value) -> Result<Value> {
+        let a = Value::from_simple_type(optional_element_type(o)?);
+        apply("Optional/null.dhall", &[&a, o])
+    }
+}
+
+impl Natural {
+    /// `Natural/lessThan`: whether `x` is less than `y`.
+    pub fn less_than(x: &Value, y: &Value) -> Result<Value> {
+        apply("Natural/lessThan.dhall", &[x, y])
+    }
+
+    /// `Natural/max`: the larger of `a` and `b`.
+    pub fn max(a: &Value, b: &Value) -> Result<Value> {
+        apply("Natural/max.dhall", &[a, b])
+    }
+
+    /// `Natural/min`: the smaller of `a` and `b`.
+    pub fn min(a: &Value, b: &Value) -> Result<Value> {
+        apply("Natural/min.dhall", &[a, b])
+    }
+
+    /// `Natural/sum`: the sum of the numbers of `xs`.
+    pub fn sum(xs: &Value) -> Result<Value> {
+        apply("Natural/sum.dhall", &[xs])
+    }
+}
+
+impl Text {
+    /// `Text/concatSep`: concatenates the texts of `xs`, with `separator` between them.
+    pub fn concat_sep(separator: &Value, xs: &Value) -> Result<Value> {
+        apply("Text/concatSep.dhall", &[separator, xs])
+    }
+
+    /// `Text/default`: the text in `o`, or `""` if there is none.
+    pub fn default(o: &Value) -> Result<Value> {
+        apply("Text/default.dhall", &[o])
+    }
+}
+
+impl Bool {
+    /// `Bool/not`: the negation of `b`.
+    pub fn not(b: &Value) -> Result<Value> {
+        apply("Bool/not.dhall", &[b])
+    }
+
+    /// `Bool/and`: whether all the booleans of `xs` are `True`.
+    pub fn and(xs: &Value) -> Result<Value> {
+        apply("Bool/and.dhall", &[xs])
+    }
+
+    /// `Bool/or`: whether any of the booleans of `xs` is `True`.
+    pub fn or(xs: &Value) -> Result<Value> {
+        apply("Bool/or.dhall", &[xs])
+    }
+}
+
+/// Reads the files of `PRELUDE_FILES` as if they were in `PRELUDE_ROOT`.
+struct PreludeResolver;
+
+impl ImportResolver for PreludeResolver {
+    fn read_file(&self, path: &Path) -> dhall::error::Result<String> {
+        let name = path.strip_prefix(PRELUDE_ROOT).ok().and_then(|path| {
+            let names: Option<Vec<_>> =
+                path.iter().map(|name| name.to_str()).collect();
+            Some(names?.join("/"))
+        });
+        match PRELUDE_FILES
+            .iter()
+            .find(|(file, _)| Some(*file) == name.as_deref())
+        {
+            Some((_, text)) => Ok(text.to_string()),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` is not in the Prelude", path.display()),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Applies the Prelude function in the file `file` to `args`, and evaluates the result.
+fn apply(file: &str, args: &[&Value]) -> Result<Value> {
+    Ctxt::with_new(|cx| {
+        let path: PathBuf = [PRELUDE_ROOT, file].iter().collect();
+        let mut env = ImportEnv::new(cx).with_resolver(PreludeResolver);
+        let text = PreludeResolver.read_file(&path);
+        let function = text
+            .and_then(|text| Parsed::parse_file_contents(&path, text))
+            .and_then(|parsed| parsed.resolve_with_env(&mut env))
+            .map_err(ErrorKind::Dhall)?;
+        let mut hir = function.as_hir().clone();
+        for arg in args {
+            let app = OpKind::App(hir, arg.to_hir(cx)?);
+            hir = Hir::new(HirKind::Expr(ExprKind::Op(app)), Span::Artificial);
+        }
+        let tir =
+            typecheck(cx, &hir).map_err(|e| ErrorKind::Dhall(e.into()))?;
+        let value = tir.eval(&TyEnv::new(cx));
+        Value::from_nir_and_ty(cx, &value, tir.ty().as_nir())
+    })
+}
+
+fn value_type(x: &Value) -> Result<SimpleType> {
+    match x.ty() {
+        Some(ty) => Ok(ty.clone()),
+        None => Err(Error(ErrorKind::Deserialize(format!(
+            "the type of `{}` is not known",
+            x
+        )))),
+    }
+}
+
+fn list_element_type(xs: &Value) -> Result<SimpleType> {
+    match value_type(xs)? {
+        SimpleType::List(a) => Ok(*a),
+        ty => Err(type_error("a list", &ty)),
+    }
+}
+
+fn optional_element_type(o: &Value) -> Result<SimpleType> {
+    match value_type(o)? {
+        SimpleType::Optional(a) => Ok(*a),
+        ty => Err(type_error("an optional value", &ty)),
+    }
+}
+
+fn type_error(expected: &str, found: &SimpleType) -> Error {
+    Error(ErrorKind::Deserialize(format!(
+        "expected {}, found a value of type `{}`",
+        expected, found
+    )))
+}
+
+/// The type of the output of the function `f`.
+fn output_type(f: &Value) -> Result<SimpleType> {
+    match f.ty().and_then(SimpleType::output) {
+        Some(ty) => Ok(ty.clone()),
+        None => Err(Error(ErrorKind::Deserialize(format!(
+            "expected a function with a simple output type, found `{}`",
+            f
+        )))),
+    }
+}
@@ -1,5 +1,7 @@
 use serde::ser;
+use std::cell::{Cell, RefCell};
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
 
 use dhall::syntax::NumKind;
 
@@ -49,15 +51,46 @@ where
     T: ser::Serialize,
 {
     fn to_dhall(&self, ty: Option<&SimpleType>) -> Result<Value> {
-        let sval: SimpleValue = self.serialize(Serializer)?;
-        sval.into_value(ty)
+        /// Restores the previous `IN_SERIALIZER` when dropped.
+        struct Guard(bool);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                IN_SERIALIZER.with(|v| v.set(self.0));
+            }
+        }
+
+        let _guard = Guard(IN_SERIALIZER.with(|v| v.replace(true)));
+        // A `Value` is taken as is, with its own type.
+        let value = Cell::new(None);
+        let sval: SimpleValue = self.serialize(Serializer {
+            top_level_value: Some(&value),
+        })?;
+        match value.into_inner() {
+            Some(value) => Ok(value),
+            None => sval.into_value(ty),
+        }
     }
 }
 
+/// The name of the newtype struct that a [`Value`] serializes as, so that `Serializer` knows to
+/// take it as is.
+const VALUE_TOKEN: &str = "$serde_dhall::private::Value";
+
+thread_local! {
+    /// Whether a `Serializer` is running, i.e. whether a `Value` that is serialized should be
+    /// kept for it in `SERIALIZED_VALUE`. Other serializers only see a `PlainValue`.
+    static IN_SERIALIZER: Cell<bool> = Cell::new(false);
+    /// The `Value` that is being serialized into Dhall, if any.
+    static SERIALIZED_VALUE: RefCell<Option<Value>> = RefCell::new(None);
+}
+
 #[derive(Default, Clone, Copy)]
-struct Serializer;
+struct Serializer<'a> {
+    /// Where to put the value when the whole data is a `Value`.
+    top_level_value: Option<&'a Cell<Option<Value>>>,
+}
 
-impl ser::Serializer for Serializer {
+impl ser::Serializer for Serializer<'_> {
     type Ok = SimpleValue;
     type Error = Error;
 
@@ -85,6 +118,16 @@ impl ser::Serializer for Serializer {
     fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
         Ok(Num(NumKind::Integer(v)))
     }
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => Err(ErrorKind::Serialize(format!(
+                "Integer out of range for serialization: {}",
+                v
+            ))
+            .into()),
+        }
+    }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
         self.serialize_u64(u64::from(v))
@@ -98,6 +141,16 @@ impl ser::Serializer for Serializer {
     fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
         Ok(Num(NumKind::Natural(v)))
     }
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok> {
+        match u64::try_from(v) {
+            Ok(v) => self.serialize_u64(v),
+            Err(_) => Err(ErrorKind::Serialize(format!(
+                "Natural out of range for serialization: {}",
+                v
+            ))
+            .into()),
+        }
+    }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
         self.serialize_f64(f64::from(v))
@@ -127,7 +180,9 @@ impl ser::Serializer for Serializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        Ok(Optional(Some(Box::new(v.serialize(self)?))))
+        Ok(Optional(Some(Box::new(
+            v.serialize(Serializer::default())?,
+        ))))
     }
 
     fn serialize_unit(self) -> Result<Self::Ok> {
@@ -139,12 +194,30 @@ impl ser::Serializer for Serializer {
     }
     fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         _value: &T,
     ) -> Result<Self::Ok>
     where
         T: ?Sized + ser::Serialize,
     {
+        let value = match name {
+            VALUE_TOKEN => SERIALIZED_VALUE.with(|v| v.borrow_mut().take()),
+            _ => None,
+        };
+        if let Some(value) = value {
+            if let Some(top_level_value) = self.top_level_value {
+                top_level_value.set(Some(value));
+                return Ok(Record(Default::default()));
+            }
+            return value.to_simple_value().ok_or_else(|| {
+                ErrorKind::Serialize(format!(
+                    "{} can't be part of serialized data: {}",
+                    not_data_kind(&value),
+                    value
+                ))
+                .into()
+            });
+        }
         Err(ErrorKind::Serialize(
             "Unsupported data for serialization: newtype struct".to_owned(),
         )
@@ -176,7 +249,7 @@ impl ser::Serializer for Serializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let value = value.serialize(self)?;
+        let value = value.serialize(Serializer::default())?;
         Ok(Union(variant.to_owned(), Some(Box::new(value))))
     }
     fn serialize_tuple_variant(
@@ -238,7 +311,7 @@ impl ser::SerializeSeq for SeqSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        self.0.push(value.serialize(Serializer)?);
+        self.0.push(value.serialize(Serializer::default())?);
         Ok(())
     }
 
@@ -258,7 +331,7 @@ impl ser::SerializeTuple for TupleSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        self.0.push(value.serialize(Serializer)?);
+        self.0.push(value.serialize(Serializer::default())?);
         Ok(())
     }
 
@@ -288,7 +361,7 @@ impl ser::SerializeMap for MapSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let key = match key.serialize(Serializer)? {
+        let key = match key.serialize(Serializer::default())? {
             Text(key) => key,
             _ => return Err(<Error as ser::Error>::custom("not a string")),
         };
@@ -304,7 +377,7 @@ impl ser::SerializeMap for MapSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let val: SimpleValue = val.serialize(Serializer)?;
+        let val: SimpleValue = val.serialize(Serializer::default())?;
         if let Some(key) = self.key.take() {
             self.map.insert(key, val);
         } else {
@@ -329,7 +402,7 @@ impl ser::SerializeStruct for StructSerializer {
     where
         T: ?Sized + ser::Serialize,
     {
-        let val: SimpleValue = val.serialize(Serializer)?;
+        let val: SimpleValue = val.serialize(Serializer::default())?;
         self.0.insert(key.into(), val);
         Ok(())
     }
@@ -394,3 +467,104 @@ impl serde::ser::Serialize for SimpleValue {
         }
     }
 }
+
+/// Values are given as plain data: an optional value is the value or nothing, and a union
+/// alternative is a map with its name in `tag` and its payload, if any, in `value`. This lets
+/// other serde formats write them. Types can't be written this way.
+impl serde::ser::Serialize for Value {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        /// Restores the previous `SERIALIZED_VALUE` when dropped.
+        struct Guard(Option<Value>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                SERIALIZED_VALUE.with(|v| *v.borrow_mut() = self.0.take());
+            }
+        }
+
+        if !IN_SERIALIZER.with(|v| v.get()) {
+            return PlainValue(self).serialize(serializer);
+        }
+        let previous = SERIALIZED_VALUE.with(|v| v.replace(Some(self.clone())));
+        let _guard = Guard(previous);
+        serializer.serialize_newtype_struct(VALUE_TOKEN, &PlainValue(self))
+    }
+}
+
+/// What other serializers see of a `Value`.
+struct PlainValue<'a>(&'a Value);
+
+impl serde::ser::Serialize for PlainValue<'_> {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        match self.0.to_simple_value() {
+            Some(val) => PlainData(&val).serialize(serializer),
+            None => Err(ser::Error::custom(format!(
+                "{} can't be serialized as data: {}",
+                not_data_kind(self.0),
+                self.0
+            ))),
+        }
+    }
+}
+
+/// What a value that isn't a simple value is, for error messages.
+fn not_data_kind(value: &Value) -> &'static str {
+    match value.to_simple_type() {
+        Some(_) => "a type",
+        None => "a function",
+    }
+}
+
+/// Serializes a value with its unions as maps, as described on `impl Serialize for Value`.
+struct PlainData<'a>(&'a SimpleValue);
+
+impl serde::ser::Serialize for PlainData<'_> {
+    fn serialize<S>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        match self.0 {
+            List(xs) => {
+                let mut seq = serializer.serialize_seq(Some(xs.len()))?;
+                for x in xs {
+                    seq.serialize_element(&PlainData(x))?;
+                }
+                seq.end()
+            }
+            Optional(Some(x)) => serializer.serialize_some(&PlainData(x)),
+            Record(m) => {
+                let mut map = serializer.serialize_map(Some(m.len()))?;
+                for (k, v) in m {
+                    map.serialize_entry(k, &PlainData(v))?;
+                }
+                map.end()
+            }
+            Union(field_name, x) => {
+                let len = if x.is_some() { 2 } else { 1 };
+                let mut map = serializer.serialize_map(Some(len))?;
+                map.serialize_entry("tag", field_name)?;
+                if let Some(x) = x {
+                    map.serialize_entry("value", &PlainData(x))?;
+                }
+                map.end()
+            }
+            val => val.serialize(serializer),
+        }
+    }
+}
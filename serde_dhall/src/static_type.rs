@@ -77,11 +77,17 @@ macro_rules! derive_builtin {
 
 derive_builtin!(bool, Bool);
 derive_builtin!(usize, Natural);
+derive_builtin!(u128, Natural);
 derive_builtin!(u64, Natural);
 derive_builtin!(u32, Natural);
+derive_builtin!(u16, Natural);
+derive_builtin!(u8, Natural);
 derive_builtin!(isize, Integer);
+derive_builtin!(i128, Integer);
 derive_builtin!(i64, Integer);
 derive_builtin!(i32, Integer);
+derive_builtin!(i16, Integer);
+derive_builtin!(i8, Integer);
 derive_builtin!(f64, Double);
 derive_builtin!(f32, Double);
 derive_builtin!(String, Text);
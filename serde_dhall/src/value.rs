@@ -1,25 +1,32 @@
 use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
 use std::result::Result as StdResult;
 
 use dhall::builtins::Builtin;
 use dhall::operations::OpKind;
-use dhall::semantics::{Hir, HirKind, Nir, NirKind};
+use dhall::semantics::AlphaVar;
+use dhall::semantics::{Hir, HirKind, Nir, NirKind, NzVar};
 pub use dhall::syntax::NumKind;
-use dhall::syntax::{Expr, ExprKind, Span};
-use dhall::Ctxt;
+use dhall::syntax::{alpha_normalize, Const, Expr, ExprKind, Label, Span, V};
+use dhall::{Ctxt, Parsed};
 
-use crate::{Error, ErrorKind, FromDhall, Result, ToDhall};
+use crate::{Error, ErrorKind, FromDhall, Result};
 
 #[derive(Debug, Clone)]
 enum ValueKind {
     /// Invariant: the value must be printable with the given type.
     Val(SimpleValue, Option<SimpleType>),
     Ty(SimpleType),
+    /// A function with a simple type, like `λ(n : Natural) → n + 1`, in normal form.
+    Fn(Expr, SimpleType),
 }
 
 #[doc(hidden)]
 /// An arbitrary Dhall value.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Values are normalized when they are read, so equality is semantic rather than syntactic: the
+/// values read from `1 + 1` and from `2` are equal, and hash to the same value.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Value {
     kind: ValueKind,
 }
@@ -97,7 +104,7 @@ pub struct Value {
 /// [`from_str()`]: crate::from_str()
 /// [`from_file()`]: crate::from_file()
 /// [`from_simple_value()`]: crate::from_simple_value()
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum SimpleValue {
     /// Numbers and booleans - `True`, `1`, `+2`, `3.24`
     Num(NumKind),
@@ -113,6 +120,86 @@ pub enum SimpleValue {
     Union(String, Option<Box<SimpleValue>>),
 }
 
+/// Walks a [`SimpleValue`], to convert it to other data structures without going through serde.
+///
+/// Each method is called on the corresponding part of the value when it is passed to
+/// [`SimpleValue::accept`]. By default, the methods only visit the contents of the value; override
+/// the ones you are interested in. To keep walking the value from an overridden method, call
+/// [`SimpleValue::accept`] on the subvalues.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::{SimpleValue, SimpleValueVisitor};
+///
+/// /// Collects the names of the fields that hold text.
+/// struct TextFields(Vec<String>);
+///
+/// impl SimpleValueVisitor for TextFields {
+///     fn visit_record_field(&mut self, name: &str, val: &SimpleValue) {
+///         if let SimpleValue::Text(_) = val {
+///             self.0.push(name.to_owned());
+///         }
+///         val.accept(self);
+///     }
+/// }
+///
+/// let value: SimpleValue =
+///     serde_dhall::from_str(r#"{ x = "a", y = { z = "b", w = 1 } }"#).parse()?;
+/// let mut visitor = TextFields(Vec::new());
+/// value.accept(&mut visitor);
+/// assert_eq!(visitor.0, vec!["x", "z"]);
+/// # Ok(())
+/// # }
+/// ```
+pub trait SimpleValueVisitor {
+    /// Called on numbers and booleans.
+    fn visit_num(&mut self, _num: &NumKind) {}
+
+    /// Called on text.
+    fn visit_text(&mut self, _text: &str) {}
+
+    /// Called on `Some` and `None` values.
+    fn visit_optional(&mut self, val: Option<&SimpleValue>) {
+        if let Some(val) = val {
+            val.accept(self);
+        }
+    }
+
+    /// Calls [`SimpleValueVisitor::visit_list_element`] on each element.
+    fn visit_list(&mut self, vals: &[SimpleValue]) {
+        for (i, val) in vals.iter().enumerate() {
+            self.visit_list_element(i, val);
+        }
+    }
+
+    /// Called on each element of a list, with its position.
+    fn visit_list_element(&mut self, _index: usize, val: &SimpleValue) {
+        val.accept(self);
+    }
+
+    /// Calls [`SimpleValueVisitor::visit_record_field`] on each field, in order of their names.
+    fn visit_record(&mut self, fields: &BTreeMap<String, SimpleValue>) {
+        for (name, val) in fields {
+            self.visit_record_field(name, val);
+        }
+    }
+
+    /// Called on each field of a record, with its name. The entries of a `toMap`-style list are
+    /// read as the fields of a record.
+    fn visit_record_field(&mut self, _name: &str, val: &SimpleValue) {
+        val.accept(self);
+    }
+
+    /// Called on a union value, with its payload if the alternative has one.
+    fn visit_union_variant(&mut self, _name: &str, val: Option<&SimpleValue>) {
+        if let Some(val) = val {
+            val.accept(self);
+        }
+    }
+}
+
 /// The type of a value that can be decoded by `serde_dhall`, e.g. `{ x: Bool, y: List Natural }`.
 ///
 /// A `SimpleType` is used when deserializing values to ensure they are of the expected type.
@@ -146,6 +233,7 @@ pub enum SimpleValue {
 /// `{ x: T, y: T }`  | `HashMap<String, T>`, structs
 /// `< x: T \| y: U >`  | enums
 /// `Prelude.Map.Type Text T`  | `HashMap<String, T>`, structs
+/// `Prelude.Map.Type Text T`  | `HashMap<u64, T>`, `BTreeMap<i64, T>`, ... (keys are parsed)
 /// `T -> U`  | unsupported
 /// `Prelude.JSON.Type`  | unsupported
 /// `Prelude.Map.Type T U`  | unsupported
@@ -185,7 +273,8 @@ pub enum SimpleValue {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum SimpleType {
     /// Corresponds to the Dhall type `Bool`
     Bool,
@@ -205,6 +294,16 @@ pub enum SimpleType {
     Record(HashMap<String, SimpleType>),
     /// Corresponds to the Dhall type `< x : T | y : U >`
     Union(HashMap<String, Option<SimpleType>>),
+    /// Corresponds to the Dhall constant `Type`, the type of types
+    Type,
+    /// Corresponds to the Dhall constant `Kind`, the type of `Type`
+    Kind,
+    /// A variable bound by the closest enclosing [`SimpleType::Pi`] with that name
+    Var(String),
+    /// Corresponds to the Dhall type `∀(x : A) → B`. When `x` is `_`, this is the function type
+    /// `A → B`. Types that only differ by the names of their bound variables are equal, like
+    /// `∀(a : Type) → a` and `∀(b : Type) → b`.
+    Pi(String, Box<SimpleType>, Box<SimpleType>),
 }
 
 impl Value {
@@ -223,6 +322,11 @@ impl Value {
             Value {
                 kind: ValueKind::Ty(ty),
             }
+        } else if let Ok(ty @ SimpleType::Pi(..)) = SimpleType::from_nir(ty) {
+            let expr = x.to_hir_noenv().to_expr(cx, Default::default());
+            Value {
+                kind: ValueKind::Fn(expr, ty),
+            }
         } else {
             let expr = x.to_hir_noenv().to_expr(cx, Default::default());
             return Err(Error(ErrorKind::Deserialize(format!(
@@ -232,6 +336,19 @@ impl Value {
         })
     }
 
+    /// Reads a value from an expression that wasn't normalized. Only literals can be read.
+    pub(crate) fn from_expr_and_ty(x: &Expr, ty: &Nir) -> Result<Self> {
+        match SimpleValue::from_expr(x) {
+            Ok(val) => Ok(Value {
+                kind: ValueKind::Val(val, SimpleType::from_nir(ty).ok()),
+            }),
+            Err(NotSimpleValue) => Err(Error(ErrorKind::Deserialize(format!(
+                "this is not a simple value without normalization: {}",
+                x
+            )))),
+        }
+    }
+
     /// Converts a Value into a SimpleValue.
     pub(crate) fn to_simple_value(&self) -> Option<SimpleValue> {
         match &self.kind {
@@ -240,6 +357,22 @@ impl Value {
         }
     }
 
+    /// The type `ty`, as a value.
+    pub(crate) fn from_simple_type(ty: SimpleType) -> Self {
+        Value {
+            kind: ValueKind::Ty(ty),
+        }
+    }
+
+    /// The type of the value, if known. This is `None` if the value is a type.
+    pub(crate) fn ty(&self) -> Option<&SimpleType> {
+        match &self.kind {
+            ValueKind::Val(_, ty) => ty.as_ref(),
+            ValueKind::Ty(_) => None,
+            ValueKind::Fn(_, ty) => Some(ty),
+        }
+    }
+
     /// Converts a Value into a SimpleType.
     pub(crate) fn to_simple_type(&self) -> Option<SimpleType> {
         match &self.kind {
@@ -248,19 +381,193 @@ impl Value {
         }
     }
 
+    /// Whether the value, or the type if this is a type, is nested more than `max_depth` levels
+    /// deep. Scalars have depth 1.
+    pub(crate) fn exceeds_depth(&self, max_depth: usize) -> bool {
+        match &self.kind {
+            ValueKind::Val(val, _) => val.exceeds_depth(max_depth),
+            ValueKind::Ty(ty) | ValueKind::Fn(_, ty) => {
+                ty.exceeds_depth(max_depth)
+            }
+        }
+    }
+
+    /// The number of elements of the value, if it is a list.
+    pub(crate) fn list_len(&self) -> Option<usize> {
+        match &self.kind {
+            ValueKind::Val(SimpleValue::List(xs), _) => Some(xs.len()),
+            _ => None,
+        }
+    }
+
     /// Converts a value back to the corresponding AST expression.
     pub(crate) fn to_expr(&self) -> Expr {
         match &self.kind {
             ValueKind::Val(val, ty) => val.to_expr(ty.as_ref()).unwrap(),
             ValueKind::Ty(ty) => ty.to_expr(),
+            ValueKind::Fn(expr, _) => expr.clone(),
+        }
+    }
+
+    /// Converts a value to `Hir`, e.g. to apply a function to it.
+    pub(crate) fn to_hir<'cx>(&self, cx: Ctxt<'cx>) -> Result<Hir<'cx>> {
+        match &self.kind {
+            ValueKind::Val(val, ty) => val.to_hir(ty.as_ref()),
+            ValueKind::Ty(ty) => Ok(ty.to_hir()),
+            ValueKind::Fn(expr, _) => {
+                let resolved = Parsed::from_expr_without_imports(expr.clone())
+                    .skip_resolve(cx)
+                    .map_err(ErrorKind::Dhall)?;
+                Ok(resolved.as_hir().clone())
+            }
+        }
+    }
+
+    /// Merges two record values recursively, like the `∧` operator. Fields that are records on
+    /// both sides are merged in turn; any other field present on both sides is an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::{from_str, Value};
+    ///
+    /// let left: Value = from_str("{ a = { b = 1 } }").parse()?;
+    /// let right: Value = from_str("{ a = { c = True }, d = \"x\" }").parse()?;
+    /// let merged = Value::merge(&left, &right).unwrap();
+    /// assert_eq!(merged.to_string(), "{ a = { b = 1, c = True }, d = \"x\" }");
+    ///
+    /// assert!(Value::merge(&left, &left).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn merge(left: &Value, right: &Value) -> StdResult<Value, MergeError> {
+        match (&left.kind, &right.kind) {
+            (
+                ValueKind::Val(l @ SimpleValue::Record(_), lty),
+                ValueKind::Val(r @ SimpleValue::Record(_), rty),
+            ) => {
+                let val = SimpleValue::merge(l, r, "")?;
+                let ty = match (lty, rty) {
+                    (Some(lty), Some(rty)) => Some(SimpleType::merge(lty, rty)),
+                    _ => None,
+                };
+                Ok(Value {
+                    kind: ValueKind::Val(val, ty),
+                })
+            }
+            _ => Err(MergeError::NotARecord),
         }
     }
+
+    /// Merges two record values like the `⫽` operator: the fields of `right` replace the fields
+    /// of the same name in `left`. Nested records are not merged. If either value is not a
+    /// record, this returns `right`.
+    pub fn right_biased_merge(left: &Value, right: &Value) -> Value {
+        match (&left.kind, &right.kind) {
+            (
+                ValueKind::Val(SimpleValue::Record(l), lty),
+                ValueKind::Val(SimpleValue::Record(r), rty),
+            ) => {
+                let mut val = l.clone();
+                val.extend(r.clone());
+                let ty = match (lty, rty) {
+                    (
+                        Some(SimpleType::Record(lty)),
+                        Some(SimpleType::Record(rty)),
+                    ) => {
+                        let mut ty = lty.clone();
+                        ty.extend(rty.clone());
+                        Some(SimpleType::Record(ty))
+                    }
+                    _ => None,
+                };
+                Value {
+                    kind: ValueKind::Val(SimpleValue::Record(val), ty),
+                }
+            }
+            _ => right.clone(),
+        }
+    }
+
+    /// Deserializes the value into a Rust type, like [`from_dhall_value()`].
+    ///
+    /// [`from_dhall_value()`]: crate::from_dhall_value()
+    pub fn try_into<T: FromDhall>(self) -> Result<T> {
+        T::from_dhall(&self)
+    }
 }
 
 #[derive(Debug)]
 struct NotSimpleValue;
 
+/// The error returned by [`Value::merge()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// One of the values is not a record.
+    NotARecord,
+    /// A field is present on both sides and is not a record on both sides. This holds the path of
+    /// the field, like `a.b`.
+    FieldCollision(String),
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter,
+    ) -> StdResult<(), std::fmt::Error> {
+        match self {
+            MergeError::NotARecord => f.write_str("can only merge records"),
+            MergeError::FieldCollision(path) => {
+                write!(f, "cannot merge records: duplicate field `{}`", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
 impl SimpleValue {
+    /// Merges two records recursively; `path` is the path of these records in the merged value.
+    fn merge(
+        left: &SimpleValue,
+        right: &SimpleValue,
+        path: &str,
+    ) -> StdResult<SimpleValue, MergeError> {
+        let (left, right) = match (left, right) {
+            (SimpleValue::Record(l), SimpleValue::Record(r)) => (l, r),
+            _ => return Err(MergeError::FieldCollision(path.to_owned())),
+        };
+        let mut merged = left.clone();
+        for (k, r) in right {
+            let v = match left.get(k) {
+                Some(l) if path.is_empty() => SimpleValue::merge(l, r, k)?,
+                Some(l) => {
+                    SimpleValue::merge(l, r, &format!("{}.{}", path, k))?
+                }
+                None => r.clone(),
+            };
+            merged.insert(k.clone(), v);
+        }
+        Ok(SimpleValue::Record(merged))
+    }
+
+    /// Calls the method of `visitor` that corresponds to the kind of this value.
+    pub fn accept<V: SimpleValueVisitor + ?Sized>(&self, visitor: &mut V) {
+        match self {
+            SimpleValue::Num(num) => visitor.visit_num(num),
+            SimpleValue::Text(text) => visitor.visit_text(text),
+            SimpleValue::Optional(val) => {
+                visitor.visit_optional(val.as_deref())
+            }
+            SimpleValue::List(vals) => visitor.visit_list(vals),
+            SimpleValue::Record(fields) => visitor.visit_record(fields),
+            SimpleValue::Union(name, val) => {
+                visitor.visit_union_variant(name, val.as_deref())
+            }
+        }
+    }
+
     fn from_nir(nir: &Nir) -> StdResult<Self, NotSimpleValue> {
         Ok(match nir.kind() {
             NirKind::Num(lit) => SimpleValue::Num(lit.clone()),
@@ -344,6 +651,114 @@ impl SimpleValue {
         })
     }
 
+    /// Like `from_nir`, but reads the literal syntax of an expression that wasn't normalized.
+    fn from_expr(expr: &Expr) -> StdResult<Self, NotSimpleValue> {
+        let is_map_entry = |kvs: &BTreeMap<Label, Expr>| {
+            kvs.len() == 2
+                && kvs.contains_key("mapKey")
+                && kvs.contains_key("mapValue")
+        };
+        Ok(match expr.kind() {
+            ExprKind::Annot(x, _) => Self::from_expr(x)?,
+            ExprKind::Num(lit) => SimpleValue::Num(lit.clone()),
+            ExprKind::TextLit(x) if x.tail().is_empty() => {
+                SimpleValue::Text(x.head().to_owned())
+            }
+            ExprKind::SomeLit(x) => {
+                SimpleValue::Optional(Some(Box::new(Self::from_expr(x)?)))
+            }
+            ExprKind::Op(OpKind::App(f, _))
+                if matches!(
+                    f.kind(),
+                    ExprKind::Builtin(Builtin::OptionalNone)
+                ) =>
+            {
+                SimpleValue::Optional(None)
+            }
+            ExprKind::EmptyListLit(t) => match t.kind() {
+                ExprKind::Op(OpKind::App(_, t)) => match t.kind() {
+                    ExprKind::RecordType(kts) if is_map_entry(kts) => {
+                        SimpleValue::Record(Default::default())
+                    }
+                    _ => SimpleValue::List(vec![]),
+                },
+                _ => SimpleValue::List(vec![]),
+            },
+            ExprKind::NEListLit(xs) => match xs[0].kind() {
+                ExprKind::RecordLit(kvs) if is_map_entry(kvs) => {
+                    SimpleValue::Record(
+                        xs.iter()
+                            .map(|x| match x.kind() {
+                                ExprKind::RecordLit(kvs) => {
+                                    match Self::from_expr(&kvs["mapKey"])? {
+                                        SimpleValue::Text(k) => Ok((
+                                            k,
+                                            Self::from_expr(&kvs["mapValue"])?,
+                                        )),
+                                        _ => Err(NotSimpleValue),
+                                    }
+                                }
+                                _ => Err(NotSimpleValue),
+                            })
+                            .collect::<StdResult<_, _>>()?,
+                    )
+                }
+                _ => SimpleValue::List(
+                    xs.iter()
+                        .map(Self::from_expr)
+                        .collect::<StdResult<_, _>>()?,
+                ),
+            },
+            ExprKind::RecordLit(kvs) => SimpleValue::Record(
+                kvs.iter()
+                    .map(|(k, v)| Ok((k.into(), Self::from_expr(v)?)))
+                    .collect::<StdResult<_, _>>()?,
+            ),
+            ExprKind::Op(OpKind::Field(ut, field)) => match ut.kind() {
+                ExprKind::UnionType(kts) if kts.get(field) == Some(&None) => {
+                    SimpleValue::Union(field.into(), None)
+                }
+                _ => return Err(NotSimpleValue),
+            },
+            ExprKind::Op(OpKind::App(f, x)) => match f.kind() {
+                ExprKind::Op(OpKind::Field(ut, field))
+                    if matches!(
+                        ut.kind(),
+                        ExprKind::UnionType(kts)
+                            if matches!(kts.get(field), Some(Some(_)))
+                    ) =>
+                {
+                    SimpleValue::Union(
+                        field.into(),
+                        Some(Box::new(Self::from_expr(x)?)),
+                    )
+                }
+                _ => return Err(NotSimpleValue),
+            },
+            _ => return Err(NotSimpleValue),
+        })
+    }
+
+    // Whether this value is nested more than `max_depth` levels deep.
+    fn exceeds_depth(&self, max_depth: usize) -> bool {
+        if max_depth == 0 {
+            return true;
+        }
+        let max_depth = max_depth - 1;
+        match self {
+            SimpleValue::Num(_) | SimpleValue::Text(_) => false,
+            SimpleValue::Optional(x) | SimpleValue::Union(_, x) => {
+                x.iter().any(|x| x.exceeds_depth(max_depth))
+            }
+            SimpleValue::List(xs) => {
+                xs.iter().any(|x| x.exceeds_depth(max_depth))
+            }
+            SimpleValue::Record(kvs) => {
+                kvs.values().any(|x| x.exceeds_depth(max_depth))
+            }
+        }
+    }
+
     // Converts this to `Hir`, using the optional type annotation. Without the type, things like
     // empty lists and unions will fail to convert.
     fn to_hir<'cx>(&self, ty: Option<&SimpleType>) -> Result<Hir<'cx>> {
@@ -457,10 +872,269 @@ impl SimpleValue {
 }
 
 #[derive(Debug)]
-struct NotSimpleType;
+pub(crate) struct NotSimpleType;
 
 impl SimpleType {
-    fn from_nir(nir: &Nir) -> StdResult<Self, NotSimpleType> {
+    /// The type `Optional inner`.
+    pub fn optional(inner: SimpleType) -> Self {
+        SimpleType::Optional(Box::new(inner))
+    }
+
+    /// The type `List inner`.
+    pub fn list(inner: SimpleType) -> Self {
+        SimpleType::List(Box::new(inner))
+    }
+
+    /// The record type with the given fields. Together with the variants for the scalar types,
+    /// like `SimpleType::Natural`, this builds types at runtime, e.g. to pass to
+    /// [`type_annotation()`].
+    ///
+    /// [`type_annotation()`]: crate::Deserializer::type_annotation()
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # fn main() -> serde_dhall::Result<()> {
+    /// use serde_dhall::SimpleType;
+    ///
+    /// let ty = SimpleType::record(vec![
+    ///     ("name", SimpleType::Text),
+    ///     ("ports", SimpleType::list(SimpleType::Natural)),
+    /// ]);
+    /// assert_eq!(ty.to_string(), "{ name : Text, ports : List Natural }");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn record<K: Into<String>>(
+        fields: impl IntoIterator<Item = (K, SimpleType)>,
+    ) -> Self {
+        SimpleType::Record(
+            fields.into_iter().map(|(k, t)| (k.into(), t)).collect(),
+        )
+    }
+
+    /// The union type with the given alternatives, each with the type of its payload if it has
+    /// one.
+    pub fn union<K: Into<String>>(
+        alternatives: impl IntoIterator<Item = (K, Option<SimpleType>)>,
+    ) -> Self {
+        SimpleType::Union(
+            alternatives
+                .into_iter()
+                .map(|(k, t)| (k.into(), t))
+                .collect(),
+        )
+    }
+
+    /// The constant `Kind`, which is the type of `Type` and of type constructors like
+    /// `Type → Type`.
+    pub fn kind() -> Self {
+        SimpleType::Kind
+    }
+
+    /// The type variable `name`, to use in the body of a [`KindType`].
+    pub fn var(name: impl Into<String>) -> Self {
+        SimpleType::Var(name.into())
+    }
+
+    /// The function type `input → output`.
+    pub fn function(input: SimpleType, output: SimpleType) -> Self {
+        SimpleType::Pi("_".to_owned(), Box::new(input), Box::new(output))
+    }
+
+    /// Whether the type of this expression is `Kind`, like for `Type` or `Type → Type`.
+    ///
+    /// Dhall is impredicative: a polymorphic function type like `∀(a : Type) → a → a` quantifies
+    /// over types but is itself of type `Type`, so it is not kind-typed.
+    pub fn is_kind_typed(&self) -> bool {
+        let expr = self.to_expr();
+        Ctxt::with_new(|cx| {
+            Parsed::from_expr_without_imports(expr)
+                .skip_resolve(cx)
+                .and_then(|resolved| Ok(resolved.typecheck(cx)?))
+                .map(|typed| typed.ty().as_const() == Some(Const::Kind))
+                .unwrap_or(false)
+        })
+    }
+
+    /// The output type of this function type, unless it depends on the input, like in
+    /// `∀(a : Type) → List a`.
+    pub(crate) fn output(&self) -> Option<&SimpleType> {
+        match self {
+            SimpleType::Pi(x, _, output) if !output.mentions(x) => Some(output),
+            _ => None,
+        }
+    }
+
+    /// Whether the variable `x` appears free in this type.
+    fn mentions(&self, x: &str) -> bool {
+        match self {
+            SimpleType::Bool
+            | SimpleType::Natural
+            | SimpleType::Integer
+            | SimpleType::Double
+            | SimpleType::Text
+            | SimpleType::Type
+            | SimpleType::Kind => false,
+            SimpleType::Optional(t) | SimpleType::List(t) => t.mentions(x),
+            SimpleType::Record(kts) => kts.values().any(|t| t.mentions(x)),
+            SimpleType::Union(kts) => {
+                kts.values().flatten().any(|t| t.mentions(x))
+            }
+            SimpleType::Var(y) => x == y,
+            SimpleType::Pi(y, a, b) => {
+                a.mentions(x) || (x != y && b.mentions(x))
+            }
+        }
+    }
+
+    /// Whether this is a kind, like `Type` or `Type → Type`, syntactically. The `∀`s with such a
+    /// body are type constructors.
+    fn is_kind(&self) -> bool {
+        match self {
+            SimpleType::Type | SimpleType::Kind => true,
+            SimpleType::Pi(_, _, body) => body.is_kind(),
+            _ => false,
+        }
+    }
+
+    /// Compares up to the names of the variables bound by `∀`s: each scope holds the names
+    /// bound around its side, the outermost first, and bound variables are compared by how far
+    /// away their binder is.
+    fn alpha_eq<'a>(
+        &'a self,
+        other: &'a SimpleType,
+        scope_l: &mut Vec<&'a str>,
+        scope_r: &mut Vec<&'a str>,
+    ) -> bool {
+        use SimpleType::*;
+        match (self, other) {
+            (Bool, Bool)
+            | (Natural, Natural)
+            | (Integer, Integer)
+            | (Double, Double)
+            | (Text, Text)
+            | (Type, Type)
+            | (Kind, Kind) => true,
+            (Optional(l), Optional(r)) | (List(l), List(r)) => {
+                l.alpha_eq(r, scope_l, scope_r)
+            }
+            (Record(l), Record(r)) => {
+                l.len() == r.len()
+                    && l.iter().all(|(k, l)| match r.get(k) {
+                        Some(r) => l.alpha_eq(r, scope_l, scope_r),
+                        None => false,
+                    })
+            }
+            (Union(l), Union(r)) => {
+                l.len() == r.len()
+                    && l.iter().all(|(k, l)| match (l, r.get(k)) {
+                        (None, Some(None)) => true,
+                        (Some(l), Some(Some(r))) => {
+                            l.alpha_eq(r, scope_l, scope_r)
+                        }
+                        _ => false,
+                    })
+            }
+            (Var(l), Var(r)) => {
+                match (debruijn_index(scope_l, l), debruijn_index(scope_r, r)) {
+                    (Some(l), Some(r)) => l == r,
+                    (None, None) => l == r,
+                    _ => false,
+                }
+            }
+            (Pi(x, la, lb), Pi(y, ra, rb)) => {
+                if !la.alpha_eq(ra, scope_l, scope_r) {
+                    return false;
+                }
+                scope_l.push(x);
+                scope_r.push(y);
+                let eq = lb.alpha_eq(rb, scope_l, scope_r);
+                scope_l.pop();
+                scope_r.pop();
+                eq
+            }
+            _ => false,
+        }
+    }
+
+    /// Consistent with `alpha_eq`: the names of bound variables are not hashed.
+    fn hash_in<'a, H: Hasher>(
+        &'a self,
+        scope: &mut Vec<&'a str>,
+        state: &mut H,
+    ) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            SimpleType::Bool
+            | SimpleType::Natural
+            | SimpleType::Integer
+            | SimpleType::Double
+            | SimpleType::Text
+            | SimpleType::Type
+            | SimpleType::Kind => {}
+            SimpleType::Var(x) => match debruijn_index(scope, x) {
+                Some(i) => i.hash(state),
+                None => x.hash(state),
+            },
+            SimpleType::Optional(t) | SimpleType::List(t) => {
+                t.hash_in(scope, state)
+            }
+            SimpleType::Record(kts) => {
+                for (k, t) in kts.iter().collect::<BTreeMap<_, _>>() {
+                    k.hash(state);
+                    t.hash_in(scope, state);
+                }
+            }
+            SimpleType::Union(kts) => {
+                for (k, t) in kts.iter().collect::<BTreeMap<_, _>>() {
+                    k.hash(state);
+                    std::mem::discriminant(t).hash(state);
+                    if let Some(t) = t {
+                        t.hash_in(scope, state);
+                    }
+                }
+            }
+            SimpleType::Pi(x, a, b) => {
+                a.hash_in(scope, state);
+                scope.push(x);
+                b.hash_in(scope, state);
+                scope.pop();
+            }
+        }
+    }
+
+    /// The type of the merge of two records that [`SimpleValue::merge()`] could merge.
+    fn merge(left: &SimpleType, right: &SimpleType) -> SimpleType {
+        match (left, right) {
+            (SimpleType::Record(l), SimpleType::Record(r)) => {
+                let mut merged = l.clone();
+                for (k, r) in r {
+                    let ty = match l.get(k) {
+                        Some(l) => SimpleType::merge(l, r),
+                        None => r.clone(),
+                    };
+                    merged.insert(k.clone(), ty);
+                }
+                SimpleType::Record(merged)
+            }
+            _ => right.clone(),
+        }
+    }
+
+    pub(crate) fn from_nir(nir: &Nir) -> StdResult<Self, NotSimpleType> {
+        Self::from_nir_in(nir, &[], false)
+    }
+
+    /// `scope` holds the names of the enclosing `∀`s, the outermost first. Type constructors
+    /// like `Type → Type` are only simple where they are allowed by `kind_ok`, i.e. as the
+    /// annotation of a `∀`: on their own they are functions on types, not types.
+    fn from_nir_in(
+        nir: &Nir,
+        scope: &[String],
+        kind_ok: bool,
+    ) -> StdResult<Self, NotSimpleType> {
+        let from_nir = |nir| Self::from_nir_in(nir, scope, false);
         Ok(match nir.kind() {
             NirKind::BuiltinType(b) => match b {
                 Builtin::Bool => SimpleType::Bool,
@@ -471,31 +1145,133 @@ impl SimpleType {
                 _ => unreachable!(),
             },
             NirKind::OptionalType(t) => {
-                SimpleType::Optional(Box::new(Self::from_nir(t)?))
-            }
-            NirKind::ListType(t) => {
-                SimpleType::List(Box::new(Self::from_nir(t)?))
+                SimpleType::Optional(Box::new(from_nir(t)?))
             }
+            NirKind::ListType(t) => SimpleType::List(Box::new(from_nir(t)?)),
             NirKind::RecordType(kts) => SimpleType::Record(
                 kts.iter()
-                    .map(|(k, v)| Ok((k.into(), Self::from_nir(v)?)))
+                    .map(|(k, v)| Ok((k.into(), from_nir(v)?)))
                     .collect::<StdResult<_, _>>()?,
             ),
             NirKind::UnionType(kts) => SimpleType::Union(
                 kts.iter()
                     .map(|(k, v)| {
-                        Ok((
-                            k.into(),
-                            v.as_ref().map(Self::from_nir).transpose()?,
-                        ))
+                        Ok((k.into(), v.as_ref().map(from_nir).transpose()?))
                     })
                     .collect::<StdResult<_, _>>()?,
             ),
+            NirKind::Const(Const::Type) => SimpleType::Type,
+            NirKind::Const(Const::Kind) => SimpleType::Kind,
+            NirKind::Var(NzVar::Bound(level)) => {
+                let x = &scope[*level];
+                // A shadowed variable can't be referred to by its name alone.
+                if scope[level + 1..].contains(x) {
+                    return Err(NotSimpleType);
+                }
+                SimpleType::Var(x.clone())
+            }
+            NirKind::PiClosure {
+                binder,
+                annot,
+                closure,
+            } => {
+                let x = String::from(&binder.to_label());
+                let var = NirKind::Var(NzVar::new(scope.len()));
+                let body = closure.apply(Nir::from_kind(var));
+                let mut body_scope = scope.to_vec();
+                body_scope.push(x.clone());
+                let body = Self::from_nir_in(&body, &body_scope, kind_ok)?;
+                if !kind_ok && body.is_kind() {
+                    return Err(NotSimpleType);
+                }
+                SimpleType::Pi(
+                    x,
+                    Box::new(Self::from_nir_in(annot, scope, true)?),
+                    Box::new(body),
+                )
+            }
             _ => return Err(NotSimpleType),
         })
     }
 
+    /// Removes from this type the record fields of type `Optional _` that are absent from
+    /// `actual`, recursively. Used to let values omit optional fields.
+    pub(crate) fn without_missing_optionals(
+        &self,
+        actual: &SimpleType,
+    ) -> Self {
+        use SimpleType::*;
+        match (self, actual) {
+            (Record(kts), Record(actual_kts)) => Record(
+                kts.iter()
+                    .filter_map(|(k, t)| match (t, actual_kts.get(k)) {
+                        (Optional(_), None) => None,
+                        (t, Some(actual_t)) => Some((
+                            k.clone(),
+                            t.without_missing_optionals(actual_t),
+                        )),
+                        (t, None) => Some((k.clone(), t.clone())),
+                    })
+                    .collect(),
+            ),
+            (Optional(t), Optional(actual_t)) => {
+                Optional(Box::new(t.without_missing_optionals(actual_t)))
+            }
+            (List(t), List(actual_t)) => {
+                List(Box::new(t.without_missing_optionals(actual_t)))
+            }
+            (Union(kts), Union(actual_kts)) => Union(
+                kts.iter()
+                    .map(|(k, t)| {
+                        let t = match (t, actual_kts.get(k)) {
+                            (Some(t), Some(Some(actual_t))) => {
+                                Some(t.without_missing_optionals(actual_t))
+                            }
+                            (t, _) => t.clone(),
+                        };
+                        (k.clone(), t)
+                    })
+                    .collect(),
+            ),
+            _ => self.clone(),
+        }
+    }
+
+    fn exceeds_depth(&self, max_depth: usize) -> bool {
+        if max_depth == 0 {
+            return true;
+        }
+        let max_depth = max_depth - 1;
+        match self {
+            SimpleType::Bool
+            | SimpleType::Natural
+            | SimpleType::Integer
+            | SimpleType::Double
+            | SimpleType::Text
+            | SimpleType::Type
+            | SimpleType::Kind
+            | SimpleType::Var(_) => false,
+            SimpleType::Optional(t) | SimpleType::List(t) => {
+                t.exceeds_depth(max_depth)
+            }
+            SimpleType::Record(kts) => {
+                kts.values().any(|t| t.exceeds_depth(max_depth))
+            }
+            SimpleType::Union(kts) => {
+                kts.values().flatten().any(|t| t.exceeds_depth(max_depth))
+            }
+            SimpleType::Pi(_, a, b) => {
+                a.exceeds_depth(max_depth) || b.exceeds_depth(max_depth)
+            }
+        }
+    }
+
     pub(crate) fn to_hir<'cx>(&self) -> Hir<'cx> {
+        self.to_hir_in(&[])
+    }
+
+    /// `scope` holds the names of the enclosing `∀`s, the innermost last.
+    fn to_hir_in<'cx>(&self, scope: &[&str]) -> Hir<'cx> {
         let hir = |k| Hir::new(HirKind::Expr(k), Span::Artificial);
         hir(match self {
             SimpleType::Bool => ExprKind::Builtin(Builtin::Bool),
@@ -505,24 +1281,45 @@ impl SimpleType {
             SimpleType::Text => ExprKind::Builtin(Builtin::Text),
             SimpleType::Optional(t) => ExprKind::Op(OpKind::App(
                 hir(ExprKind::Builtin(Builtin::Optional)),
-                t.to_hir(),
+                t.to_hir_in(scope),
             )),
             SimpleType::List(t) => ExprKind::Op(OpKind::App(
                 hir(ExprKind::Builtin(Builtin::List)),
-                t.to_hir(),
+                t.to_hir_in(scope),
             )),
             SimpleType::Record(kts) => ExprKind::RecordType(
                 kts.iter()
-                    .map(|(k, t)| (k.as_str().into(), t.to_hir()))
+                    .map(|(k, t)| (k.as_str().into(), t.to_hir_in(scope)))
                     .collect(),
             ),
             SimpleType::Union(kts) => ExprKind::UnionType(
                 kts.iter()
                     .map(|(k, t)| {
-                        (k.as_str().into(), t.as_ref().map(|t| t.to_hir()))
+                        (
+                            k.as_str().into(),
+                            t.as_ref().map(|t| t.to_hir_in(scope)),
+                        )
                     })
                     .collect(),
             ),
+            SimpleType::Type => ExprKind::Const(Const::Type),
+            SimpleType::Kind => ExprKind::Const(Const::Kind),
+            SimpleType::Var(x) => {
+                let kind = match scope.iter().rev().position(|y| y == x) {
+                    Some(idx) => HirKind::Var(AlphaVar::new(idx)),
+                    None => HirKind::MissingVar(V(x.as_str().into(), 0)),
+                };
+                return Hir::new(kind, Span::Artificial);
+            }
+            SimpleType::Pi(x, a, b) => {
+                let mut body_scope = scope.to_vec();
+                body_scope.push(x);
+                ExprKind::Pi(
+                    x.as_str().into(),
+                    a.to_hir_in(scope),
+                    b.to_hir_in(&body_scope),
+                )
+            }
         })
     }
 
@@ -532,9 +1329,63 @@ impl SimpleType {
     }
 }
 
+/// Builds the type of a function that is polymorphic over types, like `∀(a : Type) → a → a`.
+///
+/// # Example
+///
+/// ```rust
+/// # fn main() -> serde_dhall::Result<()> {
+/// use serde_dhall::{KindType, SimpleType};
+///
+/// let a = || SimpleType::var("a");
+/// let id = KindType::new()
+///     .param("a")
+///     .build(SimpleType::function(a(), a()));
+/// assert_eq!(id.to_string(), "∀(a : Type) → a → a");
+///
+/// let parsed: SimpleType =
+///     serde_dhall::from_str("∀(a : Type) → a → a").parse()?;
+/// assert_eq!(parsed, id);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct KindType {
+    params: Vec<(String, SimpleType)>,
+}
+
+impl KindType {
+    /// A type without parameters yet.
+    pub fn new() -> Self {
+        KindType::default()
+    }
+
+    /// Adds the parameter `∀(name : Type)`, after the ones added before.
+    pub fn param(self, name: impl Into<String>) -> Self {
+        self.param_of_kind(name, SimpleType::Type)
+    }
+
+    /// Adds the parameter `∀(name : kind)`, e.g. with `kind` being `Type → Type` for a type
+    /// constructor.
+    pub fn param_of_kind(
+        mut self,
+        name: impl Into<String>,
+        kind: SimpleType,
+    ) -> Self {
+        self.params.push((name.into(), kind));
+        self
+    }
+
+    /// The type that binds the parameters in `body`.
+    pub fn build(self, body: SimpleType) -> SimpleType {
+        self.params.into_iter().rev().fold(body, |body, (x, kind)| {
+            SimpleType::Pi(x, Box::new(kind), Box::new(body))
+        })
+    }
+}
+
 impl crate::deserialize::Sealed for Value {}
 impl crate::deserialize::Sealed for SimpleType {}
-impl crate::serialize::Sealed for Value {}
 
 impl FromDhall for Value {
     fn from_dhall(v: &Value) -> Result<Self> {
@@ -551,11 +1402,6 @@ impl FromDhall for SimpleType {
         })
     }
 }
-impl ToDhall for Value {
-    fn to_dhall(&self, _ty: Option<&SimpleType>) -> Result<Value> {
-        Ok(self.clone())
-    }
-}
 
 impl Eq for ValueKind {}
 impl PartialEq for ValueKind {
@@ -564,10 +1410,48 @@ impl PartialEq for ValueKind {
         match (self, other) {
             (Val(a, _), Val(b, _)) => a == b,
             (Ty(a), Ty(b)) => a == b,
+            (Fn(a, _), Fn(b, _)) => alpha_normalize(a) == alpha_normalize(b),
             _ => false,
         }
     }
 }
+/// Compares up to alpha-equivalence: the names of the variables bound by `∀`s don't matter.
+impl PartialEq for SimpleType {
+    fn eq(&self, other: &Self) -> bool {
+        self.alpha_eq(other, &mut Vec::new(), &mut Vec::new())
+    }
+}
+
+impl Eq for SimpleType {}
+
+/// Consistent with `PartialEq`: the fields of records and unions are hashed in order of their
+/// names, so that the order of the underlying `HashMap` doesn't matter, and bound variables are
+/// hashed by the distance to their binder instead of by name.
+impl Hash for SimpleType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash_in(&mut Vec::new(), state)
+    }
+}
+
+/// How many `∀`s are between a variable and its binder in `scope`, the names bound by the
+/// enclosing `∀`s with the outermost first, or `None` if it is free.
+fn debruijn_index(scope: &[&str], x: &str) -> Option<usize> {
+    scope.iter().rev().position(|y| *y == x)
+}
+
+/// Consistent with `PartialEq`: the type annotation of a value is not hashed, and functions are
+/// hashed up to the names of their bound variables.
+impl Hash for ValueKind {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            ValueKind::Val(val, _) => val.hash(state),
+            ValueKind::Ty(ty) => ty.hash(state),
+            ValueKind::Fn(expr, _) => alpha_normalize(expr).hash(state),
+        }
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(
         &self,
@@ -593,6 +1477,43 @@ fn test_display_simpletype() {
     assert_eq!(ty.to_string(), "List (Optional Natural)".to_string())
 }
 
+#[test]
+fn test_hash_simpletype() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashSet;
+
+    fn parse(s: &str) -> SimpleType {
+        crate::from_str(s).parse().unwrap()
+    }
+    fn hash(ty: &SimpleType) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        ty.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    let tys = [
+        parse("{ x : Natural, y : < A | B : List Bool > }"),
+        parse("{ y : < B : List Bool | A >, x : Natural }"),
+        parse("let T = Natural in { y : < B : List Bool | A >, x : T }"),
+    ];
+    for ty in &tys[1..] {
+        assert_eq!(ty, &tys[0]);
+        assert_eq!(hash(ty), hash(&tys[0]));
+    }
+
+    let set: HashSet<_> = tys
+        .iter()
+        .cloned()
+        .chain(vec![parse("{ x : Natural }"), parse("List Natural")])
+        .collect();
+    assert_eq!(set.len(), 3);
+
+    let a = parse("∀(a : Type) → ∀(x : a) → List a");
+    let b = parse("∀(b : Type) → ∀(y : b) → List b");
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+}
+
 #[test]
 fn test_display_value() {
     use SimpleType::*;
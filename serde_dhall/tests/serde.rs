@@ -4,6 +4,35 @@ mod serde {
         from_str, serialize, FromDhall, StaticType, ToDhall, Value,
     };
     use std::collections;
+    use std::path::{Path, PathBuf};
+
+    /// A directory for the files of a test, removed when dropped, even if the test fails.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "serde_dhall_{}_{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
 
     fn assert_de<T>(s: &str, x: T)
     where
@@ -50,9 +79,40 @@ mod serde {
         assert_serde("1", 1u32);
         assert_serde("1", 1usize);
 
+        assert_serde("1", 1u128);
+        assert_serde("1", 1u16);
+        assert_serde("1", 1u8);
+        assert_serde("18446744073709551615", u64::MAX as u128);
+        assert_serde("18446744073709551615", u64::MAX);
+
         assert_serde("+1", 1i64);
         assert_serde("+1", 1i32);
         assert_serde("+1", 1isize);
+        assert_serde("+1", 1i128);
+        assert_serde("+1", 1i16);
+        assert_serde("-1", -1i8);
+        assert_serde("-9223372036854775808", i64::MIN as i128);
+
+        // Out of range values are rejected.
+        assert!(from_str("256").parse::<u8>().is_err());
+        assert!(from_str("65536").parse::<u16>().is_err());
+        assert!(from_str("+128").parse::<i8>().is_err());
+        assert!(from_str("-129").parse::<i8>().is_err());
+        assert!(from_str("-1").parse::<u64>().is_err());
+        assert_eq!(
+            serialize(&(u64::MAX as u128 + 1))
+                .static_type_annotation()
+                .to_string()
+                .map_err(|e| e.to_string()),
+            Err(
+                "Natural out of range for serialization: 18446744073709551616"
+                    .to_string()
+            )
+        );
+        assert!(serialize(&(i64::MIN as i128 - 1)).to_string().is_err());
+
+        // `usize` is at least as wide as `u32` on all supported platforms.
+        assert_serde("4294967295", u32::MAX as usize);
 
         assert_serde("1.0", 1.0f64);
         assert_serde("1.0", 1.0f32);
@@ -150,6 +210,136 @@ mod serde {
             .is_err());
     }
 
+    #[test]
+    fn enums_from_text() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum LogLevel {
+            Debug,
+            Warn,
+            #[serde(rename = "error")]
+            Error,
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Config {
+            level: LogLevel,
+            levels: Vec<LogLevel>,
+        }
+        let parse = |s: &str| {
+            from_str(s).parse::<LogLevel>().map_err(|e| e.to_string())
+        };
+        assert_eq!(parse("\"Warn\""), Ok(LogLevel::Warn));
+        assert_eq!(parse("\"error\""), Ok(LogLevel::Error));
+        // Unions still work.
+        assert_eq!(parse("< Debug | Warn >.Debug"), Ok(LogLevel::Debug));
+        assert_eq!(
+            parse("\"Info\""),
+            Err("unknown variant `Info`, expected one of `Debug`, `Warn`, \
+                 `error`"
+                .to_string())
+        );
+        assert_eq!(
+            from_str("{ level = \"Debug\", levels = [\"Warn\", \"error\"] }")
+                .parse::<Config>()
+                .map_err(|e| e.to_string()),
+            Ok(Config {
+                level: LogLevel::Debug,
+                levels: vec![LogLevel::Warn, LogLevel::Error],
+            })
+        );
+
+        // Variants with a payload can't be written as text.
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Bar {
+            X,
+            Y(i64),
+        }
+        assert_eq!(from_str("\"X\"").parse::<Bar>().ok(), Some(Bar::X));
+        assert!(from_str("\"Y\"").parse::<Bar>().is_err());
+    }
+
+    #[test]
+    fn check_enum_exhaustive() {
+        #[allow(dead_code)]
+        #[derive(Debug, Deserialize, StaticType)]
+        enum Mode {
+            Fast,
+            Safe(u64),
+        }
+        let check = |data: &str| {
+            from_str(data)
+                .check_enum_exhaustive::<Mode>()
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(check("< Fast | Safe : Natural >"), Ok(()));
+        assert_eq!(check("< Safe : Natural | Fast >.Safe 1"), Ok(()));
+        assert_eq!(
+            check("< Fast | Safe : Natural | Slow >.Fast"),
+            Err("the enum doesn't match the union: the union has extra \
+                 alternatives `Slow`"
+                .to_owned())
+        );
+        assert_eq!(
+            check("< Safe : Natural | Slow >"),
+            Err("the enum doesn't match the union: the union has extra \
+                 alternatives `Slow`, and the union is missing the variants \
+                 `Fast`"
+                .to_owned())
+        );
+        assert!(check("{ x = 1 }").is_err());
+    }
+
+    #[test]
+    fn merge() {
+        #[derive(Debug, PartialEq, Eq, Deserialize, StaticType)]
+        struct Config {
+            port: u64,
+            debug: bool,
+        }
+        #[derive(Debug, PartialEq, Eq, Deserialize, StaticType)]
+        enum Mode {
+            Fast,
+            Safe(u64),
+        }
+
+        // A record computed by `merge`.
+        let data = "
+            let Env = < Dev | Prod : Natural >
+            let config =
+                  λ(env : Env) →
+                    merge
+                      { Dev = { port = 8080, debug = True }
+                      , Prod = λ(port : Natural) → { port, debug = False }
+                      }
+                      env
+            in  config (Env.Prod 80)
+        ";
+        assert_eq!(
+            from_str(data)
+                .static_type_annotation()
+                .parse::<Config>()
+                .unwrap(),
+            Config {
+                port: 80,
+                debug: false
+            }
+        );
+
+        // A union computed by `merge`.
+        let data = "
+            let Mode = < Fast | Safe : Natural >
+            in  merge { None = Mode.Fast, Some = λ(n : Natural) → Mode.Safe n }
+                  (Some 3)
+        ";
+        assert_eq!(
+            from_str(data)
+                .static_type_annotation()
+                .parse::<Mode>()
+                .unwrap(),
+            Mode::Safe(3)
+        );
+    }
+
     #[test]
     fn with_builtin_type() {
         #[derive(Debug, Deserialize, StaticType, Eq, PartialEq)]
@@ -225,120 +415,2201 @@ mod serde {
                 .unwrap(),
             Baz::X(Bar::A)
         );
+
+        // Real builtins cannot be shadowed
+        assert_eq!(
+            from_str("List/length Natural [1]")
+                .with_builtin_type("List".to_string(), u64::static_type())
+                .parse::<u64>()
+                .map_err(|e| e.to_string()),
+            Err("cannot add builtin type `List`: it would shadow the builtin of the same name".to_string())
+        );
+        let mut substs = collections::HashMap::new();
+        substs.insert("Natural".to_string(), bool::static_type());
+        assert!(from_str("True")
+            .with_builtin_types(substs)
+            .parse::<bool>()
+            .is_err());
+        // Every method that reads the source checks it.
+        let de = from_str("< A | B >")
+            .with_builtin_type("List".to_string(), u64::static_type());
+        let err = "cannot add builtin type `List`: it would shadow the builtin of the same name";
+        assert_eq!(de.parse_ast().unwrap_err().to_string(), err);
+        assert_eq!(de.fingerprint().unwrap_err().to_string(), err);
+        assert_eq!(de.freeze().unwrap_err().to_string(), err);
+        assert_eq!(
+            de.check_enum_exhaustive::<Bar>().unwrap_err().to_string(),
+            err
+        );
+        assert!(serde_dhall::from_value(from_str("1").parse().unwrap())
+            .with_builtin_type("List".to_string(), u64::static_type())
+            .parse::<u64>()
+            .is_err());
     }
 
     #[test]
-    fn test_de_untyped() {
-        use std::collections::BTreeMap;
-        use std::collections::HashMap;
+    fn build_simple_type() {
+        use serde_dhall::SimpleType;
 
-        fn parse<T: FromDhall>(s: &str) -> T {
-            from_str(s).parse().unwrap()
+        let ty = SimpleType::record(vec![("x", SimpleType::Natural)]);
+        assert_eq!(ty.to_string(), "{ x : Natural }");
+        assert_eq!(
+            from_str("{ x = 1 }")
+                .type_annotation(&ty)
+                .parse::<collections::HashMap<String, u64>>()
+                .map_err(|e| e.to_string())
+                .map(|m| m["x"]),
+            Ok(1)
+        );
+        assert!(from_str("{ x = -1 }")
+            .type_annotation(&ty)
+            .parse::<collections::HashMap<String, i64>>()
+            .is_err());
+
+        let ty = SimpleType::union(vec![
+            ("A".to_owned(), None),
+            ("B".to_owned(), Some(SimpleType::optional(SimpleType::Text))),
+        ]);
+        assert_eq!(ty, from_str("< A | B : Optional Text >").parse().unwrap());
+    }
+
+    #[test]
+    fn treat_missing_as_none() {
+        #[derive(Debug, Deserialize, StaticType, Eq, PartialEq)]
+        struct Foo {
+            x: u64,
+            y: Option<u64>,
+        }
+        #[derive(Debug, Deserialize, StaticType, Eq, PartialEq)]
+        struct Bar {
+            foo: Foo,
+            z: Option<String>,
         }
 
-        // Test tuples on record of wrong type. Fields are just taken in alphabetic order.
+        assert!(from_str("{ x = 1 }")
+            .static_type_annotation()
+            .parse::<Foo>()
+            .is_err());
         assert_eq!(
-            parse::<(u64, String, i64)>(r#"{ y = "foo", x = 1, z = +42 }"#),
-            (1, "foo".to_owned(), 42)
+            from_str("{ x = 1 }")
+                .static_type_annotation()
+                .treat_missing_as_none(true)
+                .parse::<Foo>()
+                .map_err(|e| e.to_string()),
+            Ok(Foo { x: 1, y: None })
         );
-
-        let mut expected_map = HashMap::new();
-        expected_map.insert("x".to_string(), 1);
-        expected_map.insert("y".to_string(), 2);
         assert_eq!(
-            parse::<HashMap<String, u64>>("{ x = 1, y = 2 }"),
-            expected_map
+            from_str("{ x = 1, y = Some 2 }")
+                .static_type_annotation()
+                .treat_missing_as_none(true)
+                .parse::<Foo>()
+                .map_err(|e| e.to_string()),
+            Ok(Foo { x: 1, y: Some(2) })
         );
         assert_eq!(
-            parse::<HashMap<String, u64>>("toMap { x = 1, y = 2 }"),
-            expected_map
+            from_str("{ foo = { x = 1 } }")
+                .static_type_annotation()
+                .treat_missing_as_none(true)
+                .parse::<Bar>()
+                .map_err(|e| e.to_string()),
+            Ok(Bar {
+                foo: Foo { x: 1, y: None },
+                z: None
+            })
         );
 
-        let mut expected_map = HashMap::new();
-        expected_map.insert("if".to_string(), 1);
-        expected_map.insert("FOO_BAR".to_string(), 2);
-        expected_map.insert("baz-kux".to_string(), 3);
+        // Non-optional fields are still required, and present fields must have the right type.
+        assert!(from_str("{ y = Some 2 }")
+            .static_type_annotation()
+            .treat_missing_as_none(true)
+            .parse::<Foo>()
+            .is_err());
+        assert!(from_str("{ x = 1, y = Some True }")
+            .static_type_annotation()
+            .treat_missing_as_none(true)
+            .parse::<Foo>()
+            .is_err());
+    }
+
+    #[test]
+    fn max_depth() {
+        // A record of the form `{ x = { x = ... { x = 1 } } }`, 6 levels deep.
+        let data = format!("{}1{}", "{ x = ".repeat(5), " }".repeat(5));
+
+        assert!(from_str(&data).max_depth(6).parse::<Value>().is_ok());
         assert_eq!(
-            parse::<HashMap<String, u64>>(
-                "{ `if` = 1, FOO_BAR = 2, baz-kux = 3 }"
-            ),
-            expected_map
+            from_str(&data)
+                .max_depth(5)
+                .parse::<Value>()
+                .map_err(|e| e.to_string()),
+            Err("the value is nested more than 5 levels deep".to_string())
         );
 
-        let mut expected_map = BTreeMap::new();
-        expected_map.insert("x".to_string(), 1);
-        expected_map.insert("y".to_string(), 2);
+        // The same limit applies to types.
+        let ty = format!("{}Bool{}", "List (".repeat(4), ")".repeat(4));
+        assert!(from_str(&ty).max_depth(5).parse::<Value>().is_ok());
+        assert!(from_str(&ty).max_depth(4).parse::<Value>().is_err());
+    }
+
+    #[test]
+    fn expect_list_len() {
+        let parse = |data: &str, len: usize| {
+            from_str(data)
+                .expect_list_len(len)
+                .parse::<Vec<u64>>()
+                .map_err(|e| e.to_string())
+        };
+        // Lists built by folds are read once normalized.
+        let built = "
+            List/build Natural
+              ( λ(list : Type) →
+                λ(cons : Natural → list → list) →
+                λ(nil : list) →
+                  Natural/fold 5 list (cons 1) nil
+              )
+        ";
+        assert_eq!(parse(built, 5), Ok(vec![1; 5]));
+        let folded = "
+            Natural/fold 5 (List Natural)
+              (λ(xs : List Natural) → [List/length Natural xs] # xs)
+              ([] : List Natural)
+        ";
+        assert_eq!(parse(folded, 5), Ok(vec![4, 3, 2, 1, 0]));
+
         assert_eq!(
-            parse::<BTreeMap<String, u64>>("{ x = 1, y = 2 }"),
-            expected_map
+            parse(folded, 6),
+            Err("expected a list of 6 elements, found 5 elements".to_owned())
+        );
+        assert_eq!(
+            from_str("{ x = [1] }")
+                .expect_list_len(1)
+                .parse::<Value>()
+                .map_err(|e| e.to_string()),
+            Err("expected a list of 1 element, found a value that is not a list"
+                .to_owned())
         );
+        // The list at a path is checked.
+        assert_eq!(
+            from_str("{ x = [1] }")
+                .at_path("x")
+                .expect_list_len(1)
+                .parse::<Vec<u64>>()
+                .map_err(|e| e.to_string()),
+            Ok(vec![1])
+        );
+    }
 
-        #[derive(Debug, PartialEq, Eq, Deserialize)]
+    #[test]
+    fn top_level_type_mismatch() {
+        let err = |s| from_str(s).parse::<u64>().unwrap_err().to_string();
+        assert_eq!(
+            err("{ x = 1 }"),
+            "expected a value of type `Natural`, found a value of type `{ x : Natural }`"
+        );
+        assert_eq!(
+            err("-1"),
+            "expected a value of type `Natural`, found a value of type `Integer`"
+        );
+        assert_eq!(
+            from_str("[True]").parse::<String>().unwrap_err().to_string(),
+            "expected a value of type `Text`, found a value of type `List Bool`"
+        );
+        // Errors that aren't about the top-level type are left alone.
+        assert_eq!(
+            from_str("300").parse::<u8>().unwrap_err().to_string(),
+            "invalid value: integer `300`, expected u8"
+        );
+    }
+
+    #[test]
+    fn integer_sign() {
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
         struct Foo {
-            x: u64,
-            y: Option<u64>,
+            n: i64,
+        }
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Bar {
+            n: u64,
         }
-        // Omit optional field
-        assert_eq!(parse::<Foo>("{ x = 1 }"), Foo { x: 1, y: None });
 
-        // https://github.com/Nadrieril/dhall-rust/issues/155
-        assert!(from_str("List/length [True, 42]").parse::<bool>().is_err());
+        assert_eq!(
+            from_str("{ n = +1 }").parse::<Foo>().unwrap(),
+            Foo { n: 1 }
+        );
+        assert_eq!(
+            from_str("{ n = Integer/negate -3 }")
+                .parse::<Foo>()
+                .unwrap(),
+            Foo { n: 3 }
+        );
+        assert_eq!(
+            from_str("{ n = 1 }")
+                .parse::<Foo>()
+                .unwrap_err()
+                .to_string(),
+            "expected an `Integer` like `+1`, found the `Natural` `1`"
+        );
+        assert!(from_str("{ n = 1 }")
+            .static_type_annotation()
+            .parse::<Foo>()
+            .is_err());
+        assert_eq!(
+            from_str("{ n = +1 }")
+                .parse::<Bar>()
+                .unwrap_err()
+                .to_string(),
+            "expected a `Natural`, found the `Integer` `+1`"
+        );
+        assert_eq!(from_str("{ n = 1 }").parse::<Bar>().unwrap(), Bar { n: 1 });
+
+        // Unless numbers are coerced.
+        let coerced = |s| from_str(s).coerce_numbers(true);
+        assert_eq!(coerced("{ n = 1 }").parse::<Foo>().unwrap(), Foo { n: 1 });
+        assert_eq!(coerced("{ n = +1 }").parse::<Bar>().unwrap(), Bar { n: 1 });
+        assert!(coerced("{ n = -1 }").parse::<Bar>().is_err());
     }
 
     #[test]
-    fn test_file() {
+    fn coerce_numbers() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Foo {
+            x: f64,
+            y: u8,
+            z: i32,
+        }
+
+        assert_eq!(from_str("1").parse::<f64>().unwrap(), 1.0);
+        assert_eq!(from_str("-1").parse::<f32>().unwrap(), -1.0);
+        assert!(from_str("2.0").parse::<u8>().is_err());
+
+        let data = "{ x = 1, y = 2.0, z = -3.0 }";
         assert_eq!(
-            serde_dhall::from_file(
-                "../dhall-lang/tests/parser/success/unit/BoolLitTrueA.dhall"
+            from_str(data).coerce_numbers(true).parse::<Foo>().unwrap(),
+            Foo {
+                x: 1.0,
+                y: 2,
+                z: -3
+            }
+        );
+        assert!(from_str(data).parse::<Foo>().is_err());
+
+        let parse_u8 = |s| from_str(s).coerce_numbers(true).parse::<u8>();
+        assert!(parse_u8("2.5").is_err());
+        assert!(parse_u8("256.0").is_err());
+        assert!(parse_u8("-1.0").is_err());
+        assert!(parse_u8("Infinity").is_err());
+    }
+
+    #[test]
+    fn max_normal_form_nodes() {
+        let replicate = |n| {
+            format!(
+                "Natural/fold {} (List Natural) (λ(xs : List Natural) → xs # [1]) ([] : List Natural)",
+                n
             )
-            .static_type_annotation()
-            .parse::<bool>()
-            .map_err(|e| e.to_string()),
-            Ok(true)
+        };
+        assert_eq!(
+            from_str(&replicate(3))
+                .max_normal_form_nodes(100)
+                .parse::<Vec<u64>>()
+                .unwrap(),
+            vec![1, 1, 1]
         );
         assert_eq!(
-            serde_dhall::from_binary_file(
-                "../dhall-lang/tests/parser/success/unit/BoolLitTrueB.dhallb"
-            )
-            .static_type_annotation()
-            .parse::<bool>()
-            .map_err(|e| e.to_string()),
-            Ok(true)
+            from_str(&replicate(1_000_000))
+                .max_normal_form_nodes(100)
+                .parse::<Vec<u64>>()
+                .map_err(|e| e.to_string()),
+            Err("normalization produced more than 100 nodes".to_owned())
         );
+        // The limit also applies to the value selected with `at_path`.
+        assert!(from_str(&format!("{{ xs = {} }}", replicate(1_000_000)))
+            .max_normal_form_nodes(100)
+            .at_path("xs")
+            .parse::<Vec<u64>>()
+            .is_err());
     }
 
     #[test]
-    fn test_import() {
-        assert_de(
-            "../dhall-lang/tests/parser/success/unit/BoolLitTrueA.dhall",
-            true,
+    fn union_as_tagged() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        #[serde(tag = "kind")]
+        enum Server {
+            Web { port: u64 },
+            Db { path: String },
+        }
+
+        let web = Server::Web { port: 80 };
+        let record = r#"{ kind = "Web", port = 80 }"#;
+        assert_eq!(from_str(record).parse::<Server>().unwrap(), web);
+        assert_eq!(
+            from_str(record)
+                .union_as_tagged("kind")
+                .parse::<Server>()
+                .unwrap(),
+            web
+        );
+
+        let union = "< Web : { port : Natural } | Db : { path : Text } >";
+        let data = format!(
+            "[ {u}.Web {{ port = 80 }}, {u}.Db {{ path = \"/db\" }} ]",
+            u = union
         );
         assert_eq!(
-            serde_dhall::from_str(
-                "../dhall-lang/tests/parser/success/unit/BoolLitTrueA.dhall"
-            )
-            .imports(false)
-            .static_type_annotation()
-            .parse::<bool>()
-            .map_err(|e| e.to_string()),
-            Err("UnexpectedImport(Import { mode: Code, location: Local(Parent, FilePath { file_path: [\"dhall-lang\", \"tests\", \"parser\", \"success\", \"unit\", \"BoolLitTrueA.dhall\"] }), hash: None })".to_string())
+            from_str(&data)
+                .union_as_tagged("kind")
+                .parse::<Vec<Server>>()
+                .unwrap(),
+            vec![
+                web,
+                Server::Db {
+                    path: "/db".to_owned()
+                }
+            ]
+        );
+        assert!(from_str(&data).parse::<Vec<Server>>().is_err());
+
+        // Externally tagged enums still read unions.
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Plain {
+            Web { port: u64 },
+        }
+        assert_eq!(
+            from_str("< Web : { port : Natural } >.Web { port = 80 }")
+                .union_as_tagged("kind")
+                .parse::<Plain>()
+                .unwrap(),
+            Plain::Web { port: 80 }
         );
     }
 
     #[test]
-    #[ignore] // Way too slow
-    fn test_prelude() {
+    fn parse_with_warnings() {
+        use serde_dhall::WarningKind;
+
+        let data = "let x = 1 in let y = 2 in { a = y }";
+        let (val, warnings) = from_str(data)
+            .parse_with_warnings::<collections::HashMap<String, u64>>()
+            .unwrap();
+        assert_eq!(val.get("a"), Some(&2));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::UnusedLet);
+        assert_eq!(warnings[0].span, Some((0, data.len())));
+
+        // Builtin types don't count as unused bindings.
+        let ty = from_str("Natural").parse().unwrap();
+        let (_, warnings) = from_str("[] : List Foo")
+            .with_builtin_type("Foo".to_string(), ty)
+            .parse_with_warnings::<Vec<u64>>()
+            .unwrap();
+        assert!(warnings.is_empty());
+
+        // No warnings for values that don't typecheck.
+        assert!(from_str("let x = 1 in True + 1")
+            .parse_with_warnings::<u64>()
+            .is_err());
+    }
+
+    #[test]
+    fn parse_ast() {
+        let data = "let x = 1 in { a = x + 1 }";
         assert_eq!(
-            serde_dhall::from_str(
-                "https://prelude.dhall-lang.org/package.dhall"
-            )
-            .parse::<Value>()
-            .map(|_| ())
-            .map_err(|e| e.to_string()),
-            Ok(())
+            from_str(data).parse_ast().unwrap().to_string(),
+            "let x = 1 in { a = x + 1 }"
+        );
+        assert_eq!(
+            from_str(data).parse::<Value>().unwrap().to_string(),
+            "{ a = 2 }"
+        );
+
+        // The expression is still typechecked.
+        let ty = from_str("{ a : Natural }").parse().unwrap();
+        assert!(from_str(data).type_annotation(&ty).parse_ast().is_ok());
+        let ty = from_str("{ a : Bool }").parse().unwrap();
+        assert!(from_str(data).type_annotation(&ty).parse_ast().is_err());
+        assert!(from_str("1 + True").parse_ast().is_err());
+
+        // Builtin types are not part of the returned expression.
+        let ty = from_str("{ a : Natural }").parse().unwrap();
+        assert_eq!(
+            from_str("[] : List Foo")
+                .with_builtin_type("Foo".to_string(), ty)
+                .parse_ast()
+                .unwrap()
+                .to_string(),
+            "[] : List Foo"
+        );
+
+        // A path can only be found in the evaluated config.
+        assert_eq!(
+            from_str(data)
+                .at_path("a")
+                .parse_ast()
+                .map_err(|e| e.to_string()),
+            Err("cannot read the expression at `a`: paths are only found in the \
+                 evaluated config"
+                .to_owned())
         );
     }
 
+    #[test]
+    #[cfg(feature = "json")]
+    fn from_json_value() {
+        use serde_json::json;
+        use std::collections::HashMap;
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Foo {
+            x: u64,
+            y: i64,
+            z: Vec<Option<String>>,
+            w: Option<bool>,
+            e: Baz,
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Baz {
+            A,
+            B(f64),
+        }
+
+        let ty: serde_dhall::SimpleType = serde_dhall::from_str(
+            "{ x : Natural, y : Integer, z : List (Optional Text), w : Optional Bool, e : < A | B : Double > }",
+        )
+        .parse()
+        .unwrap();
+        let from_json = |v| serde_dhall::from_json_value::<Foo>(&v, &ty);
+
+        assert_eq!(
+            from_json(json!({
+                "x": 1,
+                "y": -2,
+                "z": ["a", null],
+                "e": "A",
+            }))
+            .unwrap(),
+            Foo {
+                x: 1,
+                y: -2,
+                z: vec![Some("a".to_string()), None],
+                w: None,
+                e: Baz::A,
+            }
+        );
+        assert_eq!(
+            from_json(json!({ "x": 1, "y": 2, "z": [], "w": true, "e": 1.5 }))
+                .unwrap()
+                .e,
+            Baz::B(1.5)
+        );
+        assert_eq!(
+            from_json(json!({ "x": -1, "y": 2, "z": [], "e": "A" }))
+                .map_err(|e| e.to_string()),
+            Err("expected a value of type `Natural`, found: -1".to_string())
+        );
+        assert_eq!(
+            from_json(json!({ "x": 1, "y": 2, "z": [], "e": "A", "f": 0 }))
+                .map_err(|e| e.to_string()),
+            Err("unexpected field `f` for a record of type `{ e : < A | B: Double >, w : Optional Bool, x : Natural, y : Integer, z : List (Optional Text) }`".to_string())
+        );
+
+        let ty = serde_dhall::from_str("List { a : Natural }")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            serde_dhall::from_json_value::<Vec<HashMap<String, u64>>>(
+                &json!([]),
+                &ty
+            )
+            .unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn serialize_value() {
+        let value: Value = from_str(
+            "{ name = \"web\", port = 8080, tls = None Bool, \
+               mode = < Dev | Prod : Natural >.Prod 2, \
+               hosts = [\"a\", \"b\"], limits = { cpu = 1.5, nice = -1 } }",
+        )
+        .parse()
+        .unwrap();
+
+        // CBOR
+        let cbor = serde_cbor::to_vec(&value).unwrap();
+        let cbor: serde_cbor::Value = serde_cbor::from_slice(&cbor).unwrap();
+        let text = |s: &str| serde_cbor::Value::Text(s.to_owned());
+        let map = |entries: Vec<(&str, serde_cbor::Value)>| {
+            serde_cbor::Value::Map(
+                entries.into_iter().map(|(k, v)| (text(k), v)).collect(),
+            )
+        };
+        assert_eq!(
+            cbor,
+            map(vec![
+                (
+                    "hosts",
+                    serde_cbor::Value::Array(vec![text("a"), text("b")])
+                ),
+                (
+                    "limits",
+                    map(vec![
+                        ("cpu", serde_cbor::Value::Float(1.5)),
+                        ("nice", serde_cbor::Value::Integer(-1)),
+                    ])
+                ),
+                (
+                    "mode",
+                    map(vec![
+                        ("tag", text("Prod")),
+                        ("value", serde_cbor::Value::Integer(2)),
+                    ])
+                ),
+                ("name", text("web")),
+                ("port", serde_cbor::Value::Integer(8080)),
+                ("tls", serde_cbor::Value::Null),
+            ])
+        );
+
+        // TOML, which can't write `None`, and wants tables last.
+        let value: Value = from_str(
+            "{ name = \"web\", port = Some 8080, tier = < Dev | Prod >.Dev }",
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            toml::to_string(&value).map_err(|e| e.to_string()),
+            Ok("name = \"web\"\nport = 8080\n\n[tier]\ntag = \"Dev\"\n"
+                .to_owned())
+        );
+
+        // Types aren't data.
+        let ty: Value = from_str("Natural").parse().unwrap();
+        assert_eq!(
+            serde_cbor::to_vec(&ty).map_err(|e| e.to_string()),
+            Err("a type can't be serialized as data: Natural".to_owned())
+        );
+        let f: Value = from_str("Natural/even").parse().unwrap();
+        assert_eq!(
+            serde_cbor::to_vec(&f).map_err(|e| e.to_string()),
+            Err("a function can't be serialized as data: Natural/even"
+                .to_owned())
+        );
+
+        // Serializing to Dhall keeps the value as it is.
+        let value: Value = from_str("< A | B : Bool >.A").parse().unwrap();
+        assert_eq!(
+            serialize(&value).to_string().map_err(|e| e.to_string()),
+            Ok("< A | B: Bool >.A".to_owned())
+        );
+        assert_eq!(
+            serialize(&ty).to_string().map_err(|e| e.to_string()),
+            Ok("Natural".to_owned())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn serialize_value_to_json() {
+        let value: Value = from_str(
+            "{ port = Some 8080, tls = None Bool, \
+               mode = < Dev | Prod : Natural >.Prod 2, dev = < Dev | Prod >.Dev }",
+        )
+        .parse()
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&value).map_err(|e| e.to_string()),
+            Ok(r#"{"dev":{"tag":"Dev"},"mode":{"tag":"Prod","value":2},"port":8080,"tls":null}"#
+                .to_owned())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn to_json_with_source_map() {
+        use serde_json::{json, Value as JsonValue};
+
+        /// The pointers of the values that are not arrays nor objects, or that are empty.
+        fn leaves(json: &JsonValue, pointer: String, out: &mut Vec<String>) {
+            match json {
+                JsonValue::Array(xs) if !xs.is_empty() => {
+                    for (i, x) in xs.iter().enumerate() {
+                        leaves(x, format!("{}/{}", pointer, i), out)
+                    }
+                }
+                JsonValue::Object(kvs) if !kvs.is_empty() => {
+                    for (k, v) in kvs {
+                        let k = k.replace('~', "~0").replace('/', "~1");
+                        leaves(v, format!("{}/{}", pointer, k), out)
+                    }
+                }
+                _ => out.push(pointer),
+            }
+        }
+
+        let dir = TempDir::new("source_map");
+        std::fs::write(dir.join("extra.dhall"), "{ x = [1, 2] }").unwrap();
+        let source = format!(
+            r#"let defaults = {{ retries = 3 }}
+let Mode = < Fast | Limited : Natural >
+in  {{ users =
+        [ {{ name = "alice", `a/b` = Some 1.5, mode = Mode.Fast }}
+        , {{ name = "bob", `a/b` = None Double, mode = Mode.Limited 2 }}
+        ]
+    , defaults
+    , extra = {}
+    , server.port = 8080
+    , empty = [] : List Natural
+    }}"#,
+            dir.join("extra.dhall").display()
+        );
+        let expr = from_str(&source).parse_ast().unwrap();
+        let (json, source_map) =
+            serde_dhall::to_json_with_source_map(&expr, &source).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "users": [
+                    { "name": "alice", "a/b": 1.5, "mode": "Fast" },
+                    { "name": "bob", "a/b": null, "mode": 2 },
+                ],
+                "defaults": { "retries": 3 },
+                "extra": { "x": [1, 2] },
+                "server": { "port": 8080 },
+                "empty": [],
+            })
+        );
+
+        let mut pointers = Vec::new();
+        leaves(&json, String::new(), &mut pointers);
+        assert_eq!(pointers.len(), 11);
+        for pointer in pointers {
+            let span = source_map
+                .get(&pointer)
+                .unwrap_or_else(|| panic!("no span for `{}`", pointer));
+            assert!(json.pointer(&pointer).is_some());
+            assert!(span.start <= span.end && span.end <= source.len());
+        }
+
+        let at = |pointer: &str| {
+            let span = source_map[pointer];
+            (span.to_string(), &source[span.start..span.end])
+        };
+        // The whole document is the body of the `let`s.
+        assert_eq!(
+            at(""),
+            (
+                "line 3, col 5".to_owned(),
+                &source[source.find("{ users").unwrap()..]
+            )
+        );
+        assert_eq!(
+            at("/users/1/name"),
+            ("line 5, col 20".to_owned(), "\"bob\"")
+        );
+        assert_eq!(at("/users/0/a~1b"), ("line 4, col 42".to_owned(), "1.5"));
+        assert_eq!(
+            at("/users/1/mode"),
+            ("line 5, col 55".to_owned(), "Mode.Limited 2")
+        );
+        // Values that come from an import map to the import.
+        let import = dir.join("extra.dhall").display().to_string();
+        assert_eq!(at("/extra/x/1"), ("line 8, col 15".to_owned(), &*import));
+        assert_eq!(at("/server/port"), ("line 9, col 21".to_owned(), "8080"));
+    }
+
+    #[test]
+    fn value_eq_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        use std::hash::{Hash, Hasher};
+
+        fn parse(s: &str) -> Value {
+            from_str(s).parse().unwrap()
+        }
+        fn hash(v: &Value) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            v.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        assert_eq!(parse("1 + 1"), parse("2"));
+        assert_eq!(hash(&parse("1 + 1")), hash(&parse("2")));
+        assert_eq!(
+            parse("{ b = [True], a = Some \"x\" }"),
+            parse("let t = \"x\" in { a = Some t, b = [True || False] }")
+        );
+        assert_ne!(parse("1"), parse("2"));
+
+        let configs: HashSet<Value> = vec![
+            parse("{ x = 1 }"),
+            parse("{ x = 0 + 1 }"),
+            parse("{ x = 2 }"),
+            parse("Natural"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(configs.len(), 3);
+    }
+
+    #[test]
+    fn value_merge() {
+        use serde_dhall::MergeError;
+
+        fn parse(s: &str) -> Value {
+            from_str(s).parse().unwrap()
+        }
+        let left = parse("{ a = { b = 1, c = [] : List Bool }, d = True }");
+        let right = parse("{ a = { e = None Text }, f = 2 }");
+
+        let merged = Value::merge(&left, &right).unwrap();
+        assert_eq!(
+            merged,
+            parse(
+                r#"{ a = { b = 1, c = [] : List Bool, e = None Text }, d = True, f = 2 }"#
+            )
+        );
+        // The type is merged too, so empty values can still be printed.
+        assert_eq!(
+            merged.to_string(),
+            "{ a = { b = 1, c = [] : List Bool, e = None Text }, d = True, f = 2 }"
+        );
+
+        assert_eq!(
+            Value::merge(&left, &parse("{ a = { b = 2 } }")),
+            Err(MergeError::FieldCollision("a.b".to_owned()))
+        );
+        assert_eq!(
+            Value::merge(&left, &parse("{ d = { x = 1 } }")),
+            Err(MergeError::FieldCollision("d".to_owned()))
+        );
+        assert_eq!(
+            Value::merge(&left, &parse("1")),
+            Err(MergeError::NotARecord)
+        );
+
+        let merged = Value::right_biased_merge(
+            &left,
+            &parse("{ a = { e = 1 }, d = \"x\" }"),
+        );
+        assert_eq!(merged.to_string(), "{ a = { e = 1 }, d = \"x\" }");
+        assert_eq!(Value::right_biased_merge(&left, &parse("1")), parse("1"));
+    }
+
+    #[test]
+    fn merge_sources() {
+        use serde_dhall::{MergeStrategy, Source};
+
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Db {
+            host: String,
+            pool: u64,
+        }
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Config {
+            name: String,
+            port: u64,
+            db: Db,
+        }
+
+        // Each layer has its own directory, with imports relative to it.
+        let dir = TempDir::new("merge_sources");
+        std::fs::create_dir_all(dir.join("base")).unwrap();
+        std::fs::create_dir_all(dir.join("prod")).unwrap();
+        std::fs::write(dir.join("base/port.dhall"), "80").unwrap();
+        std::fs::write(
+            dir.join("base/config.dhall"),
+            "{ name = \"app\", port = ./port.dhall, \
+               db = { host = \"localhost\", pool = 4 } }",
+        )
+        .unwrap();
+        std::fs::write(dir.join("prod/host.dhall"), "\"db.internal\"").unwrap();
+        std::fs::write(
+            dir.join("prod/config.dhall"),
+            "{ db = { host = ./host.dhall } }",
+        )
+        .unwrap();
+        let sources = [
+            Source::File(dir.join("base/config.dhall")),
+            Source::File(dir.join("prod/config.dhall")),
+            Source::Str("{ port = 8080 }"),
+        ];
+        let merged = |strategy| {
+            serde_dhall::merge_sources(&sources, strategy)
+                .static_type_annotation()
+                .parse::<Config>()
+                .map_err(|e| e.to_string())
+        };
+        let combined = merged(MergeStrategy::Combine);
+        let preferred = merged(MergeStrategy::Prefer);
+        let untyped =
+            serde_dhall::merge_sources(&sources, MergeStrategy::Prefer)
+                .parse::<Value>()
+                .map(|v| v.to_string());
+
+        // `port` is set twice, which `∧` doesn't allow.
+        assert!(combined.is_err());
+        // `⫽` replaces the whole `db`, so the result doesn't have a `pool`.
+        assert!(preferred.is_err());
+        assert_eq!(
+            untyped.unwrap(),
+            "{ db = { host = \"db.internal\" }, name = \"app\", port = 8080 }"
+        );
+
+        // Without the collision, `∧` merges the nested records.
+        let sources = [
+            Source::Str("{ name = \"app\", db = { host = \"localhost\" } }"),
+            Source::Str("{ db = { pool = 4 } }"),
+            Source::Str("{ port = 8080 }"),
+        ];
+        assert_eq!(
+            serde_dhall::merge_sources(&sources, MergeStrategy::Combine)
+                .static_type_annotation()
+                .parse::<Config>()
+                .map_err(|e| e.to_string()),
+            Ok(Config {
+                name: "app".to_owned(),
+                port: 8080,
+                db: Db {
+                    host: "localhost".to_owned(),
+                    pool: 4
+                },
+            })
+        );
+        assert_eq!(
+            serde_dhall::merge_sources(&[], MergeStrategy::Prefer)
+                .parse::<Value>()
+                .map(|v| v.to_string())
+                .map_err(|e| e.to_string()),
+            Ok("{=}".to_owned())
+        );
+    }
+
+    #[test]
+    fn from_value() {
+        use serde_dhall::{
+            from_dhall_value, from_value, NumKind, SimpleType, SimpleValue,
+        };
+
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Server {
+            host: String,
+            port: u64,
+        }
+
+        let value: Value =
+            from_str("{ host = \"a\", port = 80 }").parse().unwrap();
+        let server = Server {
+            host: "a".to_owned(),
+            port: 80,
+        };
+        assert_eq!(from_dhall_value::<Server>(&value).unwrap(), server);
+        assert_eq!(value.clone().try_into::<Server>().unwrap(), server);
+        assert_eq!(
+            from_value(value.clone()).parse::<Server>().unwrap(),
+            server
+        );
+        assert_eq!(
+            from_dhall_value::<collections::BTreeMap<String, SimpleValue>>(
+                &value
+            )
+            .unwrap()["port"],
+            SimpleValue::Num(NumKind::Natural(80))
+        );
+
+        // Annotations are still checked.
+        assert!(from_value(value.clone())
+            .static_type_annotation()
+            .parse::<Server>()
+            .is_ok());
+        let ty: SimpleType = from_str("{ host : Text }").parse().unwrap();
+        assert!(from_value(value.clone())
+            .type_annotation(&ty)
+            .parse::<Server>()
+            .is_err());
+        assert!(from_dhall_value::<u64>(&value).is_err());
+    }
+
+    #[test]
+    fn at_path() {
+        let config = r#"
+            let web = { host = "example.com", port = 80 + 8000 }
+            in { services = { web, db = { port = 5432 } }, debug = True }
+        "#;
+        assert_eq!(
+            from_str(config)
+                .at_path("services.web.port")
+                .parse::<u64>()
+                .unwrap(),
+            8080
+        );
+        // The annotation applies to the selected value.
+        #[derive(Debug, PartialEq, Deserialize, StaticType)]
+        struct Db {
+            port: u64,
+        }
+        assert_eq!(
+            from_str(config)
+                .at_path("services.db")
+                .static_type_annotation()
+                .parse::<Db>()
+                .unwrap(),
+            Db { port: 5432 }
+        );
+
+        let error = |path: &str| {
+            from_str(config)
+                .at_path(path)
+                .parse::<Value>()
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("services.mail.port"),
+            "cannot read `services.mail.port`: `services` has no field `mail`"
+        );
+        assert_eq!(
+            error("debug.level"),
+            "cannot read `debug.level`: `debug` is not a record"
+        );
+        assert_eq!(
+            error("service"),
+            "cannot read `service`: the config has no field `service`"
+        );
+
+        // An empty path reads the whole config.
+        assert_eq!(
+            from_str("{ x = 1 }").at_path("").parse::<Value>().unwrap(),
+            from_str("{ x = 1 }").parse::<Value>().unwrap()
+        );
+
+        // Quoted field names can contain dots.
+        let hosts = "{ hosts = { `example.com` = { port = 443 } } }";
+        assert_eq!(
+            from_str(hosts)
+                .at_path("hosts.`example.com`.port")
+                .parse::<u64>()
+                .unwrap(),
+            443
+        );
+        assert_eq!(
+            from_str(hosts)
+                .at_path("hosts.`example.org`.port")
+                .parse::<u64>()
+                .unwrap_err()
+                .to_string(),
+            "cannot read `hosts.`example.org`.port`: `hosts` has no field `example.org`"
+        );
+    }
+
+    #[test]
+    fn substitute_env_vars() {
+        std::env::set_var("SERDE_DHALL_TEST_HOST", "example.com");
+        std::env::set_var("SERDE_DHALL_TEST_QUOTE", "say \"${hi}\"\n");
+        std::env::remove_var("SERDE_DHALL_TEST_MISSING");
+        std::env::remove_var("SERDE_DHALL_TEST_MISSING2");
+        let parse = |s: &str| {
+            from_str(s)
+                .substitute_env_vars(true)
+                .parse::<String>()
+                .map_err(|e| e.to_string())
+        };
+
+        assert_eq!(
+            parse(r#""http://$ENV{SERDE_DHALL_TEST_HOST}:80/""#),
+            Ok("http://example.com:80/".to_owned())
+        );
+        // Values are escaped.
+        assert_eq!(
+            parse(r#""$ENV{SERDE_DHALL_TEST_QUOTE}""#),
+            Ok("say \"${hi}\"\n".to_owned())
+        );
+        // Other uses of `$` are left alone.
+        assert_eq!(
+            parse(r#""$ENV{} $ENV{a b} \$1 ${"x"}""#),
+            Ok("$ENV{} $ENV{a b} $1 x".to_owned())
+        );
+        assert_eq!(
+            parse(
+                r#""$ENV{SERDE_DHALL_TEST_MISSING2} $ENV{SERDE_DHALL_TEST_HOST} $ENV{SERDE_DHALL_TEST_MISSING} $ENV{SERDE_DHALL_TEST_MISSING2}""#
+            ),
+            Err("missing environment variables: SERDE_DHALL_TEST_MISSING2, \
+                 SERDE_DHALL_TEST_MISSING"
+                .to_owned())
+        );
+        // Substitution is off by default.
+        assert_eq!(
+            from_str(r#""$ENV{SERDE_DHALL_TEST_HOST}""#)
+                .parse::<String>()
+                .unwrap(),
+            "$ENV{SERDE_DHALL_TEST_HOST}"
+        );
+    }
+
+    #[test]
+    fn normalize() {
+        use serde_dhall::SimpleType;
+
+        #[derive(Debug, PartialEq, Eq, Deserialize)]
+        struct Config {
+            name: String,
+            port: Option<u64>,
+            tags: collections::BTreeMap<String, bool>,
+            mode: Mode,
+        }
+        #[derive(Debug, PartialEq, Eq, Deserialize)]
+        enum Mode {
+            Fast,
+            Slow(u64),
+        }
+
+        let config = r#"
+            { name = "web"
+            , port = Some 80
+            , tags = [ { mapKey = "public", mapValue = True } ]
+            , mode = < Fast | Slow : Natural >.Slow 2
+            } : { name : Text
+                , port : Optional Natural
+                , tags : List { mapKey : Text, mapValue : Bool }
+                , mode : < Fast | Slow : Natural >
+                }
+        "#;
+        assert_eq!(
+            from_str(config).normalize(false).parse::<Config>().unwrap(),
+            Config {
+                name: "web".to_owned(),
+                port: Some(80),
+                tags: vec![("public".to_owned(), true)].into_iter().collect(),
+                mode: Mode::Slow(2),
+            }
+        );
+
+        let parse = |s: &str| {
+            from_str(s)
+                .normalize(false)
+                .parse::<u64>()
+                .map_err(|e| e.to_string())
+        };
+        // A lambda is rejected by a scalar target.
+        assert_eq!(
+            parse("λ(x : Natural) → x"),
+            Err("this is not a simple value without normalization: \
+                 λ(x : Natural) → x"
+                .to_owned())
+        );
+        let app = "(λ(x : Natural) → x) 1";
+        assert_eq!(from_str(app).parse::<u64>().unwrap(), 1);
+        assert_eq!(
+            parse(app),
+            Err("this is not a simple value without normalization: \
+                 (λ(x : Natural) → x) 1"
+                .to_owned())
+        );
+        assert!(parse("1 + 1").is_err());
+        assert!(parse("let x = 1 in x").is_err());
+        assert_eq!(parse("1"), Ok(1));
+        // Types are still normalized.
+        assert_eq!(
+            from_str("let T = Natural in List T")
+                .normalize(false)
+                .parse::<SimpleType>()
+                .unwrap(),
+            SimpleType::List(Box::new(SimpleType::Natural))
+        );
+    }
+
+    #[test]
+    fn fingerprint() {
+        let fingerprint = |s: &str| from_str(s).fingerprint().unwrap();
+
+        let a = fingerprint("{ port = 8080, hosts = [\"a\"] }");
+        assert!(a.starts_with("sha256:"));
+        assert_eq!(a.len(), "sha256:".len() + 64);
+        // Formatting, evaluation and the names of bound variables don't matter.
+        assert_eq!(
+            a,
+            fingerprint("let p = 8000 in {hosts=[\"a\"],port=p + 80}")
+        );
+        assert_eq!(
+            fingerprint("λ(a : Natural) → a + 1"),
+            fingerprint("λ(b : Natural) → b + 1")
+        );
+        assert_ne!(a, fingerprint("{ port = 8081, hosts = [\"a\"] }"));
+        assert_eq!(
+            Some(a.clone()),
+            dhall::syntax::parse_expr("{ port = 8080, hosts = [\"a\"] }")
+                .ok()
+                .and_then(|e| dhall::fingerprint(&e).ok())
+        );
+        assert!(from_str("1 + True").fingerprint().is_err());
+        // Like `parse`, it reads the value at the path, and bounds normalization.
+        assert_eq!(
+            from_str(
+                "{ server = { port = 8080, hosts = [\"a\"] }, debug = True }"
+            )
+            .at_path("server")
+            .fingerprint()
+            .unwrap(),
+            a
+        );
+        assert!(from_str("{ x = 1 }").at_path("y").fingerprint().is_err());
+        assert!(from_str("List/replicate 1000 Natural 1")
+            .max_normal_form_nodes(100)
+            .fingerprint()
+            .is_err());
+
+        // It is the hash that protects imports.
+        let dir = TempDir::new("fingerprint");
+        let file = dir.join("config.dhall");
+        std::fs::write(&file, "{ hosts = [\"a\"], port = 8080 }").unwrap();
+        assert_eq!(serde_dhall::from_file(&file).fingerprint().unwrap(), a);
+        let import = format!("{} {}", file.display(), a);
+        assert_eq!(from_str(&import).fingerprint().unwrap(), a);
+    }
+
+    #[test]
+    fn type_annotation_from_file() {
+        let dir = TempDir::new("schema");
+        std::fs::write(dir.join("port.dhall"), "Natural").unwrap();
+        std::fs::write(
+            dir.join("schema.dhall"),
+            "{ host : Text, port : ./port.dhall }",
+        )
+        .unwrap();
+        let schema = dir.join("schema.dhall");
+        let data = dir.join("data.dhall");
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Config {
+            host: String,
+            port: u64,
+        }
+        std::fs::write(&data, r#"{ host = "localhost", port = 8000 + 80 }"#)
+            .unwrap();
+        assert_eq!(
+            serde_dhall::from_file(&data)
+                .type_annotation_from_file(&schema)
+                .parse::<Config>()
+                .unwrap(),
+            Config {
+                host: "localhost".to_owned(),
+                port: 8080,
+            }
+        );
+
+        // Data that doesn't match the schema is rejected.
+        std::fs::write(&data, r#"{ host = "localhost", port = "80" }"#)
+            .unwrap();
+        assert!(serde_dhall::from_file(&data)
+            .type_annotation_from_file(&schema)
+            .parse::<Value>()
+            .is_err());
+
+        // The schema is read with the same options as the data.
+        assert!(from_str(r#"{ host = "localhost", port = 80 }"#)
+            .type_annotation_from_file(&schema)
+            .imports(false)
+            .parse::<Value>()
+            .is_err());
+        assert!(from_str(r#"{ host = "localhost", port = 80 }"#)
+            .type_annotation_from_file(dir.join("missing.dhall"))
+            .parse::<Value>()
+            .is_err());
+    }
+
+    #[test]
+    fn to_env_string() {
+        use serde_dhall::{to_env_string, ToEnvError};
+
+        let env =
+            |s: &str| to_env_string(&from_str(s).parse::<Value>().unwrap());
+
+        assert_eq!(
+            env(r#"
+                { logLevel = "debug"
+                , db = { host = "localhost", port = 5432, ssl = False, user = None Text }
+                , `max-retries` = Some 3
+                , weird = "it's $HOME `ls` \"quoted\"\nline"
+                }
+            "#),
+            Ok("DB_HOST='localhost'\n\
+                DB_PORT=5432\n\
+                DB_SSL=false\n\
+                LOG_LEVEL='debug'\n\
+                MAX_RETRIES=3\n\
+                WEIRD='it'\\''s $HOME `ls` \"quoted\"\nline'\n"
+                .to_owned())
+        );
+
+        assert_eq!(env("1"), Err(ToEnvError::NotARecord));
+        assert_eq!(env("Natural"), Err(ToEnvError::NotARecord));
+        // The fields of a record type are types.
+        assert_eq!(
+            env("{ port : Natural, host : Text }"),
+            Err(ToEnvError::UnsupportedType("host".to_owned()))
+        );
+        assert_eq!(
+            env("{ a = { b = [1] } }"),
+            Err(ToEnvError::UnsupportedType("a.b".to_owned()))
+        );
+        assert_eq!(
+            env("{ a = < X | Y >.X }"),
+            Err(ToEnvError::UnsupportedType("a".to_owned()))
+        );
+        assert_eq!(
+            env("{ `2fa` = True }"),
+            Err(ToEnvError::InvalidName("2fa".to_owned()))
+        );
+        assert_eq!(
+            env("{ logLevel = 1, log_level = 2 }"),
+            Err(ToEnvError::DuplicateName(
+                "logLevel".to_owned(),
+                "log_level".to_owned()
+            ))
+        );
+        assert_eq!(
+            env("{ a = { b = 1 }, a_b = 2 }"),
+            Err(ToEnvError::DuplicateName(
+                "a.b".to_owned(),
+                "a_b".to_owned()
+            ))
+        );
+
+        // A shell reads back the same values.
+        #[cfg(unix)]
+        {
+            let vars =
+                env(r#"{ a = "it's $HOME `ls` \"q\"\n\\", b = 2 }"#).unwrap();
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("{}printf '%s|%s' \"$A\" \"$B\"", vars))
+                .output()
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                "it's $HOME `ls` \"q\"\n\\|2"
+            );
+        }
+    }
+
+    #[test]
+    fn load_config() {
+        #[derive(Debug, PartialEq, Deserialize, Serialize, StaticType)]
+        struct Database {
+            host: String,
+            port: u64,
+        }
+        #[derive(Debug, PartialEq, Deserialize, Serialize, StaticType)]
+        struct Config {
+            name: String,
+            debug: bool,
+            retries: u64,
+            db: Database,
+            tag: Option<String>,
+        }
+        let defaults = || Config {
+            name: "default".to_owned(),
+            debug: false,
+            retries: 1,
+            db: Database {
+                host: "localhost".to_owned(),
+                port: 5432,
+            },
+            tag: None,
+        };
+
+        let dir = TempDir::new("load_config");
+        std::fs::write(dir.join("retries.dhall"), "3").unwrap();
+        std::fs::write(
+            dir.join("config.dhall"),
+            r#"{ name = "file", retries = ./retries.dhall, db = { host = "db", port = 1 } }"#,
+        )
+        .unwrap();
+        let path = dir.join("config.dhall");
+
+        // Without environment variables, the file wins over the defaults.
+        assert_eq!(
+            serde_dhall::load_config(&path, defaults(), "LOAD_CONFIG_UNSET_")
+                .unwrap(),
+            Config {
+                name: "file".to_owned(),
+                retries: 3,
+                db: Database {
+                    host: "db".to_owned(),
+                    port: 1,
+                },
+                ..defaults()
+            }
+        );
+
+        // Removes the variables even if an assertion fails, so that they don't leak into the
+        // other tests.
+        struct Vars(&'static [&'static str]);
+        impl Drop for Vars {
+            fn drop(&mut self) {
+                for name in self.0 {
+                    std::env::remove_var(name);
+                }
+            }
+        }
+        let _vars = Vars(&[
+            "LOAD_CONFIG_RETRIES",
+            "LOAD_CONFIG_DEBUG",
+            "LOAD_CONFIG_DB_PORT",
+            "LOAD_CONFIG_TAG",
+            "LOAD \"CONFIG\" ${x}_RETRIES",
+        ]);
+
+        // Environment variables win over both.
+        std::env::set_var("LOAD_CONFIG_RETRIES", "5");
+        std::env::set_var("LOAD_CONFIG_DEBUG", "True");
+        std::env::set_var("LOAD_CONFIG_DB_PORT", "6543");
+        std::env::set_var("LOAD_CONFIG_TAG", "v1 \"quoted\"");
+        assert_eq!(
+            serde_dhall::load_config(&path, defaults(), "LOAD_CONFIG_")
+                .unwrap(),
+            Config {
+                name: "file".to_owned(),
+                debug: true,
+                retries: 5,
+                db: Database {
+                    host: "db".to_owned(),
+                    port: 6543,
+                },
+                tag: Some("v1 \"quoted\"".to_owned()),
+            }
+        );
+
+        // The prefix is not Dhall code.
+        std::env::set_var("LOAD \"CONFIG\" ${x}_RETRIES", "7");
+        assert_eq!(
+            serde_dhall::load_config(
+                &path,
+                defaults(),
+                "LOAD \"CONFIG\" ${x}_"
+            )
+            .unwrap()
+            .retries,
+            7
+        );
+        assert_eq!(
+            serde_dhall::load_config(&path, defaults(), "LOAD=")
+                .unwrap_err()
+                .to_string(),
+            "`LOAD=` is not a valid prefix for environment variables"
+        );
+
+        std::env::set_var("LOAD_CONFIG_RETRIES", "five");
+        assert!(serde_dhall::load_config(&path, defaults(), "LOAD_CONFIG_")
+            .is_err());
+        assert!(serde_dhall::load_config(
+            dir.join("missing.dhall"),
+            defaults(),
+            "LOAD_CONFIG_"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn flatten() {
+        use serde_dhall::SimpleValue;
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Mode {
+            Fast,
+            Limited(u64),
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Database {
+            host: String,
+            port: Option<u16>,
+            mode: Mode,
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Config {
+            name: String,
+            #[serde(flatten)]
+            db: Database,
+        }
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Service {
+            config: Config,
+            #[serde(flatten)]
+            rest: collections::BTreeMap<String, SimpleValue>,
+        }
+
+        let service = from_str(
+            r#"
+            { config =
+                { name = "app"
+                , host = "localhost"
+                , port = Some 5432
+                , mode = < Fast | Limited : Natural >.Limited 10
+                }
+            , replicas = 2
+            }
+            "#,
+        )
+        .parse::<Service>()
+        .unwrap();
+        assert_eq!(
+            service.config,
+            Config {
+                name: "app".to_owned(),
+                db: Database {
+                    host: "localhost".to_owned(),
+                    port: Some(5432),
+                    mode: Mode::Limited(10),
+                },
+            }
+        );
+        assert_eq!(
+            service.rest.get("replicas"),
+            Some(&SimpleValue::Num(serde_dhall::NumKind::Natural(2)))
+        );
+
+        let config = from_str(
+            r#"{ name = "app", host = "h", port = None Natural, mode = < Fast | Limited : Natural >.Fast }"#,
+        )
+        .parse::<Config>()
+        .unwrap();
+        assert_eq!(config.db.port, None);
+        assert_eq!(config.db.mode, Mode::Fast);
+
+        // Unions are still read as such into `SimpleValue`s.
+        assert_eq!(
+            from_str("< Fast | Limited : Natural >.Limited 10")
+                .parse::<SimpleValue>()
+                .unwrap(),
+            SimpleValue::Union(
+                "Limited".to_owned(),
+                Some(Box::new(SimpleValue::Num(
+                    serde_dhall::NumKind::Natural(10)
+                )))
+            )
+        );
+    }
+
+    #[test]
+    fn test_de_untyped() {
+        use std::collections::BTreeMap;
+        use std::collections::HashMap;
+
+        fn parse<T: FromDhall>(s: &str) -> T {
+            from_str(s).parse().unwrap()
+        }
+
+        // Test tuples on record of wrong type. Fields are just taken in alphabetic order.
+        assert_eq!(
+            parse::<(u64, String, i64)>(r#"{ y = "foo", x = 1, z = +42 }"#),
+            (1, "foo".to_owned(), 42)
+        );
+
+        let mut expected_map = HashMap::new();
+        expected_map.insert("x".to_string(), 1);
+        expected_map.insert("y".to_string(), 2);
+        assert_eq!(
+            parse::<HashMap<String, u64>>("{ x = 1, y = 2 }"),
+            expected_map
+        );
+        assert_eq!(
+            parse::<HashMap<String, u64>>("toMap { x = 1, y = 2 }"),
+            expected_map
+        );
+
+        let mut expected_map = HashMap::new();
+        expected_map.insert("if".to_string(), 1);
+        expected_map.insert("FOO_BAR".to_string(), 2);
+        expected_map.insert("baz-kux".to_string(), 3);
+        assert_eq!(
+            parse::<HashMap<String, u64>>(
+                "{ `if` = 1, FOO_BAR = 2, baz-kux = 3 }"
+            ),
+            expected_map
+        );
+
+        let mut expected_map = BTreeMap::new();
+        expected_map.insert("x".to_string(), 1);
+        expected_map.insert("y".to_string(), 2);
+        assert_eq!(
+            parse::<BTreeMap<String, u64>>("{ x = 1, y = 2 }"),
+            expected_map
+        );
+
+        // Keys are parsed when the key type isn't a string.
+        let mut expected_map = HashMap::new();
+        expected_map.insert(80, "http".to_string());
+        expected_map.insert(443, "https".to_string());
+        assert_eq!(
+            parse::<HashMap<u64, String>>(
+                r#"[
+                    { mapKey = "80", mapValue = "http" },
+                    { mapKey = "443", mapValue = "https" },
+                ]"#
+            ),
+            expected_map
+        );
+        let mut expected_map = BTreeMap::new();
+        expected_map.insert(-1, 'a');
+        expected_map.insert(2, 'b');
+        assert_eq!(
+            parse::<BTreeMap<i64, char>>(r#"toMap { `-1` = "a", `2` = "b" }"#),
+            expected_map
+        );
+        let mut expected_map = HashMap::new();
+        expected_map.insert(std::path::PathBuf::from("a/b"), 1);
+        assert_eq!(
+            parse::<HashMap<std::path::PathBuf, u64>>(
+                r#"[{ mapKey = "a/b", mapValue = 1 }]"#
+            ),
+            expected_map
+        );
+        assert!(from_str(r#"{ x = 1 }"#)
+            .parse::<HashMap<u64, u64>>()
+            .is_err());
+
+        #[derive(Debug, PartialEq, Eq, Deserialize)]
+        struct Foo {
+            x: u64,
+            y: Option<u64>,
+        }
+        // Omit optional field
+        assert_eq!(parse::<Foo>("{ x = 1 }"), Foo { x: 1, y: None });
+
+        // https://github.com/Nadrieril/dhall-rust/issues/155
+        assert!(from_str("List/length [True, 42]").parse::<bool>().is_err());
+    }
+
+    #[test]
+    fn simple_value_visitor() {
+        use serde_dhall::{NumKind, SimpleValue, SimpleValueVisitor};
+
+        #[derive(Default)]
+        struct SumNaturals(u64);
+        impl SimpleValueVisitor for SumNaturals {
+            fn visit_num(&mut self, num: &NumKind) {
+                if let NumKind::Natural(n) = num {
+                    self.0 += n;
+                }
+            }
+        }
+
+        let value: SimpleValue = from_str(
+            r#"
+            let Shape = < Circle : Natural | Square : { side : Natural } | Empty >
+            in  { a = 1
+                , b = [ Some 2, None Natural ]
+                , c = { d = "3", e = +4, f = [ Shape.Circle 5, Shape.Square { side = 6 }, Shape.Empty ] }
+                , g = toMap { x = 7 }
+                }
+            "#,
+        )
+        .parse()
+        .unwrap();
+        let mut visitor = SumNaturals::default();
+        value.accept(&mut visitor);
+        assert_eq!(visitor.0, 1 + 2 + 5 + 6 + 7);
+    }
+
+    #[test]
+    fn test_file() {
+        assert_eq!(
+            serde_dhall::from_file(
+                "../dhall-lang/tests/parser/success/unit/BoolLitTrueA.dhall"
+            )
+            .static_type_annotation()
+            .parse::<bool>()
+            .map_err(|e| e.to_string()),
+            Ok(true)
+        );
+        assert_eq!(
+            serde_dhall::from_binary_file(
+                "../dhall-lang/tests/parser/success/unit/BoolLitTrueB.dhallb"
+            )
+            .static_type_annotation()
+            .parse::<bool>()
+            .map_err(|e| e.to_string()),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn preserve_field_order() {
+        use serde::de::{Deserializer, MapAccess, Visitor};
+
+        /// A map that keeps the order of its entries, like `IndexMap`.
+        #[derive(Debug, PartialEq)]
+        struct Ordered<V>(Vec<(String, V)>);
+
+        impl<'de, V: Deserialize<'de>> Deserialize<'de> for Ordered<V> {
+            fn deserialize<D: Deserializer<'de>>(
+                d: D,
+            ) -> Result<Self, D::Error> {
+                struct OrderedVisitor<V>(std::marker::PhantomData<V>);
+                impl<'de, V: Deserialize<'de>> Visitor<'de> for OrderedVisitor<V> {
+                    type Value = Ordered<V>;
+                    fn expecting(
+                        &self,
+                        f: &mut std::fmt::Formatter,
+                    ) -> std::fmt::Result {
+                        write!(f, "a map")
+                    }
+                    fn visit_map<A: MapAccess<'de>>(
+                        self,
+                        mut map: A,
+                    ) -> Result<Ordered<V>, A::Error> {
+                        let mut entries = Vec::new();
+                        while let Some(entry) = map.next_entry()? {
+                            entries.push(entry);
+                        }
+                        Ok(Ordered(entries))
+                    }
+                }
+                d.deserialize_map(OrderedVisitor(std::marker::PhantomData))
+            }
+        }
+
+        let keys = |s: &str, preserve: bool| {
+            from_str(s)
+                .preserve_field_order(preserve)
+                .parse::<Ordered<u64>>()
+                .map(|m| m.0.into_iter().map(|(k, _)| k).collect::<Vec<_>>())
+                .map_err(|e| e.to_string())
+        };
+        let ordered =
+            |v: &[&str]| Ok(v.iter().map(|k| k.to_string()).collect());
+        assert_eq!(keys("{ b = 1, a = 2 }", true), ordered(&["b", "a"]));
+        assert_eq!(keys("{ b = 1, a = 2 }", false), ordered(&["a", "b"]));
+        // Records built by functions get the order of their literal.
+        assert_eq!(
+            keys("let f = λ(n : Natural) → { y = n, x = n + 1 } in f 1", true),
+            ordered(&["y", "x"])
+        );
+        // Record types give their order too.
+        assert_eq!(
+            keys("{ b = 1, a = 2 } // { c = 3 } : { c : Natural, b : Natural, a : Natural }", true),
+            ordered(&["c", "b", "a"])
+        );
+        // Without a literal or type with the same fields, the fields are sorted.
+        assert_eq!(
+            keys("{ c = 1, b = 2 } // { a = 3 }", true),
+            ordered(&["a", "b", "c"])
+        );
+
+        let nested = from_str("{ z = { y = 1, x = 2 }, a = { c = 3, b = 4 } }")
+            .preserve_field_order(true)
+            .parse::<Ordered<Ordered<u64>>>()
+            .map_err(|e| e.to_string());
+        let entries = |v: &[(&str, u64)]| {
+            Ordered(v.iter().map(|(k, x)| (k.to_string(), *x)).collect())
+        };
+        assert_eq!(
+            nested,
+            Ok(Ordered(vec![
+                ("z".to_owned(), entries(&[("y", 1), ("x", 2)])),
+                ("a".to_owned(), entries(&[("c", 3), ("b", 4)])),
+            ]))
+        );
+
+        // Records with the same fields each keep their own order, even when it is the sorted
+        // one.
+        let same_fields = "{ x = { a = 1, b = 2 }, y = { b = 3, a = 4 } }";
+        assert_eq!(
+            from_str(same_fields)
+                .preserve_field_order(true)
+                .parse::<Ordered<Ordered<u64>>>()
+                .map_err(|e| e.to_string()),
+            Ok(Ordered(vec![
+                ("x".to_owned(), entries(&[("a", 1), ("b", 2)])),
+                ("y".to_owned(), entries(&[("b", 3), ("a", 4)])),
+            ]))
+        );
+        assert_eq!(
+            from_str(same_fields)
+                .preserve_field_order(true)
+                .at_path("y")
+                .parse::<Ordered<u64>>()
+                .map_err(|e| e.to_string()),
+            Ok(entries(&[("b", 3), ("a", 4)]))
+        );
+
+        // The order is kept through imports.
+        let dir = TempDir::new("field_order");
+        std::fs::write(dir.join("a.dhall"), "{ b = 1, a = 2 }").unwrap();
+        let imported = serde_dhall::from_file(dir.join("a.dhall"))
+            .preserve_field_order(true)
+            .parse::<Ordered<u64>>()
+            .map_err(|e| e.to_string());
+        let main = format!("{}/a.dhall", dir.display());
+        let through_import = keys(&main, true);
+        std::fs::write(dir.join("same_fields.dhall"), same_fields).unwrap();
+        let nested_import =
+            from_str(&format!("{{ z = {}/same_fields.dhall }}", dir.display()))
+                .preserve_field_order(true)
+                .at_path("z")
+                .parse::<Ordered<Ordered<u64>>>()
+                .map_err(|e| e.to_string());
+        assert_eq!(imported, Ok(entries(&[("b", 1), ("a", 2)])));
+        assert_eq!(through_import, ordered(&["b", "a"]));
+        assert_eq!(
+            nested_import,
+            Ok(Ordered(vec![
+                ("x".to_owned(), entries(&[("a", 1), ("b", 2)])),
+                ("y".to_owned(), entries(&[("b", 3), ("a", 4)])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn binary_diagnostic() {
+        let dir = TempDir::new("diagnostic");
+        let expr =
+            dhall::syntax::parse_expr("{ x = 1, y = [True], z = \"a\\n\" }")
+                .unwrap();
+        let data = dhall::syntax::binary::encode(&expr).unwrap();
+        std::fs::write(dir.join("good.dhallb"), &data).unwrap();
+        std::fs::write(dir.join("truncated.dhallb"), &data[..data.len() - 3])
+            .unwrap();
+        let good = serde_dhall::binary_diagnostic(dir.join("good.dhallb"))
+            .map_err(|e| e.to_string());
+        let truncated =
+            serde_dhall::binary_diagnostic(dir.join("truncated.dhallb"))
+                .map_err(|e| e.to_string());
+        assert_eq!(
+            good,
+            Ok(r#"[8, {"x": [15, 1], "y": [4, null, true], "z": [18, "a\n"]}]"#
+                .to_owned())
+        );
+        assert!(truncated.is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        assert_de(
+            "../dhall-lang/tests/parser/success/unit/BoolLitTrueA.dhall",
+            true,
+        );
+        assert_eq!(
+            serde_dhall::from_str(
+                "../dhall-lang/tests/parser/success/unit/BoolLitTrueA.dhall"
+            )
+            .imports(false)
+            .static_type_annotation()
+            .parse::<bool>()
+            .map_err(|e| e.to_string()),
+            Err("UnexpectedImport(Import { mode: Code, location: Local(Parent, FilePath { file_path: [\"dhall-lang\", \"tests\", \"parser\", \"success\", \"unit\", \"BoolLitTrueA.dhall\"] }), hash: None })".to_string())
+        );
+    }
+
+    #[test]
+    fn on_import_resolved() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let dir = TempDir::new("on_import");
+        std::fs::write(dir.join("a.dhall"), "1").unwrap();
+        std::fs::write(dir.join("b.dhall"), "./a.dhall + 2").unwrap();
+        std::fs::write(
+            dir.join("main.dhall"),
+            "[./a.dhall, ./b.dhall, ./a.dhall]",
+        )
+        .unwrap();
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let data = serde_dhall::from_file(dir.join("main.dhall"))
+            .on_import_resolved({
+                let seen = seen.clone();
+                move |import, meta| {
+                    seen.borrow_mut().push((import.to_string(), meta.clone()))
+                }
+            })
+            .parse::<Vec<u64>>()
+            .map_err(|e| e.to_string());
+        assert_eq!(data, Ok(vec![1, 3, 1]));
+
+        let seen = seen.borrow();
+        let fetched: Vec<_> = seen
+            .iter()
+            .filter(|(_, meta)| !meta.from_cache)
+            .map(|(import, meta)| (import.as_str(), meta.size))
+            .collect();
+        let a = dir.join("a.dhall").display().to_string();
+        let b = dir.join("b.dhall").display().to_string();
+        // Each distinct file is reported as fetched exactly once.
+        assert_eq!(
+            fetched,
+            vec![(a.as_str(), Some(1)), (b.as_str(), Some(13))]
+        );
+        // `a.dhall` is imported twice more, from the cache.
+        assert_eq!(seen.len(), 4);
+        assert!(seen.iter().filter(|(import, _)| import == &a).all(
+            |(_, meta)| meta.hash.to_string() == seen[0].1.hash.to_string()
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn canonicalize_imports() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let dir = TempDir::new("canonicalize");
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(dir.join("shared/a.dhall"), "1").unwrap();
+        std::os::unix::fs::symlink(dir.join("shared"), dir.join("link"))
+            .unwrap();
+        std::fs::write(
+            dir.join("main.dhall"),
+            "[./shared/a.dhall, ./other/../shared/a.dhall, ./link/a.dhall]",
+        )
+        .unwrap();
+
+        // The files that were read, as opposed to taken from the cache.
+        let fetched = |canonicalize: bool| {
+            let fetched = Rc::new(RefCell::new(Vec::new()));
+            let data = serde_dhall::from_file(dir.join("main.dhall"))
+                .canonicalize_imports(canonicalize)
+                .on_import_resolved({
+                    let fetched = fetched.clone();
+                    move |import, meta| {
+                        if !meta.from_cache {
+                            fetched.borrow_mut().push(import.to_string())
+                        }
+                    }
+                })
+                .parse::<Vec<u64>>()
+                .map_err(|e| e.to_string());
+            assert_eq!(data, Ok(vec![1, 1, 1]));
+            let fetched = fetched.borrow().clone();
+            fetched
+        };
+        let without = fetched(false);
+        let with = fetched(true);
+        let real = std::fs::canonicalize(dir.join("shared/a.dhall"));
+
+        // `..` is collapsed either way, but the symlink is only followed when canonicalizing.
+        assert_eq!(
+            without,
+            vec![
+                dir.join("shared/a.dhall").display().to_string(),
+                dir.join("link/a.dhall").display().to_string(),
+            ]
+        );
+        assert_eq!(with, vec![real.unwrap().display().to_string()]);
+    }
+
+    #[test]
+    fn use_cache() {
+        use serde_dhall::PersistentCache;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let dir = TempDir::new("use_cache");
+        std::fs::write(dir.join("port.dhall"), "8080").unwrap();
+        std::fs::write(
+            dir.join("main.dhall"),
+            "{ port = ./port.dhall, hosts = [\"a\", \"b\"] }",
+        )
+        .unwrap();
+        let cache = PersistentCache::new(&dir.join("cache"));
+
+        // The imports resolved while reading `file`, which are none if it comes from the cache.
+        let read = |file: &str| {
+            let imports = Rc::new(Cell::new(0));
+            let port = serde_dhall::from_file(dir.join(file))
+                .with_cache(cache.clone())
+                .at_path("port")
+                .on_import_resolved({
+                    let imports = imports.clone();
+                    move |_, _| imports.set(imports.get() + 1)
+                })
+                .parse::<u64>()
+                .map_err(|e| e.to_string());
+            (port, imports.get())
+        };
+        let first = read("main.dhall");
+        let second = read("main.dhall");
+        // The cache is dropped when an imported file changes.
+        std::fs::write(dir.join("port.dhall"), "80").unwrap();
+        let changed = read("main.dhall");
+        // Environment variables aren't cached.
+        std::fs::write(dir.join("env.dhall"), "{ port = env:HOME as Text }")
+            .unwrap();
+        let env = serde_dhall::from_file(dir.join("env.dhall"))
+            .with_cache(cache.clone())
+            .parse::<std::collections::HashMap<String, String>>()
+            .is_ok();
+        let entries = std::fs::read_dir(dir.join("cache")).unwrap().count();
+        // Nor are import alternatives that fell back, since the file that was missing can be
+        // created later.
+        std::fs::write(
+            dir.join("alt.dhall"),
+            "{ port = ./local.dhall ? ./port.dhall }",
+        )
+        .unwrap();
+        let fallback = read("alt.dhall");
+        std::fs::write(dir.join("local.dhall"), "8000").unwrap();
+        let created = read("alt.dhall");
+
+        assert_eq!(first, (Ok(8080), 1));
+        assert_eq!(second, (Ok(8080), 0));
+        assert_eq!(changed, (Ok(80), 1));
+        assert!(env);
+        assert_eq!(entries, 1);
+        assert_eq!(fallback, (Ok(80), 1));
+        assert_eq!(created, (Ok(8000), 1));
+    }
+
+    #[test]
+    fn parallel_imports() {
+        let dir = TempDir::new("parallel");
+        for i in 0..8 {
+            std::fs::write(
+                dir.join(format!("{}.dhall", i)),
+                format!("{{ n = {}, next = ./{}.dhall as Text }}", i, i + 1),
+            )
+            .unwrap();
+        }
+        std::fs::write(dir.join("8.dhall"), "8").unwrap();
+        let imports: Vec<_> =
+            (0..8).map(|i| format!("(./{}.dhall).n", i)).collect();
+        std::fs::write(
+            dir.join("main.dhall"),
+            format!("[{}, ./8.dhall]", imports.join(", ")),
+        )
+        .unwrap();
+        // Two independent broken imports, and an import cycle.
+        std::fs::write(
+            dir.join("errors.dhall"),
+            "[./missing.dhall, ./bad.dhall]",
+        )
+        .unwrap();
+        std::fs::write(dir.join("bad.dhall"), "1 +").unwrap();
+        std::fs::write(dir.join("cycle.dhall"), "[./0.dhall, ./cycle.dhall]")
+            .unwrap();
+
+        let parse = |file: &str, parallel: bool| {
+            serde_dhall::from_file(dir.join(file))
+                .parallel_imports(parallel)
+                .parse::<Vec<u64>>()
+                .map_err(|e| e.to_string())
+        };
+        let results: Vec<_> = ["main.dhall", "errors.dhall", "cycle.dhall"]
+            .iter()
+            .map(|file| (parse(file, true), parse(file, false)))
+            .collect();
+
+        assert_eq!(results[0].0, Ok((0..9).collect()));
+        for (parallel, sequential) in &results {
+            assert_eq!(parallel, sequential);
+        }
+        // The first import that fails is the one reported.
+        assert!(results[1].0.as_ref().unwrap_err().contains("missing.dhall"));
+        assert!(results[2].0.as_ref().unwrap_err().contains("cycle"));
+    }
+
+    #[test]
+    fn freeze() {
+        // A source without remote imports comes back unchanged: `missing` has no contents to
+        // hash, so it gets no `sha256:`.
+        assert_eq!(
+            serde_dhall::from_str("let x = 1 in [x, missing ? 2]")
+                .freeze()
+                .map_err(|e| e.to_string()),
+            Ok("let x = 1 in [x, missing ? 2]".to_owned())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "reqwest")]
+    fn freeze_remote() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        // Serve `1` over http, once.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nConnection: close\r\n\r\n1",
+                )
+                .unwrap();
+        });
+
+        let frozen = serde_dhall::from_str(&format!(
+            "http://127.0.0.1:{}/one.dhall + 1",
+            port
+        ))
+        .freeze()
+        .map_err(|e| e.to_string());
+        server.join().unwrap();
+        assert_eq!(
+            frozen,
+            Ok(format!(
+                "http://127.0.0.1:{}/one.dhall sha256:d60d8415e36e86dae7f42933d3b0c4fe3ca238f057fba206c7e9fbf5d784fe15 + 1",
+                port
+            ))
+        );
+    }
+
+    #[test]
+    #[ignore] // Way too slow
+    fn test_prelude() {
+        assert_eq!(
+            serde_dhall::from_str(
+                "https://prelude.dhall-lang.org/package.dhall"
+            )
+            .parse::<Value>()
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn kind_types() {
+        use serde_dhall::{KindType, SimpleType};
+        let parse = |s: &str| from_str(s).parse::<SimpleType>().ok();
+
+        let a = || SimpleType::var("a");
+        let id = KindType::new()
+            .param("a")
+            .build(SimpleType::function(a(), a()));
+        assert_eq!(id.to_string(), "∀(a : Type) → a → a");
+        assert_eq!(parse("∀(a : Type) → a → a").unwrap(), id);
+        assert_eq!(parse("let Id = ∀(a : Type) → a → a in Id").unwrap(), id);
+        // Dhall is impredicative: this type quantifies over types but is a `Type`.
+        assert!(!id.is_kind_typed());
+        // Functions with such a type are values.
+        assert_eq!(
+            from_str("λ(a : Type) → λ(x : a) → x")
+                .type_annotation(&id)
+                .parse::<Value>()
+                .unwrap()
+                .to_string(),
+            "λ(a : Type) → λ(x : a) → x"
+        );
+
+        assert_eq!(parse("Type").unwrap(), SimpleType::Type);
+        assert_eq!(parse("Kind").unwrap(), SimpleType::kind());
+        assert!(SimpleType::Type.is_kind_typed());
+        assert!(!SimpleType::kind().is_kind_typed());
+        assert!(!SimpleType::Natural.is_kind_typed());
+
+        let constructor =
+            SimpleType::function(SimpleType::Type, SimpleType::Type);
+        assert!(constructor.is_kind_typed());
+        let ty = KindType::new()
+            .param_of_kind("f", constructor)
+            .param("a")
+            .build(SimpleType::list(a()));
+        assert_eq!(ty.to_string(), "∀(f : Type → Type) → ∀(a : Type) → List a");
+        assert!(!ty.is_kind_typed());
+        assert!(KindType::new()
+            .param("a")
+            .build(SimpleType::Type)
+            .is_kind_typed());
+        assert_eq!(
+            parse("∀(f : Type → Type) → ∀(a : Type) → List a").unwrap(),
+            ty
+        );
+
+        // The innermost variable shadows the outer one.
+        let ty = KindType::new().param("a").param("a").build(a());
+        assert_eq!(parse("∀(a : Type) → ∀(a : Type) → a").unwrap(), ty);
+        assert!(parse("∀(a : Type) → ∀(a : Type) → a@1").is_none());
+
+        // Type constructors are only simple as the kind of a parameter.
+        assert!(parse("Type → Type").is_none());
+        assert!(parse("∀(a : Type) → Type").is_none());
+
+        // Types are equal up to the names of their bound variables.
+        let b = || SimpleType::var("b");
+        let id_b = KindType::new()
+            .param("b")
+            .build(SimpleType::function(b(), b()));
+        assert_eq!(id_b, id);
+        let k = KindType::new().param("a").param("b");
+        assert_ne!(k.clone().build(a()), k.build(b()));
+        assert_ne!(SimpleType::var("a"), SimpleType::var("b"));
+    }
+
+    #[test]
+    fn prelude_functions() {
+        use serde_dhall::prelude::{Bool, List, Natural, Optional, Text};
+        let v = |s: &str| serde_dhall::from_str(s).parse::<Value>().unwrap();
+        let show = |r: serde_dhall::Result<Value>| r.unwrap().to_string();
+
+        let xs = v("[1, 2, 3]");
+        assert_eq!(
+            show(List::map(&v("λ(n : Natural) → n * 2"), &xs)),
+            "[2, 4, 6]"
+        );
+        assert_eq!(
+            show(List::map(&v("λ(n : Natural) → Natural/isZero n"), &xs)),
+            "[False, False, False]"
+        );
+        assert_eq!(show(List::filter(&v("Natural/even"), &xs)), "[2]");
+        assert_eq!(
+            show(List::map(&v("Natural/show"), &v("[] : List Natural"))),
+            "[] : List Text"
+        );
+        assert_eq!(show(List::concat(&v("[[1], [2, 3]]"))), "[1, 2, 3]");
+        assert_eq!(show(List::null(&xs)), "False");
+        assert_eq!(show(List::null(&v("[] : List Text"))), "True");
+        assert_eq!(
+            show(List::replicate(&v("2"), &v("\"a\""))),
+            "[\"a\", \"a\"]"
+        );
+        assert!(List::map(&v("λ(n : Natural) → n"), &v("1")).is_err());
+        assert!(List::map(&v("λ(n : Natural) → n"), &v("[True]"))
+            .unwrap_err()
+            .to_string()
+            .contains("wrong type of function argument"));
+        assert_eq!(
+            List::map(&v("λ(a : Type) → λ(x : a) → [x]"), &xs)
+                .map_err(|e| e.to_string()),
+            Err("expected a function with a simple output type, found \
+                 `λ(a : Type) → λ(x : a) → [x]`"
+                .to_owned())
+        );
+
+        assert_eq!(show(Optional::default(&v("0"), &v("Some 1"))), "1");
+        assert_eq!(show(Optional::default(&v("0"), &v("None Natural"))), "0");
+        assert_eq!(
+            show(Optional::map(&v("λ(n : Natural) → n + 1"), &v("Some 1"))),
+            "Some 2"
+        );
+        assert_eq!(show(Optional::null(&v("None Natural"))), "True");
+
+        assert_eq!(show(Natural::less_than(&v("1"), &v("2"))), "True");
+        assert_eq!(show(Natural::less_than(&v("2"), &v("2"))), "False");
+        assert_eq!(show(Natural::max(&v("1"), &v("2"))), "2");
+        assert_eq!(show(Natural::min(&v("1"), &v("2"))), "1");
+        assert_eq!(show(Natural::sum(&xs)), "6");
+
+        assert_eq!(
+            show(Text::concat_sep(&v("\", \""), &v("[\"a\", \"b\"]"))),
+            "\"a, b\""
+        );
+        assert_eq!(show(Text::default(&v("None Text"))), "\"\"");
+
+        assert_eq!(show(Bool::not(&v("True"))), "False");
+        assert_eq!(show(Bool::and(&v("[True, False]"))), "False");
+        assert_eq!(show(Bool::or(&v("[True, False]"))), "True");
+    }
+
     // TODO: test various builder configurations
     // In particular test cloning and reusing builder
 }
@@ -66,5 +66,25 @@ mod simple_value {
                 not_simple
             ))
         );
+        for not_simple in &[
+            "λ(f : Type → Type) → λ(x : f Natural) → x",
+            "∀(a : Type) → Type",
+        ] {
+            assert_eq!(
+                from_str(not_simple)
+                    .parse::<Value>()
+                    .map_err(|e| e.to_string()),
+                Err(format!(
+                    "this is neither a simple type nor a simple value: {}",
+                    not_simple
+                ))
+            );
+        }
+
+        // Functions with a simple type are values, equal up to the names of their variables.
+        let f = from_str("λ(x : Natural) → x + 0").parse::<Value>().unwrap();
+        assert_eq!(f.to_string(), "λ(x : Natural) → x");
+        assert_eq!(f, from_str("λ(y : Natural) → y").parse::<Value>().unwrap());
+        assert_ne!(f, from_str("λ(y : Natural) → 0").parse::<Value>().unwrap());
     }
 }